@@ -1,7 +1,6 @@
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
-use url::Url;
 
 #[derive(Parser)]
 pub struct App {
@@ -24,10 +23,9 @@ pub struct App {
 pub enum Subcommands {
     Main,
     ExecutionNode {
-        #[clap(long, env)]
-        api_base_url: Url,
-        #[clap(long, env)]
-        runtime: Option<String>,
+        /// The path to the execution node's config file, see `doxa_execution_node::config`
+        #[clap(long, env = "EXECUTION_NODE_CONFIG")]
+        config: PathBuf,
         #[clap(long, env)]
         docker_username: Option<String>,
         #[clap(long, env)]
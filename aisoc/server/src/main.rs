@@ -1,10 +1,14 @@
-use std::{env, path::PathBuf, sync::Arc};
+use std::{env, io, sync::Arc};
 
 use clap::StructOpt;
 use climatehack::{dataset::Datasets, ClimateHackCompetition};
-use doxa_execution_node::manager::docker::{self, Docker, DockerCredentials};
-use doxa_executor::settings::AgentRetrieval;
-use doxa_server::{tracing::warn, CompetitionSystem};
+use doxa_execution_node::{
+    config::NodeConfig, manager::docker::DockerCredentials, ExecutionNode, NodeSecrets,
+};
+use doxa_server::{
+    tracing::{error, warn},
+    CompetitionSystem,
+};
 use uttt::UTTTCompetition;
 
 mod cli;
@@ -40,15 +44,13 @@ async fn main() -> std::io::Result<()> {
             doxa_server::setup_server_from_env(true, competition_system).await
         }
         cli::Subcommands::ExecutionNode {
-            api_base_url,
-            runtime,
+            config,
             docker_username,
             docker_password,
         } => {
             doxa_server::telemetry::init_telemetry();
-            use doxa_execution_node::manager::{
-                CompetitionManagerSettings, CompetitionNodeManager,
-            };
+
+            let config = NodeConfig::load(&config).expect("failed to load the node config");
 
             let datasets = Arc::new(
                 Datasets::load_from_directory(app.climatehack_datasets_dir, false)
@@ -64,39 +66,31 @@ async fn main() -> std::io::Result<()> {
             let mq_url = env::var("MQ_URL").expect("MQ_URL must be set");
             let system_account_secret = env::var("DOXA_SYSTEM_ACCOUNT_SECRET")
                 .expect("DOXA_SYSTEM_ACCOUNT_SECRET must be set");
-            let mq_pool = doxa_mq::establish_pool(mq_url.to_string(), 25).await;
-
-            let docker_settings = docker::DockerBackendSettings {
-                image: "registry.dewardt.uk/doxa/evaluation_environment".to_string(),
-                //image: "registry.dewardt.uk/doxa/evaluation_environment".to_string(),
-                docker: Docker::connect_with_unix_defaults().unwrap(),
-                credentials: Some(DockerCredentials {
-                    username: docker_username,
-                    password: docker_password,
-                    ..Default::default()
-                }),
-                runtime,
-                memory_limit_bytes: None,
-            };
-            let settings = CompetitionManagerSettings {
-                executor_permits: 1,
-                api_base_url: api_base_url.clone(),
-                request_client: Default::default(),
-                mq_pool,
-                executor_settings: Arc::new(doxa_executor::Settings {
-                    agent_retrieval: AgentRetrieval::new(
-                        "https://doxa.uclaisociety.co.uk/api/storage/download/".to_string(),
-                        system_account_secret,
-                    ),
-                    scratch_base_image: PathBuf::from("./dev/vm/images/scratch.img"),
-                    base_mounts: Vec::new(),
-                }),
-                docker_settings,
-            };
+            let mq = doxa_mq::establish_mq(&mq_url, 25)
+                .await
+                .expect("failed to set up the message queue");
 
-            let competition_node_manager =
-                CompetitionNodeManager::new(Arc::new(climatehack), settings);
-            competition_node_manager.start().await;
+            let mut node = ExecutionNode::new(
+                config,
+                mq,
+                NodeSecrets {
+                    system_account_secret,
+                    docker_credentials: Some(DockerCredentials {
+                        username: docker_username,
+                        password: docker_password,
+                        ..Default::default()
+                    }),
+                },
+            );
+            node.add_competition(climatehack);
+            node.add_competition(UTTTCompetition);
+            if let Err(error) = node.start().await {
+                error!(%error, debug = ?error, "the node config doesn't match the competitions");
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    error.to_string(),
+                ));
+            }
 
             Ok(())
         }
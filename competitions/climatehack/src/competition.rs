@@ -4,7 +4,7 @@ use doxa_competition::{
     client::{
         async_trait,
         limiter::{LimiterConfig, TokenBucket, ONE_DAY, ONE_HOUR},
-        serde_json, AgentUpload, Competition, Context, GameEvent,
+        serde_json, AgentUpload, Competition, Context, GameEvent, Priority,
     },
     error::ContextError,
 };
@@ -46,6 +46,7 @@ impl Competition for ClimateHackCompetition {
                     dataset: self.primary_dataset.clone(),
                 },
                 &agent.execution_environment,
+                Priority::Placement,
             )
             .await?;
 
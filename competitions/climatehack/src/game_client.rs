@@ -1,7 +1,7 @@
 use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use doxa_competition::{
-    client::{async_trait, GameClient, GameContext, GameError, Mount, VMBackend},
+    client::{async_trait, Checkpoint, GameClient, GameContext, GameError, Mount, VMBackend},
     tokio::{self, io::AsyncWriteExt},
     tracing::{debug, info},
 };
@@ -41,6 +41,14 @@ pub enum ClimateHackGameEvent {
     },
 }
 
+/// The progress saved after each group is scored so that an interrupted evaluation can carry on
+/// from the next group.
+#[derive(Serialize, Deserialize)]
+struct ClimateHackCheckpoint {
+    next_group: u32,
+    total_score: f64,
+}
+
 pub struct ClimateHackGameClient {
     pub(crate) datasets: Arc<Datasets>,
     pub(crate) python_bin: PathBuf,
@@ -51,6 +59,7 @@ impl ClimateHackGameClient {
     async fn run_inner<'a, B: VMBackend>(
         &self,
         match_request: ClimateHackMatchRequest,
+        resume_from: Option<ClimateHackCheckpoint>,
         context: &mut GameContext<'a, Self, B>,
     ) -> Result<(), GameError<ClimateHackError>> {
         context.expect_n_agents(1)?;
@@ -98,8 +107,14 @@ impl ClimateHackGameClient {
 
         context.set_max_message_time(Some(MAX_SERIES_GROUP_TIME));
 
-        let mut total_score = 0.0;
-        for checkpoint in 0..group_count {
+        let (first_group, mut total_score) = match resume_from {
+            Some(resume_from) => {
+                info!(next_group=%resume_from.next_group, "resuming climate hack evaluation");
+                (resume_from.next_group, resume_from.total_score)
+            }
+            None => (0, 0.0),
+        };
+        for checkpoint in first_group..group_count {
             debug!(%checkpoint, "started checkpoint");
 
             context
@@ -165,6 +180,13 @@ impl ClimateHackGameClient {
                 )
                 .await?;
 
+            context
+                .checkpoint(&ClimateHackCheckpoint {
+                    next_group: checkpoint + 1,
+                    total_score,
+                })
+                .await?;
+
             info!(checkpoint=%checkpoint, "completed scoring checkpoint");
         }
 
@@ -200,7 +222,18 @@ impl GameClient for ClimateHackGameClient {
         match_request: ClimateHackMatchRequest,
         context: &mut GameContext<'a, Self, B>,
     ) -> Result<(), GameError<Self::Error>> {
-        self.run_inner(match_request, context).await
+        self.run_inner(match_request, None, context).await
+    }
+
+    async fn resume<'a, B: VMBackend>(
+        &self,
+        match_request: ClimateHackMatchRequest,
+        checkpoint: Checkpoint,
+        context: &mut GameContext<'a, Self, B>,
+    ) -> Result<(), GameError<Self::Error>> {
+        let resume_from = checkpoint.state()?;
+        self.run_inner(match_request, Some(resume_from), context)
+            .await
     }
 
     fn additional_mounts(&self, match_request: &Self::MatchRequest) -> Vec<Mount> {
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use doxa_db::{
+    action,
+    diesel::PgConnection,
+    model::audit::{AuditLogFilter, InsertableAuditEntry},
+    serde_json::{json, Value},
+};
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    /// Lists the audit log newest first
+    List(ListArgs),
+}
+
+#[derive(Parser)]
+pub struct ListArgs {
+    /// Only entries for actions performed by this user
+    #[clap(long)]
+    actor: Option<String>,
+    /// e.g. `agent.activate`
+    #[clap(long)]
+    action: Option<String>,
+    /// e.g. `user:12`, `agent:{id}` or `competition:{name}`
+    #[clap(long)]
+    target: Option<String>,
+    /// Only entries at or after this RFC 3339 timestamp
+    #[clap(long)]
+    since: Option<DateTime<Utc>>,
+    /// Only entries before this RFC 3339 timestamp
+    #[clap(long)]
+    until: Option<DateTime<Utc>>,
+    #[clap(long, default_value = "100")]
+    limit: i64,
+}
+
+pub fn handle_subcommand(command: AuditCommands, conn: &PgConnection) {
+    match command {
+        AuditCommands::List(args) => list(args, conn),
+    }
+}
+
+/// Records an action performed with `doxa_adm`, these have no actor.
+pub fn record(conn: &PgConnection, action: &str, target: String, mut metadata: Value) {
+    metadata["source"] = json!("doxa_adm");
+
+    action::audit::record(
+        conn,
+        &InsertableAuditEntry::new(None, action)
+            .with_target(target)
+            .with_metadata(metadata),
+    )
+    .expect("failed to write to the audit log");
+}
+
+pub fn list(args: ListArgs, conn: &PgConnection) {
+    let actor = args.actor.map(|username| {
+        action::user::get_user_by_username(conn, &username)
+            .unwrap()
+            .expect("User does not exist")
+            .id
+    });
+
+    let entries = action::audit::list_entries(
+        conn,
+        &AuditLogFilter {
+            actor,
+            action: args.action,
+            target: args.target,
+            since: args.since,
+            until: args.until,
+            before_id: None,
+        },
+        args.limit,
+    )
+    .unwrap();
+
+    println!("ID CREATED_AT ACTOR ACTION TARGET METADATA");
+    for entry in entries {
+        println!(
+            "{} {} {} {} {} {}",
+            entry.id,
+            entry.created_at.to_rfc3339(),
+            entry
+                .actor
+                .map(|actor| actor.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            entry.action,
+            entry.target.unwrap_or_else(|| "-".to_string()),
+            entry.metadata
+        );
+    }
+}
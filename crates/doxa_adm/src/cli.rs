@@ -2,7 +2,10 @@
 
 use clap::{Parser, Subcommand};
 
-use crate::{competition::CompetitionCommands, user::UserCommands};
+use crate::{
+    audit::AuditCommands, competition::CompetitionCommands, dead_letter::DeadLetterCommands,
+    user::UserCommands,
+};
 
 #[derive(Parser)]
 pub struct Cli {
@@ -18,4 +21,11 @@ pub enum MainCommands {
     #[clap(subcommand)]
     /// Commands for managing competitions
     Competition(CompetitionCommands),
+    #[clap(subcommand)]
+    /// Commands for inspecting and replaying messages that could not be processed, this uses
+    /// `MQ_URL` to connect to the message queue
+    DeadLetter(DeadLetterCommands),
+    #[clap(subcommand)]
+    /// Commands for reading the audit log of administrative actions
+    Audit(AuditCommands),
 }
@@ -1,8 +1,13 @@
+use chrono::Utc;
 use clap::{Parser, Subcommand};
 use doxa_db::{
     action,
     diesel::PgConnection,
-    model::competition::{Competition, Enrollment, InsertableCompetition},
+    model::{
+        audit::{actions, competition_target, invite_target, user_target},
+        competition::{Competition, CompetitionRole, Enrollment, InsertableCompetition},
+    },
+    serde_json::json,
     was_unique_key_violation,
 };
 
@@ -20,6 +25,32 @@ pub enum CompetitionCommands {
     ListCompetitionEnrollments { competition_name: String },
     /// Creates a competition with a specific name
     Create(CreateCompetitonArgs),
+    /// Grants a user a role in a competition
+    GrantRole(RoleArgs),
+    /// Revokes a role from a user in a competition
+    RevokeRole(RoleArgs),
+    /// Lists the roles users have in a competition
+    ListRoles { competition_name: String },
+    /// Creates an invite code that users can enroll themselves with
+    CreateInvite(CreateInviteArgs),
+    /// Lists the invite codes of a competition
+    ListInvites { competition_name: String },
+    /// Revokes an invite code so it can't be used anymore
+    RevokeInvite {
+        competition_name: String,
+        invite_id: i32,
+    },
+    /// Sets whether users can enroll themselves without an invite code
+    SetOpenEnrollment {
+        competition_name: String,
+        #[clap(possible_values = &["true", "false"])]
+        open_enrollment: String,
+    },
+    /// Sets the maximum number of members in a team, teams can't be created if this is left out
+    SetMaxTeamSize {
+        competition_name: String,
+        max_team_size: Option<i32>,
+    },
 }
 
 #[derive(Parser)]
@@ -28,6 +59,30 @@ pub struct EnrollArgs {
     competition_name: String,
 }
 
+#[derive(Parser)]
+pub struct RoleArgs {
+    username: String,
+    competition_name: String,
+    /// Organisers have every permission, moderators can see private data and manage agents and
+    /// viewers can only see private data
+    #[clap(possible_values = &["organiser", "moderator", "viewer"])]
+    role: String,
+}
+
+#[derive(Parser)]
+pub struct CreateInviteArgs {
+    competition_name: String,
+    /// How many users can enroll with the code
+    #[clap(long)]
+    max_uses: i32,
+    /// The code never expires if this isn't set, otherwise it must be at most 365
+    #[clap(long)]
+    expires_in_days: Option<u32>,
+    /// Only users with an email address at this domain (e.g. `warwick.ac.uk`) can use the code
+    #[clap(long)]
+    email_domain: Option<String>,
+}
+
 #[derive(Parser)]
 pub struct CreateCompetitonArgs {
     /// The name of the competition to create
@@ -49,6 +104,25 @@ pub fn handle_subcommand(subcommand: CompetitionCommands, conn: &PgConnection) {
             list_competition_enrollments(competition_name, conn)
         }
         CompetitionCommands::Create(args) => create(args, conn),
+        CompetitionCommands::GrantRole(args) => grant_role(args, conn),
+        CompetitionCommands::RevokeRole(args) => revoke_role(args, conn),
+        CompetitionCommands::ListRoles { competition_name } => list_roles(competition_name, conn),
+        CompetitionCommands::CreateInvite(args) => create_invite(args, conn),
+        CompetitionCommands::ListInvites { competition_name } => {
+            list_invites(competition_name, conn)
+        }
+        CompetitionCommands::RevokeInvite {
+            competition_name,
+            invite_id,
+        } => revoke_invite(competition_name, invite_id, conn),
+        CompetitionCommands::SetOpenEnrollment {
+            competition_name,
+            open_enrollment,
+        } => set_open_enrollment(competition_name, open_enrollment == "true", conn),
+        CompetitionCommands::SetMaxTeamSize {
+            competition_name,
+            max_team_size,
+        } => set_max_team_size(competition_name, max_team_size, conn),
     }
 }
 
@@ -61,11 +135,20 @@ fn print_competition_table(competitions: &[Competition]) {
 }
 
 fn print_competition_table_header() {
-    println!("ID NAME");
+    println!("ID NAME OPEN_ENROLLMENT MAX_TEAM_SIZE");
 }
 
 fn print_competition_row(competition: &Competition) {
-    println!("{} {}", competition.id, competition.name);
+    println!(
+        "{} {} {} {}",
+        competition.id,
+        competition.name,
+        competition.open_enrollment,
+        competition
+            .max_team_size
+            .map(|size| size.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    );
 }
 
 fn print_single_competition(competition: &Competition) {
@@ -102,6 +185,13 @@ pub fn enroll(args: EnrollArgs, conn: &PgConnection) {
     assert_eq!(enrollment.competition, competition.id);
     assert_eq!(enrollment.user_id, user.id);
 
+    crate::audit::record(
+        conn,
+        actions::ENROLL_USER,
+        user_target(user.id),
+        json!({ "competition": competition.name }),
+    );
+
     println!(
         "User (id={}, username={}) is now enrolled in competition (id={},name={})",
         enrollment.user_id, username, enrollment.competition, competition.name
@@ -133,6 +223,13 @@ pub fn create(args: CreateCompetitonArgs, conn: &PgConnection) {
         Err(e) => panic!("failed to create competition: {}", e),
     };
 
+    crate::audit::record(
+        conn,
+        actions::CREATE_COMPETITION,
+        competition_target(&competition.name),
+        json!({}),
+    );
+
     print_single_competition(&competition);
 }
 
@@ -155,3 +252,197 @@ pub fn list_competition_enrollments(competition_name: String, conn: &PgConnectio
 
     crate::user::print_user_table(&users);
 }
+
+pub fn grant_role(args: RoleArgs, conn: &PgConnection) {
+    let user = action::user::get_user_by_username(conn, &args.username)
+        .unwrap()
+        .expect("User does not exist");
+    let competition = action::competition::get_competition_by_name(conn, &args.competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    let granted = action::competition::grant_competition_role(
+        conn,
+        &CompetitionRole {
+            user_id: user.id,
+            competition: competition.id,
+            role: args.role.clone(),
+            granted_at: Utc::now(),
+        },
+    )
+    .unwrap();
+
+    if granted.is_none() {
+        eprintln!("The user already has this role so ignoring");
+        return;
+    }
+
+    crate::audit::record(
+        conn,
+        actions::GRANT_ROLE,
+        user_target(user.id),
+        json!({ "competition": competition.name, "role": args.role }),
+    );
+
+    println!(
+        "User (id={}, username={}) is now {} of competition (id={},name={})",
+        user.id, user.username, args.role, competition.id, competition.name
+    );
+}
+
+pub fn revoke_role(args: RoleArgs, conn: &PgConnection) {
+    let user = action::user::get_user_by_username(conn, &args.username)
+        .unwrap()
+        .expect("User does not exist");
+    let competition = action::competition::get_competition_by_name(conn, &args.competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    action::competition::revoke_competition_role(conn, user.id, competition.id, &args.role)
+        .unwrap()
+        .expect("User does not have this role");
+
+    crate::audit::record(
+        conn,
+        actions::REVOKE_ROLE,
+        user_target(user.id),
+        json!({ "competition": competition.name, "role": args.role }),
+    );
+
+    println!(
+        "User (id={}, username={}) is no longer {} of competition (id={},name={})",
+        user.id, user.username, args.role, competition.id, competition.name
+    );
+}
+
+pub fn list_roles(competition_name: String, conn: &PgConnection) {
+    let competition = action::competition::get_competition_by_name(conn, &competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    let roles = action::competition::list_competition_roles(conn, competition.id).unwrap();
+
+    println!("USER_ID USERNAME ROLE GRANTED_AT");
+    for (role, user) in roles {
+        println!(
+            "{} {} {} {}",
+            user.id,
+            user.username,
+            role.role,
+            role.granted_at.to_rfc3339()
+        );
+    }
+}
+
+pub fn create_invite(args: CreateInviteArgs, conn: &PgConnection) {
+    let competition = action::competition::get_competition_by_name(conn, &args.competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    // This is recorded in the audit log with no actor like the other `doxa_adm` commands
+    let invite = doxa_auth::enrollment::create_invite(
+        conn,
+        &competition,
+        None,
+        args.max_uses,
+        args.expires_in_days,
+        args.email_domain,
+    )
+    .expect("failed to create invite");
+
+    println!(
+        "Created invite (id={}) for competition (id={},name={}), the code is {}",
+        invite.id, competition.id, competition.name, invite.code
+    );
+}
+
+pub fn list_invites(competition_name: String, conn: &PgConnection) {
+    let competition = action::competition::get_competition_by_name(conn, &competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    let invites = action::competition::list_competition_invites(conn, competition.id).unwrap();
+
+    println!("ID CODE USES MAX_USES EXPIRES_AT EMAIL_DOMAIN ACTIVE");
+    let now = Utc::now();
+    for invite in invites {
+        let active =
+            doxa_auth::enrollment::invite_is_active(&invite, now) && invite.uses < invite.max_uses;
+        println!(
+            "{} {} {} {} {} {} {}",
+            invite.id,
+            invite.code,
+            invite.uses,
+            invite.max_uses,
+            invite
+                .expires_at
+                .map(|expires_at| expires_at.to_rfc3339())
+                .unwrap_or_else(|| "-".to_string()),
+            invite.email_domain.as_deref().unwrap_or("-"),
+            active
+        );
+    }
+}
+
+pub fn revoke_invite(competition_name: String, invite_id: i32, conn: &PgConnection) {
+    let competition = action::competition::get_competition_by_name(conn, &competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    action::competition::revoke_invite(conn, competition.id, invite_id, Utc::now())
+        .unwrap()
+        .expect("The invite does not exist or has already been revoked");
+
+    crate::audit::record(
+        conn,
+        actions::REVOKE_INVITE,
+        invite_target(invite_id),
+        json!({ "competition": competition.name }),
+    );
+
+    println!("Revoked invite (id={})", invite_id);
+}
+
+pub fn set_open_enrollment(competition_name: String, open_enrollment: bool, conn: &PgConnection) {
+    let competition = action::competition::get_competition_by_name(conn, &competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    let competition =
+        action::competition::set_open_enrollment(conn, competition.id, open_enrollment).unwrap();
+
+    crate::audit::record(
+        conn,
+        actions::SET_OPEN_ENROLLMENT,
+        competition_target(&competition.name),
+        json!({ "open_enrollment": open_enrollment }),
+    );
+
+    print_single_competition(&competition);
+}
+
+pub fn set_max_team_size(
+    competition_name: String,
+    max_team_size: Option<i32>,
+    conn: &PgConnection,
+) {
+    if let Some(max_team_size) = max_team_size {
+        assert!(max_team_size >= 1, "The max team size must be at least 1");
+    }
+
+    let competition = action::competition::get_competition_by_name(conn, &competition_name)
+        .unwrap()
+        .expect("Competition does not exist");
+
+    let competition =
+        action::competition::set_max_team_size(conn, competition.id, max_team_size).unwrap();
+
+    crate::audit::record(
+        conn,
+        actions::SET_MAX_TEAM_SIZE,
+        competition_target(&competition.name),
+        json!({ "max_team_size": max_team_size }),
+    );
+
+    print_single_competition(&competition);
+}
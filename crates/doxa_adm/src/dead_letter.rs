@@ -0,0 +1,97 @@
+use clap::{Parser, Subcommand};
+use doxa_core::tokio;
+use doxa_db::{
+    diesel::PgConnection,
+    model::audit::{actions, queue_target},
+    serde_json::json,
+};
+use doxa_mq::{model::DeadLetter, MQ};
+
+/// The number of bytes of each message that are shown when listing dead letters.
+const PREVIEW_LEN: usize = 80;
+
+#[derive(Subcommand)]
+pub enum DeadLetterCommands {
+    /// Lists the oldest messages in a queue's dead letter queue without removing them
+    List(DeadLetterArgs),
+    /// Publishes the oldest messages in a queue's dead letter queue back to the queue so that they
+    /// are processed again
+    Replay(DeadLetterArgs),
+}
+
+#[derive(Parser)]
+pub struct DeadLetterArgs {
+    /// The queue that the messages were originally published to, e.g. `gameevent.uttt`
+    queue: String,
+    #[clap(long, default_value = "20")]
+    /// The maximum number of messages
+    limit: usize,
+}
+
+pub fn handle_subcommand(subcommand: DeadLetterCommands, conn: &PgConnection) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let mq = get_mq().await;
+
+        match subcommand {
+            DeadLetterCommands::List(args) => list(args, mq).await,
+            DeadLetterCommands::Replay(args) => replay(args, mq, conn).await,
+        }
+    })
+}
+
+async fn get_mq() -> MQ {
+    let url = std::env::var("MQ_URL").expect("MQ_URL must be set");
+
+    doxa_mq::establish_mq(&url, 1)
+        .await
+        .expect("Failed to connect to the message queue")
+}
+
+fn print_dead_letter(dead_letter: &DeadLetter) {
+    let preview_len = dead_letter.data.len().min(PREVIEW_LEN);
+
+    println!(
+        "{} {} bytes: {}",
+        dead_letter.failed_at,
+        dead_letter.data.len(),
+        dead_letter.reason
+    );
+    println!(
+        "    {}",
+        String::from_utf8_lossy(&dead_letter.data[..preview_len])
+    );
+}
+
+pub async fn list(args: DeadLetterArgs, mq: MQ) {
+    let dead_letters = doxa_mq::action::peek_dead_letters(mq.as_ref(), &args.queue, args.limit)
+        .await
+        .unwrap();
+
+    if dead_letters.is_empty() {
+        println!("There are no dead letters for {}", args.queue);
+    }
+
+    for dead_letter in &dead_letters {
+        print_dead_letter(dead_letter);
+    }
+}
+
+pub async fn replay(args: DeadLetterArgs, mq: MQ, conn: &PgConnection) {
+    let replayed = doxa_mq::action::replay_dead_letters(mq.as_ref(), &args.queue, args.limit)
+        .await
+        .unwrap();
+
+    crate::audit::record(
+        conn,
+        actions::REPLAY_DEAD_LETTERS,
+        queue_target(&args.queue),
+        json!({ "replayed": replayed }),
+    );
+
+    println!("Replayed {} messages to {}", replayed, args.queue);
+}
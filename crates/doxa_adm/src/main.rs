@@ -4,8 +4,10 @@ use doxa_db::diesel::PgConnection;
 
 // TODO: re-enable agent and competition
 // mod agent;
+mod audit;
 mod cli;
 mod competition;
+mod dead_letter;
 mod user;
 
 fn get_db_connection() -> PgConnection {
@@ -27,5 +29,9 @@ fn main() {
         cli::MainCommands::Competition(subcommand) => {
             competition::handle_subcommand(subcommand, &connection)
         }
+        cli::MainCommands::DeadLetter(subcommand) => {
+            dead_letter::handle_subcommand(subcommand, &connection)
+        }
+        cli::MainCommands::Audit(subcommand) => audit::handle_subcommand(subcommand, &connection),
     }
 }
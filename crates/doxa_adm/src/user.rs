@@ -1,4 +1,12 @@
-use doxa_db::{action, diesel::PgConnection, model::user::User};
+use doxa_db::{
+    action,
+    diesel::PgConnection,
+    model::{
+        audit::{actions, user_target},
+        user::User,
+    },
+    serde_json::json,
+};
 
 use clap::{Parser, Subcommand};
 
@@ -9,6 +17,9 @@ pub enum UserCommands {
     /// Admin subcommands
     #[clap(subcommand)]
     Admin(AdminCommands),
+    /// Logs a user out everywhere and revokes all of their API tokens (e.g. if their account has
+    /// been compromised)
+    RevokeSessions { username: String },
 }
 
 #[derive(Subcommand)]
@@ -37,6 +48,7 @@ pub fn handle_subcommand(command: UserCommands, conn: &PgConnection) {
     match command {
         UserCommands::List {} => list_users(conn),
         UserCommands::Admin(subcommand) => handle_admin_subcommand(subcommand, conn),
+        UserCommands::RevokeSessions { username } => revoke_sessions(username, conn),
     }
 }
 
@@ -44,7 +56,7 @@ pub fn handle_admin_subcommand(command: AdminCommands, conn: &PgConnection) {
     match command {
         AdminCommands::List {} => list_admins(conn),
         AdminCommands::Promote(args) => set_admin_status(args.username, conn, true),
-        AdminCommands::Demote(args) => set_admin_status(args.username, conn, true),
+        AdminCommands::Demote(args) => set_admin_status(args.username, conn, false),
     }
 }
 
@@ -84,5 +96,26 @@ pub fn list_admins(conn: &PgConnection) {
 pub fn set_admin_status(username: String, conn: &PgConnection, admin_status: bool) {
     let user = action::user::set_admin_status(conn, username, admin_status).unwrap();
 
+    let audit_action = if admin_status {
+        actions::PROMOTE_ADMIN
+    } else {
+        actions::DEMOTE_ADMIN
+    };
+    crate::audit::record(conn, audit_action, user_target(user.id), json!({}));
+
     print_single_user(&user);
 }
+
+pub fn revoke_sessions(username: String, conn: &PgConnection) {
+    let user = action::user::get_user_by_username(conn, &username)
+        .unwrap()
+        .expect("User does not exist");
+
+    let revoked =
+        doxa_auth::session::revoke_sessions(conn, user.id, None, true, "doxa_adm").unwrap();
+
+    println!(
+        "Revoked all sessions of user (id={}, username={}) and {} API token(s)",
+        user.id, user.username, revoked.api_tokens_revoked
+    );
+}
@@ -0,0 +1,292 @@
+//! Personal API tokens are long lived tokens that users create for non-interactive use such as
+//! uploading agents from a CI pipeline.
+//! Unlike the tokens from logging in, API tokens are limited to a set of [`TokenScope`]s and are
+//! never treated as admin.
+//!
+//! Tokens are created with `POST /api/auth/tokens` (`{"name": ..., "scopes": [...],
+//! "expires_in_days": ...}`), listed with `GET /api/auth/tokens` and revoked with
+//! `POST /api/auth/tokens/{id}/revoke`. Scopes are `read`, `upload:{competition}` and
+//! `activate:{competition}` (the competition can be `*`). Tokens expire after 90 days by default
+//! and can only be used for `GET` requests (with the `read` scope) and the routes that check their
+//! scopes.
+
+use std::{fmt, str::FromStr};
+
+use diesel::PgConnection;
+use doxa_core::chrono::{Duration, Utc};
+use doxa_db::{
+    action,
+    model::api_token::{ApiToken, InsertableApiToken},
+    DieselError,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::{
+    ApiTokenNotFound, CreateApiTokenError, InvalidApiTokenExpiry, InvalidApiTokenName,
+    InvalidTokenScope, RevokeApiTokenError,
+};
+
+/// All API tokens start with this so they can be told apart from the tokens from logging in.
+pub const API_TOKEN_PREFIX: &str = "doxa_pat_";
+
+const API_TOKEN_SECRET_BYTES: usize = 32;
+
+pub const DEFAULT_API_TOKEN_EXPIRY_DAYS: u32 = 90;
+pub const MAX_API_TOKEN_EXPIRY_DAYS: u32 = 365;
+pub const MAX_API_TOKEN_NAME_LEN: usize = 64;
+
+/// How often the time a token was last used is updated, so that requests made with a token don't
+/// each write to the database.
+const LAST_USED_INTERVAL_SECS: i64 = 5 * 60;
+
+/// Used in place of a competition name to allow every competition.
+pub const ANY_COMPETITION: &str = "*";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenScope {
+    /// Read-only access (`GET` requests) to everything the user can see.
+    Read,
+    /// Allows uploading agents to the competition.
+    Upload(String),
+    /// Allows activating and deactivating the user's agents in the competition.
+    Activate(String),
+}
+
+impl TokenScope {
+    /// Whether a token with this scope can perform an action that requires `required`.
+    pub fn allows(&self, required: &TokenScope) -> bool {
+        fn competition_allows(scope: &str, required: &str) -> bool {
+            scope == ANY_COMPETITION || scope == required
+        }
+
+        match (self, required) {
+            (TokenScope::Read, TokenScope::Read) => true,
+            (TokenScope::Upload(scope), TokenScope::Upload(required)) => {
+                competition_allows(scope, required)
+            }
+            (TokenScope::Activate(scope), TokenScope::Activate(required)) => {
+                competition_allows(scope, required)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenScope::Read => write!(f, "read"),
+            TokenScope::Upload(competition) => write!(f, "upload:{}", competition),
+            TokenScope::Activate(competition) => write!(f, "activate:{}", competition),
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = InvalidTokenScope;
+
+    /// Parses scopes of the form `read`, `upload:{competition}` or `activate:{competition}` where
+    /// the competition may be [`ANY_COMPETITION`].
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTokenScope {
+            scope: scope.to_string(),
+        };
+
+        if scope == "read" {
+            return Ok(TokenScope::Read);
+        }
+
+        let (kind, competition) = scope.split_once(':').ok_or_else(invalid)?;
+        if competition.is_empty() {
+            return Err(invalid());
+        }
+
+        match kind {
+            "upload" => Ok(TokenScope::Upload(competition.to_string())),
+            "activate" => Ok(TokenScope::Activate(competition.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The scopes of the API token used to authenticate a request, see
+/// [`crate::guard::AuthGuard::scoped_id`].
+#[derive(Debug, Clone)]
+pub struct ApiTokenAuth {
+    pub token_id: i32,
+    pub scopes: Vec<TokenScope>,
+}
+
+impl ApiTokenAuth {
+    pub fn allows(&self, required: &TokenScope) -> bool {
+        self.scopes.iter().any(|scope| scope.allows(required))
+    }
+}
+
+fn generate_token() -> String {
+    use rand::Rng;
+
+    let secret: Vec<u8> = rand::thread_rng()
+        .sample_iter(rand::distributions::Standard)
+        .take(API_TOKEN_SECRET_BYTES)
+        .collect();
+
+    format!(
+        "{}{}",
+        API_TOKEN_PREFIX,
+        base64::encode_config(secret, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+/// Only the hash of a token is stored so that the tokens can't be recovered from the database.
+/// Tokens have plenty of entropy so a (fast) unsalted hash is sufficient.
+pub fn hash_token(token: &str) -> String {
+    base64::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Creates a token for the user, this returns the token (which can't be retrieved again) along
+/// with its record.
+pub fn create_token(
+    conn: &PgConnection,
+    owner: i32,
+    name: String,
+    scopes: &[String],
+    expires_in_days: Option<u32>,
+) -> Result<(String, ApiToken), CreateApiTokenError> {
+    if name.is_empty() || name.len() > MAX_API_TOKEN_NAME_LEN {
+        return Err(InvalidApiTokenName.into());
+    }
+
+    if scopes.is_empty() {
+        return Err(InvalidTokenScope {
+            scope: String::new(),
+        }
+        .into());
+    }
+
+    // Scopes are stored in their canonical form
+    let scopes = scopes
+        .iter()
+        .map(|scope| Ok(scope.parse::<TokenScope>()?.to_string()))
+        .collect::<Result<Vec<_>, InvalidTokenScope>>()?;
+
+    let expires_in_days = expires_in_days.unwrap_or(DEFAULT_API_TOKEN_EXPIRY_DAYS);
+    if expires_in_days == 0 || expires_in_days > MAX_API_TOKEN_EXPIRY_DAYS {
+        return Err(InvalidApiTokenExpiry.into());
+    }
+
+    let token = generate_token();
+    let now = Utc::now();
+    let record = action::api_token::create_token(
+        conn,
+        &InsertableApiToken {
+            owner,
+            name,
+            token_hash: hash_token(&token),
+            scopes,
+            created_at: now,
+            expires_at: now + Duration::days(expires_in_days as i64),
+        },
+    )?;
+
+    Ok((token, record))
+}
+
+/// Finds the owner and scopes of a token, this returns `None` if the token is unknown, revoked or
+/// expired.
+pub fn authenticate_token(
+    conn: &PgConnection,
+    token: &str,
+) -> Result<Option<(i32, ApiTokenAuth)>, DieselError> {
+    let record = match action::api_token::use_token(
+        conn,
+        &hash_token(token),
+        Utc::now(),
+        Duration::seconds(LAST_USED_INTERVAL_SECS),
+    )? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    // Scopes are validated when the token is created so any that fail to parse are ignored
+    let scopes = record
+        .scopes
+        .iter()
+        .filter_map(|scope| scope.parse().ok())
+        .collect();
+
+    Ok(Some((
+        record.owner,
+        ApiTokenAuth {
+            token_id: record.id,
+            scopes,
+        },
+    )))
+}
+
+pub fn revoke_token(
+    conn: &PgConnection,
+    owner: i32,
+    id: i32,
+) -> Result<ApiToken, RevokeApiTokenError> {
+    Ok(action::api_token::revoke_token(conn, owner, id, Utc::now())?.ok_or(ApiTokenNotFound)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scope(scope: &str) -> TokenScope {
+        scope.parse().unwrap()
+    }
+
+    #[test]
+    fn scopes_parse() {
+        assert_eq!(scope("read"), TokenScope::Read);
+        assert_eq!(scope("upload:uttt"), TokenScope::Upload("uttt".to_string()));
+        assert_eq!(scope("activate:*"), TokenScope::Activate("*".to_string()));
+
+        for invalid in ["", "write", "upload", "upload:", "read:uttt", "delete:uttt"] {
+            assert!(invalid.parse::<TokenScope>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn scopes_display_as_they_are_parsed() {
+        for canonical in ["read", "upload:uttt", "activate:*"] {
+            assert_eq!(scope(canonical).to_string(), canonical);
+        }
+    }
+
+    #[test]
+    fn scopes_only_allow_their_competition() {
+        assert!(scope("upload:uttt").allows(&scope("upload:uttt")));
+        assert!(!scope("upload:uttt").allows(&scope("upload:chess")));
+        assert!(!scope("upload:uttt").allows(&scope("activate:uttt")));
+        assert!(!scope("activate:uttt").allows(&scope("upload:uttt")));
+    }
+
+    #[test]
+    fn any_competition_allows_every_competition() {
+        assert!(scope("upload:*").allows(&scope("upload:uttt")));
+        assert!(scope("activate:*").allows(&scope("activate:chess")));
+        assert!(!scope("upload:*").allows(&scope("activate:uttt")));
+        // `*` is only special in the token's scope
+        assert!(!scope("upload:uttt").allows(&scope("upload:*")));
+    }
+
+    #[test]
+    fn read_must_be_granted() {
+        assert!(scope("read").allows(&TokenScope::Read));
+        assert!(!scope("upload:*").allows(&TokenScope::Read));
+        assert!(!scope("read").allows(&scope("upload:uttt")));
+
+        let token = ApiTokenAuth {
+            token_id: 1,
+            scopes: vec![scope("upload:uttt"), scope("read")],
+        };
+        assert!(token.allows(&TokenScope::Read));
+        assert!(token.allows(&scope("upload:uttt")));
+        assert!(!token.allows(&scope("activate:uttt")));
+    }
+}
@@ -0,0 +1,24 @@
+//! Writing to and reading the append-only audit log of administrative and security-relevant
+//! actions (see [`doxa_db::model::audit::actions`] for what is recorded).
+//!
+//! Database triggers reject updating, deleting or truncating the rows of the `audit_log` table.
+//! Admins can query it with `GET /api/admin/audit` using the `actor` (username), `action`, `target`
+//! (e.g. `agent:{id}`), `since`, `until`, `before` and `limit` query parameters, or with
+//! `doxa_adm audit list`.
+
+use actix_web::web;
+use doxa_core::error::RespondableErrorWrapper;
+use doxa_db::{model::audit::InsertableAuditEntry, PgPool};
+
+/// Appends an entry to the audit log, routes should do this before responding so that an action
+/// isn't reported as successful without a record of it.
+pub async fn record(
+    pool: &web::Data<PgPool>,
+    entry: InsertableAuditEntry,
+) -> Result<(), RespondableErrorWrapper> {
+    let pool = pool.clone();
+    let conn = web::block(move || pool.get()).await??;
+    web::block(move || doxa_db::action::audit::record(&conn, &entry)).await??;
+
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! Storage for the state that `doxa_auth` keeps outside of the database, i.e. the rate limiter
+//! counters (see [`crate::limiter`]) and the delegated auth records.
+//!
+//! Redis is used in production, the [`InMemoryBackend`] is useful for single node deployments and
+//! tests.
+//!
+//! The backend is chosen by `REDIS_DB_URL` (see [`establish_backends`]). The backend tests always
+//! run against the in-memory backend and also against redis when `DOXA_TEST_REDIS_URL` is set.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use doxa_core::redis::RedisPool;
+
+pub use crate::error::BackendError;
+
+pub mod memory;
+pub mod redis;
+
+pub use self::{memory::InMemoryBackend, redis::RedisBackend};
+
+/// Fixed window counters used by the [`GenericLimiter`](crate::limiter::GenericLimiter).
+///
+/// TTLs are in whole seconds.
+#[async_trait]
+pub trait LimiterBackend: Send + Sync + 'static {
+    /// Increments the counter, starting a new window of `window` if the counter doesn't exist (or
+    /// its window has ended).
+    /// This returns the new value of the counter and the time left in the window.
+    async fn increment(&self, key: &str, window: Duration) -> Result<(u32, u64), BackendError>;
+
+    /// Undoes an increment, this does nothing if the window has since ended.
+    async fn decrement(&self, key: &str) -> Result<(), BackendError>;
+
+    /// Returns the value of the counter and the time left in the window, or `None` if there is no
+    /// window in progress.
+    async fn counter(&self, key: &str) -> Result<Option<(u32, u64)>, BackendError>;
+}
+
+/// Expiring values and sets used by the delegated auth flow and delegated sessions.
+#[async_trait]
+pub trait DelegatedAuthBackend: Send + Sync + 'static {
+    async fn set(&self, key: &str, value: String, ttl: Duration) -> Result<(), BackendError>;
+
+    async fn get(&self, key: &str) -> Result<Option<String>, BackendError>;
+
+    async fn delete(&self, key: &str) -> Result<(), BackendError>;
+
+    async fn exists(&self, key: &str) -> Result<bool, BackendError>;
+
+    /// Sets never expire, stale members must be removed with [`DelegatedAuthBackend::remove_member`].
+    async fn add_member(&self, set: &str, member: &str) -> Result<(), BackendError>;
+
+    async fn members(&self, set: &str) -> Result<Vec<String>, BackendError>;
+
+    async fn remove_member(&self, set: &str, member: &str) -> Result<(), BackendError>;
+}
+
+/// Both of the backends, usually these are the same underlying backend.
+#[derive(Clone)]
+pub struct Backends {
+    pub limiter: Arc<dyn LimiterBackend>,
+    pub delegated_auth: Arc<dyn DelegatedAuthBackend>,
+}
+
+impl Backends {
+    pub fn redis(pool: RedisPool) -> Self {
+        let backend = Arc::new(RedisBackend::new(pool));
+
+        Backends {
+            limiter: backend.clone(),
+            delegated_auth: backend,
+        }
+    }
+
+    /// Everything is lost when the process exits and it can't be shared between multiple servers.
+    pub fn in_memory() -> Self {
+        let backend = Arc::new(InMemoryBackend::new());
+
+        Backends {
+            limiter: backend.clone(),
+            delegated_auth: backend,
+        }
+    }
+}
+
+/// Creates the backends for `url`.
+///
+/// - `redis://...` (or `rediss://...`) uses a redis connection pool.
+/// - `memory://` uses an [`InMemoryBackend`].
+pub async fn establish_backends(url: &str, max_connections: usize) -> Backends {
+    if url == "memory://" {
+        Backends::in_memory()
+    } else {
+        Backends::redis(doxa_core::redis::establish_pool(url, max_connections).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, time::Duration};
+
+    use doxa_core::tokio;
+
+    use super::*;
+    use crate::{
+        delegated::DelegatedAuthManager,
+        error::DelegatedAuthError,
+        limiter::{GenericLimiter, LimiterConfig, TokenBucket},
+    };
+
+    /// The in memory backend is always tested, the redis backend is also tested if
+    /// `DOXA_TEST_REDIS_URL` is set.
+    async fn backends() -> Vec<Backends> {
+        let mut backends = vec![Backends::in_memory()];
+
+        if let Ok(url) = std::env::var("DOXA_TEST_REDIS_URL") {
+            backends.push(Backends::redis(
+                doxa_core::redis::establish_pool(url, 4).await,
+            ));
+        }
+
+        backends
+    }
+
+    /// Keys are random so that the tests don't interfere with earlier runs against redis.
+    fn unique_key() -> String {
+        use rand::Rng;
+
+        format!("TEST-{}", rand::thread_rng().gen::<u64>())
+    }
+
+    #[tokio::test]
+    async fn permits_run_out() {
+        for backends in backends().await {
+            let limiter = GenericLimiter::new(backends.limiter);
+            let buckets = [TokenBucket::new(Duration::from_secs(60), 2)];
+            let key = unique_key();
+
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+
+            let reached = limiter
+                .get_permit(&key, &buckets)
+                .await
+                .unwrap()
+                .unwrap_err();
+            assert!(reached.ttl > 0 && reached.ttl <= 60);
+
+            let status = limiter.peek(&key, &buckets).await.unwrap();
+            assert_eq!(status.buckets[0].remaining, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn permits_are_replenished_after_the_window() {
+        for backends in backends().await {
+            let limiter = GenericLimiter::new(backends.limiter);
+            let buckets = [TokenBucket::new(Duration::from_secs(1), 1)];
+            let key = unique_key();
+
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_err());
+
+            tokio::time::sleep(Duration::from_millis(2100)).await;
+
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_are_undone_in_earlier_buckets() {
+        for backends in backends().await {
+            let limiter = GenericLimiter::new(backends.limiter);
+            let buckets = [
+                TokenBucket::new(Duration::from_secs(60), 5),
+                TokenBucket::new(Duration::from_secs(120), 2),
+            ];
+            let key = unique_key();
+
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+
+            for _ in 0..3 {
+                let reached = limiter
+                    .get_permit(&key, &buckets)
+                    .await
+                    .unwrap()
+                    .unwrap_err();
+                // The wait is for the second bucket
+                assert!(reached.ttl > 60 && reached.ttl <= 120);
+            }
+
+            let status = limiter.peek(&key, &buckets).await.unwrap();
+            assert_eq!(status.buckets[0].remaining, 3);
+            assert_eq!(status.buckets[1].remaining, 0);
+            assert_eq!(status.most_restrictive().unwrap().limit, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn peeking_does_not_take_a_permit() {
+        for backends in backends().await {
+            let limiter = GenericLimiter::new(backends.limiter);
+            let buckets = [TokenBucket::new(Duration::from_secs(60), 1)];
+            let key = unique_key();
+
+            let status = limiter.peek(&key, &buckets).await.unwrap();
+            assert_eq!(status.buckets[0].remaining, 1);
+            assert_eq!(status.buckets[0].reset, 0);
+
+            assert!(limiter.get_permit(&key, &buckets).await.unwrap().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn permits_are_given_back_when_another_key_is_limited() {
+        for backends in backends().await {
+            let generic = Arc::new(GenericLimiter::new(backends.limiter));
+            let mut config = LimiterConfig::new(unique_key());
+            config.add_limit(TokenBucket::new(Duration::from_secs(60), 1));
+            let limiter = config.build(&generic);
+
+            assert!(limiter.get_permit("second").await.unwrap().is_ok());
+            assert!(limiter
+                .get_permits(&["first", "second"])
+                .await
+                .unwrap()
+                .is_err());
+
+            let status = limiter.peek("first").await.unwrap();
+            assert_eq!(status.buckets[0].remaining, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn delegated_auth_flow() {
+        for backends in backends().await {
+            let manager = DelegatedAuthManager::new(backends.delegated_auth);
+            let creation = manager
+                .create("http://localhost/verify".parse().unwrap())
+                .await
+                .unwrap();
+
+            assert!(matches!(
+                manager
+                    .check_authenticated(&creation.verification_code, "wrong")
+                    .await,
+                Err(DelegatedAuthError::InvalidSecret(_))
+            ));
+            assert_eq!(
+                manager
+                    .check_authenticated(&creation.verification_code, &creation.auth_secret)
+                    .await
+                    .unwrap(),
+                None
+            );
+
+            manager
+                .authenticate(&creation.verification_code, 4)
+                .await
+                .unwrap();
+            assert_eq!(
+                manager
+                    .check_authenticated(&creation.verification_code, &creation.auth_secret)
+                    .await
+                    .unwrap(),
+                Some(4)
+            );
+
+            // The record is removed once the user is logged in
+            assert!(matches!(
+                manager
+                    .check_authenticated(&creation.verification_code, &creation.auth_secret)
+                    .await,
+                Err(DelegatedAuthError::Expired(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn delegated_sessions_can_be_revoked() {
+        for backends in backends().await {
+            let manager = DelegatedAuthManager::new(backends.delegated_auth);
+            let user_id = rand::Rng::gen_range(&mut rand::thread_rng(), 1..1_000_000);
+
+            let first = manager
+                .record_session(user_id, &unique_key())
+                .await
+                .unwrap();
+            let second_token = unique_key();
+            let second = manager
+                .record_session(user_id, &second_token)
+                .await
+                .unwrap();
+
+            // Sessions created in the same instant can be listed in either order
+            let sessions = manager.list_sessions(user_id).await.unwrap();
+            assert_eq!(
+                sessions.iter().map(|s| &s.id).collect::<HashSet<_>>(),
+                HashSet::from([&first.id, &second.id])
+            );
+
+            // Other users can't revoke the session
+            assert!(manager
+                .revoke_session(user_id + 1, &second.id)
+                .await
+                .is_err());
+
+            manager.revoke_session(user_id, &second.id).await.unwrap();
+            assert!(manager.is_token_revoked(&second_token).await.unwrap());
+            assert_eq!(manager.list_sessions(user_id).await.unwrap().len(), 1);
+
+            assert_eq!(manager.revoke_all_sessions(user_id).await.unwrap(), 1);
+            assert!(manager.list_sessions(user_id).await.unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use super::{BackendError, DelegatedAuthBackend, LimiterBackend};
+
+/// Expired entries are only removed when they're accessed, so every this many writes the whole
+/// map is checked for expired entries.
+const PURGE_INTERVAL: usize = 1024;
+
+struct Expiring<T> {
+    value: T,
+    expires_at: Instant,
+}
+
+impl<T> Expiring<T> {
+    fn new(value: T, ttl: Duration) -> Self {
+        Expiring {
+            value,
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at <= now
+    }
+
+    /// The whole number of seconds left, rounded up so that an entry that still exists never has
+    /// a TTL of 0.
+    fn ttl(&self, now: Instant) -> u64 {
+        let left = self.expires_at.saturating_duration_since(now);
+
+        left.as_secs() + u64::from(left.subsec_nanos() > 0)
+    }
+}
+
+/// A map where every entry expires.
+struct ExpiringMap<T> {
+    entries: HashMap<String, Expiring<T>>,
+    writes: usize,
+}
+
+impl<T> Default for ExpiringMap<T> {
+    fn default() -> Self {
+        ExpiringMap {
+            entries: HashMap::new(),
+            writes: 0,
+        }
+    }
+}
+
+impl<T> ExpiringMap<T> {
+    /// Returns the entry for the key, removing it if it has expired.
+    fn get_mut(&mut self, key: &str, now: Instant) -> Option<&mut Expiring<T>> {
+        if self
+            .entries
+            .get(key)
+            .map_or(false, |entry| entry.is_expired(now))
+        {
+            self.entries.remove(key);
+        }
+
+        self.entries.get_mut(key)
+    }
+
+    fn insert(&mut self, key: &str, entry: Expiring<T>, now: Instant) {
+        self.writes += 1;
+        if self.writes % PURGE_INTERVAL == 0 {
+            self.entries.retain(|_, entry| !entry.is_expired(now));
+        }
+
+        self.entries.insert(key.to_string(), entry);
+    }
+}
+
+/// Keeps everything in the memory of the process with the same semantics as the
+/// [`RedisBackend`](super::RedisBackend), so it can only be used when there is a single server.
+#[derive(Default)]
+pub struct InMemoryBackend {
+    counters: Mutex<ExpiringMap<u32>>,
+    values: Mutex<ExpiringMap<String>>,
+    sets: Mutex<HashMap<String, BTreeSet<String>>>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl LimiterBackend for InMemoryBackend {
+    async fn increment(&self, key: &str, window: Duration) -> Result<(u32, u64), BackendError> {
+        let now = Instant::now();
+        let mut counters = self.counters.lock().unwrap();
+
+        if let Some(counter) = counters.get_mut(key, now) {
+            counter.value += 1;
+
+            return Ok((counter.value, counter.ttl(now)));
+        }
+
+        let counter = Expiring::new(1, window);
+        let ttl = counter.ttl(now);
+        counters.insert(key, counter, now);
+
+        Ok((1, ttl))
+    }
+
+    async fn decrement(&self, key: &str) -> Result<(), BackendError> {
+        let now = Instant::now();
+
+        if let Some(counter) = self.counters.lock().unwrap().get_mut(key, now) {
+            counter.value = counter.value.saturating_sub(1);
+        }
+
+        Ok(())
+    }
+
+    async fn counter(&self, key: &str) -> Result<Option<(u32, u64)>, BackendError> {
+        let now = Instant::now();
+
+        Ok(self
+            .counters
+            .lock()
+            .unwrap()
+            .get_mut(key, now)
+            .map(|counter| (counter.value, counter.ttl(now))))
+    }
+}
+
+#[async_trait]
+impl DelegatedAuthBackend for InMemoryBackend {
+    async fn set(&self, key: &str, value: String, ttl: Duration) -> Result<(), BackendError> {
+        let now = Instant::now();
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key, Expiring::new(value, ttl), now);
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, BackendError> {
+        let now = Instant::now();
+
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .get_mut(key, now)
+            .map(|entry| entry.value.clone()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BackendError> {
+        self.values.lock().unwrap().entries.remove(key);
+
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, BackendError> {
+        let now = Instant::now();
+
+        Ok(self.values.lock().unwrap().get_mut(key, now).is_some())
+    }
+
+    async fn add_member(&self, set: &str, member: &str) -> Result<(), BackendError> {
+        self.sets
+            .lock()
+            .unwrap()
+            .entry(set.to_string())
+            .or_default()
+            .insert(member.to_string());
+
+        Ok(())
+    }
+
+    async fn members(&self, set: &str) -> Result<Vec<String>, BackendError> {
+        Ok(self
+            .sets
+            .lock()
+            .unwrap()
+            .get(set)
+            .map(|members| members.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn remove_member(&self, set: &str, member: &str) -> Result<(), BackendError> {
+        let mut sets = self.sets.lock().unwrap();
+
+        if let Some(members) = sets.get_mut(set) {
+            members.remove(member);
+
+            // Like redis, empty sets are removed
+            if members.is_empty() {
+                sets.remove(set);
+            }
+        }
+
+        Ok(())
+    }
+}
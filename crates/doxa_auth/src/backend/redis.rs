@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use doxa_core::redis::{
+    redis::{self, AsyncCommands},
+    RedisPool,
+};
+
+use super::{BackendError, DelegatedAuthBackend, LimiterBackend};
+
+const REDIS_RATE_LIMIT_INCR_LUA: &str = r#"
+    local current = redis.call('incr', KEYS[1])
+    if current == 1 then
+        redis.call('expire', KEYS[1], tonumber(ARGV[1]))
+    end
+
+    local ttl = redis.call('ttl', KEYS[1])
+
+    return { current, ttl }
+"#;
+
+const REDIS_RATE_LIMIT_DECR_LUA: &str = r#"
+    if redis.call('exists', KEYS[1]) == 1 then
+        redis.call('decr', KEYS[1])
+        -- TODO: consider also removing the key if value = 0
+    end
+"#;
+
+/// Stores everything in redis so that it can be shared between multiple servers.
+pub struct RedisBackend {
+    redis_pool: RedisPool,
+    inc_expire_script: redis::Script,
+    undo_inc_script: redis::Script,
+}
+
+impl RedisBackend {
+    pub fn new(redis_pool: RedisPool) -> Self {
+        let inc_expire_script = redis::Script::new(REDIS_RATE_LIMIT_INCR_LUA);
+        let undo_inc_script = redis::Script::new(REDIS_RATE_LIMIT_DECR_LUA);
+
+        RedisBackend {
+            redis_pool,
+            inc_expire_script,
+            undo_inc_script,
+        }
+    }
+}
+
+#[async_trait]
+impl LimiterBackend for RedisBackend {
+    async fn increment(&self, key: &str, window: Duration) -> Result<(u32, u64), BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        // NOTE: in redis 7 (yet to be released at the time of writing), EXPIRE supports additional
+        // options which greatly simplify this without the need of a script.
+        //
+        // Also expiretime is an option to use instead of TTL.
+        //
+        // The script returns the expiretime atomically here because it's possible for the key to
+        // have expired between the time we detect there are no permits left and then request the
+        // expiration time.
+        Ok(self
+            .inc_expire_script
+            .prepare_invoke()
+            .key(key)
+            .arg(window.as_secs())
+            .invoke_async(&mut redis)
+            .await?)
+    }
+
+    async fn decrement(&self, key: &str) -> Result<(), BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(self
+            .undo_inc_script
+            .prepare_invoke()
+            .key(key)
+            .invoke_async(&mut redis)
+            .await?)
+    }
+
+    async fn counter(&self, key: &str) -> Result<Option<(u32, u64)>, BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        let ttl: i64 = redis.ttl(key).await?;
+        // Technically it's possible for the key to expire between these two calls so we check the
+        // option
+        let current: Option<u32> = redis.get(key).await?;
+
+        Ok(current.map(|current| (current, ttl.max(0) as u64)))
+    }
+}
+
+#[async_trait]
+impl DelegatedAuthBackend for RedisBackend {
+    async fn set(&self, key: &str, value: String, ttl: Duration) -> Result<(), BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis
+            .set_ex(key, value, ttl.as_secs().max(1) as usize)
+            .await?)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis.get(key).await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis.del(key).await?)
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis.exists(key).await?)
+    }
+
+    async fn add_member(&self, set: &str, member: &str) -> Result<(), BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis.sadd(set, member).await?)
+    }
+
+    async fn members(&self, set: &str) -> Result<Vec<String>, BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis.smembers(set).await?)
+    }
+
+    async fn remove_member(&self, set: &str, member: &str) -> Result<(), BackendError> {
+        let mut redis = self.redis_pool.get().await?;
+
+        Ok(redis.srem(set, member).await?)
+    }
+}
@@ -6,7 +6,7 @@ use doxa_core::{autha_client::flow::FlowResponse, EndpointResult};
 use doxa_db::{model::competition::Enrollment, PgPool};
 
 use crate::{
-    error::{CheckEnrollmentError, CompetitionNotFound, UpsertUserError},
+    error::{CheckEnrollmentError, CompetitionNotFound, UpsertUserError, UserNotEnrolled},
     route::response,
 };
 
@@ -24,7 +24,7 @@ pub const TOKEN_GENERATION_BYTES: usize = 5;
 /// one in the database.
 /// This means that if the token generation is updated to a new value in the database it can be
 /// used to invalidate all active auth tokens.
-fn new_token_generation() -> String {
+pub(crate) fn new_token_generation() -> String {
     use rand::Rng;
 
     let generation: Vec<u8> = rand::thread_rng()
@@ -84,26 +84,15 @@ pub async fn handle_flow_response(
 
 /// If the user is enrolled then this returns `Ok(enrollment)` containing the enrollment
 /// In any other case (including both that the user is not enrolled or there has been
-/// some internal error with the database) an error is returned
-///
-/// TEMPORARILY THIS CURRENTLY ALWAYS RETURNS AN ENROLLMENT EVEN IF THE USER IS NOT ACTUALLY
-/// ENROLLED
+/// some internal error with the database) an error is returned.
+/// Users enroll themselves with an invite code or through open enrollment, see
+/// [`crate::enrollment`].
 pub fn is_enrolled(
     conn: &PgConnection,
     user_id: i32,
     competition: String,
 ) -> Result<Enrollment, CheckEnrollmentError> {
-    // TODO: properly fix.
-    // Currently this DOES NOT PERFORM AN ENROLLMENT CHECK.
-    // This is to help with some convenience issues until the enrollment process is improved.
-    let competition = action::competition::get_competition_by_name(conn, &competition)?
-        .ok_or(CompetitionNotFound)?;
-
-    Ok(Enrollment {
-        user_id,
-        competition: competition.id,
-    })
-
-    //action::competition::get_competition_by_name(conn, &competition)?.ok_or(CompetitionNotFound)?;
-    //Ok(action::competition::get_enrollment(conn, user_id, competition)?.ok_or(UserNotEnrolled)?)
+    action::competition::get_competition_by_name(conn, &competition)?.ok_or(CompetitionNotFound)?;
+
+    Ok(action::competition::get_enrollment(conn, user_id, competition)?.ok_or(UserNotEnrolled)?)
 }
@@ -1,23 +1,33 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use doxa_core::{
-    chrono::{self, DateTime, Utc},
-    redis::{redis::AsyncCommands, RedisPool},
-};
+use doxa_core::chrono::{self, DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::error::{DelegatedAuthError, DelegatedAuthExpired, InvalidDelegatedAuthSecret};
+use crate::{
+    api_token::hash_token,
+    backend::DelegatedAuthBackend,
+    controller::JWT_LIFE,
+    error::{
+        BackendError, DelegatedAuthError, DelegatedAuthExpired, DelegatedSessionError,
+        DelegatedSessionNotFound, InvalidDelegatedAuthSecret,
+    },
+    session::{token_expires_at, Revocations},
+};
 
 const AUTH_SECRET_LEN: usize = 20;
 const VERIFICATION_CODE_LEN: usize = 24;
 const AUTH_EXPIRATION: Duration = Duration::from_secs(60 * 60 * 3);
 const POST_AUTH_EXPIRATION: Duration = Duration::from_secs(60 * 30);
+const SESSION_ID_LEN: usize = 12;
+/// How long a user's revocations are cached for, revocations made through the API clear the cache
+/// but others (e.g. from `doxa_adm` or a password reset) can take this long to reach access tokens.
+pub const REVOCATIONS_CACHE_LIFE: Duration = Duration::from_secs(30);
 
 #[derive(Serialize)]
 pub struct DelegatedAuthCreation {
-    verification_code: String,
+    pub(crate) verification_code: String,
     verify_url: String,
-    auth_secret: String,
+    pub(crate) auth_secret: String,
     expires: DateTime<Utc>,
 }
 
@@ -28,6 +38,18 @@ struct DelegatedAuthRecord {
     authenticated_user_id: Option<i32>,
 }
 
+/// A session created by completing delegated auth, these last until the refresh token that was
+/// issued expires.
+#[derive(Serialize, Deserialize)]
+pub struct DelegatedSession {
+    pub id: String,
+    pub user_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The hash of the refresh token issued for the session, see [`hash_token`]
+    token_hash: String,
+}
+
 fn generate_secret(len: usize) -> String {
     use rand::Rng;
 
@@ -40,16 +62,37 @@ fn generate_secret(len: usize) -> String {
 }
 
 pub struct DelegatedAuthManager {
-    redis: RedisPool,
+    backend: Arc<dyn DelegatedAuthBackend>,
 }
 
 fn delegated_auth_key(verification_code: &str) -> String {
     format!("DELEGATED_AUTH-{}", verification_code)
 }
 
+fn delegated_session_key(id: &str) -> String {
+    format!("DELEGATED_SESSION-{}", id)
+}
+
+fn user_delegated_sessions_key(user_id: i32) -> String {
+    format!("DELEGATED_SESSIONS-{}", user_id)
+}
+
+fn revoked_token_key(token_hash: &str) -> String {
+    format!("REVOKED_TOKEN-{}", token_hash)
+}
+
+fn revocations_key(user_id: i32) -> String {
+    format!("REVOCATIONS-{}", user_id)
+}
+
+/// The time until `time` (at least 1 second so it can be used as a TTL).
+fn time_until(time: DateTime<Utc>) -> Duration {
+    Duration::from_secs((time - Utc::now()).num_seconds().max(1) as u64)
+}
+
 impl DelegatedAuthManager {
-    pub fn new(redis: RedisPool) -> Self {
-        DelegatedAuthManager { redis }
+    pub fn new(backend: Arc<dyn DelegatedAuthBackend>) -> Self {
+        DelegatedAuthManager { backend }
     }
 
     pub async fn create(
@@ -61,19 +104,18 @@ impl DelegatedAuthManager {
 
         let key = delegated_auth_key(&verification_code);
 
-        let mut conn = self.redis.get().await?;
-        conn.set(
-            &key,
-            serde_json::to_string(&DelegatedAuthRecord {
-                auth_secret: auth_secret.clone(),
-                authenticated_user_id: None,
-            })
-            .unwrap(),
-        )
-        .await?;
-        let expiration = Utc::now() + chrono::Duration::from_std(AUTH_EXPIRATION).unwrap();
-        conn.expire(&key, AUTH_EXPIRATION.as_secs() as usize)
+        self.backend
+            .set(
+                &key,
+                serde_json::to_string(&DelegatedAuthRecord {
+                    auth_secret: auth_secret.clone(),
+                    authenticated_user_id: None,
+                })
+                .unwrap(),
+                AUTH_EXPIRATION,
+            )
             .await?;
+        let expiration = Utc::now() + chrono::Duration::from_std(AUTH_EXPIRATION).unwrap();
 
         verify_base_url
             .query_pairs_mut()
@@ -97,9 +139,7 @@ impl DelegatedAuthManager {
     ) -> Result<Option<i32>, DelegatedAuthError> {
         let key = delegated_auth_key(verification_code);
 
-        let mut conn = self.redis.get().await?;
-
-        let record: Option<String> = conn.get(&key).await?;
+        let record = self.backend.get(&key).await?;
         let record: DelegatedAuthRecord =
             serde_json::from_str(&record.ok_or(DelegatedAuthExpired)?).unwrap();
 
@@ -108,7 +148,7 @@ impl DelegatedAuthManager {
         }
 
         if let Some(user_id) = record.authenticated_user_id {
-            conn.del(&key).await?;
+            self.backend.delete(&key).await?;
 
             Ok(Some(user_id))
         } else {
@@ -124,18 +164,161 @@ impl DelegatedAuthManager {
     ) -> Result<(), DelegatedAuthError> {
         let key = delegated_auth_key(verification_code);
 
-        let mut conn = self.redis.get().await?;
-
-        let record: Option<String> = conn.get(&key).await?;
+        let record = self.backend.get(&key).await?;
         let mut record: DelegatedAuthRecord =
             serde_json::from_str(&record.ok_or(DelegatedAuthExpired)?).unwrap();
 
         record.authenticated_user_id = Some(user_id);
-        conn.set(&key, serde_json::to_string(&record).unwrap())
-            .await?;
-        conn.expire(&key, POST_AUTH_EXPIRATION.as_secs() as usize)
+        self.backend
+            .set(
+                &key,
+                serde_json::to_string(&record).unwrap(),
+                POST_AUTH_EXPIRATION,
+            )
             .await?;
 
         Ok(())
     }
+
+    /// Records the session created by issuing `refresh_token` once delegated auth completes so
+    /// that it can be listed and revoked.
+    pub async fn record_session(
+        &self,
+        user_id: i32,
+        refresh_token: &str,
+    ) -> Result<DelegatedSession, DelegatedSessionError> {
+        let created_at = Utc::now();
+        let session = DelegatedSession {
+            id: generate_secret(SESSION_ID_LEN),
+            user_id,
+            created_at,
+            expires_at: token_expires_at(refresh_token)
+                .unwrap_or_else(|| created_at + chrono::Duration::from_std(JWT_LIFE).unwrap()),
+            token_hash: hash_token(refresh_token),
+        };
+
+        self.backend
+            .set(
+                &delegated_session_key(&session.id),
+                serde_json::to_string(&session).unwrap(),
+                time_until(session.expires_at),
+            )
+            .await?;
+        self.backend
+            .add_member(&user_delegated_sessions_key(user_id), &session.id)
+            .await?;
+
+        Ok(session)
+    }
+
+    /// Lists the user's delegated sessions that haven't expired or been revoked, oldest first.
+    pub async fn list_sessions(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<DelegatedSession>, DelegatedSessionError> {
+        let user_key = user_delegated_sessions_key(user_id);
+
+        let ids = self.backend.members(&user_key).await?;
+        let mut sessions: Vec<DelegatedSession> = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.backend.get(&delegated_session_key(&id)).await? {
+                Some(record) => sessions.push(serde_json::from_str(&record).unwrap()),
+                // The session has expired
+                None => self.backend.remove_member(&user_key, &id).await?,
+            }
+        }
+
+        sessions.sort_by_key(|session| session.created_at);
+
+        Ok(sessions)
+    }
+
+    /// Revokes one of the user's delegated sessions, the refresh token that was issued for it
+    /// is rejected until it would have expired.
+    pub async fn revoke_session(
+        &self,
+        user_id: i32,
+        id: &str,
+    ) -> Result<DelegatedSession, DelegatedSessionError> {
+        let key = delegated_session_key(id);
+
+        let record = self.backend.get(&key).await?;
+        let session: DelegatedSession =
+            serde_json::from_str(&record.ok_or(DelegatedSessionNotFound)?).unwrap();
+
+        if session.user_id != user_id {
+            return Err(DelegatedSessionNotFound.into());
+        }
+
+        self.backend
+            .set(
+                &revoked_token_key(&session.token_hash),
+                "1".to_string(),
+                time_until(session.expires_at),
+            )
+            .await?;
+        self.backend.delete(&key).await?;
+        self.backend
+            .remove_member(&user_delegated_sessions_key(user_id), id)
+            .await?;
+
+        Ok(session)
+    }
+
+    /// Revokes all of the user's delegated sessions, returning how many were revoked.
+    pub async fn revoke_all_sessions(&self, user_id: i32) -> Result<usize, DelegatedSessionError> {
+        let sessions = self.list_sessions(user_id).await?;
+
+        for session in &sessions {
+            match self.revoke_session(user_id, &session.id).await {
+                // The session expired after it was listed
+                Ok(_) | Err(DelegatedSessionError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(sessions.len())
+    }
+
+    /// Whether the token was issued for a delegated session that has since been revoked.
+    pub async fn is_token_revoked(&self, token: &str) -> Result<bool, DelegatedSessionError> {
+        Ok(self
+            .backend
+            .exists(&revoked_token_key(&hash_token(token)))
+            .await?)
+    }
+
+    /// The user's revocations if they have been cached by
+    /// [`DelegatedAuthManager::cache_revocations`].
+    pub async fn cached_revocations(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<Revocations>, BackendError> {
+        Ok(self
+            .backend
+            .get(&revocations_key(user_id))
+            .await?
+            .map(|record| serde_json::from_str(&record).unwrap()))
+    }
+
+    /// Caches the user's revocations for [`REVOCATIONS_CACHE_LIFE`] so that access tokens can be
+    /// checked without a database query.
+    pub async fn cache_revocations(
+        &self,
+        user_id: i32,
+        revocations: &Revocations,
+    ) -> Result<(), BackendError> {
+        self.backend
+            .set(
+                &revocations_key(user_id),
+                serde_json::to_string(revocations).unwrap(),
+                REVOCATIONS_CACHE_LIFE,
+            )
+            .await
+    }
+
+    /// Should be called after revoking any of the user's tokens so that it applies straight away.
+    pub async fn clear_cached_revocations(&self, user_id: i32) -> Result<(), BackendError> {
+        self.backend.delete(&revocations_key(user_id)).await
+    }
 }
@@ -0,0 +1,215 @@
+//! Users enrolling themselves in competitions, either with an invite code created by an organiser
+//! or without one when the competition has open enrollment.
+//!
+//! Users enroll with `POST /api/competition/{name}/_enroll` (`{"code": ...}`) or
+//! `doxa user enroll <competition> [code]`, the code is only needed when the competition doesn't
+//! have open enrollment. Organisers create invites with a usage limit, an optional expiry (of at
+//! most 365 days) and an optional email domain using `POST /api/competition/{name}/_invites`, list
+//! them with `GET` on the same route and revoke them with
+//! `POST /api/competition/{name}/_invites/{id}/revoke`. Invites restricted to an email domain can
+//! only be used by local accounts with a verified email address at that domain. Enrollment is
+//! opened or closed with `POST /api/competition/{name}/_enrollment` (`{"open_enrollment": true}`).
+//! The same can be done with the `create-invite`, `list-invites`, `revoke-invite` and
+//! `set-open-enrollment` commands of `doxa_adm competition`.
+//!
+//! Users that uploaded agents before enrollment was checked are enrolled by the
+//! `competition_invites` migration.
+
+use diesel::{Connection, PgConnection};
+use doxa_core::chrono::{DateTime, Duration, Utc};
+use doxa_db::{
+    action,
+    model::{
+        audit::{actions, invite_target, user_target, InsertableAuditEntry},
+        competition::{Competition, CompetitionInvite, Enrollment, InsertableCompetitionInvite},
+        user::User,
+    },
+    DieselError,
+};
+use serde_json::json;
+
+use crate::error::{
+    AlreadyEnrolled, CompetitionNotFound, CreateInviteError, EmailDomainNotAllowed,
+    EnrollmentClosed, InvalidInviteCode, InvalidInviteExpiry, InvalidInviteMaxUses,
+    SelfEnrollError,
+};
+
+/// Ambiguous characters (e.g. `0` and `O`) are left out so codes can be read out or copied by hand.
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LENGTH: usize = 10;
+
+pub const MAX_INVITE_EXPIRY_DAYS: u32 = 365;
+
+/// Generates a code such as `K7PQ2-WX9RD`.
+fn generate_invite_code() -> String {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let code: String = (0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_CODE_ALPHABET[rng.gen_range(0..INVITE_CODE_ALPHABET.len())] as char)
+        .collect();
+
+    format!(
+        "{}-{}",
+        &code[..INVITE_CODE_LENGTH / 2],
+        &code[INVITE_CODE_LENGTH / 2..]
+    )
+}
+
+/// Codes are accepted regardless of case and surrounding whitespace.
+fn normalise_invite_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+/// Creates an invite that can be used `max_uses` times, `created_by` is `None` for invites created
+/// through `doxa_adm`.
+pub fn create_invite(
+    conn: &PgConnection,
+    competition: &Competition,
+    created_by: Option<i32>,
+    max_uses: i32,
+    expires_in_days: Option<u32>,
+    email_domain: Option<String>,
+) -> Result<CompetitionInvite, CreateInviteError> {
+    if max_uses < 1 {
+        return Err(InvalidInviteMaxUses.into());
+    }
+
+    if matches!(expires_in_days, Some(days) if days == 0 || days > MAX_INVITE_EXPIRY_DAYS) {
+        return Err(InvalidInviteExpiry.into());
+    }
+
+    let now = Utc::now();
+    let email_domain = email_domain
+        .map(|domain| domain.trim().trim_start_matches('@').to_ascii_lowercase())
+        .filter(|domain| !domain.is_empty());
+
+    conn.transaction(|| {
+        let invite = action::competition::create_invite(
+            conn,
+            &InsertableCompetitionInvite {
+                competition: competition.id,
+                code: generate_invite_code(),
+                created_by,
+                created_at: now,
+                expires_at: expires_in_days.map(|days| now + Duration::days(days as i64)),
+                max_uses,
+                email_domain,
+            },
+        )?;
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(created_by, actions::CREATE_INVITE)
+                .with_target(invite_target(invite.id))
+                .with_metadata(json!({
+                    "competition": competition.name,
+                    "max_uses": invite.max_uses,
+                    "expires_at": invite.expires_at,
+                    "email_domain": invite.email_domain,
+                })),
+        )?;
+
+        Ok(invite)
+    })
+}
+
+/// Whether the invite can still be used, this doesn't check the number of uses.
+pub fn invite_is_active(invite: &CompetitionInvite, now: DateTime<Utc>) -> bool {
+    invite.revoked_at.is_none()
+        && invite
+            .expires_at
+            .map(|expires| now < expires)
+            .unwrap_or(true)
+}
+
+/// The user's verified email address, which is the address local accounts verified when
+/// registering. The `email` in the extra info of autha users isn't verified by doxa so it isn't
+/// used, which means autha users can't use invites restricted to an email domain.
+pub fn verified_user_email(
+    conn: &PgConnection,
+    user: &User,
+) -> Result<Option<String>, DieselError> {
+    Ok(
+        action::local_account::get_account_by_username(conn, &user.username)?
+            .and_then(|(account, _)| account.email_verified_at.map(|_| account.email)),
+    )
+}
+
+fn email_has_domain(email: &str, domain: &str) -> bool {
+    email
+        .rsplit_once('@')
+        .map(|(_, email_domain)| email_domain.eq_ignore_ascii_case(domain))
+        .unwrap_or(false)
+}
+
+/// Enrolls the user in the competition using the invite `code`, or without one if the competition
+/// has open enrollment.
+/// Using an invite counts towards its `max_uses` (unless the user was already enrolled).
+pub fn self_enroll(
+    conn: &PgConnection,
+    user_id: i32,
+    competition_name: &str,
+    code: Option<&str>,
+) -> Result<Enrollment, SelfEnrollError> {
+    conn.transaction(|| {
+        let now = Utc::now();
+        let competition = action::competition::get_competition_by_name(conn, competition_name)?
+            .ok_or(CompetitionNotFound)?;
+
+        if action::competition::get_enrollment(conn, user_id, competition.name.clone())?.is_some() {
+            return Err(AlreadyEnrolled.into());
+        }
+
+        let invite = match code {
+            Some(code) => {
+                let invite = action::competition::get_invite_by_code(
+                    conn,
+                    competition.id,
+                    &normalise_invite_code(code),
+                )?
+                .filter(|invite| invite_is_active(invite, now))
+                .ok_or(InvalidInviteCode)?;
+
+                if let Some(domain) = &invite.email_domain {
+                    let user = action::user::get_user_by_id(conn, user_id)?;
+                    let email = verified_user_email(conn, &user)?;
+                    if !email
+                        .map(|email| email_has_domain(&email, domain))
+                        .unwrap_or(false)
+                    {
+                        return Err(EmailDomainNotAllowed.into());
+                    }
+                }
+
+                let invite =
+                    action::competition::use_invite(conn, invite.id)?.ok_or(InvalidInviteCode)?;
+
+                Some(invite)
+            }
+            None if competition.open_enrollment => None,
+            None => return Err(EnrollmentClosed.into()),
+        };
+
+        let enrollment = action::competition::enroll_user_if_not_enrolled(
+            conn,
+            &Enrollment {
+                user_id,
+                competition: competition.id,
+            },
+        )?
+        .ok_or(AlreadyEnrolled)?;
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(Some(user_id), actions::SELF_ENROLL)
+                .with_target(user_target(user_id))
+                .with_metadata(json!({
+                    "competition": competition.name,
+                    "invite": invite.map(|invite| invite.id),
+                })),
+        )?;
+
+        Ok(enrollment)
+    })
+}
@@ -103,6 +103,113 @@ impl_respondable_error!(
     "This token does not have permission to access this resource"
 );
 
+#[derive(Debug, Display, Error)]
+pub struct InvalidApiToken;
+
+impl_respondable_error!(
+    InvalidApiToken,
+    UNAUTHORIZED,
+    "INVALID_TOKEN",
+    "This API token does not exist, has been revoked or has expired"
+);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "API token is missing the scope `{}`", scope)]
+pub struct ApiTokenScopeMissing {
+    pub scope: String,
+}
+
+impl_respondable_error!(
+    ApiTokenScopeMissing,
+    FORBIDDEN,
+    "TOKEN_SCOPE_MISSING",
+    "This API token does not have the scope required for this action"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct ApiTokensNotAllowed;
+
+impl_respondable_error!(
+    ApiTokensNotAllowed,
+    FORBIDDEN,
+    "API_TOKEN_NOT_ALLOWED",
+    "This action cannot be performed with an API token"
+);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "invalid token scope `{}`", scope)]
+pub struct InvalidTokenScope {
+    pub scope: String,
+}
+
+impl_respondable_error!(
+    InvalidTokenScope,
+    BAD_REQUEST,
+    "INVALID_TOKEN_SCOPE",
+    "Token scopes must be `read`, `upload:{competition}` or `activate:{competition}` (the competition can be `*`)"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidApiTokenName;
+
+impl_respondable_error!(
+    InvalidApiTokenName,
+    BAD_REQUEST,
+    "INVALID_TOKEN_NAME",
+    "Token names must be between 1 and 64 characters long"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidApiTokenExpiry;
+
+impl_respondable_error!(
+    InvalidApiTokenExpiry,
+    BAD_REQUEST,
+    "INVALID_TOKEN_EXPIRY",
+    "Tokens must expire in between 1 and 365 days"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct ApiTokenNotFound;
+
+impl_respondable_error!(
+    ApiTokenNotFound,
+    NOT_FOUND,
+    "TOKEN_NOT_FOUND",
+    "You don't have an active token with that ID"
+);
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum CreateApiTokenError {
+    #[from]
+    Diesel(DieselError),
+    #[from]
+    InvalidScope(InvalidTokenScope),
+    #[from]
+    InvalidName(InvalidApiTokenName),
+    #[from]
+    InvalidExpiry(InvalidApiTokenExpiry),
+}
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum RevokeApiTokenError {
+    #[from]
+    Diesel(DieselError),
+    #[from]
+    NotFound(ApiTokenNotFound),
+}
+
+/// The errors from [`crate::guard::AuthGuard::id_required`].
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum UserRequiredError {
+    #[from]
+    SystemAccount(SystemAccountsNotAllowed),
+    #[from]
+    ApiToken(ApiTokensNotAllowed),
+    #[from]
+    ScopeMissing(ApiTokenScopeMissing),
+}
+
 #[derive(Debug, Display, Error, RespondableError, From)]
 pub enum LoginError {
     #[from]
@@ -159,16 +266,22 @@ pub struct RateLimitReached {
     pub ttl: u64,
 }
 
+/// An error from one of the backends that store state outside of the database, see
+/// [`crate::backend`].
 #[derive(Debug, Display, Error, RespondableError, From)]
-pub enum GetLimiterPermitError {
+pub enum BackendError {
     Redis(RedisError),
     RedisPool(RedisPoolError),
 }
 
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum GetLimiterPermitError {
+    Backend(BackendError),
+}
+
 #[derive(Debug, Display, Error, RespondableError, From)]
 pub enum DelegatedAuthError {
-    Redis(RedisError),
-    RedisPool(RedisPoolError),
+    Backend(BackendError),
     InvalidSecret(InvalidDelegatedAuthSecret),
     Expired(DelegatedAuthExpired),
 }
@@ -189,11 +302,7 @@ impl_respondable_error!(
 );
 
 // TODO: find a way to include the ttl in the error message (some kind of formatting with automatic
-// conversion to a human readable time period), also find a way to include in the HTTP response
-// header (this would allow some more interesting UI stuff probably).
-//
-// Maybe create some optional functions of `impl_respondable_error`, doesn't need to be generic
-// i.e. get_rate_limit_ttl(), or it could be generic e.g. append_http_headers(&mut headers)
+// conversion to a human readable time period).
 #[macro_export]
 macro_rules! create_rate_limit_error {
     ($name:ident, $error_message:expr) => {
@@ -218,10 +327,317 @@ macro_rules! create_rate_limit_error {
             }
 
             fn inject_headers(&self, builder: &mut doxa_core::error::HttpResponseBuilder) {
-                builder.insert_header((actix_web::http::header::RETRY_AFTER, self.source.ttl));
+                builder
+                    .insert_header((actix_web::http::header::RETRY_AFTER, self.source.ttl))
+                    .insert_header(("RateLimit-Remaining", 0))
+                    .insert_header(("RateLimit-Reset", self.source.ttl));
             }
         }
     };
 }
 
 create_rate_limit_error!(TooManyLoginAttempts, "There have been too many login attempts to your account please wait a while and then try again");
+
+create_rate_limit_error!(
+    TooManyAccountEmails,
+    "Too many emails have been sent to that address, please wait a while and then try again"
+);
+
+create_rate_limit_error!(
+    TooManyRequests,
+    "You are making too many requests, please wait a while and then try again"
+);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "invalid competition role `{}`", role)]
+pub struct InvalidCompetitionRole {
+    pub role: String,
+}
+
+impl_respondable_error!(
+    InvalidCompetitionRole,
+    BAD_REQUEST,
+    "INVALID_COMPETITION_ROLE",
+    "Competition roles must be `organiser`, `moderator` or `viewer`"
+);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "missing the {} permission", permission)]
+pub struct CompetitionPermissionMissing {
+    pub permission: String,
+}
+
+impl_respondable_error!(
+    CompetitionPermissionMissing,
+    FORBIDDEN,
+    "MISSING_PERMISSION",
+    "You do not have permission to do this in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct SessionRevoked;
+
+impl_respondable_error!(
+    SessionRevoked,
+    UNAUTHORIZED,
+    "SESSION_REVOKED",
+    "This session has been revoked, please log in again"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct AccessTokenRevoked;
+
+impl_respondable_error!(
+    AccessTokenRevoked,
+    UNAUTHORIZED,
+    "ACCESS_TOKEN_REVOKED",
+    "This access token has been revoked, please exchange the refresh token for a new one"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct DelegatedSessionNotFound;
+
+impl_respondable_error!(
+    DelegatedSessionNotFound,
+    NOT_FOUND,
+    "DELEGATED_SESSION_NOT_FOUND",
+    "No active delegated session with that ID exists"
+);
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum DelegatedSessionError {
+    Backend(BackendError),
+    NotFound(DelegatedSessionNotFound),
+}
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidAuditLogLimit;
+
+impl_respondable_error!(
+    InvalidAuditLogLimit,
+    BAD_REQUEST,
+    "INVALID_LIMIT",
+    "The limit must be between 1 and 1000"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidToken;
+
+impl_respondable_error!(
+    InvalidToken,
+    UNAUTHORIZED,
+    "INVALID_TOKEN",
+    "The token is invalid or has expired"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct NotRefreshToken;
+
+impl_respondable_error!(
+    NotRefreshToken,
+    BAD_REQUEST,
+    "NOT_REFRESH_TOKEN",
+    "Only refresh tokens can be exchanged for access tokens"
+);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "unknown auth flow `{}/{}`", provider, flow)]
+pub struct UnknownAuthFlow {
+    pub provider: String,
+    pub flow: String,
+}
+
+impl_respondable_error!(
+    UnknownAuthFlow,
+    BAD_REQUEST,
+    "UNKNOWN_AUTH_FLOW",
+    "This auth provider does not support that flow"
+);
+
+#[derive(Debug, Display, Error, From)]
+pub struct InvalidFlowPayload {
+    source: serde_json::Error,
+}
+
+impl_respondable_error!(
+    InvalidFlowPayload,
+    BAD_REQUEST,
+    "INVALID_FLOW_PAYLOAD",
+    "The payload is missing fields required by this flow"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct IncorrectCredentials;
+
+impl_respondable_error!(
+    IncorrectCredentials,
+    BAD_REQUEST,
+    "INCORRECT_CREDENTIALS",
+    "The username or password is incorrect"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct EmailNotVerified;
+
+impl_respondable_error!(
+    EmailNotVerified,
+    FORBIDDEN,
+    "EMAIL_NOT_VERIFIED",
+    "Verify your email address using the link that was sent to it before logging in"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct PasswordTooShort;
+
+impl_respondable_error!(
+    PasswordTooShort,
+    BAD_REQUEST,
+    "PASSWORD_TOO_SHORT",
+    "Passwords must be at least 8 characters long"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidEmail;
+
+impl_respondable_error!(
+    InvalidEmail,
+    BAD_REQUEST,
+    "INVALID_EMAIL",
+    "That is not a valid email address"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidAccountCode;
+
+impl_respondable_error!(
+    InvalidAccountCode,
+    BAD_REQUEST,
+    "INVALID_CODE",
+    "The code is incorrect, has already been used or has expired"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct PasswordHashFailed;
+
+impl_respondable_error!(
+    PasswordHashFailed,
+    INTERNAL_SERVER_ERROR,
+    "INTERNAL_SERVER_ERROR"
+);
+
+#[derive(Debug, Display, Error, From)]
+pub struct SendEmailFailed {
+    source: std::io::Error,
+}
+
+impl_respondable_error!(
+    SendEmailFailed,
+    INTERNAL_SERVER_ERROR,
+    "INTERNAL_SERVER_ERROR"
+);
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum LocalAuthError {
+    Diesel(DieselError),
+    InvalidToken(InvalidToken),
+    NotRefreshToken(NotRefreshToken),
+    UserNotFoundAuth(UserNotFoundAuth),
+    InvalidFlowPayload(InvalidFlowPayload),
+    RegistrationDisabled(RegistrationDisabled),
+    UserAlreadyExists(UserAlreadyExists),
+    IncorrectCredentials(IncorrectCredentials),
+    EmailNotVerified(EmailNotVerified),
+    PasswordTooShort(PasswordTooShort),
+    InvalidEmail(InvalidEmail),
+    InvalidAccountCode(InvalidAccountCode),
+    PasswordHash(PasswordHashFailed),
+    SendEmail(SendEmailFailed),
+}
+
+#[derive(Debug, Display, Error)]
+pub struct AlreadyEnrolled;
+
+impl_respondable_error!(
+    AlreadyEnrolled,
+    BAD_REQUEST,
+    "ALREADY_ENROLLED",
+    "You are already enrolled in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidInviteCode;
+
+impl_respondable_error!(
+    InvalidInviteCode,
+    BAD_REQUEST,
+    "INVALID_INVITE_CODE",
+    "The invite code is incorrect, has expired or has no uses left"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct EmailDomainNotAllowed;
+
+impl_respondable_error!(
+    EmailDomainNotAllowed,
+    FORBIDDEN,
+    "EMAIL_DOMAIN_NOT_ALLOWED",
+    "This invite code can only be used by accounts with a verified email address at a different domain"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct EnrollmentClosed;
+
+impl_respondable_error!(
+    EnrollmentClosed,
+    FORBIDDEN,
+    "ENROLLMENT_CLOSED",
+    "An invite code is required to enroll in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidInviteMaxUses;
+
+impl_respondable_error!(
+    InvalidInviteMaxUses,
+    BAD_REQUEST,
+    "INVALID_MAX_USES",
+    "Invites must have at least one use"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidInviteExpiry;
+
+impl_respondable_error!(
+    InvalidInviteExpiry,
+    BAD_REQUEST,
+    "INVALID_INVITE_EXPIRY",
+    "Invites must expire in between 1 and 365 days"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InviteNotFound;
+
+impl_respondable_error!(
+    InviteNotFound,
+    NOT_FOUND,
+    "INVITE_NOT_FOUND",
+    "The invite does not exist or has already been revoked"
+);
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum SelfEnrollError {
+    Diesel(DieselError),
+    CompetitionNotFound(CompetitionNotFound),
+    AlreadyEnrolled(AlreadyEnrolled),
+    InvalidInviteCode(InvalidInviteCode),
+    EmailDomainNotAllowed(EmailDomainNotAllowed),
+    EnrollmentClosed(EnrollmentClosed),
+}
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum CreateInviteError {
+    Diesel(DieselError),
+    CompetitionNotFound(CompetitionNotFound),
+    InvalidMaxUses(InvalidInviteMaxUses),
+    InvalidExpiry(InvalidInviteExpiry),
+}
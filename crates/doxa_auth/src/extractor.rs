@@ -1,25 +1,31 @@
-use doxa_core::{
-    autha_client::{error::AuthaError, jwt::Scope},
-    error::RespondableErrorWrapper,
-};
+use doxa_core::{error::RespondableErrorWrapper, tracing::warn};
 
 use std::future::Future;
 use std::pin::Pin;
 
-use actix_web::{dev, web, FromRequest, HttpRequest};
+use actix_web::{dev, http::Method, web, FromRequest, HttpRequest};
+use doxa_db::PgPool;
 
 use crate::{
-    error::{InvalidAuthenticationHeader, MissingAuthentication, NotAccessToken},
-    guard::AuthGuard,
+    api_token::{self, API_TOKEN_PREFIX},
+    delegated::DelegatedAuthManager,
+    error::{
+        AccessTokenRevoked, InvalidApiToken, InvalidAuthenticationHeader, MissingAuthentication,
+        NotAccessToken, SessionRevoked,
+    },
+    guard::{AuthGuard, AuthGuardInner},
+    session::{self, AccessTokenRevocation, Revocations},
     settings::Settings,
 };
 
-impl FromRequest for AuthGuard<()> {
+impl<T: AuthGuardInner + 'static> FromRequest for AuthGuard<T> {
     type Error = RespondableErrorWrapper;
     type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
 
     fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
         let settings = req.app_data::<web::Data<Settings>>().unwrap().clone();
+        let pool = req.app_data::<web::Data<PgPool>>().unwrap().clone();
+        let read_only = matches!(*req.method(), Method::GET | Method::HEAD);
 
         let auth_header = req
             .headers()
@@ -41,21 +47,71 @@ impl FromRequest for AuthGuard<()> {
             let token = &auth_header[7..];
 
             if token == settings.system_account_secret {
-                return Ok(AuthGuard::new(None, true, ()));
+                let inner = T::construct(None, true, false, pool).await?;
+                return Ok(AuthGuard::new(None, true, inner));
+            }
+
+            if token.starts_with(API_TOKEN_PREFIX) {
+                let token = token.to_string();
+                let conn = {
+                    let pool = pool.clone();
+                    web::block(move || pool.get()).await??
+                };
+                let (user, api_token) =
+                    web::block(move || api_token::authenticate_token(&conn, &token))
+                        .await??
+                        .ok_or(InvalidApiToken)?;
+
+                let inner = T::construct(Some(user), false, true, pool).await?;
+                return Ok(AuthGuard::from_api_token(user, api_token, read_only, inner));
             }
 
-            let token = settings
-                .autha_client
-                .verify_jwt(token)
-                .map_err(AuthaError::from)?;
+            let token = settings.auth_provider.verify_token(token)?;
 
-            if !token.has_scope(&Scope::Access) {
+            if !token.access {
                 return Err(NotAccessToken.into());
             }
 
-            let admin = token.has_scope(&Scope::Admin);
+            let admin = token.admin;
+            let user = token.user;
+
+            let revocations = revocations(&settings, &pool, user).await?;
+            match session::check_access_token_revoked(&revocations, &auth_header[7..]) {
+                Some(AccessTokenRevocation::Session) => return Err(SessionRevoked.into()),
+                Some(AccessTokenRevocation::AccessTokens) => return Err(AccessTokenRevoked.into()),
+                None => {}
+            }
+
+            let inner = T::construct(Some(user), admin, false, pool).await?;
 
-            Ok(AuthGuard::new(Some(token.user()), admin, ()))
+            Ok(AuthGuard::new(Some(user), admin, inner))
         })
     }
 }
+
+/// The user's revocations, these are cached so that most requests don't need a database query.
+/// The database is used if the cache fails so that a backend outage doesn't lock everyone out.
+async fn revocations(
+    settings: &Settings,
+    pool: &web::Data<PgPool>,
+    user: i32,
+) -> Result<Revocations, RespondableErrorWrapper> {
+    let delegated_auth = DelegatedAuthManager::new(settings.delegated_auth_backend.clone());
+    match delegated_auth.cached_revocations(user).await {
+        Ok(Some(revocations)) => return Ok(revocations),
+        Ok(None) => {}
+        Err(e) => warn!(error=%e, debug=?e, "failed to get cached revocations"),
+    }
+
+    let conn = {
+        let pool = pool.clone();
+        web::block(move || pool.get()).await??
+    };
+    let revocations = web::block(move || session::get_revocations(&conn, user)).await??;
+
+    if let Err(e) = delegated_auth.cache_revocations(user, &revocations).await {
+        warn!(error=%e, debug=?e, "failed to cache revocations");
+    }
+
+    Ok(revocations)
+}
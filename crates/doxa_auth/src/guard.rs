@@ -1,13 +1,23 @@
 use std::{future::Future, pin::Pin};
 
-use actix_web::{web, HttpResponse};
-use doxa_core::RespondableError;
+use actix_web::web;
+use doxa_core::error::RespondableErrorWrapper;
 
-use crate::error::{SystemAccountsNotAllowed, UserNotAdmin};
+use crate::{
+    api_token::{ApiTokenAuth, TokenScope},
+    error::{
+        ApiTokenScopeMissing, ApiTokensNotAllowed, SystemAccountsNotAllowed, UserNotAdmin,
+        UserRequiredError,
+    },
+};
 
 pub struct AuthGuard<T: AuthGuardInner = ()> {
     user: Option<i32>,
     is_admin: bool,
+    /// Set if the request was authenticated with an API token rather than by logging in
+    api_token: Option<ApiTokenAuth>,
+    /// Whether the request only reads data (i.e. it is a `GET` or `HEAD` request)
+    read_only: bool,
     inner: T,
 }
 
@@ -16,16 +26,76 @@ impl<T: AuthGuardInner> AuthGuard<T> {
         AuthGuard {
             user,
             is_admin,
+            api_token: None,
+            read_only: false,
             inner,
         }
     }
 
+    /// A guard for a request authenticated with an API token, these are never admin.
+    pub fn from_api_token(user: i32, api_token: ApiTokenAuth, read_only: bool, inner: T) -> Self {
+        AuthGuard {
+            user: Some(user),
+            is_admin: false,
+            api_token: Some(api_token),
+            read_only,
+            inner,
+        }
+    }
+
+    /// The ID of the user.
+    ///
+    /// If the request was authenticated with an API token this is `None` unless the request is
+    /// read only and the token has the `read` scope, so that routes which don't know about API
+    /// tokens can't be used with them. Those routes should use [`AuthGuard::scoped_id`] instead.
     pub fn id(&self) -> Option<i32> {
+        if !self.api_token_allowed() {
+            return None;
+        }
+
         self.user
     }
 
-    pub fn id_required(&self) -> Result<i32, SystemAccountsNotAllowed> {
-        self.user.ok_or(SystemAccountsNotAllowed)
+    /// The same as [`AuthGuard::id`] except this returns an error instead of `None`.
+    pub fn id_required(&self) -> Result<i32, UserRequiredError> {
+        if !self.api_token_allowed() {
+            return Err(ApiTokensNotAllowed.into());
+        }
+
+        Ok(self.user.ok_or(SystemAccountsNotAllowed)?)
+    }
+
+    fn api_token_allowed(&self) -> bool {
+        match &self.api_token {
+            Some(api_token) => self.read_only && api_token.allows(&TokenScope::Read),
+            None => true,
+        }
+    }
+
+    /// The ID of the user for an action that API tokens can perform if they have the `required`
+    /// scope.
+    /// This returns an error if the request was authenticated with an API token that doesn't
+    /// have the scope.
+    pub fn scoped_id(&self, required: &TokenScope) -> Result<Option<i32>, ApiTokenScopeMissing> {
+        if let Some(api_token) = &self.api_token {
+            if !api_token.allows(required) {
+                return Err(ApiTokenScopeMissing {
+                    scope: required.to_string(),
+                });
+            }
+        }
+
+        Ok(self.user)
+    }
+
+    /// The same as [`AuthGuard::scoped_id`] except the request can't be from a system account.
+    pub fn scoped_id_required(&self, required: &TokenScope) -> Result<i32, UserRequiredError> {
+        Ok(self.scoped_id(required)?.ok_or(SystemAccountsNotAllowed)?)
+    }
+
+    /// The API token used to authenticate the request, if any.
+    pub fn api_token(&self) -> Option<&ApiTokenAuth> {
+        self.api_token.as_ref()
     }
 
     pub fn admin(&self) -> bool {
@@ -35,23 +105,33 @@ impl<T: AuthGuardInner> AuthGuard<T> {
     pub fn inner(self) -> T {
         self.inner
     }
+
+    pub fn inner_ref(&self) -> &T {
+        &self.inner
+    }
 }
 
 // TODO: think of better name
+/// Extra checks or data loaded when extracting an [`AuthGuard`], `user` is `None` for the system
+/// account.
+/// `api_token` is set if the request was authenticated with an API token, which only grant the
+/// permissions in their scopes so implementations must not give them any roles.
 pub trait AuthGuardInner: Sized {
     fn construct(
-        user: i32,
+        user: Option<i32>,
         is_admin: bool,
+        api_token: bool,
         connnection: web::Data<doxa_db::PgPool>,
-    ) -> Pin<Box<dyn Future<Output = Result<Self, HttpResponse>>>>;
+    ) -> Pin<Box<dyn Future<Output = Result<Self, RespondableErrorWrapper>>>>;
 }
 
 impl AuthGuardInner for () {
     fn construct(
-        _user: i32,
+        _user: Option<i32>,
         _is_admin: bool,
+        _api_token: bool,
         _connnection: web::Data<doxa_db::PgPool>,
-    ) -> Pin<Box<dyn Future<Output = Result<Self, HttpResponse>>>> {
+    ) -> Pin<Box<dyn Future<Output = Result<Self, RespondableErrorWrapper>>>> {
         Box::pin(async { Ok(()) })
     }
 }
@@ -61,15 +141,16 @@ pub struct Admin;
 
 impl AuthGuardInner for Admin {
     fn construct(
-        _user: i32,
+        _user: Option<i32>,
         is_admin: bool,
+        _api_token: bool,
         _connnection: web::Data<doxa_db::PgPool>,
-    ) -> Pin<Box<dyn Future<Output = Result<Self, HttpResponse>>>> {
+    ) -> Pin<Box<dyn Future<Output = Result<Self, RespondableErrorWrapper>>>> {
         Box::pin(async move {
             if is_admin {
                 Ok(Admin)
             } else {
-                Err(UserNotAdmin.as_response())
+                Err(UserNotAdmin.into())
             }
         })
     }
@@ -1,8 +1,18 @@
+pub mod api_token;
+pub mod audit;
+pub mod backend;
 pub mod controller;
+pub mod enrollment;
 pub mod error;
 pub mod extractor;
 pub mod guard;
 pub mod limiter;
+pub mod local;
+pub mod mailer;
+pub mod provider;
+pub mod rate_limit;
+pub mod role;
+pub mod session;
 pub mod settings;
 
 pub(crate) mod delegated;
@@ -17,7 +27,7 @@ pub(crate) use doxa_core::autha_client::flow::User as AuthaUser;
 
 pub fn config(settings: Settings) -> impl FnOnce(&mut actix_web::web::ServiceConfig) {
     move |cfg| {
-        let delegated_auth = DelegatedAuthManager::new(settings.redis_db.clone());
+        let delegated_auth = DelegatedAuthManager::new(settings.delegated_auth_backend.clone());
         cfg.app_data(Data::new(delegated_auth));
         cfg.app_data(Data::new(settings));
         route::config(cfg);
@@ -1,39 +1,20 @@
-//! Generic rate limiting system using redis as a backend.
+//! Generic rate limiting system, the counters are kept in a [`LimiterBackend`] (usually redis).
 
 use std::{fmt::Display, sync::Arc, time::Duration};
 
-use doxa_core::{
-    redis::{
-        redis::{self, AsyncCommands},
-        RedisPool,
-    },
-    tracing::trace,
-};
+use doxa_core::{error::HttpResponseBuilder, tracing::trace};
+
+use serde::Serialize;
 
-use crate::error::{GetLimiterPermitError, RateLimitReached};
+use crate::{
+    backend::LimiterBackend,
+    error::{GetLimiterPermitError, RateLimitReached},
+};
 
 pub const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
 pub const ONE_DAY: Duration = Duration::from_secs(60 * 60 * 24);
 
-const REDIS_RATE_LIMIT_INCR_LUA: &str = r#"
-    local current = redis.call('incr', KEYS[1])
-    if current == 1 then
-        redis.call('expire', KEYS[1], tonumber(ARGV[1]))
-    end
-
-    local ttl = redis.call('ttl', KEYS[1])
-
-    return { current, ttl }
-"#;
-
-const REDIS_RATE_LIMIT_DECR_LUA: &str = r#"
-    if redis.call('exists', KEYS[1]) == 1 then
-        redis.call('decr', KEYS[1])
-        -- TODO: consider also removing the key if value = 0
-    end
-"#;
-
-/// A neat wrapper around a redis backend that helps with rate limiting various actions.
+/// A neat wrapper around a [`LimiterBackend`] that helps with rate limiting various actions.
 ///
 /// Generally it's better to use a `ConfiguredLimiter` (which wraps around this) as you don't need
 /// to specify options for every method.
@@ -41,9 +22,7 @@ const REDIS_RATE_LIMIT_DECR_LUA: &str = r#"
 /// The rate limiters are designed to be approximately correct but block the large majority of
 /// traffic over the limit (especially in consecutive windows).
 pub struct GenericLimiter {
-    redis_pool: RedisPool,
-    inc_expire_script: redis::Script,
-    undo_inc_script: redis::Script,
+    backend: Arc<dyn LimiterBackend>,
 }
 
 fn limiter_key(base_key: &str, limiter_id: usize) -> String {
@@ -51,15 +30,8 @@ fn limiter_key(base_key: &str, limiter_id: usize) -> String {
 }
 
 impl GenericLimiter {
-    pub fn new(redis_pool: RedisPool) -> Self {
-        let inc_expire_script = redis::Script::new(REDIS_RATE_LIMIT_INCR_LUA);
-        let undo_inc_script = redis::Script::new(REDIS_RATE_LIMIT_DECR_LUA);
-
-        GenericLimiter {
-            redis_pool,
-            inc_expire_script,
-            undo_inc_script,
-        }
+    pub fn new(backend: Arc<dyn LimiterBackend>) -> Self {
+        GenericLimiter { backend }
     }
 
     /// Tries to acquire a permit to perform an action.
@@ -80,23 +52,10 @@ impl GenericLimiter {
         // limiter is available, that way we first check that one and skip the remaining checks
         // until that key expires.
 
-        let mut redis = self.redis_pool.get().await?;
-
-        // NOTE: in redis 7 (yet to be released at the time of writing), EXPIRE supports additional
-        // options which greatly simplify this without the need of a script.
-        //
-        // Also expiretime is an option to use instead of TTL.
-
         for (limiter_id, limiter) in limiters.iter().enumerate() {
-            // NOTE: the script returns the expiretime atomically here because it's possible for
-            // the key to have expired between the time we detect there are no permits left and
-            // then request the expiration time.
-            let (current, ttl): (u32, u64) = self
-                .inc_expire_script
-                .prepare_invoke()
-                .key(limiter_key(base_key, limiter_id))
-                .arg(limiter.duration.as_secs())
-                .invoke_async(&mut redis)
+            let (current, ttl) = self
+                .backend
+                .increment(&limiter_key(base_key, limiter_id), limiter.duration)
                 .await?;
 
             if current > limiter.permits {
@@ -110,14 +69,12 @@ impl GenericLimiter {
                 // NOTE: there may have been an insertion, we will not undo that. This could lead
                 // to a shorter time from the first successfull request (after this) until permits
                 // are replenished, but this will not allow any kind of throughput advantage when
-                // considering fixed windows. UPDATE: see the comment in the lua script, there is a
-                // potential fix to this but I'd prefer to have some more comprehensive tests
-                // first.
+                // considering fixed windows. UPDATE: see the comment in the redis backend's lua
+                // script, there is a potential fix to this but I'd prefer to have some more
+                // comprehensive tests first.
                 for limiter_id in 0..end_limiter_id {
-                    self.undo_inc_script
-                        .prepare_invoke()
-                        .key(limiter_key(base_key, limiter_id))
-                        .invoke_async(&mut redis)
+                    self.backend
+                        .decrement(&limiter_key(base_key, limiter_id))
                         .await?;
                 }
 
@@ -126,12 +83,9 @@ impl GenericLimiter {
                 // buckets after this one as we know that none of the previous ones have
                 // expired).
                 for (limiter_id, limiter) in limiters.iter().enumerate().skip(end_limiter_id + 1) {
-                    let ttl = redis.ttl(limiter_key(base_key, limiter_id)).await?;
-
-                    // Technically it's possible for the token to expire between these two calls so
-                    // we check the option
-                    if let Some(permits) = redis
-                        .get::<_, Option<u32>>(limiter_key(base_key, limiter_id))
+                    if let Some((permits, ttl)) = self
+                        .backend
+                        .counter(&limiter_key(base_key, limiter_id))
                         .await?
                     {
                         if permits > limiter.permits && max_ttl < ttl {
@@ -146,6 +100,107 @@ impl GenericLimiter {
 
         Ok(Ok(()))
     }
+
+    /// Gives back a permit taken with [`GenericLimiter::get_permit`] for an action that didn't
+    /// happen after all.
+    pub async fn release_permit(
+        &self,
+        base_key: &str,
+        limiters: &[TokenBucket],
+    ) -> Result<(), GetLimiterPermitError> {
+        for limiter_id in 0..limiters.len() {
+            self.backend
+                .decrement(&limiter_key(base_key, limiter_id))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Reports how many permits are left in each of the buckets without taking a permit.
+    /// Like [`GenericLimiter::get_permit`] this is approximate, the buckets are read one at a time.
+    pub async fn peek(
+        &self,
+        base_key: &str,
+        limiters: &[TokenBucket],
+    ) -> Result<LimitStatus, GetLimiterPermitError> {
+        let mut buckets = Vec::with_capacity(limiters.len());
+        for (limiter_id, limiter) in limiters.iter().enumerate() {
+            // There is no counter if the window hasn't started
+            let (used, reset) = self
+                .backend
+                .counter(&limiter_key(base_key, limiter_id))
+                .await?
+                .unwrap_or((0, 0));
+
+            buckets.push(BucketStatus {
+                limit: limiter.permits,
+                remaining: limiter.permits.saturating_sub(used),
+                window: limiter.duration.as_secs(),
+                reset: if used > 0 { reset } else { 0 },
+            });
+        }
+
+        Ok(LimitStatus { buckets })
+    }
+}
+
+/// The state of a single [`TokenBucket`] for a key.
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketStatus {
+    /// The number of permits in each window
+    pub limit: u32,
+    pub remaining: u32,
+    /// The length of the window in seconds
+    pub window: u64,
+    /// Seconds until the window ends and the permits are replenished, this is 0 when no permits
+    /// have been used
+    pub reset: u64,
+}
+
+/// The state of every bucket of a limiter for a key, see [`Limiter::peek`].
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitStatus {
+    pub buckets: Vec<BucketStatus>,
+}
+
+impl LimitStatus {
+    /// The bucket that will run out first, i.e. the one with the fewest remaining permits (the one
+    /// that takes longest to reset if there is a tie).
+    pub fn most_restrictive(&self) -> Option<&BucketStatus> {
+        self.buckets
+            .iter()
+            .min_by(|a, b| a.remaining.cmp(&b.remaining).then(b.reset.cmp(&a.reset)))
+    }
+
+    /// Combines the status of two keys of the same limiter that are both charged for an action,
+    /// each bucket has the fewest remaining permits of the two.
+    pub fn merge(self, other: LimitStatus) -> LimitStatus {
+        let buckets = self
+            .buckets
+            .into_iter()
+            .zip(other.buckets)
+            .map(|(a, b)| match a.remaining.cmp(&b.remaining) {
+                std::cmp::Ordering::Less => a,
+                std::cmp::Ordering::Greater => b,
+                std::cmp::Ordering::Equal if a.reset >= b.reset => a,
+                std::cmp::Ordering::Equal => b,
+            })
+            .collect();
+
+        LimitStatus { buckets }
+    }
+
+    /// Adds the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers from the
+    /// IETF rate limit headers draft using the most restrictive bucket.
+    pub fn insert_headers(&self, builder: &mut HttpResponseBuilder) {
+        if let Some(bucket) = self.most_restrictive() {
+            builder
+                .insert_header(("RateLimit-Limit", bucket.limit))
+                .insert_header(("RateLimit-Remaining", bucket.remaining))
+                .insert_header(("RateLimit-Reset", bucket.reset));
+        }
+    }
 }
 
 /// Represents a rate limiter where a user can take n (n = `permits`) in a given timeframe
@@ -203,6 +258,11 @@ impl Limiter {
         Limiter { generic, config }
     }
 
+    /// Combines the key with this limiter's unique limiter_id.
+    fn key<K: Display>(&self, key: K) -> String {
+        format!("{}-{}", self.config.limiter_id, key)
+    }
+
     /// Tries to acquire a permit to perform an action.
     /// `Ok(Ok(()))` is the only response that represents the user is allowed to perform this
     /// action.
@@ -221,10 +281,56 @@ impl Limiter {
         key: K,
     ) -> Result<Result<(), RateLimitReached>, GetLimiterPermitError> {
         self.generic
-            .get_permit(
-                &format!("{}-{}", self.config.limiter_id, key),
-                &self.config.limits,
-            )
+            .get_permit(&self.key(key), &self.config.limits)
             .await
     }
+
+    /// Takes a permit for every key, for actions that are charged to several entities (e.g. a
+    /// team and its member).
+    /// If any of the keys has hit its limits the permits already taken for the earlier keys are
+    /// given back so that the action isn't charged to anyone.
+    pub async fn get_permits<K: Display>(
+        &self,
+        keys: &[K],
+    ) -> Result<Result<(), RateLimitReached>, GetLimiterPermitError> {
+        for (i, key) in keys.iter().enumerate() {
+            if let Err(e) = self.get_permit(key).await? {
+                for key in &keys[..i] {
+                    self.generic
+                        .release_permit(&self.key(key), &self.config.limits)
+                        .await?;
+                }
+
+                return Ok(Err(e));
+            }
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// Reports the remaining permits for the key without taking one, see
+    /// [`GenericLimiter::peek`].
+    pub async fn peek<K: Display>(&self, key: K) -> Result<LimitStatus, GetLimiterPermitError> {
+        self.generic.peek(&self.key(key), &self.config.limits).await
+    }
+
+    /// Reports the remaining permits of several keys that are all charged for an action (see
+    /// [`Limiter::get_permits`]), merged with [`LimitStatus::merge`].
+    pub async fn peek_all<K: Display>(
+        &self,
+        keys: &[K],
+    ) -> Result<LimitStatus, GetLimiterPermitError> {
+        let mut status: Option<LimitStatus> = None;
+        for key in keys {
+            let key_status = self.peek(key).await?;
+            status = Some(match status {
+                Some(status) => status.merge(key_status),
+                None => key_status,
+            });
+        }
+
+        Ok(status.unwrap_or(LimitStatus {
+            buckets: Vec::new(),
+        }))
+    }
 }
@@ -0,0 +1,606 @@
+//! A built-in auth provider for deployments that don't run autha.
+//!
+//! Accounts have a username, email and password (hashed with argon2) and must verify their email
+//! before they can log in.
+//! The refresh and access tokens are JWTs with the same claims (`sub`, `iat` and `exp`) as those
+//! issued by autha so the [`AuthGuard`](crate::guard::AuthGuard) extractor, session revocation,
+//! the CLI and delegated auth don't need to know which provider is in use.
+//!
+//! Setting `DOXA_AUTH_PROVIDER=local` uses this provider instead of autha, its tokens are signed
+//! with `DOXA_JWT_SECRET`. It supports the `register`, `login`, `request_password_reset` and
+//! `reset_password` flows of `POST /api/auth/provider_flow` with `provider_name` set to `local`,
+//! registering requires `DOXA_ALLOW_REGISTRATION=true`.
+//!
+//! Email verification and password reset links are built from `DOXA_VERIFY_EMAIL_URL` and
+//! `DOXA_RESET_PASSWORD_URL` with the code in the `code` query parameter, the code is then sent to
+//! `/api/auth/verify_email` or the `reset_password` flow. Registering with or requesting a reset
+//! for an email that already has an account responds the same as any other email (the owner is
+//! emailed instead) and both are limited per email address. Logins are limited per client IP and
+//! username, with a higher limit for each username.
+
+use std::{sync::Arc, time::Duration};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use diesel::PgConnection;
+use doxa_core::chrono::{Duration as ChronoDuration, Utc};
+use doxa_db::{
+    action,
+    model::{
+        local_account::{
+            LocalAccount, LocalAccountToken, RESET_PASSWORD_PURPOSE, VERIFY_EMAIL_PURPOSE,
+        },
+        user::InsertableLocalUser,
+    },
+};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    api_token::hash_token,
+    controller::{new_token_generation, JWT_LIFE},
+    error::{
+        EmailNotVerified, IncorrectCredentials, InvalidAccountCode, InvalidEmail, InvalidToken,
+        LocalAuthError, NotRefreshToken, PasswordHashFailed, PasswordTooShort,
+        RegistrationDisabled, SendEmailFailed, UserAlreadyExists, UserNotFoundAuth,
+    },
+    limiter::{GenericLimiter, Limiter, LimiterConfig, TokenBucket},
+    mailer::{Email, Mailer},
+    session,
+};
+
+/// The name to use as `provider_name` in `/auth/provider_flow`.
+pub const LOCAL_PROVIDER_NAME: &str = "local";
+
+pub const ACCESS_TOKEN_LIFE: Duration = Duration::from_secs(60 * 60);
+pub const VERIFY_EMAIL_CODE_LIFE: Duration = Duration::from_secs(60 * 60 * 24 * 2);
+pub const RESET_PASSWORD_CODE_LIFE: Duration = Duration::from_secs(60 * 60);
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+const CODE_BYTES: usize = 32;
+
+const REFRESH_SCOPE: &str = "refresh";
+const ACCESS_SCOPE: &str = "access";
+const ADMIN_SCOPE: &str = "admin";
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: i32,
+    scopes: Vec<String>,
+    iat: i64,
+    exp: i64,
+}
+
+/// A token that has been verified by [`LocalAuth::verify_token`].
+pub struct LocalToken {
+    pub user: i32,
+    pub access: bool,
+    pub admin: bool,
+}
+
+#[derive(Serialize)]
+pub struct AccessToken {
+    pub access_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct Register {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RequestPasswordReset {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPassword {
+    pub code: String,
+    pub password: String,
+}
+
+/// The result of a flow, either the user is now logged in or they need to act on an email.
+pub enum LocalFlowResponse {
+    Authenticated { refresh_token: String },
+    Incomplete { payload: Value },
+}
+
+pub struct LocalAuth {
+    jwt_secret: Vec<u8>,
+    mailer: Arc<dyn Mailer>,
+    /// The page that verifies the email, the code is added as the `code` query parameter
+    verify_email_url: Url,
+    /// The page for choosing a new password, the code is added as the `code` query parameter
+    reset_password_url: Url,
+    login_limiter: Limiter,
+    login_username_limiter: Limiter,
+    email_limiter: Limiter,
+    /// Verified against when logging in with an unknown username so that it takes as long as
+    /// an incorrect password
+    dummy_password_hash: String,
+}
+
+impl LocalAuth {
+    pub fn new(
+        jwt_secret: Vec<u8>,
+        mailer: Arc<dyn Mailer>,
+        verify_email_url: Url,
+        reset_password_url: Url,
+        generic_limiter: &Arc<GenericLimiter>,
+    ) -> Self {
+        let mut login_limiter = LimiterConfig::new("LOCAL_LOGIN".into());
+        login_limiter
+            // 10 per 15 mins
+            .add_limit(TokenBucket::new(Duration::from_secs(60 * 15), 10))
+            // 30 per day
+            .add_limit(TokenBucket::new(Duration::from_secs(60 * 60 * 24), 30));
+
+        // Shared by every client so that a password can't be guessed from many addresses, this is
+        // higher than the limit for each client so that others can't easily lock the user out
+        let mut login_username_limiter = LimiterConfig::new("LOCAL_LOGIN_USERNAME".into());
+        login_username_limiter
+            // 50 per 15 mins
+            .add_limit(TokenBucket::new(Duration::from_secs(60 * 15), 50))
+            // 200 per day
+            .add_limit(TokenBucket::new(Duration::from_secs(60 * 60 * 24), 200));
+
+        let mut email_limiter = LimiterConfig::new("LOCAL_EMAIL".into());
+        email_limiter
+            // 3 per 15 mins
+            .add_limit(TokenBucket::new(Duration::from_secs(60 * 15), 3))
+            // 10 per day
+            .add_limit(TokenBucket::new(Duration::from_secs(60 * 60 * 24), 10));
+
+        LocalAuth {
+            jwt_secret,
+            mailer,
+            verify_email_url,
+            reset_password_url,
+            login_limiter: login_limiter.build(generic_limiter),
+            login_username_limiter: login_username_limiter.build(generic_limiter),
+            email_limiter: email_limiter.build(generic_limiter),
+            dummy_password_hash: hash_password(&new_token_generation())
+                .expect("failed to hash the dummy password"),
+        }
+    }
+
+    /// Limits the login attempts for each username from each client, keyed by
+    /// [`login_limiter_key`].
+    pub fn login_limiter(&self) -> &Limiter {
+        &self.login_limiter
+    }
+
+    /// Limits the login attempts for each username across every client.
+    pub fn login_username_limiter(&self) -> &Limiter {
+        &self.login_username_limiter
+    }
+
+    /// Limits the registrations and password resets (and so the emails sent) for each email
+    /// address, keyed by [`normalize_email`].
+    pub fn email_limiter(&self) -> &Limiter {
+        &self.email_limiter
+    }
+
+    fn issue_token(&self, user: i32, scopes: Vec<String>, life: Duration) -> String {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: user,
+            scopes,
+            iat: now.timestamp(),
+            exp: (now + ChronoDuration::from_std(life).unwrap()).timestamp(),
+        };
+
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(&self.jwt_secret),
+        )
+        .expect("failed to encode a JWT")
+    }
+
+    pub fn issue_refresh_token(&self, user: i32) -> String {
+        self.issue_token(user, vec![REFRESH_SCOPE.to_string()], JWT_LIFE)
+    }
+
+    pub fn verify_token(&self, token: &str) -> Result<LocalToken, InvalidToken> {
+        let claims = jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(&self.jwt_secret),
+            &Validation::default(),
+        )
+        .map_err(|_| InvalidToken)?
+        .claims;
+
+        let has_scope = |scope: &str| claims.scopes.iter().any(|s| s == scope);
+
+        Ok(LocalToken {
+            user: claims.sub,
+            access: has_scope(ACCESS_SCOPE),
+            admin: has_scope(ADMIN_SCOPE),
+        })
+    }
+
+    /// Exchanges a refresh token for an access token, whether the user is an admin is read from
+    /// the database so promoting or demoting a user takes effect on their next access token.
+    pub fn authorize(
+        &self,
+        conn: &PgConnection,
+        refresh_token: &str,
+    ) -> Result<AccessToken, LocalAuthError> {
+        let token = self.verify_token(refresh_token)?;
+        if token.access {
+            return Err(NotRefreshToken.into());
+        }
+
+        let user =
+            action::user::get_user_by_id_optional(conn, token.user)?.ok_or(UserNotFoundAuth)?;
+
+        let mut scopes = vec![ACCESS_SCOPE.to_string()];
+        if user.admin {
+            scopes.push(ADMIN_SCOPE.to_string());
+        }
+
+        Ok(AccessToken {
+            access_token: self.issue_token(user.id, scopes, ACCESS_TOKEN_LIFE),
+        })
+    }
+
+    /// Creates the account and sends the email verification code, the user can't log in until
+    /// their email has been verified.
+    /// If the email already has an account the response is the same and the owner of the email is
+    /// sent a verification code again (if it isn't verified) or told that they already have an
+    /// account, so this can't be used to find out which emails have accounts.
+    pub fn register(
+        &self,
+        conn: &PgConnection,
+        allow_registration: bool,
+        register: Register,
+    ) -> Result<LocalFlowResponse, LocalAuthError> {
+        if !allow_registration {
+            return Err(RegistrationDisabled.into());
+        }
+
+        if register.password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(PasswordTooShort.into());
+        }
+
+        if action::user::get_user_by_username(conn, &register.username)?.is_some() {
+            return Err(UserAlreadyExists.into());
+        }
+
+        // Hashed before checking the email so that both cases take about as long
+        let password_hash = hash_password(&register.password)?;
+
+        let email = validate_email(&register.email)?;
+        let response = LocalFlowResponse::Incomplete {
+            payload: serde_json::json!({ "next": "verify_email", "email": email }),
+        };
+
+        if let Some(account) = action::local_account::get_account_by_email(conn, &email)? {
+            if account.email_verified_at.is_none() {
+                self.send_verification_email(conn, &account)?;
+            } else {
+                self.send_already_registered_email(conn, &account)?;
+            }
+
+            return Ok(response);
+        }
+
+        let user = InsertableLocalUser {
+            username: register.username,
+            token_generation: new_token_generation(),
+            extra: serde_json::json!({}),
+            admin: false,
+        };
+        let account = LocalAccount {
+            user_id: 0,
+            email,
+            password_hash,
+            email_verified_at: None,
+            created_at: Utc::now(),
+        };
+        let (_, account) = action::local_account::create_account(conn, &user, account)?;
+
+        self.send_verification_email(conn, &account)?;
+
+        Ok(response)
+    }
+
+    pub fn login(
+        &self,
+        conn: &PgConnection,
+        login: Login,
+    ) -> Result<LocalFlowResponse, LocalAuthError> {
+        let account = action::local_account::get_account_by_username(conn, &login.username)?;
+
+        // Unknown usernames get the same error after the same amount of work as incorrect
+        // passwords so that they can't be told apart
+        let password_hash = account
+            .as_ref()
+            .map_or(&self.dummy_password_hash, |(account, _)| {
+                &account.password_hash
+            });
+        let password_correct = verify_password(&login.password, password_hash);
+
+        let (account, user) = match account {
+            Some(account) if password_correct => account,
+            _ => return Err(IncorrectCredentials.into()),
+        };
+
+        if account.email_verified_at.is_none() {
+            return Err(EmailNotVerified.into());
+        }
+
+        Ok(LocalFlowResponse::Authenticated {
+            refresh_token: self.issue_refresh_token(user.id),
+        })
+    }
+
+    /// Uses the code from the verification email and logs the user in.
+    pub fn verify_email(
+        &self,
+        conn: &PgConnection,
+        code: &str,
+    ) -> Result<LocalFlowResponse, LocalAuthError> {
+        let token = action::local_account::use_token(
+            conn,
+            &hash_token(code),
+            VERIFY_EMAIL_PURPOSE,
+            Utc::now(),
+        )?
+        .ok_or(InvalidAccountCode)?;
+
+        action::local_account::set_email_verified(conn, token.user_id, Utc::now())?;
+
+        Ok(LocalFlowResponse::Authenticated {
+            refresh_token: self.issue_refresh_token(token.user_id),
+        })
+    }
+
+    /// Sends a password reset code if there is an account with the email, the response is the
+    /// same either way so this can't be used to find out which emails have accounts.
+    pub fn request_password_reset(
+        &self,
+        conn: &PgConnection,
+        request: RequestPasswordReset,
+    ) -> Result<LocalFlowResponse, LocalAuthError> {
+        let email = validate_email(&request.email)?;
+        if let Some(account) = action::local_account::get_account_by_email(conn, &email)? {
+            let code = create_code(
+                conn,
+                account.user_id,
+                RESET_PASSWORD_PURPOSE,
+                RESET_PASSWORD_CODE_LIFE,
+            )?;
+
+            self.mailer
+                .send(&Email {
+                    to: account.email,
+                    subject: "Reset your DOXA password".to_string(),
+                    body: format!(
+                        "Use this link to choose a new password: {}\n\nIf you didn't request this you can ignore this email.",
+                        with_code(&self.reset_password_url, &code)
+                    ),
+                })
+                .map_err(SendEmailFailed::from)?;
+        }
+
+        Ok(LocalFlowResponse::Incomplete {
+            payload: serde_json::json!({ "next": "reset_password" }),
+        })
+    }
+
+    /// Sets the new password and logs the user out everywhere else.
+    /// Receiving the code also proves the user owns the email so it is marked as verified.
+    pub fn reset_password(
+        &self,
+        conn: &PgConnection,
+        reset: ResetPassword,
+    ) -> Result<LocalFlowResponse, LocalAuthError> {
+        if reset.password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(PasswordTooShort.into());
+        }
+
+        let token = action::local_account::use_token(
+            conn,
+            &hash_token(&reset.code),
+            RESET_PASSWORD_PURPOSE,
+            Utc::now(),
+        )?
+        .ok_or(InvalidAccountCode)?;
+
+        let user_id = token.user_id;
+        let account = action::local_account::set_password_hash(
+            conn,
+            user_id,
+            hash_password(&reset.password)?,
+        )?;
+        if account.email_verified_at.is_none() {
+            action::local_account::set_email_verified(conn, user_id, Utc::now())?;
+        }
+
+        session::revoke_sessions(conn, user_id, Some(user_id), false, "password_reset")?;
+
+        Ok(LocalFlowResponse::Authenticated {
+            refresh_token: self.issue_refresh_token(user_id),
+        })
+    }
+
+    fn send_already_registered_email(
+        &self,
+        conn: &PgConnection,
+        account: &LocalAccount,
+    ) -> Result<(), LocalAuthError> {
+        let user = action::user::get_user_by_id(conn, account.user_id)?;
+
+        self.mailer
+            .send(&Email {
+                to: account.email.clone(),
+                subject: "You already have a DOXA account".to_string(),
+                body: format!(
+                    "Someone tried to register with this email address but it already has an account with the username {}. You can log in or reset your password instead.\n\nIf you didn't request this you can ignore this email.",
+                    user.username
+                ),
+            })
+            .map_err(SendEmailFailed::from)?;
+
+        Ok(())
+    }
+
+    fn send_verification_email(
+        &self,
+        conn: &PgConnection,
+        account: &LocalAccount,
+    ) -> Result<(), LocalAuthError> {
+        let code = create_code(
+            conn,
+            account.user_id,
+            VERIFY_EMAIL_PURPOSE,
+            VERIFY_EMAIL_CODE_LIFE,
+        )?;
+
+        self.mailer
+            .send(&Email {
+                to: account.email.clone(),
+                subject: "Verify your DOXA email address".to_string(),
+                body: format!(
+                    "Use this link to verify your email address: {}",
+                    with_code(&self.verify_email_url, &code)
+                ),
+            })
+            .map_err(SendEmailFailed::from)?;
+
+        Ok(())
+    }
+}
+
+/// The key of [`LocalAuth::login_limiter`], so that one client running out of attempts doesn't
+/// stop the user logging in from elsewhere.
+pub fn login_limiter_key(client_ip: &str, username: &str) -> String {
+    format!("{}-{}", client_ip, username)
+}
+
+/// Emails are case insensitive in practice so they are stored, looked up and rate limited in this
+/// form, otherwise the same address could have several accounts or avoid the limit by changing
+/// the case.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Normalizes the email (see [`normalize_email`]) and checks that it looks like `local@domain`.
+/// Emails with whitespace or control characters are rejected, otherwise they could add headers to
+/// the emails that are sent.
+pub fn validate_email(email: &str) -> Result<String, InvalidEmail> {
+    let email = normalize_email(email);
+    if email.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return Err(InvalidEmail);
+    }
+
+    match email.split_once('@') {
+        Some((local, domain))
+            if !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.') =>
+        {
+            Ok(email)
+        }
+        _ => Err(InvalidEmail),
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, PasswordHashFailed> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| PasswordHashFailed)
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// Creates a single use code, only its hash is stored.
+fn create_code(
+    conn: &PgConnection,
+    user_id: i32,
+    purpose: &str,
+    life: Duration,
+) -> Result<String, LocalAuthError> {
+    use rand::Rng;
+
+    let code: Vec<u8> = rand::thread_rng()
+        .sample_iter(rand::distributions::Standard)
+        .take(CODE_BYTES)
+        .collect();
+    let code = base64::encode_config(code, base64::URL_SAFE_NO_PAD);
+
+    let now = Utc::now();
+    action::local_account::create_token(
+        conn,
+        &LocalAccountToken {
+            token_hash: hash_token(&code),
+            user_id,
+            purpose: purpose.to_string(),
+            created_at: now,
+            expires_at: now + ChronoDuration::from_std(life).unwrap(),
+            used_at: None,
+        },
+    )?;
+
+    Ok(code)
+}
+
+fn with_code(url: &Url, code: &str) -> Url {
+    let mut url = url.clone();
+    url.query_pairs_mut().append_pair("code", code);
+
+    url
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn emails_are_normalized() {
+        assert_eq!(
+            validate_email("  Someone@Example.com ").unwrap(),
+            "someone@example.com"
+        );
+    }
+
+    #[test]
+    fn invalid_emails_are_rejected() {
+        for email in [
+            "",
+            "someone",
+            "@example.com",
+            "someone@",
+            "someone@localhost",
+            "someone@example.com.",
+            "some@one@example.com",
+            "some one@example.com",
+            "someone@example.com\r\nBcc: other@example.com",
+        ] {
+            assert!(validate_email(email).is_err(), "{:?} was accepted", email);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Delivery of the emails sent by the built-in auth provider (see [`crate::local`]).
+//!
+//! There is no SMTP support, deployments using local accounts either read the emails from the
+//! log or have another process pick them up from a directory.
+//!
+//! Emails are written to files in `DOXA_MAIL_DIR`, or to the log if it isn't set.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use doxa_core::{chrono::Utc, tracing::info};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> io::Result<()>;
+}
+
+/// Writes emails to the log, this is intended for development.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        info!(to=%email.to, subject=%email.subject, body=%email.body, "sending email");
+
+        Ok(())
+    }
+}
+
+/// Writes each email to its own file in a directory.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(FileMailer { dir })
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        use rand::Rng;
+
+        // A line break in a header would start a new header (e.g. `Bcc:`)
+        if [&email.to, &email.subject]
+            .iter()
+            .any(|header| header.contains(|c| c == '\r' || c == '\n'))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "email headers can't contain line breaks",
+            ));
+        }
+
+        let name = format!(
+            "{}-{:08x}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            rand::thread_rng().gen::<u32>()
+        );
+
+        let mut file = fs::File::create(self.dir.join(name))?;
+        write!(
+            file,
+            "To: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            email.to, email.subject, email.body
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use doxa_core::{
+    autha_client::{error::AuthaError, jwt::Scope},
+    error::RespondableErrorWrapper,
+};
+
+use crate::{local::LocalAuth, AuthaClient};
+
+/// Where users log in, either autha or the built-in [`LocalAuth`].
+#[derive(Clone)]
+pub enum AuthProvider {
+    Autha(Arc<AuthaClient>),
+    Local(Arc<LocalAuth>),
+}
+
+/// A refresh or access token that has been verified by the provider.
+pub struct VerifiedToken {
+    pub user: i32,
+    pub access: bool,
+    pub admin: bool,
+}
+
+impl AuthProvider {
+    pub fn verify_token(&self, token: &str) -> Result<VerifiedToken, RespondableErrorWrapper> {
+        Ok(match self {
+            AuthProvider::Autha(autha) => {
+                let token = autha.verify_jwt(token).map_err(AuthaError::from)?;
+
+                VerifiedToken {
+                    user: token.user(),
+                    access: token.has_scope(&Scope::Access),
+                    admin: token.has_scope(&Scope::Admin),
+                }
+            }
+            AuthProvider::Local(local) => {
+                let token = local.verify_token(token)?;
+
+                VerifiedToken {
+                    user: token.user,
+                    access: token.access,
+                    admin: token.admin,
+                }
+            }
+        })
+    }
+
+    pub async fn issue_refresh_token(&self, user: i32) -> Result<String, RespondableErrorWrapper> {
+        Ok(match self {
+            AuthProvider::Autha(autha) => autha.issue_refresh_token(user).await??.refresh_token,
+            AuthProvider::Local(local) => local.issue_refresh_token(user),
+        })
+    }
+}
@@ -0,0 +1,663 @@
+//! Middleware that rate limits whole groups of API routes using the [`GenericLimiter`].
+//!
+//! Individual routes that take a permit for a specific action (e.g. uploading an agent) should
+//! keep using their own [`Limiter`], this is for protecting routes that are expensive to serve
+//! such as game events, leaderboards and the auth flows.
+//!
+//! Groups of routes are matched with `*` for one path segment and a trailing `**` for the rest.
+//! Requests are keyed by user, or by IP address for anonymous requests, requests with API tokens
+//! and the auth flows, and admins are exempt. Requests over the limit get a `429` with a
+//! `Retry-After` header. `doxa_server` limits game events and transcripts, leaderboards and the
+//! auth routes by default.
+//!
+//! Set `DOXA_TRUST_PROXY_HEADERS=true` when running behind a reverse proxy so the client IP is
+//! taken from the last entry of the `Forwarded`/`X-Forwarded-For` headers, which is the one added
+//! by the proxy.
+
+use std::{
+    future::{ready, Future, Ready},
+    net::SocketAddr,
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::HeaderMap,
+};
+use doxa_core::{error::RespondableErrorWrapper, tracing::warn};
+
+use crate::{
+    api_token::API_TOKEN_PREFIX,
+    error::TooManyRequests,
+    limiter::{GenericLimiter, Limiter, LimiterConfig},
+    provider::AuthProvider,
+    settings::Settings,
+};
+
+/// How the clients of a route group are told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Requests from logged in users are limited per user, anonymous requests and those made with
+    /// an API token are limited per IP address.
+    UserOrIp,
+    /// Requests are always limited per IP address, this is useful for routes that are used before
+    /// the user has logged in.
+    Ip,
+}
+
+/// A set of route patterns that share a [`LimiterConfig`].
+///
+/// Patterns are matched against the full request path, segment by segment. A `*` segment matches
+/// any single segment and a trailing `**` matches any number of remaining segments, e.g.
+/// `/api/competition/*/_game/*/events` or `/api/competition/*/_leaderboard/**`.
+pub struct RouteGroup {
+    patterns: Vec<Vec<String>>,
+    config: LimiterConfig,
+    key: RateLimitKey,
+    exempt_admins: bool,
+}
+
+impl RouteGroup {
+    /// By default the group is keyed by [`RateLimitKey::UserOrIp`] and admins are exempt.
+    pub fn new(config: LimiterConfig) -> Self {
+        RouteGroup {
+            patterns: Vec::new(),
+            config,
+            key: RateLimitKey::UserOrIp,
+            exempt_admins: true,
+        }
+    }
+
+    pub fn route(mut self, pattern: &str) -> Self {
+        self.patterns
+            .push(split_path(pattern).map(String::from).collect());
+        self
+    }
+
+    pub fn key(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn exempt_admins(mut self, exempt_admins: bool) -> Self {
+        self.exempt_admins = exempt_admins;
+        self
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+fn pattern_matches(pattern: &[String], path: &str) -> bool {
+    let mut segments = split_path(path);
+
+    for part in pattern {
+        if part == "**" {
+            return true;
+        }
+
+        match segments.next() {
+            Some(segment) if part == "*" || part == segment => {}
+            _ => return false,
+        }
+    }
+
+    segments.next().is_none()
+}
+
+struct ConfiguredGroup {
+    patterns: Vec<Vec<String>>,
+    key: RateLimitKey,
+    exempt_admins: bool,
+    limiter: Limiter,
+}
+
+impl ConfiguredGroup {
+    fn matches(&self, path: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern_matches(pattern, path))
+    }
+}
+
+struct RateLimitInner {
+    groups: Vec<ConfiguredGroup>,
+    auth_provider: AuthProvider,
+    system_account_secret: String,
+    trust_proxy_headers: bool,
+}
+
+/// The client a request was made by, as far as the rate limiter is concerned.
+struct Client {
+    key: Option<String>,
+    admin: bool,
+}
+
+impl RateLimitInner {
+    /// This only does a cheap check of the bearer token (no database queries), so revoked
+    /// sessions are still limited under their user. The route itself will reject them.
+    /// API tokens can't be verified without a database query so they are treated as anonymous,
+    /// otherwise each made up token would get its own limit.
+    fn identify(&self, req: &ServiceRequest) -> Client {
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        let token = match token {
+            Some(token) => token,
+            None => {
+                return Client {
+                    key: None,
+                    admin: false,
+                }
+            }
+        };
+
+        if token == self.system_account_secret {
+            Client {
+                key: Some("system".to_string()),
+                admin: true,
+            }
+        } else if token.starts_with(API_TOKEN_PREFIX) {
+            Client {
+                key: None,
+                admin: false,
+            }
+        } else {
+            match self.auth_provider.verify_token(token) {
+                Ok(token) if token.access => Client {
+                    key: Some(format!("user-{}", token.user)),
+                    admin: token.admin,
+                },
+                _ => Client {
+                    key: None,
+                    admin: false,
+                },
+            }
+        }
+    }
+
+    fn client_ip(&self, req: &ServiceRequest) -> String {
+        client_ip(req.peer_addr(), req.headers(), self.trust_proxy_headers)
+    }
+}
+
+/// The IP address of the client that made a request, when `trust_proxy_headers` is set this is
+/// taken from the headers added by the reverse proxy (see [`forwarded_client_ip`]).
+pub(crate) fn client_ip(
+    peer_addr: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trust_proxy_headers: bool,
+) -> String {
+    if trust_proxy_headers {
+        if let Some(addr) = forwarded_client_ip(headers) {
+            return addr;
+        }
+    }
+
+    peer_addr
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The client address that the reverse proxy in front of the server added to the `Forwarded` or
+/// `X-Forwarded-For` header, which is the last entry. Earlier entries are whatever the client sent
+/// so they can't be trusted.
+fn forwarded_client_ip(headers: &HeaderMap) -> Option<String> {
+    fn last_entry<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+        headers
+            .get_all(name)
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .last()
+    }
+
+    let forwarded_for = last_entry(headers, "Forwarded").and_then(|element| {
+        element
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+            .map(|(_, addr)| addr.trim().trim_matches('"'))
+    });
+
+    forwarded_for
+        .or_else(|| last_entry(headers, "X-Forwarded-For"))
+        .map(|addr| match addr.parse::<SocketAddr>() {
+            Ok(addr) => addr.ip().to_string(),
+            Err(_) => addr
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+        })
+}
+
+/// Actix middleware applying the configured [`RouteGroup`]s, the first group that matches the
+/// request path is used. Requests over the limit receive a `429` with a `Retry-After` header.
+///
+/// If the limiter's backend fails the request is let through rather than taking down every route
+/// in the group.
+#[derive(Clone)]
+pub struct RateLimit {
+    inner: Arc<RateLimitInner>,
+}
+
+impl RateLimit {
+    /// When `trust_proxy_headers` is set the client IP is taken from the `Forwarded` or
+    /// `X-Forwarded-For` headers, only enable this when the server is behind a reverse proxy that
+    /// appends to them.
+    pub fn new(
+        settings: &Settings,
+        generic: &Arc<GenericLimiter>,
+        trust_proxy_headers: bool,
+        groups: Vec<RouteGroup>,
+    ) -> Self {
+        let groups = groups
+            .into_iter()
+            .map(|group| ConfiguredGroup {
+                patterns: group.patterns,
+                key: group.key,
+                exempt_admins: group.exempt_admins,
+                limiter: group.config.build(generic),
+            })
+            .collect();
+
+        RateLimit {
+            inner: Arc::new(RateLimitInner {
+                groups,
+                auth_provider: settings.auth_provider.clone(),
+                system_account_secret: settings.system_account_secret.clone(),
+                trust_proxy_headers,
+            }),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            inner: self.inner.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    inner: Arc<RateLimitInner>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let group = inner.groups.iter().find(|group| group.matches(req.path()));
+
+            if let Some(group) = group {
+                let client = inner.identify(&req);
+
+                if !(group.exempt_admins && client.admin) {
+                    let key = match (group.key, client.key) {
+                        (RateLimitKey::UserOrIp, Some(key)) => key,
+                        _ => format!("ip-{}", inner.client_ip(&req)),
+                    };
+
+                    match group.limiter.get_permit(&key).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(reached)) => {
+                            return Err(RespondableErrorWrapper::from(TooManyRequests::from(
+                                reached,
+                            ))
+                            .into());
+                        }
+                        Err(e) => {
+                            warn!(error=%e, debug=?e, key=%key, "failed to get rate limit permit, allowing the request");
+                        }
+                    }
+                }
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use actix_web::{
+        http::{
+            header::{HeaderName, HeaderValue},
+            StatusCode,
+        },
+        test, web, App, HttpResponse,
+    };
+    use async_trait::async_trait;
+    use doxa_core::{
+        chrono::Utc,
+        redis::{redis::ErrorKind, RedisError},
+        tokio,
+    };
+    use jsonwebtoken::{EncodingKey, Header};
+
+    use super::*;
+    use crate::{
+        backend::{Backends, LimiterBackend},
+        error::BackendError,
+        limiter::TokenBucket,
+        local::LocalAuth,
+        mailer::LogMailer,
+    };
+
+    const JWT_SECRET: &[u8] = b"test secret";
+    const SYSTEM_SECRET: &str = "system secret";
+
+    fn settings(generic: &Arc<GenericLimiter>) -> Settings {
+        let url: url::Url = "http://localhost/".parse().unwrap();
+
+        Settings {
+            allow_registration: false,
+            auth_provider: AuthProvider::Local(Arc::new(LocalAuth::new(
+                JWT_SECRET.to_vec(),
+                Arc::new(LogMailer),
+                url.clone(),
+                url.clone(),
+                generic,
+            ))),
+            delegated_auth_backend: Backends::in_memory().delegated_auth,
+            delegated_auth_url: url,
+            system_account_secret: SYSTEM_SECRET.to_string(),
+            trust_proxy_headers: false,
+        }
+    }
+
+    /// An access token with the same claims as those issued by [`LocalAuth`].
+    fn access_token(user: i32, admin: bool) -> String {
+        let mut scopes = vec!["access"];
+        if admin {
+            scopes.push("admin");
+        }
+
+        let now = Utc::now().timestamp();
+        jsonwebtoken::encode(
+            &Header::default(),
+            &serde_json::json!({ "sub": user, "scopes": scopes, "iat": now, "exp": now + 3600 }),
+            &EncodingKey::from_secret(JWT_SECRET),
+        )
+        .unwrap()
+    }
+
+    /// A group for `/limited/**` that allows one request a minute.
+    fn group() -> RouteGroup {
+        let mut config = LimiterConfig::new("TEST".to_string());
+        config.add_limit(TokenBucket::new(Duration::from_secs(60), 1));
+
+        RouteGroup::new(config).route("/limited/**")
+    }
+
+    fn request(path: &str, ip: &str, token: Option<&str>) -> test::TestRequest {
+        let mut request = test::TestRequest::get()
+            .uri(path)
+            .peer_addr(format!("{}:4000", ip).parse().unwrap());
+        if let Some(token) = token {
+            request = request.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+
+        request
+    }
+
+    fn status<B>(response: Result<ServiceResponse<B>, actix_web::Error>) -> StatusCode {
+        match response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    macro_rules! app {
+        ($backend:expr, $group:expr) => {{
+            let generic = Arc::new(GenericLimiter::new($backend));
+            let rate_limit = RateLimit::new(&settings(&generic), &generic, false, vec![$group]);
+
+            test::init_service(
+                App::new()
+                    .wrap(rate_limit)
+                    .route("/limited/a", web::get().to(HttpResponse::Ok))
+                    .route("/open", web::get().to(HttpResponse::Ok)),
+            )
+            .await
+        }};
+    }
+
+    /// A limiter backend that is always down.
+    struct FailingBackend;
+
+    fn backend_down() -> BackendError {
+        RedisError::from((ErrorKind::IoError, "backend down")).into()
+    }
+
+    #[async_trait]
+    impl LimiterBackend for FailingBackend {
+        async fn increment(
+            &self,
+            _key: &str,
+            _window: Duration,
+        ) -> Result<(u32, u64), BackendError> {
+            Err(backend_down())
+        }
+
+        async fn decrement(&self, _key: &str) -> Result<(), BackendError> {
+            Err(backend_down())
+        }
+
+        async fn counter(&self, _key: &str) -> Result<Option<(u32, u64)>, BackendError> {
+            Err(backend_down())
+        }
+    }
+
+    #[test]
+    fn patterns_match_whole_segments() {
+        let pattern = |p: &str| split_path(p).map(String::from).collect::<Vec<_>>();
+
+        let events = pattern("/api/competition/*/_game/*/events");
+        assert!(pattern_matches(
+            &events,
+            "/api/competition/uttt/_game/4/events"
+        ));
+        assert!(!pattern_matches(&events, "/api/competition/uttt/_game/4"));
+        assert!(!pattern_matches(
+            &events,
+            "/api/competition/uttt/_game/4/events/2"
+        ));
+
+        let leaderboard = pattern("/api/competition/*/_leaderboard/**");
+        assert!(pattern_matches(
+            &leaderboard,
+            "/api/competition/uttt/_leaderboard/"
+        ));
+        assert!(pattern_matches(
+            &leaderboard,
+            "/api/competition/uttt/_leaderboard/active/2"
+        ));
+        assert!(!pattern_matches(
+            &leaderboard,
+            "/api/competition/uttt/_leaderboards"
+        ));
+    }
+
+    #[tokio::test]
+    async fn users_are_limited_separately_and_everyone_else_by_ip() {
+        let app = app!(Backends::in_memory().limiter, group());
+        let first = access_token(1, false);
+        let second = access_token(2, false);
+
+        let limited = |token: Option<&str>| request("/limited/a", "10.0.0.1", token).to_request();
+        assert_eq!(
+            status(app.call(limited(Some(first.as_str()))).await),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(app.call(limited(Some(first.as_str()))).await),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(app.call(limited(Some(second.as_str()))).await),
+            StatusCode::OK
+        );
+
+        // Anonymous requests and API tokens share the IP's limit
+        assert_eq!(status(app.call(limited(None)).await), StatusCode::OK);
+        assert_eq!(
+            status(app.call(limited(Some("doxa_pat_made_up"))).await),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(
+                app.call(request("/limited/a", "10.0.0.2", None).to_request())
+                    .await
+            ),
+            StatusCode::OK
+        );
+
+        // Routes outside of every group aren't limited
+        for _ in 0..3 {
+            assert_eq!(
+                status(
+                    app.call(request("/open", "10.0.0.1", None).to_request())
+                        .await
+                ),
+                StatusCode::OK
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_groups_ignore_the_user() {
+        let app = app!(Backends::in_memory().limiter, group().key(RateLimitKey::Ip));
+
+        let first = access_token(1, false);
+        let second = access_token(2, false);
+        assert_eq!(
+            status(
+                app.call(request("/limited/a", "10.0.0.1", Some(first.as_str())).to_request())
+                    .await
+            ),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(
+                app.call(request("/limited/a", "10.0.0.1", Some(second.as_str())).to_request())
+                    .await
+            ),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn admins_are_exempt_unless_disabled() {
+        let admin = access_token(1, true);
+
+        let app = app!(Backends::in_memory().limiter, group());
+        for token in [admin.as_str(), SYSTEM_SECRET] {
+            for _ in 0..3 {
+                assert_eq!(
+                    status(
+                        app.call(request("/limited/a", "10.0.0.1", Some(token)).to_request())
+                            .await
+                    ),
+                    StatusCode::OK
+                );
+            }
+        }
+
+        let app = app!(Backends::in_memory().limiter, group().exempt_admins(false));
+        let limited = || request("/limited/a", "10.0.0.1", Some(admin.as_str())).to_request();
+        assert_eq!(status(app.call(limited()).await), StatusCode::OK);
+        assert_eq!(
+            status(app.call(limited()).await),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_are_let_through_when_the_backend_fails() {
+        let app = app!(Arc::new(FailingBackend), group());
+
+        for _ in 0..3 {
+            assert_eq!(
+                status(
+                    app.call(request("/limited/a", "10.0.0.1", None).to_request())
+                        .await
+                ),
+                StatusCode::OK
+            );
+        }
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+
+        map
+    }
+
+    #[test]
+    fn the_address_added_by_the_proxy_is_used() {
+        let ip = |h: &[(&'static str, &'static str)]| forwarded_client_ip(&headers(h));
+
+        assert_eq!(
+            ip(&[("x-forwarded-for", "1.1.1.1, 10.0.0.1")]).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            ip(&[
+                ("x-forwarded-for", "1.1.1.1"),
+                ("x-forwarded-for", "10.0.0.1:4000")
+            ])
+            .as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            ip(&[(
+                "forwarded",
+                "for=1.1.1.1, for=\"[2001:db8::1]:4711\";proto=https"
+            )])
+            .as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(ip(&[]), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Roles give users extra permissions within a single competition, such as seeing private events
+//! or pausing the competition, without making them an admin of the whole platform.
+//! Admins have every permission in every competition.
+//!
+//! Roles are granted with `doxa_adm competition grant-role <username> <competition> <role>`.
+//! Organisers have every permission (including pausing the competition through
+//! `/api/admin/queues/{competition}/pause`), moderators can see private data and activate or
+//! deactivate other users' agents and viewers can only see private data such as logs, transcripts
+//! and errors. Competition routes check these with `AuthGuard<CompetitionRoleGuard<C>>`, API tokens
+//! never have any roles.
+
+use std::{fmt, str::FromStr};
+
+use diesel::PgConnection;
+use doxa_db::{action, DieselError};
+
+use crate::error::{CompetitionPermissionMissing, InvalidCompetitionRole};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompetitionRole {
+    /// Runs the competition, this has every permission.
+    Organiser,
+    /// Looks after the agents in the competition.
+    Moderator,
+    /// Can see the private data of the competition, such as agent logs.
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompetitionPermission {
+    /// Seeing events, logs and transcripts that are normally only visible to the agent's owner.
+    ViewPrivate,
+    /// Activating and deactivating agents owned by other users.
+    ManageAgents,
+    /// Bypassing the upload and activation rate limits.
+    BypassLimits,
+    /// Pausing and resuming the execution of matches.
+    Pause,
+    /// Creating invite codes and opening or closing enrollment.
+    ManageEnrollment,
+}
+
+impl CompetitionRole {
+    pub const ALL: [CompetitionRole; 3] = [
+        CompetitionRole::Organiser,
+        CompetitionRole::Moderator,
+        CompetitionRole::Viewer,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompetitionRole::Organiser => "organiser",
+            CompetitionRole::Moderator => "moderator",
+            CompetitionRole::Viewer => "viewer",
+        }
+    }
+
+    pub fn grants(&self, permission: CompetitionPermission) -> bool {
+        use CompetitionPermission::*;
+
+        match self {
+            CompetitionRole::Organiser => true,
+            CompetitionRole::Moderator => matches!(permission, ViewPrivate | ManageAgents),
+            CompetitionRole::Viewer => matches!(permission, ViewPrivate),
+        }
+    }
+}
+
+impl fmt::Display for CompetitionRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CompetitionRole {
+    type Err = InvalidCompetitionRole;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        CompetitionRole::ALL
+            .iter()
+            .copied()
+            .find(|known| known.as_str() == role)
+            .ok_or_else(|| InvalidCompetitionRole {
+                role: role.to_string(),
+            })
+    }
+}
+
+impl fmt::Display for CompetitionPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompetitionPermission::ViewPrivate => "view private",
+            CompetitionPermission::ManageAgents => "manage agents",
+            CompetitionPermission::BypassLimits => "bypass limits",
+            CompetitionPermission::Pause => "pause",
+            CompetitionPermission::ManageEnrollment => "manage enrollment",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The roles a user has in a single competition.
+#[derive(Debug, Clone, Default)]
+pub struct CompetitionRoles {
+    is_admin: bool,
+    roles: Vec<CompetitionRole>,
+}
+
+impl CompetitionRoles {
+    /// Loads the roles of the user in the competition, the system account (`user` is `None`) only
+    /// has roles when it is admin.
+    pub fn load(
+        conn: &PgConnection,
+        user: Option<i32>,
+        is_admin: bool,
+        competition: &str,
+    ) -> Result<Self, DieselError> {
+        let roles = match user {
+            Some(user) => {
+                action::competition::list_user_competition_roles(conn, user, competition)?
+                    .iter()
+                    // Roles are validated when they are granted so any that fail to parse are ignored
+                    .filter_map(|role| role.parse().ok())
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Ok(CompetitionRoles { is_admin, roles })
+    }
+
+    /// The roles of an admin, which has every permission regardless of their roles.
+    pub fn admin() -> Self {
+        CompetitionRoles {
+            is_admin: true,
+            roles: Vec::new(),
+        }
+    }
+
+    pub fn roles(&self) -> &[CompetitionRole] {
+        &self.roles
+    }
+
+    pub fn has(&self, permission: CompetitionPermission) -> bool {
+        self.is_admin || self.roles.iter().any(|role| role.grants(permission))
+    }
+
+    /// The same as [`CompetitionRoles::has`] except this returns an error if the permission is
+    /// missing.
+    pub fn require(
+        &self,
+        permission: CompetitionPermission,
+    ) -> Result<(), CompetitionPermissionMissing> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(CompetitionPermissionMissing {
+                permission: permission.to_string(),
+            })
+        }
+    }
+}
@@ -1,8 +1,27 @@
+use std::sync::Arc;
+
 use actix_web::{web, HttpRequest, HttpResponse};
-use doxa_core::EndpointResult;
-use doxa_db::PgPool;
+use doxa_core::{error::RespondableErrorWrapper, EndpointResult};
+use doxa_db::{
+    action,
+    model::audit::{actions, user_target, AuditLogFilter, InsertableAuditEntry},
+    PgPool,
+};
+use serde_json::json;
 
-use crate::{controller, delegated::DelegatedAuthManager, guard::AuthGuard, Settings};
+use crate::{
+    api_token, audit, controller,
+    delegated::DelegatedAuthManager,
+    error::{
+        InvalidAuditLogLimit, InvalidFlowPayload, SessionRevoked, TooManyAccountEmails,
+        TooManyLoginAttempts, UnknownAuthFlow, UserNotFound,
+    },
+    guard::{Admin, AuthGuard},
+    local::{self, LocalAuth, LOCAL_PROVIDER_NAME},
+    provider::AuthProvider,
+    rate_limit::client_ip,
+    session, Settings,
+};
 
 pub mod request;
 pub(crate) mod response;
@@ -16,15 +35,76 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             "/auth/authorize_delegated",
             web::post().to(authorize_delegated),
         )
-        .route("/auth/check_delegated", web::post().to(check_delegated));
+        .route("/auth/check_delegated", web::post().to(check_delegated))
+        .route("/auth/tokens", web::get().to(list_api_tokens))
+        .route("/auth/tokens", web::post().to(create_api_token))
+        .route("/auth/tokens/{id}/revoke", web::post().to(revoke_api_token))
+        .route("/auth/sessions/revoke", web::post().to(revoke_sessions))
+        .route(
+            "/auth/sessions/delegated",
+            web::get().to(list_delegated_sessions),
+        )
+        .route(
+            "/auth/sessions/delegated/{id}/revoke",
+            web::post().to(revoke_delegated_session),
+        )
+        .route(
+            "/admin/users/{username}/sessions",
+            web::get().to(admin_list_delegated_sessions),
+        )
+        .route(
+            "/admin/users/{username}/revoke_sessions",
+            web::post().to(admin_revoke_sessions),
+        )
+        .route("/admin/audit", web::get().to(admin_audit_log));
+}
+
+/// Refresh tokens issued before the user logged out everywhere and those issued for revoked
+/// delegated sessions can't be exchanged for access tokens.
+async fn check_refresh_token(
+    db_pool: web::Data<PgPool>,
+    settings: &Settings,
+    delegated_auth: &DelegatedAuthManager,
+    refresh_token: &str,
+) -> Result<(), RespondableErrorWrapper> {
+    if delegated_auth.is_token_revoked(refresh_token).await? {
+        return Err(SessionRevoked.into());
+    }
+
+    // Invalid tokens are rejected by the provider when authorizing
+    if let Ok(token) = settings.auth_provider.verify_token(refresh_token) {
+        let user = token.user;
+        let refresh_token = refresh_token.to_string();
+        let conn = web::block(move || db_pool.get()).await??;
+        if web::block(move || session::check_token_revoked(&conn, user, &refresh_token)).await?? {
+            return Err(SessionRevoked.into());
+        }
+    }
+
+    Ok(())
 }
 
 async fn authorize(
+    db_pool: web::Data<PgPool>,
     settings: web::Data<Settings>,
+    delegated_auth: web::Data<DelegatedAuthManager>,
     request: web::Json<request::Authorize>,
 ) -> EndpointResult {
     let refresh_token = request.into_inner().refresh_token;
-    Ok(HttpResponse::Ok().json(settings.autha_client.authorize(refresh_token).await??))
+    check_refresh_token(db_pool.clone(), &settings, &delegated_auth, &refresh_token).await?;
+
+    match &settings.auth_provider {
+        AuthProvider::Autha(autha) => {
+            Ok(HttpResponse::Ok().json(autha.authorize(refresh_token).await??))
+        }
+        AuthProvider::Local(local) => {
+            let local = local.clone();
+            let conn = web::block(move || db_pool.get()).await??;
+            let access_token = web::block(move || local.authorize(&conn, &refresh_token)).await??;
+
+            Ok(HttpResponse::Ok().json(access_token))
+        }
+    }
 }
 
 async fn start_delegated(
@@ -48,11 +128,11 @@ async fn check_delegated(
         .await?;
 
     let response = if let Some(user_id) = auth {
-        let refresh_token = settings
-            .autha_client
-            .issue_refresh_token(user_id)
-            .await??
-            .refresh_token;
+        let refresh_token = settings.auth_provider.issue_refresh_token(user_id).await?;
+
+        delegated_auth
+            .record_session(user_id, &refresh_token)
+            .await?;
 
         // TODO: use autha/jwt/issue
         response::DelegatedAuthCheck::Authenticated {
@@ -84,7 +164,20 @@ async fn provider_flow(
     settings: web::Data<Settings>,
     req: HttpRequest,
 ) -> EndpointResult {
-    let autha = &settings.autha_client;
+    let autha = match &settings.auth_provider {
+        AuthProvider::Autha(autha) => autha,
+        AuthProvider::Local(local) => {
+            let client_ip = client_ip(req.peer_addr(), req.headers(), settings.trust_proxy_headers);
+            return local_provider_flow(
+                db_pool,
+                local.clone(),
+                &settings,
+                body.into_inner(),
+                client_ip,
+            )
+            .await;
+        }
+    };
     let body = body.into_inner();
     let bearer_auth = req
         .headers()
@@ -108,10 +201,344 @@ async fn verify_email(
     body: web::Json<request::VerifyEmail>,
     settings: web::Data<Settings>,
 ) -> EndpointResult {
-    let autha = &settings.autha_client;
     let body = body.into_inner();
+    let autha = match &settings.auth_provider {
+        AuthProvider::Autha(autha) => autha,
+        AuthProvider::Local(local) => {
+            let local = local.clone();
+            let conn = web::block(move || db_pool.get()).await??;
+            let response =
+                web::block(move || local.verify_email(&conn, &body.verification_code)).await??;
+
+            return Ok(HttpResponse::Ok().json(response::ProviderFlow::from(response)));
+        }
+    };
 
     let response = autha.verify_email(body).await??;
 
     controller::handle_flow_response(db_pool, response).await
 }
+
+/// The flows of the built-in provider, these are `register`, `login`, `request_password_reset`
+/// and `reset_password` (see [`crate::local`] for their payloads).
+async fn local_provider_flow(
+    db_pool: web::Data<PgPool>,
+    local: Arc<LocalAuth>,
+    settings: &Settings,
+    body: request::Provider,
+    client_ip: String,
+) -> EndpointResult {
+    if body.provider_name != LOCAL_PROVIDER_NAME {
+        return Err(UnknownAuthFlow {
+            provider: body.provider_name,
+            flow: body.flow_name,
+        }
+        .into());
+    }
+
+    let payload = body.payload;
+    let allow_registration = settings.allow_registration;
+    let flow = match body.flow_name.as_str() {
+        "register" => {
+            let register: local::Register = parse_flow_payload(payload)?;
+            local
+                .email_limiter()
+                .get_permit(&local::normalize_email(&register.email))
+                .await?
+                .map_err(TooManyAccountEmails::from)?;
+
+            LocalFlow::Register(register)
+        }
+        "login" => {
+            let login: local::Login = parse_flow_payload(payload)?;
+            local
+                .login_limiter()
+                .get_permit(&local::login_limiter_key(&client_ip, &login.username))
+                .await?
+                .map_err(TooManyLoginAttempts::from)?;
+            local
+                .login_username_limiter()
+                .get_permit(&login.username)
+                .await?
+                .map_err(TooManyLoginAttempts::from)?;
+
+            LocalFlow::Login(login)
+        }
+        "request_password_reset" => {
+            let request: local::RequestPasswordReset = parse_flow_payload(payload)?;
+            local
+                .email_limiter()
+                .get_permit(&local::normalize_email(&request.email))
+                .await?
+                .map_err(TooManyAccountEmails::from)?;
+
+            LocalFlow::RequestPasswordReset(request)
+        }
+        "reset_password" => LocalFlow::ResetPassword(parse_flow_payload(payload)?),
+        _ => {
+            return Err(UnknownAuthFlow {
+                provider: body.provider_name,
+                flow: body.flow_name,
+            }
+            .into())
+        }
+    };
+
+    let conn = web::block(move || db_pool.get()).await??;
+    let response = web::block(move || match flow {
+        LocalFlow::Register(register) => local.register(&conn, allow_registration, register),
+        LocalFlow::Login(login) => local.login(&conn, login),
+        LocalFlow::RequestPasswordReset(request) => local.request_password_reset(&conn, request),
+        LocalFlow::ResetPassword(reset) => local.reset_password(&conn, reset),
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(response::ProviderFlow::from(response)))
+}
+
+enum LocalFlow {
+    Register(local::Register),
+    Login(local::Login),
+    RequestPasswordReset(local::RequestPasswordReset),
+    ResetPassword(local::ResetPassword),
+}
+
+fn parse_flow_payload<T: serde::de::DeserializeOwned>(
+    payload: serde_json::Value,
+) -> Result<T, InvalidFlowPayload> {
+    Ok(serde_json::from_value(payload)?)
+}
+
+/// Creates an API token, this can't be used with an API token.
+/// The token is only included in this response.
+async fn create_api_token(
+    db_pool: web::Data<PgPool>,
+    body: web::Json<request::CreateApiToken>,
+    user: AuthGuard,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let body = body.into_inner();
+
+    let pool = db_pool.clone();
+    let conn = web::block(move || pool.get()).await??;
+    let (token, record) = web::block(move || {
+        api_token::create_token(
+            &conn,
+            user_id,
+            body.name,
+            &body.scopes,
+            body.expires_in_days,
+        )
+    })
+    .await??;
+
+    audit::record(
+        &db_pool,
+        InsertableAuditEntry::new(Some(user_id), actions::CREATE_API_TOKEN)
+            .with_target(user_target(user_id))
+            .with_metadata(json!({ "token_id": record.id, "scopes": record.scopes })),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(response::CreatedApiToken {
+        token,
+        details: record.into(),
+    }))
+}
+
+/// Lists the user's API tokens including those that have expired or been revoked.
+async fn list_api_tokens(db_pool: web::Data<PgPool>, user: AuthGuard) -> EndpointResult {
+    let user_id = user.id_required()?;
+
+    let conn = web::block(move || db_pool.get()).await??;
+    let tokens = web::block(move || doxa_db::action::api_token::list_tokens(&conn, user_id))
+        .await??
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response::ApiTokens { tokens }))
+}
+
+async fn revoke_api_token(
+    db_pool: web::Data<PgPool>,
+    path: web::Path<i32>,
+    user: AuthGuard,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let id = path.into_inner();
+
+    let pool = db_pool.clone();
+    let conn = web::block(move || pool.get()).await??;
+    let token = web::block(move || api_token::revoke_token(&conn, user_id, id)).await??;
+
+    audit::record(
+        &db_pool,
+        InsertableAuditEntry::new(Some(user_id), actions::REVOKE_API_TOKEN)
+            .with_target(user_target(user_id))
+            .with_metadata(json!({ "token_id": token.id })),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(response::ApiTokenDetails::from(token)))
+}
+
+/// Logs the user out everywhere by rotating their token generation and revoking their delegated
+/// sessions, API tokens are left alone.
+async fn revoke_sessions(
+    db_pool: web::Data<PgPool>,
+    delegated_auth: web::Data<DelegatedAuthManager>,
+    user: AuthGuard,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+
+    let conn = web::block(move || db_pool.get()).await??;
+    let revoked =
+        web::block(move || session::revoke_sessions(&conn, user_id, Some(user_id), false, "api"))
+            .await??;
+
+    let delegated_sessions_revoked = delegated_auth.revoke_all_sessions(user_id).await?;
+    delegated_auth.clear_cached_revocations(user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response::SessionsRevoked {
+        tokens_revoked_at: revoked.user.tokens_revoked_at,
+        api_tokens_revoked: revoked.api_tokens_revoked,
+        delegated_sessions_revoked,
+    }))
+}
+
+/// Lists the user's delegated sessions, excluding those that were created before their token
+/// generation was last rotated (e.g. with `doxa_adm`) as those have already been revoked.
+async fn delegated_sessions_response(
+    db_pool: web::Data<PgPool>,
+    delegated_auth: &DelegatedAuthManager,
+    user_id: i32,
+) -> EndpointResult {
+    let conn = web::block(move || db_pool.get()).await??;
+    let user = web::block(move || action::user::get_user_by_id(&conn, user_id)).await??;
+
+    let sessions = delegated_auth
+        .list_sessions(user_id)
+        .await?
+        .into_iter()
+        .filter(|delegated| !session::is_revoked(&user, Some(delegated.created_at)))
+        .map(Into::into)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response::DelegatedSessions { sessions }))
+}
+
+async fn list_delegated_sessions(
+    db_pool: web::Data<PgPool>,
+    delegated_auth: web::Data<DelegatedAuthManager>,
+    user: AuthGuard,
+) -> EndpointResult {
+    delegated_sessions_response(db_pool, &delegated_auth, user.id_required()?).await
+}
+
+async fn revoke_delegated_session(
+    db_pool: web::Data<PgPool>,
+    delegated_auth: web::Data<DelegatedAuthManager>,
+    path: web::Path<String>,
+    user: AuthGuard,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+
+    let delegated = delegated_auth
+        .revoke_session(user_id, &path.into_inner())
+        .await?;
+
+    let conn = web::block(move || db_pool.get()).await??;
+    let session_id = delegated.id.clone();
+    web::block(move || session::revoke_delegated_session_access(&conn, user_id, &session_id))
+        .await??;
+    delegated_auth.clear_cached_revocations(user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response::DelegatedSession::from(delegated)))
+}
+
+async fn get_user_id_by_username(
+    db_pool: &web::Data<PgPool>,
+    username: String,
+) -> Result<i32, RespondableErrorWrapper> {
+    let pool = db_pool.clone();
+    let conn = web::block(move || pool.get()).await??;
+    let user = web::block(move || action::user::get_user_by_username(&conn, &username))
+        .await??
+        .ok_or(UserNotFound)?;
+
+    Ok(user.id)
+}
+
+async fn admin_list_delegated_sessions(
+    db_pool: web::Data<PgPool>,
+    delegated_auth: web::Data<DelegatedAuthManager>,
+    path: web::Path<String>,
+    _admin: AuthGuard<Admin>,
+) -> EndpointResult {
+    let user_id = get_user_id_by_username(&db_pool, path.into_inner()).await?;
+
+    delegated_sessions_response(db_pool, &delegated_auth, user_id).await
+}
+
+/// Forcibly revokes every session and API token of the user, e.g. if their account has been
+/// compromised.
+async fn admin_revoke_sessions(
+    db_pool: web::Data<PgPool>,
+    delegated_auth: web::Data<DelegatedAuthManager>,
+    path: web::Path<String>,
+    admin: AuthGuard<Admin>,
+) -> EndpointResult {
+    let user_id = get_user_id_by_username(&db_pool, path.into_inner()).await?;
+    let actor = admin.id();
+
+    let conn = web::block(move || db_pool.get()).await??;
+    let revoked =
+        web::block(move || session::revoke_sessions(&conn, user_id, actor, true, "api")).await??;
+
+    let delegated_sessions_revoked = delegated_auth.revoke_all_sessions(user_id).await?;
+    delegated_auth.clear_cached_revocations(user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response::SessionsRevoked {
+        tokens_revoked_at: revoked.user.tokens_revoked_at,
+        api_tokens_revoked: revoked.api_tokens_revoked,
+        delegated_sessions_revoked,
+    }))
+}
+
+/// Lists the audit log newest first, see [`request::AuditLogQuery`] for the filters.
+async fn admin_audit_log(
+    db_pool: web::Data<PgPool>,
+    query: web::Query<request::AuditLogQuery>,
+    _admin: AuthGuard<Admin>,
+) -> EndpointResult {
+    let query = query.into_inner();
+
+    let limit = query.limit.unwrap_or(request::DEFAULT_AUDIT_LOG_LIMIT);
+    if limit < 1 || limit > request::MAX_AUDIT_LOG_LIMIT {
+        return Err(InvalidAuditLogLimit.into());
+    }
+
+    let actor = match query.actor {
+        Some(username) => Some(get_user_id_by_username(&db_pool, username).await?),
+        None => None,
+    };
+
+    let filter = AuditLogFilter {
+        actor,
+        action: query.action,
+        target: query.target,
+        since: query.since,
+        until: query.until,
+        before_id: query.before,
+    };
+
+    let conn = web::block(move || db_pool.get()).await??;
+    let entries = web::block(move || action::audit::list_entries(&conn, &filter, limit))
+        .await??
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response::AuditLog { entries }))
+}
@@ -1,6 +1,10 @@
+use doxa_core::chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+pub(crate) const DEFAULT_AUDIT_LOG_LIMIT: i64 = 100;
+pub(crate) const MAX_AUDIT_LOG_LIMIT: i64 = 1000;
+
 #[derive(Deserialize)]
 pub(crate) struct Provider {
     pub provider_name: String,
@@ -28,3 +32,30 @@ pub(crate) struct AuthorizeDelegated {
 pub(crate) struct Authorize {
     pub refresh_token: String,
 }
+
+#[derive(Deserialize)]
+pub(crate) struct CreateApiToken {
+    pub name: String,
+    /// e.g. `["upload:uttt", "activate:uttt"]`, see [`crate::api_token::TokenScope`]
+    pub scopes: Vec<String>,
+    /// Defaults to [`crate::api_token::DEFAULT_API_TOKEN_EXPIRY_DAYS`]
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AuditLogQuery {
+    /// The username of the user that performed the action
+    pub actor: Option<String>,
+    /// e.g. `agent.activate`, see [`doxa_db::model::audit::actions`]
+    pub action: Option<String>,
+    /// e.g. `user:12`, `agent:{id}` or `competition:{name}`
+    pub target: Option<String>,
+    /// RFC 3339 timestamps, `since` is inclusive and `until` is exclusive
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only entries with an ID less than this, pass the ID of the last entry to get the next page
+    pub before: Option<i64>,
+    /// Defaults to [`DEFAULT_AUDIT_LOG_LIMIT`]
+    pub limit: Option<i64>,
+}
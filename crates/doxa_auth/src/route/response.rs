@@ -1,6 +1,11 @@
+use doxa_core::chrono::{DateTime, Utc};
+use doxa_db::model::{api_token::ApiToken, audit::AuditEntry};
+
 use serde::Serialize;
 use serde_json::Value;
 
+use crate::local::LocalFlowResponse;
+
 #[derive(Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -15,6 +20,18 @@ pub(crate) enum ProviderFlow {
     },
 }
 
+impl From<LocalFlowResponse> for ProviderFlow {
+    fn from(response: LocalFlowResponse) -> Self {
+        match response {
+            LocalFlowResponse::Authenticated { refresh_token } => ProviderFlow::Authenticated {
+                auth_token: refresh_token.clone(),
+                refresh_token,
+            },
+            LocalFlowResponse::Incomplete { payload } => ProviderFlow::Incomplete { payload },
+        }
+    }
+}
+
 #[derive(Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -26,3 +43,99 @@ pub(crate) enum DelegatedAuthCheck {
     },
     Waiting,
 }
+
+#[derive(Serialize)]
+pub(crate) struct ApiTokenDetails {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiToken> for ApiTokenDetails {
+    fn from(token: ApiToken) -> Self {
+        ApiTokenDetails {
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+            revoked_at: token.revoked_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CreatedApiToken {
+    /// Only shown once, it can't be retrieved again
+    pub token: String,
+    #[serde(flatten)]
+    pub details: ApiTokenDetails,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ApiTokens {
+    pub tokens: Vec<ApiTokenDetails>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DelegatedSession {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<crate::delegated::DelegatedSession> for DelegatedSession {
+    fn from(session: crate::delegated::DelegatedSession) -> Self {
+        DelegatedSession {
+            id: session.id,
+            created_at: session.created_at,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct DelegatedSessions {
+    pub sessions: Vec<DelegatedSession>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SessionsRevoked {
+    /// Tokens from logging in issued before this time are rejected
+    pub tokens_revoked_at: Option<DateTime<Utc>>,
+    pub api_tokens_revoked: usize,
+    pub delegated_sessions_revoked: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct AuditLogEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    pub actor: Option<i32>,
+    pub action: String,
+    pub target: Option<String>,
+    pub metadata: Value,
+}
+
+impl From<AuditEntry> for AuditLogEntry {
+    fn from(entry: AuditEntry) -> Self {
+        AuditLogEntry {
+            id: entry.id,
+            created_at: entry.created_at,
+            actor: entry.actor,
+            action: entry.action,
+            target: entry.target,
+            metadata: entry.metadata,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct AuditLog {
+    pub entries: Vec<AuditLogEntry>,
+}
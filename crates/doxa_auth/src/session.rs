@@ -0,0 +1,211 @@
+//! Revoking the sessions created by logging in.
+//!
+//! Tokens from logging in are JWTs issued by the auth provider so they can't be invalidated
+//! directly, instead rotating a user's token generation records the time it happened and any token
+//! issued before that time is rejected (see [`is_revoked`]).
+//! Sessions created through delegated auth (e.g. by the CLI) are recorded in redis so they can
+//! also be listed and revoked individually. Access tokens can't be traced back to the session they
+//! were issued for so revoking a single session also revokes all of the user's access tokens,
+//! other sessions can exchange their refresh tokens for new ones.
+//!
+//! `POST /api/auth/sessions/revoke` logs the user out everywhere. Delegated sessions are listed
+//! with `GET /api/auth/sessions/delegated` and revoked with
+//! `POST /api/auth/sessions/delegated/{id}/revoke`, after which the user's existing access tokens
+//! are rejected with `ACCESS_TOKEN_REVOKED`. Admins can list a user's sessions with
+//! `GET /api/admin/users/{username}/sessions` and revoke all of their sessions and API tokens with
+//! `POST /api/admin/users/{username}/revoke_sessions` or
+//! `doxa_adm user revoke-sessions <username>`.
+//!
+//! Revocation times are cached for 30 seconds, so revocations from `doxa_adm` or a password reset
+//! can take that long to reach access tokens.
+
+use diesel::{Connection, PgConnection};
+use doxa_core::chrono::{DateTime, TimeZone, Utc};
+use doxa_db::{
+    action,
+    model::{
+        audit::{actions, user_target, InsertableAuditEntry},
+        user::User,
+    },
+    DieselError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::controller::new_token_generation;
+
+#[derive(Deserialize)]
+struct TokenClaims {
+    iat: Option<i64>,
+    exp: Option<i64>,
+}
+
+/// Reads the claims of a JWT without verifying it, this should only be used for tokens that
+/// have already been verified.
+fn token_claims(token: &str) -> Option<TokenClaims> {
+    let claims = token.split('.').nth(1)?;
+    let claims =
+        base64::decode_config(claims.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()?;
+
+    serde_json::from_slice(&claims).ok()
+}
+
+/// When the (already verified) token was issued.
+pub fn token_issued_at(token: &str) -> Option<DateTime<Utc>> {
+    token_claims(token)?.iat.map(|iat| Utc.timestamp(iat, 0))
+}
+
+/// When the (already verified) token expires.
+pub fn token_expires_at(token: &str) -> Option<DateTime<Utc>> {
+    token_claims(token)?.exp.map(|exp| Utc.timestamp(exp, 0))
+}
+
+/// Whether a token for the user issued at `issued_at` has been revoked by rotating their token
+/// generation.
+/// Issue times only have second precision so tokens issued in the same second as the revocation
+/// are kept, otherwise logging in straight after (e.g. after resetting a password) would fail.
+/// Tokens without an issue time can't be checked so they are only rejected once they expire.
+pub fn is_revoked(user: &User, issued_at: Option<DateTime<Utc>>) -> bool {
+    issued_before(issued_at, user.tokens_revoked_at)
+}
+
+fn issued_before(issued_at: Option<DateTime<Utc>>, revoked_at: Option<DateTime<Utc>>) -> bool {
+    match (revoked_at, issued_at) {
+        (Some(revoked_at), Some(issued_at)) => issued_at.timestamp() < revoked_at.timestamp(),
+        _ => false,
+    }
+}
+
+/// Why an access token is no longer valid, see [`check_access_token_revoked`].
+pub enum AccessTokenRevocation {
+    /// The user logged out everywhere
+    Session,
+    /// One of the user's delegated sessions was revoked, the token can be replaced by exchanging a
+    /// refresh token
+    AccessTokens,
+}
+
+/// When the user's tokens were last revoked, this is all that is needed to check their access
+/// tokens so it is cached in the delegated auth backend (see
+/// [`crate::delegated::REVOCATIONS_CACHE_LIFE`]) rather than loading the user for every request.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Revocations {
+    pub tokens_revoked_at: Option<DateTime<Utc>>,
+    pub access_tokens_revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<&User> for Revocations {
+    fn from(user: &User) -> Self {
+        Revocations {
+            tokens_revoked_at: user.tokens_revoked_at,
+            access_tokens_revoked_at: user.access_tokens_revoked_at,
+        }
+    }
+}
+
+/// The user's revocations, nothing has been revoked for users that don't exist.
+pub fn get_revocations(conn: &PgConnection, user_id: i32) -> Result<Revocations, DieselError> {
+    Ok(action::user::get_user_by_id_optional(conn, user_id)?
+        .map(|user| Revocations::from(&user))
+        .unwrap_or_default())
+}
+
+/// Checks whether the (already verified) access token for the user has been revoked, in the same
+/// way as [`is_revoked`].
+pub fn check_access_token_revoked(
+    revocations: &Revocations,
+    token: &str,
+) -> Option<AccessTokenRevocation> {
+    let issued_at = token_issued_at(token);
+
+    if issued_before(issued_at, revocations.tokens_revoked_at) {
+        Some(AccessTokenRevocation::Session)
+    } else if issued_before(issued_at, revocations.access_tokens_revoked_at) {
+        Some(AccessTokenRevocation::AccessTokens)
+    } else {
+        None
+    }
+}
+
+/// Checks whether the (already verified) token from logging in for the user has been revoked.
+pub fn check_token_revoked(
+    conn: &PgConnection,
+    user_id: i32,
+    token: &str,
+) -> Result<bool, DieselError> {
+    Ok(action::user::get_user_by_id_optional(conn, user_id)?
+        .map(|user| is_revoked(&user, token_issued_at(token)))
+        .unwrap_or(false))
+}
+
+/// Revokes the user's access tokens after the delegated session `session` was revoked (see
+/// [`crate::delegated::DelegatedAuthManager::revoke_session`]) so that the session can't be used
+/// until its current access token expires.
+pub fn revoke_delegated_session_access(
+    conn: &PgConnection,
+    user_id: i32,
+    session: &str,
+) -> Result<(), DieselError> {
+    conn.transaction(|| {
+        action::user::revoke_access_tokens(conn, user_id, Utc::now())?;
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(Some(user_id), actions::REVOKE_DELEGATED_SESSION)
+                .with_target(user_target(user_id))
+                .with_metadata(json!({ "session": session })),
+        )?;
+
+        Ok(())
+    })
+}
+
+pub struct RevokedSessions {
+    pub user: User,
+    pub api_tokens_revoked: usize,
+}
+
+/// Rotates the user's token generation which logs them out everywhere, when `revoke_api_tokens`
+/// is set all of the user's API tokens are revoked as well (e.g. for a compromised account).
+/// `actor` is the user that performed the revocation (`None` for the system account or
+/// `doxa_adm`) and `source` is recorded in the audit log.
+pub fn revoke_sessions(
+    conn: &PgConnection,
+    user_id: i32,
+    actor: Option<i32>,
+    revoke_api_tokens: bool,
+    source: &str,
+) -> Result<RevokedSessions, DieselError> {
+    conn.transaction(|| {
+        let now = Utc::now();
+        let user =
+            action::user::rotate_token_generation(conn, user_id, new_token_generation(), now)?;
+
+        let api_tokens_revoked = if revoke_api_tokens {
+            action::api_token::revoke_all_tokens(conn, user_id, now)?
+        } else {
+            0
+        };
+
+        let audit_action = if revoke_api_tokens {
+            actions::FORCE_REVOKE_SESSIONS
+        } else {
+            actions::REVOKE_SESSIONS
+        };
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(actor, audit_action)
+                .with_target(user_target(user_id))
+                .with_metadata(json!({
+                    "api_tokens_revoked": api_tokens_revoked,
+                    "source": source,
+                })),
+        )?;
+
+        Ok(RevokedSessions {
+            user,
+            api_tokens_revoked,
+        })
+    })
+}
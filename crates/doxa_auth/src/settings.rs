@@ -1,14 +1,15 @@
 use std::sync::Arc;
 
-use doxa_core::redis::RedisPool;
-
-use crate::AuthaClient;
+use crate::{backend::DelegatedAuthBackend, provider::AuthProvider};
 
 #[derive(Clone)]
 pub struct Settings {
     pub allow_registration: bool,
-    pub autha_client: Arc<AuthaClient>,
-    pub redis_db: RedisPool,
+    pub auth_provider: AuthProvider,
+    pub delegated_auth_backend: Arc<dyn DelegatedAuthBackend>,
     pub delegated_auth_url: url::Url,
     pub system_account_secret: String,
+    /// Whether the client IP is taken from the `Forwarded` or `X-Forwarded-For` headers, only
+    /// enable this when the server is behind a reverse proxy that appends to them.
+    pub trust_proxy_headers: bool,
 }
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use flate2::{write::GzEncoder, Compression};
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
@@ -11,7 +11,7 @@ use tokio_util::codec::{BytesCodec, FramedRead};
 
 use crate::{
     error::{CommandError, UploadError},
-    request::{post, send_request_and_parse, Settings},
+    request::{get, post, send_request_and_parse, Settings},
     ui,
 };
 
@@ -31,6 +31,21 @@ pub struct UploadArgs {
     agent_path: PathBuf,
 }
 
+#[derive(Deserialize)]
+struct LimitStatusResponse {
+    buckets: Vec<BucketStatus>,
+}
+
+#[derive(Deserialize)]
+struct BucketStatus {
+    limit: u32,
+    remaining: u32,
+    /// The length of the window in seconds
+    window: u64,
+    /// Seconds until the permits are replenished, 0 if none have been used
+    reset: u64,
+}
+
 #[derive(Deserialize)]
 struct UploadResponse {
     competition: String,
@@ -61,6 +76,8 @@ pub async fn upload(args: UploadArgs, settings: &Settings) -> Result<(), Command
         .unwrap()
         .to_owned();
 
+    check_upload_limits(settings, &competition_name).await?;
+
     let total_steps = 4;
     ui::print_step(1, total_steps, "Finding the agent");
 
@@ -182,12 +199,18 @@ pub async fn upload(args: UploadArgs, settings: &Settings) -> Result<(), Command
         e
     })?;
 
+    // There isn't a logged in user when uploading with an API token
+    let congratulations = match (&settings.api_token, &settings.user_profile) {
+        (None, Ok(profile)) => format!("Congratulations {}, you", ui::keyword(&profile.name)),
+        _ => "Congratulations, you".to_string(),
+    };
+
     ui::print_step(
         4,
         total_steps,
         format!(
-            "Congratulations {}, you successfully uploaded an agent to competition {} and it was given the id {}",
-            ui::keyword(&settings.user_profile.as_ref().expect("to successfully upload an agent the user has to be logged in").name),
+            "{} successfully uploaded an agent to competition {} and it was given the id {}",
+            congratulations,
             ui::keyword(response.competition),
             ui::keyword(response.id),
         ),
@@ -195,3 +218,52 @@ pub async fn upload(args: UploadArgs, settings: &Settings) -> Result<(), Command
 
     Ok(())
 }
+
+/// Warns the user when they are about to use their last upload and stops them from uploading when
+/// they have none left (uploads and activations share the same limits).
+/// The check is skipped if the limits can't be fetched (e.g. for older servers).
+async fn check_upload_limits(settings: &Settings, competition: &str) -> Result<(), UploadError> {
+    let status: LimitStatusResponse = match get(
+        settings,
+        &format!("competition/{}/_limits", competition),
+        false,
+    )
+    .await
+    {
+        Ok(builder) => match send_request_and_parse(builder).await {
+            Ok(status) => status,
+            Err(_) => return Ok(()),
+        },
+        Err(_) => return Ok(()),
+    };
+
+    let bucket = match status
+        .buckets
+        .iter()
+        .min_by(|a, b| a.remaining.cmp(&b.remaining).then(b.reset.cmp(&a.reset)))
+    {
+        Some(bucket) => bucket,
+        None => return Ok(()),
+    };
+
+    // Limits that only allow a single upload (e.g. 1 per minute) aren't worth warning about
+    match bucket.remaining {
+        0 => Err(UploadError::NoUploadsLeft(bucket.reset)),
+        1 if bucket.limit > 1 => {
+            let reset = if bucket.reset > 0 {
+                bucket.reset
+            } else {
+                bucket.window
+            };
+
+            ui::warn(format!(
+                "This is your last upload (or activation) for {} in the next {}",
+                ui::keyword(competition),
+                HumanDuration(Duration::from_secs(reset))
+            ));
+
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use clap::Subcommand;
+use clap::{Parser, Subcommand};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
@@ -21,6 +21,17 @@ pub enum AuthCommands {
     //Register,
     /// Shows info about the currently logged in user
     Info,
+    /// Enrolls in a competition using an invite code, or without one if the competition has open
+    /// enrollment
+    Enroll(EnrollArgs),
+}
+
+#[derive(Parser)]
+pub struct EnrollArgs {
+    /// The name of the competition to enroll in
+    competition: String,
+    /// The invite code from the competition's organisers (e.g. `K7PQ2-WX9RD`)
+    code: Option<String>,
 }
 
 // TODO: allow using the old username/password auth
@@ -61,9 +72,42 @@ pub async fn handle_subcommand(
         AuthCommands::Login => login(settings).await,
         //AuthCommands::Register => register(settings).await,
         AuthCommands::Info => info(settings).await,
+        AuthCommands::Enroll(args) => enroll(args, settings).await,
     }
 }
 
+#[derive(Serialize)]
+struct EnrollRequest {
+    code: Option<String>,
+}
+
+pub async fn enroll(args: EnrollArgs, settings: &Settings) -> Result<(), CommandError> {
+    let total_steps = 1;
+    ui::print_step(
+        1,
+        total_steps,
+        format!("Enrolling in {}", ui::keyword(&args.competition)),
+    );
+
+    let _: serde_json::Value = send_request_and_parse(
+        post(
+            settings,
+            &format!("competition/{}/_enroll", args.competition),
+            false,
+        )
+        .await?
+        .json(&EnrollRequest { code: args.code }),
+    )
+    .await?;
+
+    ui::success(format!(
+        "You are now enrolled in {}",
+        ui::keyword(&args.competition)
+    ));
+
+    Ok(())
+}
+
 #[derive(Deserialize)]
 struct Info {
     username: String,
@@ -109,6 +109,11 @@ pub enum UploadError {
     MissingExecutionConfig,
     #[display(fmt = "agents must have an extension of either .tar or .tar.gz")]
     IncorrectExtension,
+    #[display(
+        fmt = "you have no uploads left, please try again after {}",
+        "HumanDuration(Duration::from_secs(*_0))"
+    )]
+    NoUploadsLeft(#[error(not(source))] u64),
 }
 
 #[derive(Display, Error, Debug, Clone, From)]
@@ -2,7 +2,7 @@ use clap::Parser;
 use cli::Cli;
 use error::{CliError, CommandError, RequestError};
 
-use crate::request::{parse_base_url, Settings};
+use crate::request::{parse_base_url, Settings, API_TOKEN_ENV_VAR};
 
 pub mod config;
 pub mod error;
@@ -20,9 +20,16 @@ async fn main() {
     if let Err(e) = run(args).await {
         if let CliError::Command(CommandError::Request(RequestError::Doxa(doxa))) = &e {
             if doxa.error_code == "INVALID_TOKEN" {
-                ui::error(
-                    "Please login again, your authentication token is invalid or has expired",
-                );
+                if std::env::var(API_TOKEN_ENV_VAR).is_ok() {
+                    ui::error(format!(
+                        "The API token in {} is invalid, has been revoked or has expired",
+                        API_TOKEN_ENV_VAR
+                    ));
+                } else {
+                    ui::error(
+                        "Please login again, your authentication token is invalid or has expired",
+                    );
+                }
 
                 if !verbose {
                     return;
@@ -60,11 +67,15 @@ async fn run(args: Cli) -> Result<(), CliError> {
 
     let user_profile = profiles.default_profile()?;
 
+    let api_token = std::env::var(API_TOKEN_ENV_VAR)
+        .ok()
+        .filter(|token| !token.is_empty());
+
     let base_url = parse_base_url(&base_url)?;
 
     let verbose = args.verbose;
 
-    let settings = Settings::new(user_profile, base_url, config_dir, verbose);
+    let settings = Settings::new(user_profile, api_token, base_url, config_dir, verbose);
 
     match args.command {
         cli::MainCommands::User(auth) => command::auth::handle_subcommand(auth, &settings).await?,
@@ -12,8 +12,14 @@ use crate::{
     },
 };
 
+/// The environment variable that an API token (e.g. for use in CI) can be given with, when set this
+/// is used instead of the logged in user.
+pub const API_TOKEN_ENV_VAR: &str = "DOXA_TOKEN";
+
 pub struct Settings {
     pub user_profile: Result<UserProfile, NoDefaultUserProfile>,
+    /// From [`API_TOKEN_ENV_VAR`]
+    pub api_token: Option<String>,
     pub base_url: Url,
     pub config_dir: PathBuf,
     pub client: Client,
@@ -25,12 +31,14 @@ static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_P
 impl Settings {
     pub fn new(
         user_profile: Result<UserProfile, NoDefaultUserProfile>,
+        api_token: Option<String>,
         base_url: Url,
         config_dir: PathBuf,
         verbose: bool,
     ) -> Settings {
         Settings {
             user_profile,
+            api_token,
             base_url,
             client: Client::builder()
                 .user_agent(APP_USER_AGENT)
@@ -76,6 +84,8 @@ async fn maybe_add_auth(
 ) -> Result<RequestBuilder, AuthorizeError> {
     if never_auth {
         Ok(builder)
+    } else if let Some(api_token) = &settings.api_token {
+        Ok(builder.bearer_auth(api_token))
     } else if let Ok(user) = &settings.user_profile {
         let access_token = crate::token::authorize(user.auth_token.clone(), settings).await?;
 
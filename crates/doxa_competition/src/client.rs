@@ -1,6 +1,6 @@
 //! Contains methods useful for implementing a new competition
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use doxa_auth::limiter::{GenericLimiter, LimiterConfig};
 use doxa_core::actix_web::{self, web};
@@ -13,13 +13,16 @@ use crate::{
 };
 
 pub use crate::context::Context;
+pub use crate::guard::CompetitionRoleGuard;
 pub use async_trait::async_trait;
 pub use doxa_auth::limiter;
+pub use doxa_auth::role::CompetitionPermission;
 pub use doxa_db::model::storage::AgentUpload;
 pub use doxa_executor::client::{
-    ForfeitError, GameClient, GameContext, GameError, Mount, VMBackend,
+    rand, Checkpoint, DecodeError, ExecutionProfile, ForfeitError, GameClient, GameContext,
+    GameError, GameRng, Mount, VMBackend,
 };
-pub use doxa_mq::model::{ActivationEvent, GameEvent};
+pub use doxa_mq::model::{ActivationEvent, GameEvent, Priority};
 pub use serde_json;
 
 /// Returns true if the competition name is valid.
@@ -44,6 +47,10 @@ pub trait Competition: 'static + Send + Sync {
     /// See [`validate_competition_name`] for more info regarding allowed names.
     const COMPETITION_NAME: &'static str;
 
+    /// How long transcripts of agent input and output are kept before being deleted.
+    /// This defaults to two weeks, transcripts are only recorded if the game client enables them.
+    const TRANSCRIPT_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 14);
+
     // Maybe &mut self could be enforced as startup happens before everything else
     // could also be the case that startup returns Self.
     /// Runs exactly once at startup before all other functions
@@ -134,6 +141,16 @@ pub trait Competition: 'static + Send + Sync {
             web::get().to(route::game::game_events::<Self>),
         );
 
+        service.route(
+            "_game/{game_id}/logs/{agent_index}",
+            web::get().to(route::game::game_agent_logs::<Self>),
+        );
+
+        service.route(
+            "_game/{game_id}/transcript/{agent_index}",
+            web::get().to(route::game::game_agent_transcript::<Self>),
+        );
+
         service.route(
             "_game/{game_id}/players",
             web::get().to(route::game::game_players::<Self>),
@@ -148,6 +165,11 @@ pub trait Competition: 'static + Send + Sync {
             "_game/{game_id}/cancelled",
             web::get().to(route::game::game_cancelled::<Self>),
         );
+
+        service.route(
+            "_game/{game_id}/checkpoint",
+            web::post().to(route::game::game_checkpoint::<Self>),
+        );
     }
 
     /// This function should register the `/_agent/{agent_id}/...` routes.
@@ -252,11 +274,81 @@ pub trait Competition: 'static + Send + Sync {
         );
     }
 
-    /// This function registers the `/_upload`.
+    /// This function registers the `/_enroll`, `/_enrollment` and `/_invites/...` routes.
+    ///
+    /// If you want to customise this or disable this you can overwrite this function.
+    fn configure_enrollment_routes(&self, service: &mut actix_web::web::ServiceConfig) {
+        service.route("_enroll", web::post().to(route::enrollment::enroll::<Self>));
+
+        service.route(
+            "_enrollment",
+            web::get().to(route::enrollment::enrollment_status::<Self>),
+        );
+
+        service.route(
+            "_enrollment",
+            web::post().to(route::enrollment::set_open_enrollment::<Self>),
+        );
+
+        service.route(
+            "_invites",
+            web::get().to(route::enrollment::list_invites::<Self>),
+        );
+
+        service.route(
+            "_invites",
+            web::post().to(route::enrollment::create_invite::<Self>),
+        );
+
+        service.route(
+            "_invites/{invite_id}/revoke",
+            web::post().to(route::enrollment::revoke_invite::<Self>),
+        );
+    }
+
+    /// This function registers the `/_team` and `/_team/...` routes.
+    ///
+    /// If you want to customise this or disable this you can overwrite this function.
+    fn configure_team_routes(&self, service: &mut actix_web::web::ServiceConfig) {
+        service.route("_team", web::get().to(route::team::user_team::<Self>));
+
+        service.route("_team", web::post().to(route::team::create_team::<Self>));
+
+        service.route(
+            "_team/invite",
+            web::post().to(route::team::invite_to_team::<Self>),
+        );
+
+        service.route(
+            "_team/leave",
+            web::post().to(route::team::leave_team::<Self>),
+        );
+
+        service.route(
+            "_team/invites",
+            web::get().to(route::team::list_team_invites::<Self>),
+        );
+
+        service.route(
+            "_team/invites/{team_id}/accept",
+            web::post().to(route::team::accept_invite::<Self>),
+        );
+
+        service.route(
+            "_team/invites/{team_id}/decline",
+            web::post().to(route::team::decline_invite::<Self>),
+        );
+    }
+
+    /// This function registers the `/_upload` and `/_limits` routes.
     ///
     /// If you want to customise this or disable this you can overwrite this function.
     fn configure_upload_routes(&self, service: &mut actix_web::web::ServiceConfig) {
         service.route("_upload", web::post().to(route::upload::upload::<Self>));
+        service.route(
+            "_limits",
+            web::get().to(route::limits::limit_status::<Self>),
+        );
     }
 
     /// Builds a limiter to use for this competition for uploads and activations.
@@ -272,6 +364,8 @@ pub trait Competition: 'static + Send + Sync {
     /// Filter maps the events before sending them to a user.
     /// If the user was a participant in the current game then the ID if their agent will be
     /// provided in the agent field (this is the 0 indexed id).
+    /// `is_admin` is true for admins and for users with the
+    /// [`CompetitionPermission::ViewPrivate`] permission in this competition.
     /// This defaults to always returning None which will mean no events are sent to the user.
     fn event_filter(
         _game_event: <Self::GameClient as GameClient>::GameEvent,
@@ -310,8 +404,12 @@ pub(crate) trait CompetitionInner: 'static + Send + Sync {
         self: Arc<Self>,
         settings: Arc<Settings>,
         executor_permits: usize,
+        execution_profiles: Vec<ExecutionProfile>,
     ) -> Result<i32, CompetitionManagerError>;
 
+    /// The names of the profiles declared by the game client.
+    fn execution_profile_names(&self) -> Vec<String>;
+
     fn name(&self) -> &'static str;
 }
 
@@ -324,15 +422,22 @@ impl<T: Competition> CompetitionInner for T {
         competition_id: i32,
     ) {
         service.app_data(web::Data::new(Context::<T>::new(
-            settings.mq_pool.clone(),
+            settings.mq.clone(),
             settings.pg_pool.clone(),
             competition_id,
         )));
+        // Executors rely on this so it can't be overridden
+        service.route(
+            "_paused",
+            web::get().to(route::admin::competition_paused::<Self>),
+        );
         Competition::configure_game_routes(self, service);
         Competition::configure_agent_routes(self, service);
         Competition::configure_user_routes(self, service);
         Competition::configure_leaderboard_routes(self, service);
         Competition::configure_upload_routes(self, service);
+        Competition::configure_enrollment_routes(self, service);
+        Competition::configure_team_routes(self, service);
 
         Competition::configure_routes(self, service);
     }
@@ -341,8 +446,16 @@ impl<T: Competition> CompetitionInner for T {
         self: Arc<Self>,
         settings: Arc<Settings>,
         executor_permits: usize,
+        execution_profiles: Vec<ExecutionProfile>,
     ) -> Result<i32, CompetitionManagerError> {
-        CompetitionManager::start(self, settings, executor_permits).await
+        CompetitionManager::start(self, settings, executor_permits, execution_profiles).await
+    }
+
+    fn execution_profile_names(&self) -> Vec<String> {
+        <T::GameClient as GameClient>::execution_profiles()
+            .into_iter()
+            .map(|profile| profile.name)
+            .collect()
     }
 
     fn configure_upload_route(
@@ -1,41 +1,73 @@
 use std::{collections::HashSet, marker::PhantomData, ops::Deref, sync::Arc};
 
-use doxa_core::{chrono::Utc, tokio, tracing::debug};
+use doxa_core::{
+    chrono::{DateTime, Utc},
+    tokio,
+    tracing::{debug, error},
+};
 use doxa_db::{
-    diesel::PgConnection,
+    diesel::{Connection, PgConnection},
     model::{
-        game::{Game, GameParticipant, GameParticipantUser, GameResult, InsertableGame},
+        audit::{AuditEntry, InsertableAuditEntry},
+        game::{
+            Game, GameMatchRequest, GameParticipant, GameParticipantUser, GameResult,
+            InsertableGame,
+        },
         leaderboard::LeaderboardScore,
-        storage::AgentUpload,
+        storage::{AgentOwner, AgentUpload},
+        team::Team,
         user::User,
     },
     DieselError, PgPool,
 };
-use doxa_executor::{client::GameClient, event::StartEvent};
+use doxa_executor::{
+    client::GameClient, context::EVENT_IDS_PER_RUN, event::StartEvent,
+    profile::find_execution_profile,
+};
 use doxa_mq::{
-    model::{ActivationEvent, GameEvent, MatchRequest},
-    MQPool,
+    model::{ActivationEvent, CancellationEvent, GameEvent, MatchRequest, Priority},
+    MQ,
 };
 
 use crate::{
     client::Competition,
-    error::{AgentNotActive, AgentNotFound, ContextError, ParseSystemMessageError},
+    error::{
+        AgentNotActive, AgentNotFound, ContextError, GameRunsExhausted, ParseSystemMessageError,
+        UnknownExecutionProfile,
+    },
 };
 
+/// Runs a query on a connection from the pool without blocking the async runtime, this is shared
+/// by [`Context::run_query`] and the state of routes that aren't tied to a single competition.
+pub(crate) async fn run_query_with_pool<
+    T: Send + 'static,
+    F: FnOnce(&PgConnection) -> Result<T, DieselError> + Send + 'static,
+>(
+    pg_pool: &Arc<PgPool>,
+    f: F,
+) -> Result<T, ContextError> {
+    let pool = pg_pool.clone();
+    let connection = tokio::task::spawn_blocking(move || pool.get()).await??;
+
+    tokio::task::spawn_blocking(move || f(&connection))
+        .await?
+        .map_err(ContextError::from)
+}
+
 // TODO: consider moving context methods in their own folders, this file is getting a bit unwieldy
 
 #[derive(Clone)]
 pub struct Context<C: Competition + ?Sized> {
-    mq_pool: Arc<MQPool>,
+    mq: MQ,
     pg_pool: Arc<PgPool>,
     competition: PhantomData<C>,
     competition_id: i32,
 }
 
 impl<C: Competition + ?Sized> Context<C> {
-    pub(crate) fn new(mq_pool: Arc<MQPool>, pg_pool: Arc<PgPool>, competition_id: i32) -> Self {
+    pub(crate) fn new(mq: MQ, pg_pool: Arc<PgPool>, competition_id: i32) -> Self {
         Context {
-            mq_pool,
+            mq,
             pg_pool,
             competition: PhantomData,
             competition_id,
@@ -48,15 +80,61 @@ impl<C: Competition + ?Sized> Context<C> {
         self.competition_id
     }
 
+    /// Whether an admin has paused the competition, executors don't take any more match requests
+    /// while it is paused but match requests can still be emitted.
+    pub async fn is_paused(&self) -> Result<bool, ContextError> {
+        let competition = self.competition_id;
+        let competition = self
+            .run_query(move |conn| {
+                doxa_db::action::competition::get_competition_by_id(conn, competition)
+            })
+            .await?;
+
+        Ok(competition
+            .map(|competition| competition.paused)
+            .unwrap_or(false))
+    }
+
     /// This will create the game record in the database and then emit the match request event.
     ///
     /// The `GameClient` will recieve the match_request on initialization.
+    /// Games needed to place a newly activated agent should use [`Priority::Placement`] so that
+    /// they aren't held up by less urgent games.
+    ///
+    /// `execution_profile` must be one of the profiles declared by
+    /// [`GameClient::execution_profiles`], typically this is the `execution_environment` of the
+    /// agents.
     pub async fn emit_match_request(
         &self,
         agents: Vec<String>,
         match_request: <C::GameClient as GameClient>::MatchRequest,
         execution_profile: &str,
+        priority: Priority,
+    ) -> Result<(), ContextError> {
+        self.emit_seeded_match_request(agents, match_request, execution_profile, priority, None)
+            .await
+    }
+
+    /// The same as [`Context::emit_match_request`] but with a fixed seed for the game's RNG.
+    /// If the seed is `None` then one will be generated when the game starts.
+    ///
+    /// A game can be replayed by using the seed recorded in its `_START` event (see
+    /// [`Context::get_start_event`]).
+    pub async fn emit_seeded_match_request(
+        &self,
+        agents: Vec<String>,
+        match_request: <C::GameClient as GameClient>::MatchRequest,
+        execution_profile: &str,
+        priority: Priority,
+        seed: Option<u64>,
     ) -> Result<(), ContextError> {
+        if find_execution_profile::<C::GameClient>(execution_profile).is_none() {
+            return Err(UnknownExecutionProfile {
+                execution_profile: execution_profile.to_string(),
+            }
+            .into());
+        }
+
         let db = self.db_connection().await?;
         let competition = self.competition_id;
         let game = tokio::task::spawn_blocking::<_, Result<_, DieselError>>({
@@ -86,31 +164,70 @@ impl<C: Competition + ?Sized> Context<C> {
         })
         .await??;
 
-        let connection = self.mq_pool.get().await?;
-
         let match_request = MatchRequest {
             agents,
             payload: match_request,
             game_id: game.id,
+            seed,
+            attempt: 0,
         };
 
-        doxa_mq::action::emit_match_request(
-            &connection,
-            &match_request,
+        let queue = doxa_mq::action::match_request_queue_name(
             C::COMPETITION_NAME,
             execution_profile,
-        )
+            priority,
+        );
+        let data = doxa_mq::envelope::encode_match_request(
+            match_request,
+            <C::GameClient as GameClient>::MATCH_REQUEST_VERSION,
+        );
+
+        // Kept so that the game can be requeued if the execution node running it is lost
+        self.run_query({
+            let match_request = GameMatchRequest {
+                game: game.id,
+                queue: queue.clone(),
+                data: data.clone(),
+            };
+            move |conn| doxa_db::action::game::add_match_request(conn, &match_request)
+        })
         .await?;
 
+        self.mq.publish(&queue, data).await?;
+
         Ok(())
     }
 
+    /// Tells any executor that is running one of these games to stop it.
+    /// Executors also poll `_game/{game_id}/cancelled` so failing to publish the cancellation only
+    /// delays it, which is why errors are logged rather than returned.
+    pub(crate) async fn cancel_games(&self, game_ids: Vec<i32>) {
+        if game_ids.is_empty() {
+            return;
+        }
+
+        if let Err(error) = doxa_mq::action::emit_cancellation_event(
+            self.mq.as_ref(),
+            C::COMPETITION_NAME,
+            &CancellationEvent { game_ids },
+        )
+        .await
+        {
+            error!(%error, debug = ?error, "failed to emit cancellation event");
+        }
+    }
+
     /// Performs `nxn` pairwise matching.
     /// This should be run whenever a new agent has been uploaded.
-    /// This queue a match with all active agents uploaded after this one.
+    /// This queue a match with all active agents uploaded after this one, these are placement
+    /// games (see [`Priority::Placement`]).
     ///
     /// If `both_directions` is set to true then for every pair of agents two matches will be
-    /// created (a, b) and (b,a).
+    /// created (a, b) and (b,a). Only the first is needed to place the new agent, the reverse
+    /// games re-evaluate the pair so they are queued with [`Priority::Regular`].
+    ///
+    /// The games use the execution profile of the new agent, so it is only matched with agents that
+    /// use the same profile (a profile doesn't say whether it meets the needs of another one).
     pub async fn pair_matching<F: FnMut() -> <C::GameClient as GameClient>::MatchRequest>(
         &self,
         new_agent: String,
@@ -123,6 +240,7 @@ impl<C: Competition + ?Sized> Context<C> {
             .ok_or(AgentNotFound)?;
 
         let activated_at = agent.activated_at.ok_or(AgentNotActive)?;
+        let execution_profile = agent.execution_environment.clone();
 
         let active_agents = self
             .run_query(move |conn| {
@@ -137,10 +255,16 @@ impl<C: Competition + ?Sized> Context<C> {
         debug!(agents=?active_agents, before=%activated_at, agent_id=%new_agent, "agents activated before");
 
         for other_agent in active_agents {
+            if other_agent.execution_environment != execution_profile {
+                debug!(agent_id=%new_agent, other_agent_id=%other_agent.id, other_execution_profile=%other_agent.execution_environment, "not matching agents with different execution profiles");
+                continue;
+            }
+
             self.emit_match_request(
                 vec![new_agent.clone(), other_agent.id.clone()],
                 match_request_generator(),
-                "basic",
+                &execution_profile,
+                Priority::Placement,
             )
             .await?;
 
@@ -148,7 +272,8 @@ impl<C: Competition + ?Sized> Context<C> {
                 self.emit_match_request(
                     vec![other_agent.id.clone(), new_agent.clone()],
                     match_request_generator(),
-                    "basic",
+                    &execution_profile,
+                    Priority::Regular,
                 )
                 .await?;
             }
@@ -171,11 +296,7 @@ impl<C: Competition + ?Sized> Context<C> {
         &self,
         f: F,
     ) -> Result<T, ContextError> {
-        let connection = self.db_connection().await?;
-
-        tokio::task::spawn_blocking(move || f(&connection))
-            .await?
-            .map_err(ContextError::from)
+        run_query_with_pool(&self.pg_pool, f).await
     }
 
     /// Sets the score for a particular agent returning an error if it already has a score
@@ -277,6 +398,15 @@ impl<C: Competition + ?Sized> Context<C> {
             .await
     }
 
+    /// Appends an entry to the audit log, see [`doxa_db::model::audit::actions`].
+    pub async fn record_audit(
+        &self,
+        entry: InsertableAuditEntry,
+    ) -> Result<AuditEntry, ContextError> {
+        self.run_query(move |conn| doxa_db::action::audit::record(conn, &entry))
+            .await
+    }
+
     pub async fn get_user_by_id(&self, user_id: i32) -> Result<User, ContextError> {
         self.run_query(move |conn| doxa_db::action::user::get_user_by_id(conn, user_id))
             .await
@@ -315,12 +445,87 @@ impl<C: Competition + ?Sized> Context<C> {
         .await
     }
 
+    /// Returns the event of this type with the largest event ID.
+    pub async fn get_latest_event_by_type(
+        &self,
+        game_id: i32,
+        event_type: String,
+    ) -> Result<Option<GameEvent<serde_json::Value>>, ContextError> {
+        self.run_query(move |conn| {
+            doxa_db::action::game::get_latest_game_event_by_event_type(conn, game_id, event_type)
+                .map(|event| event.map(|event| event.into()))
+        })
+        .await
+    }
+
+    /// Returns the largest event ID of the game or `None` if it has no events yet.
+    pub async fn get_max_event_id(&self, game_id: i32) -> Result<Option<i32>, ContextError> {
+        self.run_query(move |conn| doxa_db::action::game::get_max_event_id(conn, game_id))
+            .await
+    }
+
+    /// Starts a run of a game, returning the first event ID the run should use or `None` if the
+    /// game doesn't exist.
+    ///
+    /// Each run `n` (starting from 0) uses the event IDs from `n * EVENT_IDS_PER_RUN` (see
+    /// [`doxa_executor::context::run_of_event`]) so that events which an earlier run left in the
+    /// queue (e.g. because its executor crashed) can't collide with the events of the new run.
+    ///
+    /// If `latest_run` is given the run is only started if that is still the game's latest run,
+    /// so that only one of several servers noticing the same interrupted run handles it.
+    ///
+    /// Once the first event ID of the next run would no longer fit in an `i32` this returns
+    /// [`GameRunsExhausted`] without starting a run.
+    pub async fn start_game_run(
+        &self,
+        game_id: i32,
+        latest_run: Option<u32>,
+    ) -> Result<Option<u32>, ContextError> {
+        let run = self
+            .run_query(move |conn| {
+                conn.transaction(|| {
+                    let game = match doxa_db::action::game::lock_game(conn, game_id)? {
+                        Some(game) => game,
+                        None => return Ok(None),
+                    };
+                    let runs = game.runs as u32;
+                    if latest_run.map_or(false, |run| Some(run) != runs.checked_sub(1)) {
+                        return Ok(None);
+                    }
+
+                    // A run could have gone past the end of its range so the next run may need to
+                    // skip some run numbers
+                    let next_event_id = doxa_db::action::game::get_max_event_id(conn, game_id)?
+                        .map(|event_id| event_id as u32 + 1)
+                        .unwrap_or(0);
+                    let run = runs.max((next_event_id + EVENT_IDS_PER_RUN - 1) / EVENT_IDS_PER_RUN);
+
+                    // Event IDs are stored as `i32`s
+                    let first_event_id = run
+                        .checked_mul(EVENT_IDS_PER_RUN)
+                        .and_then(|event_id| i32::try_from(event_id).ok());
+                    let runs = i32::try_from(run + 1).ok();
+                    let (first_event_id, runs) = match first_event_id.zip(runs) {
+                        Some(ids) => ids,
+                        None => return Ok(Some(Err(GameRunsExhausted { game_id }))),
+                    };
+                    doxa_db::action::game::set_game_runs(conn, game_id, runs)?;
+
+                    Ok(Some(Ok(first_event_id as u32)))
+                })
+            })
+            .await?;
+
+        Ok(run.transpose()?)
+    }
+
+    /// Returns the `_START` event of the game's latest run.
     pub async fn get_start_event(
         &self,
         game_id: i32,
     ) -> Result<Option<GameEvent<StartEvent>>, ContextError> {
         let event = self
-            .get_single_event_by_type(game_id, "_START".into())
+            .get_latest_event_by_type(game_id, "_START".into())
             .await?;
 
         Ok(event
@@ -340,6 +545,24 @@ impl<C: Competition + ?Sized> Context<C> {
             .await
     }
 
+    /// Deletes the transcripts of every game in this competition that were recorded before
+    /// `before`, returning the number that were deleted.
+    pub async fn delete_transcripts_before(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<usize, ContextError> {
+        let competition_id = self.competition_id;
+        self.run_query(move |conn| {
+            doxa_db::action::game::delete_competition_events_before(
+                conn,
+                competition_id,
+                "_TRANSCRIPT".to_string(),
+                before,
+            )
+        })
+        .await
+    }
+
     pub async fn get_agent(&self, agent: String) -> Result<Option<AgentUpload>, ContextError> {
         self.run_query(move |conn| doxa_db::action::storage::get_agent(conn, agent))
             .await
@@ -357,20 +580,44 @@ impl<C: Competition + ?Sized> Context<C> {
         Ok(agent.active)
     }
 
+    /// Who owns the agents that the user uploads to this competition, i.e. their team if they are
+    /// in one.
+    pub async fn get_agent_owner_for_user(&self, user_id: i32) -> Result<AgentOwner, ContextError> {
+        let competition_id = self.competition_id;
+        self.run_query(move |conn| {
+            doxa_db::action::team::agent_owner(conn, user_id, competition_id)
+        })
+        .await
+    }
+
+    /// Whether the user owns the agent, i.e. they uploaded it or it belongs to their team.
+    ///
+    /// Agents uploaded before the user joined a team are no longer theirs to manage.
+    pub async fn is_agent_owner(
+        &self,
+        user_id: i32,
+        agent: &AgentUpload,
+    ) -> Result<bool, ContextError> {
+        Ok(self.get_agent_owner_for_user(user_id).await? == agent.agent_owner())
+    }
+
+    /// Lists the agents uploaded by the user, or by anyone in their team if they are in one.
     pub async fn get_user_agents(&self, user_id: i32) -> Result<Vec<AgentUpload>, ContextError> {
         let competition_id = self.competition_id;
         self.run_query(move |conn| {
-            doxa_db::action::storage::list_agents(conn, user_id, competition_id)
+            let owner = doxa_db::action::team::agent_owner(conn, user_id, competition_id)?;
+            doxa_db::action::storage::list_agents(conn, owner, competition_id)
         })
         .await
     }
 
-    /// Gets a list of games which the user has participated where ALL of the agents involved are active
+    /// Gets a list of games which the user (or their team) has participated where ALL of the agents involved are active
     /// This is ordered by game start time ascending.
     pub async fn get_user_active_games(&self, user_id: i32) -> Result<Vec<Game>, ContextError> {
         let competition_id = self.competition_id;
         self.run_query(move |conn| {
-            doxa_db::action::game::get_user_active_games(conn, user_id, competition_id)
+            let owner = doxa_db::action::team::agent_owner(conn, user_id, competition_id)?;
+            doxa_db::action::game::get_user_active_games(conn, owner, competition_id)
         })
         .await
     }
@@ -384,13 +631,15 @@ impl<C: Competition + ?Sized> Context<C> {
             .await
     }
 
+    /// The active agent of the user, or of their team if they are in one.
     pub async fn get_active_agent(
         &self,
         user_id: i32,
     ) -> Result<Option<AgentUpload>, ContextError> {
         let competition_id = self.competition_id;
         self.run_query(move |conn| {
-            doxa_db::action::storage::get_active_agent(conn, user_id, competition_id)
+            let owner = doxa_db::action::team::agent_owner(conn, user_id, competition_id)?;
+            doxa_db::action::storage::get_active_agent(conn, owner, competition_id)
         })
         .await
     }
@@ -412,10 +661,12 @@ impl<C: Competition + ?Sized> Context<C> {
 
     /// Returns the list of active agents and their scores in descending order for this competition
     /// (only for those that exist in the leaderboard since an agent could be active but not yet on the leaderboard)
+    ///
+    /// The user is the uploader of the agent and the team is set for agents owned by a team.
     pub async fn get_leaderboard(
         &self,
         key: Option<String>,
-    ) -> Result<Vec<(User, LeaderboardScore, AgentUpload)>, ContextError> {
+    ) -> Result<Vec<(User, LeaderboardScore, AgentUpload, Option<Team>)>, ContextError> {
         let competition_id = self.competition_id;
         self.run_query(move |conn| {
             doxa_db::action::leaderboard::active_leaderboard(conn, competition_id, key)
@@ -620,9 +871,8 @@ impl<C: Competition + ?Sized> Context<C> {
     /// Adds the agent to the activation queue (this will automatically deactivate the previous
     /// agent before activating the new one including if the previous agent was this agent).
     pub async fn activate_agent(&self, agent: String) -> Result<(), ContextError> {
-        let connection = self.mq_pool.get().await?;
         doxa_mq::action::emit_activation_event(
-            &connection,
+            self.mq.as_ref(),
             &ActivationEvent {
                 agent,
                 activating: true,
@@ -637,9 +887,8 @@ impl<C: Competition + ?Sized> Context<C> {
     /// Adds the agent to the activation queue (this will automatically deactivate the previous
     /// agent before activating the new one including if the previous agent was this agent).
     pub async fn deactivate_agent(&self, agent: String) -> Result<(), ContextError> {
-        let connection = self.mq_pool.get().await?;
         doxa_mq::action::emit_activation_event(
-            &connection,
+            self.mq.as_ref(),
             &ActivationEvent {
                 agent,
                 activating: false,
@@ -1,19 +1,19 @@
 use derive_more::{Display, Error, From};
-use doxa_auth::create_rate_limit_error;
-use doxa_core::{
-    actix_web, deadpool_lapin, impl_respondable_error, lapin, tokio::task::JoinError,
-    RespondableError,
+use doxa_auth::{
+    create_rate_limit_error,
+    error::{CompetitionNotFound as CompetitionNotFoundAuth, UserNotEnrolled, UserNotFound},
 };
+use doxa_core::{actix_web, impl_respondable_error, tokio::task::JoinError, RespondableError};
 use doxa_db::{diesel::r2d2, DieselError};
+use doxa_executor::error::TranscriptDecodeError;
+use doxa_mq::MQError;
 
 #[derive(From, Error, Display, Debug, RespondableError)]
 /// A context error for a particular competition (not to be confused with the context error from an
 /// execution
 pub enum ContextError {
     #[from]
-    MessageQueue(lapin::Error),
-    #[from]
-    MessageQueuePool(deadpool_lapin::PoolError),
+    MessageQueue(MQError),
     #[from]
     DatabaseConnection(r2d2::PoolError),
     #[from]
@@ -28,6 +28,10 @@ pub enum ContextError {
     ParseSystemMessage(ParseSystemMessageError),
     #[from]
     StartEventNotFound(StartEventNotFound),
+    #[from]
+    UnknownExecutionProfile(UnknownExecutionProfile),
+    #[from]
+    GameRunsExhausted(GameRunsExhausted),
 }
 
 #[derive(From, Error, Display, Debug)]
@@ -85,6 +89,49 @@ impl_respondable_error!(
     "INTERNAL_SERVER_ERROR"
 );
 
+#[derive(Error, Display, Debug)]
+#[display(
+    fmt = "the execution profile `{}` is not declared by the game client",
+    execution_profile
+)]
+pub struct UnknownExecutionProfile {
+    pub execution_profile: String,
+}
+
+impl_respondable_error!(
+    UnknownExecutionProfile,
+    INTERNAL_SERVER_ERROR,
+    "INTERNAL_SERVER_ERROR"
+);
+
+#[derive(Error, Display, Debug)]
+#[display(
+    fmt = "game ID `{}` has been run too many times for the event IDs of another run to fit",
+    game_id
+)]
+pub struct GameRunsExhausted {
+    pub game_id: i32,
+}
+
+impl_respondable_error!(
+    GameRunsExhausted,
+    INTERNAL_SERVER_ERROR,
+    "INTERNAL_SERVER_ERROR"
+);
+
+#[derive(Error, Display, Debug)]
+#[display(fmt = "the drain timeout can't be longer than {} seconds", max_secs)]
+pub struct DrainTimeoutTooLong {
+    pub max_secs: u64,
+}
+
+impl_respondable_error!(
+    DrainTimeoutTooLong,
+    BAD_REQUEST,
+    "DRAIN_TIMEOUT_TOO_LONG",
+    "The drain timeout is longer than the maximum of a day"
+);
+
 #[derive(Debug, Display, Error)]
 pub struct UserNotOwner;
 
@@ -167,6 +214,34 @@ impl_respondable_error!(
     "INTERNAL_SERVER_ERROR"
 );
 
+#[derive(Error, Display, Debug)]
+#[display(
+    fmt = "a transcript could not be decoded (event id={}): {}",
+    event_id,
+    source
+)]
+pub struct InvalidTranscript {
+    pub source: TranscriptDecodeError,
+    pub event_id: i32,
+}
+
+impl_respondable_error!(
+    InvalidTranscript,
+    INTERNAL_SERVER_ERROR,
+    "INTERNAL_SERVER_ERROR"
+);
+
+#[derive(Error, Display, Debug)]
+#[display(fmt = "no transcript was recorded for this agent")]
+pub struct TranscriptNotFound;
+
+impl_respondable_error!(
+    TranscriptNotFound,
+    NOT_FOUND,
+    "TRANSCRIPT_NOT_FOUND",
+    "No transcript was recorded for this agent in this game, it may have expired"
+);
+
 #[derive(Error, Display, Debug)]
 pub struct IncorrectEventOrdering;
 
@@ -176,6 +251,32 @@ impl_respondable_error!(
     "INTERNAL_SERVER_ERROR"
 );
 
+#[derive(Error, Display, Debug)]
+#[display(fmt = "no competition is running with the name {}", name)]
+pub struct CompetitionNotFound {
+    pub name: String,
+}
+
+impl_respondable_error!(
+    CompetitionNotFound,
+    NOT_FOUND,
+    "COMPETITION_NOT_FOUND",
+    "No competition is running with that name"
+);
+
+#[derive(Error, Display, Debug)]
+#[display(fmt = "no execution node is registered with the name {}", name)]
+pub struct NodeNotRegistered {
+    pub name: String,
+}
+
+impl_respondable_error!(
+    NodeNotRegistered,
+    NOT_FOUND,
+    "NODE_NOT_REGISTERED",
+    "No execution node is registered with that name"
+);
+
 #[derive(Error, Display, Debug)]
 #[display(fmt = "the event type `{}` is not recognised", event_type)]
 pub struct UnknownEventType {
@@ -189,3 +290,123 @@ impl_respondable_error!(
 );
 
 create_rate_limit_error!(TooManyActivations, "There have been too many agent activations by your account to this competition, please wait and try again later (note: uploading an agent counts as an activation)");
+
+#[derive(Debug, Display, Error)]
+pub struct TeamsDisabled;
+
+impl_respondable_error!(
+    TeamsDisabled,
+    FORBIDDEN,
+    "TEAMS_DISABLED",
+    "Teams are not allowed in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct AlreadyInTeam;
+
+impl_respondable_error!(
+    AlreadyInTeam,
+    BAD_REQUEST,
+    "ALREADY_IN_TEAM",
+    "You are already in a team in this competition, you must leave it first"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct NotInTeam;
+
+impl_respondable_error!(
+    NotInTeam,
+    BAD_REQUEST,
+    "NOT_IN_TEAM",
+    "You are not in a team in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct UserInOtherTeam;
+
+impl_respondable_error!(
+    UserInOtherTeam,
+    BAD_REQUEST,
+    "USER_IN_OTHER_TEAM",
+    "That user is already in a team in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct TeamFull;
+
+impl_respondable_error!(
+    TeamFull,
+    BAD_REQUEST,
+    "TEAM_FULL",
+    "The team has reached the maximum team size for this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct TeamNameTaken;
+
+impl_respondable_error!(
+    TeamNameTaken,
+    BAD_REQUEST,
+    "TEAM_NAME_TAKEN",
+    "There is already a team with that name in this competition"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct InvalidTeamName;
+
+impl_respondable_error!(
+    InvalidTeamName,
+    BAD_REQUEST,
+    "INVALID_TEAM_NAME",
+    "Team names must be between 1 and 32 characters long"
+);
+
+#[derive(Debug, Display, Error)]
+pub struct TeamInviteNotFound;
+
+impl_respondable_error!(
+    TeamInviteNotFound,
+    NOT_FOUND,
+    "TEAM_INVITE_NOT_FOUND",
+    "You have not been invited to that team"
+);
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum CreateTeamError {
+    Diesel(DieselError),
+    CompetitionNotFound(CompetitionNotFoundAuth),
+    NotEnrolled(UserNotEnrolled),
+    TeamsDisabled(TeamsDisabled),
+    AlreadyInTeam(AlreadyInTeam),
+    InvalidName(InvalidTeamName),
+    NameTaken(TeamNameTaken),
+}
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum InviteToTeamError {
+    Diesel(DieselError),
+    CompetitionNotFound(CompetitionNotFoundAuth),
+    TeamsDisabled(TeamsDisabled),
+    NotInTeam(NotInTeam),
+    UserNotFound(UserNotFound),
+    UserInOtherTeam(UserInOtherTeam),
+    TeamFull(TeamFull),
+}
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum JoinTeamError {
+    Diesel(DieselError),
+    CompetitionNotFound(CompetitionNotFoundAuth),
+    NotEnrolled(UserNotEnrolled),
+    TeamsDisabled(TeamsDisabled),
+    AlreadyInTeam(AlreadyInTeam),
+    InviteNotFound(TeamInviteNotFound),
+    TeamFull(TeamFull),
+}
+
+#[derive(Debug, Display, Error, RespondableError, From)]
+pub enum LeaveTeamError {
+    Diesel(DieselError),
+    CompetitionNotFound(CompetitionNotFoundAuth),
+    NotInTeam(NotInTeam),
+}
@@ -0,0 +1,62 @@
+//! Guards for routes that depend on the competition, see [`CompetitionRoleGuard`].
+
+use std::{future::Future, marker::PhantomData, ops::Deref, pin::Pin};
+
+use doxa_auth::{guard::AuthGuardInner, role::CompetitionRoles};
+use doxa_core::{actix_web::web, error::RespondableErrorWrapper};
+use doxa_db::PgPool;
+
+use crate::client::Competition;
+
+/// Loads the roles the user has in the competition `C`, use it as `AuthGuard<CompetitionRoleGuard<C>>`
+/// and check permissions with `user.inner_ref().has(..)`.
+/// Admins have every permission and requests authenticated with an API token have no roles.
+pub struct CompetitionRoleGuard<C: Competition + ?Sized> {
+    roles: CompetitionRoles,
+    competition: PhantomData<C>,
+}
+
+impl<C: Competition + ?Sized> Deref for CompetitionRoleGuard<C> {
+    type Target = CompetitionRoles;
+
+    fn deref(&self) -> &Self::Target {
+        &self.roles
+    }
+}
+
+impl<C: Competition + ?Sized> AuthGuardInner for CompetitionRoleGuard<C> {
+    fn construct(
+        user: Option<i32>,
+        is_admin: bool,
+        api_token: bool,
+        connnection: web::Data<PgPool>,
+    ) -> Pin<Box<dyn Future<Output = Result<Self, RespondableErrorWrapper>>>> {
+        Box::pin(async move {
+            // Admins have every permission so there is no need to load their roles
+            if is_admin {
+                return Ok(CompetitionRoleGuard {
+                    roles: CompetitionRoles::admin(),
+                    competition: PhantomData,
+                });
+            }
+
+            // API tokens are limited to their scopes, none of which grant the user's roles
+            if api_token {
+                return Ok(CompetitionRoleGuard {
+                    roles: CompetitionRoles::default(),
+                    competition: PhantomData,
+                });
+            }
+
+            let conn = web::block(move || connnection.get()).await??;
+            let roles =
+                web::block(move || CompetitionRoles::load(&conn, user, false, C::COMPETITION_NAME))
+                    .await??;
+
+            Ok(CompetitionRoleGuard {
+                roles,
+                competition: PhantomData,
+            })
+        })
+    }
+}
@@ -1,7 +1,7 @@
 use std::{convert::Infallible, time::Duration};
 
 use crate::{
-    client::{Competition, Context, GameEvent},
+    client::{Competition, Context, GameEvent, Priority},
     error::ContextError,
 };
 use async_trait::async_trait;
@@ -35,7 +35,12 @@ impl Competition for HelloWorldCompetiton {
         agent: AgentUpload,
     ) -> Result<(), ContextError> {
         context
-            .emit_match_request(vec![agent.id], (), &agent.execution_environment)
+            .emit_match_request(
+                vec![agent.id],
+                (),
+                &agent.execution_environment,
+                Priority::Placement,
+            )
             .await?;
 
         Ok(())
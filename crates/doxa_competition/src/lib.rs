@@ -1,16 +1,20 @@
 use std::{collections::HashMap, sync::Arc};
 
-use client::{validate_competition_name, Competition, CompetitionInner};
+use client::{validate_competition_name, Competition, CompetitionInner, GameClient};
+use route::{admin::QueueDashboard, node::NodeRegistry};
 
 use doxa_core::actix_web::web;
+use doxa_executor::profile::{find_execution_profile, ExecutionProfile};
 
 pub mod client;
 pub mod context;
 pub mod error;
+pub mod guard;
 pub mod hello_world;
 pub mod manager;
 pub mod route;
 pub mod settings;
+pub mod team;
 
 pub use settings::Settings;
 
@@ -27,6 +31,8 @@ pub struct CompetitionSystem {
 struct CompetitionRecord {
     competition: Arc<dyn CompetitionInner>,
     executor_permits: usize,
+    /// The profiles that the server takes match requests for
+    execution_profiles: Vec<ExecutionProfile>,
 }
 
 impl CompetitionSystem {
@@ -40,10 +46,59 @@ impl CompetitionSystem {
     ///
     /// `executor_permits` the number of simultaneous executions for this competition.
     ///
+    /// The server only runs games of the competition's default execution profile (the first one
+    /// declared by [`client::GameClient::execution_profiles`]), use
+    /// [`CompetitionSystem::add_competition_with_profiles`] to choose others.
+    ///
     /// # Panics
     /// - If another competition has already registered a name this will panic.
     /// - If the name does not satisfy [`validate_competition_name`].
     pub fn add_competition<C: Competition>(&mut self, competition: C, executor_permits: usize) {
+        let execution_profiles = <C::GameClient as GameClient>::execution_profiles()
+            .into_iter()
+            .take(1)
+            .collect();
+
+        self.add_record(competition, executor_permits, execution_profiles);
+    }
+
+    /// The same as [`CompetitionSystem::add_competition`] except the server runs the games of
+    /// every execution profile in `execution_profiles`, these share the `executor_permits`.
+    /// If `execution_profiles` is empty then the server doesn't run any of the competition's games
+    /// and they are left to execution nodes.
+    ///
+    /// # Panics
+    /// - If any of the profiles are not declared by
+    ///   [`client::GameClient::execution_profiles`].
+    /// - In the same cases as [`CompetitionSystem::add_competition`].
+    pub fn add_competition_with_profiles<C: Competition>(
+        &mut self,
+        competition: C,
+        executor_permits: usize,
+        execution_profiles: &[&str],
+    ) {
+        let execution_profiles = execution_profiles
+            .iter()
+            .map(|&name| {
+                find_execution_profile::<C::GameClient>(name).unwrap_or_else(|| {
+                    panic!(
+                        "The competition `{}` does not declare the execution profile `{}`",
+                        C::COMPETITION_NAME,
+                        name
+                    )
+                })
+            })
+            .collect();
+
+        self.add_record(competition, executor_permits, execution_profiles);
+    }
+
+    fn add_record<C: Competition>(
+        &mut self,
+        competition: C,
+        executor_permits: usize,
+        execution_profiles: Vec<ExecutionProfile>,
+    ) {
         assert!(
             executor_permits > 0,
             "competition must have at least one permit"
@@ -66,6 +121,7 @@ impl CompetitionSystem {
                 CompetitionRecord {
                     competition,
                     executor_permits,
+                    execution_profiles,
                 },
             )
             .is_some()
@@ -84,7 +140,11 @@ impl CompetitionSystem {
             match record
                 .competition
                 .clone()
-                .start_competition_manager(settings.clone(), record.executor_permits)
+                .start_competition_manager(
+                    settings.clone(),
+                    record.executor_permits,
+                    record.execution_profiles.clone(),
+                )
                 .await
             {
                 Err(error) => {
@@ -99,8 +159,39 @@ impl CompetitionSystem {
         }
 
         let settings = settings.clone();
+        let queue_dashboard = web::Data::new(QueueDashboard::new(
+            settings.mq.clone(),
+            settings.pg_pool.clone(),
+            competitions
+                .iter()
+                .map(|(name, record, competition_id)| {
+                    (
+                        name.clone(),
+                        *competition_id,
+                        record.competition.execution_profile_names(),
+                    )
+                })
+                .collect(),
+        ));
+        let node_registry = web::Data::new(NodeRegistry::new(settings.pg_pool.clone()));
 
         move |service: &mut web::ServiceConfig| {
+            service.service(
+                web::scope("/admin/queues")
+                    .app_data(queue_dashboard.clone())
+                    .configure(route::admin::configure_queue_routes),
+            );
+            service.service(
+                web::scope("/admin/drain")
+                    .app_data(web::Data::from(settings.clone()))
+                    .configure(route::admin::configure_drain_routes),
+            );
+            service.service(
+                web::scope("/admin/nodes")
+                    .app_data(node_registry.clone())
+                    .configure(route::node::configure_node_routes),
+            );
+
             for (name, record, competition_id) in competitions.iter() {
                 let competition_limits = web::Data::new(
                     record
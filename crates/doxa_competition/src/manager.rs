@@ -4,6 +4,7 @@ use std::sync::Arc;
 
 use doxa_core::tokio::{self, join};
 use doxa_db::model::competition::InsertableCompetition;
+use doxa_executor::profile::ExecutionProfile;
 
 use crate::{
     client::{Competition, Context},
@@ -12,11 +13,16 @@ use crate::{
     Settings,
 };
 
-use self::{activation::AgentActivationManager, executor::ExecutionManager};
+use self::{
+    activation::AgentActivationManager, executor::ExecutionManager, node_monitor::LostGameManager,
+    retention::TranscriptRetentionManager,
+};
 
 mod activation;
 pub(crate) mod executor;
 mod game_event;
+pub(crate) mod node_monitor;
+mod retention;
 // mod upload;
 
 pub struct CompetitionManager<T: Competition> {
@@ -31,6 +37,7 @@ impl<T: Competition> CompetitionManager<T> {
         competition: Arc<T>,
         settings: Arc<Settings>,
         executor_permits: usize,
+        execution_profiles: Vec<ExecutionProfile>,
     ) -> Result<i32, CompetitionManagerError> {
         let manager = CompetitionManager {
             competition,
@@ -52,7 +59,7 @@ impl<T: Competition> CompetitionManager<T> {
         .await??;
 
         let context = Arc::new(Context::<T>::new(
-            manager.settings.mq_pool.clone(),
+            manager.settings.mq.clone(),
             manager.settings.pg_pool.clone(),
             competition.id,
         ));
@@ -69,9 +76,14 @@ impl<T: Competition> CompetitionManager<T> {
             context.clone(),
         );
 
+        let retention_manager = TranscriptRetentionManager::new(context.clone());
+
+        let lost_game_manager = LostGameManager::new(manager.settings.clone(), context.clone());
+
         let execution_manager = ExecutionManager::<T>::new(
             manager.settings,
             executor_permits,
+            execution_profiles,
             manager.competition.clone(),
         );
 
@@ -79,6 +91,8 @@ impl<T: Competition> CompetitionManager<T> {
             activation_manager.start(),
             execution_manager.start(),
             game_event_manager.start(),
+            retention_manager.start(),
+            lost_game_manager.start(),
         );
 
         Ok(competition.id)
@@ -2,23 +2,22 @@ use std::sync::Arc;
 
 use doxa_core::{
     chrono::{DateTime, Utc},
-    lapin::{message::Delivery, options::BasicAckOptions},
     tokio,
     tracing::{error, info, span, Level},
     tracing_futures::Instrument,
 };
 use doxa_db::model::storage::AgentUpload;
-use doxa_mq::model::ActivationEvent;
+use doxa_mq::{model::ActivationEvent, Delivery};
 
 use crate::{error::ContextError, Settings};
 
-use futures::{StreamExt, TryFutureExt};
+use futures::StreamExt;
 
 use crate::client::{Competition, Context};
 
 impl<C: Competition> Context<C> {
     /// Sets the activation flag for the given `agent_id` to true.
-    /// If another agent currently has the activation flag set to true for this user and
+    /// If another agent currently has the activation flag set to true for this user (or team) and
     /// competition it will unset it (for that agent) and return that agent - the deactivated
     /// agent.
     ///
@@ -36,7 +35,7 @@ impl<C: Competition> Context<C> {
             let deactivated_agent = doxa_db::action::storage::mark_active_agent_as_inactive(
                 conn,
                 agent.competition,
-                agent.owner,
+                agent.agent_owner(),
             )?;
 
             doxa_db::action::storage::activate_agent(conn, agent.id, activated_at)?;
@@ -50,17 +49,22 @@ impl<C: Competition> Context<C> {
     /// If either of these preconditions are false then `Ok(None)` is returned.
     /// This sets the agent's active field to false, and sets the outdated field to true for every
     /// game that this agent participated in.
-    async fn deactivate_agent_db(&self, agent_id: String) -> Result<AgentUpload, ContextError> {
+    ///
+    /// The IDs of the outdated games that may still be running are also returned.
+    async fn deactivate_agent_db(
+        &self,
+        agent_id: String,
+    ) -> Result<(AgentUpload, Vec<i32>), ContextError> {
         let competition_id = self.competition_id();
 
         self.run_query(move |conn| {
             let agent = doxa_db::action::storage::mark_agent_deactive_by_id(conn, agent_id)?;
-            doxa_db::action::game::mark_games_with_player_as_outdated(
+            let outdated_games = doxa_db::action::game::mark_games_with_player_as_outdated(
                 conn,
-                agent.owner,
+                agent.agent_owner(),
                 competition_id,
             )?;
-            Ok(agent)
+            Ok((agent, outdated_games))
         })
         .await
     }
@@ -109,18 +113,20 @@ impl<C: Competition> AgentActivationManager<C> {
             let span = span!(Level::INFO, "deactiving agent before activating new one", old_agent = %deactivated_agent.id);
 
             let competition_id = self.context.competition_id();
-            self.context
+            let outdated_games = self
+                .context
                 .run_query({
-                    let user = deactivated_agent.owner;
+                    let owner = deactivated_agent.agent_owner();
                     move |conn| {
                         doxa_db::action::game::mark_games_with_player_as_outdated(
                             conn,
-                            user,
+                            owner,
                             competition_id,
                         )
                     }
                 })
                 .await?;
+            self.context.cancel_games(outdated_games).await;
 
             self.competition
                 .on_agent_deactivated(&self.context, deactivated_agent)
@@ -145,7 +151,9 @@ impl<C: Competition> AgentActivationManager<C> {
 
     /// Both deactivates the agent and calls the deactiate handler.
     async fn deactivate_agent(&self, agent_id: String) -> Result<(), ContextError> {
-        let agent = self.context.deactivate_agent_db(agent_id).await?;
+        let (agent, outdated_games) = self.context.deactivate_agent_db(agent_id).await?;
+        self.context.cancel_games(outdated_games).await;
+
         self.competition
             .on_agent_deactivated(&self.context, agent)
             .await?;
@@ -153,37 +161,20 @@ impl<C: Competition> AgentActivationManager<C> {
         Ok(())
     }
 
-    async fn handle_activation_event(
-        &self,
-        delivery: Delivery,
-        event: ActivationEvent,
-    ) -> Result<(), ContextError> {
+    async fn handle_activation_event(&self, event: ActivationEvent) -> Result<(), ContextError> {
         if event.activating {
-            self.activate_agent(event.agent).await?;
+            self.activate_agent(event.agent).await
         } else {
-            self.deactivate_agent(event.agent).await?;
+            self.deactivate_agent(event.agent).await
         }
-
-        delivery
-            .ack(BasicAckOptions::default())
-            .await
-            .expect("Failed to acknowledge MQ");
-
-        Ok(())
     }
 
     pub async fn start(self) {
-        let connection = self
-            .settings
-            .mq_pool
-            .get()
-            .await
-            .expect("Failed to get MQ connection");
-
-        let mut consumer =
-            doxa_mq::action::get_activation_event_consumer(&connection, C::COMPETITION_NAME)
-                .await
-                .unwrap();
+        let queue = doxa_mq::action::activation_queue_name(C::COMPETITION_NAME);
+        let mut consumer = doxa_mq::action::get_activation_event_consumer(
+            self.settings.mq.clone(),
+            C::COMPETITION_NAME,
+        );
 
         info!(
             competition = %C::COMPETITION_NAME,
@@ -191,11 +182,15 @@ impl<C: Competition> AgentActivationManager<C> {
         );
 
         let future = async move {
-            while let Some(message) = consumer.next().await {
-                let (_, delivery) = message.expect("Error connecting to MQ");
-
-                let event: ActivationEvent = doxa_mq::action::deserialize(&delivery.data)
-                    .expect("Improperly formatted message");
+            while let Some(delivery) = consumer.next().await {
+                let event: ActivationEvent = match doxa_mq::action::decode(&delivery.data) {
+                    Ok(event) => event,
+                    Err(error) => {
+                        error!(%error, debug = ?error, "failed to decode activation event");
+                        self.dead_letter(&queue, &delivery, error.to_string()).await;
+                        continue;
+                    }
+                };
                 let agent_id = event.agent.clone();
 
                 let span = span!(
@@ -206,16 +201,38 @@ impl<C: Competition> AgentActivationManager<C> {
                     %event.activating,
                 );
 
-                let _: Result<(), ()> = self
-                    .handle_activation_event(delivery, event)
-                    .map_err(|error| {
-                        error!(%error, error_debug = ?error, "failed to handle activation request");
-                    })
-                    .instrument(span)
-                    .await;
+                async {
+                    match self.handle_activation_event(event).await {
+                        Ok(()) => {
+                            if let Err(error) = delivery.ack().await {
+                                error!(%error, debug = ?error, "failed to acknowledge activation event");
+                            }
+                        }
+                        Err(error) => {
+                            error!(%error, error_debug = ?error, "failed to handle activation request");
+
+                            // Give it one more chance in case the failure was temporary
+                            if delivery.redelivered {
+                                self.dead_letter(&queue, &delivery, error.to_string()).await;
+                            } else if let Err(error) = delivery.nack(true).await {
+                                error!(%error, debug = ?error, "failed to requeue activation event");
+                            }
+                        }
+                    }
+                }
+                .instrument(span)
+                .await;
             }
         };
 
         tokio::spawn(future);
     }
+
+    async fn dead_letter(&self, queue: &str, delivery: &Delivery, reason: String) {
+        if let Err(error) =
+            doxa_mq::action::dead_letter(self.settings.mq.as_ref(), queue, delivery, reason).await
+        {
+            error!(%error, debug = ?error, "failed to dead letter activation event");
+        }
+    }
 }
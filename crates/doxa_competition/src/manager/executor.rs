@@ -1,19 +1,17 @@
 use std::sync::Arc;
 
 use doxa_executor::{
-    client::{firecracker, ForfeitError, GameClient},
-    error::GameManagerError,
-    game::GameManager,
+    cancel::CancellationRegistry,
+    client::firecracker,
+    listener::{MatchRequestListener, RunningGames},
+    pause::PauseState,
+    profile::ExecutionProfile,
 };
 
 use doxa_core::{
-    lapin::options::BasicAckOptions,
     tokio::{self, sync::Semaphore},
-    tracing::{event, info, span, Instrument, Level},
+    tracing::info,
 };
-use doxa_mq::model::MatchRequest;
-use futures::future::FutureExt;
-use futures::StreamExt;
 
 use crate::{client::Competition, Settings};
 
@@ -22,6 +20,8 @@ pub struct ExecutionManager<C: Competition> {
     //firecracker_settings: Arc<FirecrackerBackendSettings>,
     settings: Arc<Settings>,
     executor_permits: usize,
+    /// The profiles to take match requests for, these share the executor permits
+    execution_profiles: Vec<ExecutionProfile>,
     competition: Arc<C>,
 }
 
@@ -30,6 +30,7 @@ impl<C: Competition> ExecutionManager<C> {
         //firecracker_settings: Arc<FirecrackerBackendSettings>,
         settings: Arc<Settings>,
         executor_permits: usize,
+        execution_profiles: Vec<ExecutionProfile>,
         competition: Arc<C>,
     ) -> Self {
         assert!(executor_permits > 0);
@@ -38,134 +39,88 @@ impl<C: Competition> ExecutionManager<C> {
             //  firecracker_settings,
             settings,
             executor_permits,
+            execution_profiles,
             competition,
         }
     }
 
-    /// Spawns a task then listens for match request
+    /// Spawns a task for each execution profile that listens for match requests.
+    /// If there are no execution profiles then all of the competition's games are left to
+    /// execution nodes.
     pub async fn start(self) {
         let competition_name = C::COMPETITION_NAME;
-        let connection = self
-            .settings
-            .mq_pool
-            .get()
-            .await
-            .expect("Failed to get MQ connection");
 
-        let mut consumer =
-            doxa_mq::action::get_match_request_consumer(&connection, competition_name, "basic")
-                .await
-                .unwrap();
-
-        info!(
-            competition =%competition_name,
-            "execution event listener",
-        );
+        if self.execution_profiles.is_empty() {
+            info!(
+                competition = %competition_name,
+                "not running games as the server has no execution profiles for this competition",
+            );
+            return;
+        }
 
         let game_client = Arc::new(self.competition.build_game_client());
 
-        tokio::spawn(async move {
-            let executor_limiter = Arc::new(Semaphore::new(self.executor_permits));
-
-            while let Some(message) = consumer.next().await {
-                let permit = executor_limiter.clone().acquire_owned().await.unwrap();
-                // TODO: remove expects and convert to error logging
-                let (_, delivery) = message.expect("Error connecting to MQ");
-                let match_request: MatchRequest<
-                    <<C as Competition>::GameClient as GameClient>::MatchRequest,
-                > = doxa_mq::action::deserialize(&delivery.data)
-                    .expect("Improperly formatted message");
-                let game_id = match_request.game_id;
-
-                let span = span!(
-                    Level::INFO,
-                    "handle match request",
-                    game_id = %game_id,
-                    agents = ?match_request.agents,
-                    competition_name = %competition_name,
-                );
-
-                let event_channel =
-                    doxa_mq::action::game_event_channel(&connection, competition_name)
-                        .await
-                        .unwrap();
-                let event_channel_name = doxa_mq::action::game_event_queue_name(competition_name);
-
-                tokio::spawn({
-                    let cancel_endpoint = format!(
-                        "{}{}/_game/{}/cancelled",
-                        self.settings.competitions_base_url, competition_name, game_id
-                    );
-                    let request_client = self.settings.request_client.clone();
-                    let executor_settings = self.settings.executor_settings.clone();
-                    let competition_name = competition_name;
-                    let game_client = game_client.clone();
-                    let firecracker_settings = self.settings.firecracker_settings.clone();
-                    // let firecracker_settings = firecracker::FirecrackerBackendSettings {
-                    //     kernel_img: executor_settings.kernel_img.clone(),
-                    //     kernel_boot_args: executor_settings.kernel_boot_args.clone(),
-                    //     firecracker_path: executor_settings.firecracker_path.clone(),
-                    //     vcpus: 6,
-                    //     original_rootfs: executor_settings.rootfs.clone(),
-                    // };
-                    async move {
-                            // In future there can be some smarter code in the event that the
-                            // code below fails or the server unexpected shutsdown, we need to
-                            // consider that some game events may have been emitted and processed.
-                            // Perhaps it's more correct to only process game events at the end?
-                            delivery
-                                .ack(BasicAckOptions::default())
-                                .await
-                                .expect("Failed to acknowledge MQ");
-
-                            let game_manager = match GameManager::<C::GameClient, firecracker::FirecrackerBackend>::new(
-                                executor_settings,
-                                firecracker_settings,
-                                event_channel,
-                                event_channel_name,
-                                competition_name,
-                                match_request,
-                                game_client
-                            )
-                            .await
-                            {
-                                Ok(game_manger) => game_manger,
-                                Err(error) => {
-                                    // Between now and when the agent was first queued it is no
-                                    // longer the correct active agent (e.g. because of error or
-                                    // because a new one was uploads or because it was deleted).
-                                    // This is not a problem, it's good that we don't run the game
-                                    // in the case.
-                                    if let GameManagerError::StartAgent(agent_error) = &error {
-                                        let agent_error = &agent_error.source;
-                                        if matches!(agent_error, &doxa_executor::error::AgentError::AgentGone) {
-                                            event!(Level::DEBUG, "not starting game because agent was gone");
-                                            return;
-                                        }
-                                    }
-                                    // Should probably emit an error game event
-                                    event!(Level::ERROR, %error, debug = ?error, "failed to start game manager");
-                                    return;
-                                }
-                            };
-
-                            info!("started game manager");
+        let cancellation_registry = CancellationRegistry::new();
+        tokio::spawn({
+            let cancellation_registry = cancellation_registry.clone();
+            let mq = self.settings.mq.clone();
+            async move {
+                cancellation_registry
+                    .listen(mq.as_ref(), competition_name)
+                    .await
+            }
+        });
 
-                            match game_manager.run_with_cancel_check(cancel_endpoint, request_client).await {
-                                Ok(()) => event!(Level::INFO, "game manager successfully completed"),
-                                Err(error) => {
-                                    if error.forfeit().is_some() {
-                                        event!(Level::INFO, forfeit=true, %error, debug = ?error, "error running game manager")
-                                    } else {
-                                        event!(Level::ERROR, forfeit=false, %error, debug = ?error, "error running game manager")
-                                    }
-                                }
-                            }
-                        }
-                        .then(|_| async move { drop(permit); })
-                        .instrument(span)
-                });
+        let pause_state = PauseState::new();
+        tokio::spawn({
+            let pause_state = pause_state.clone();
+            let mq = self.settings.mq.clone();
+            let request_client = self.settings.request_client.clone();
+            let paused_endpoint = format!(
+                "{}{}/_paused",
+                self.settings.competitions_base_url, competition_name
+            );
+            async move {
+                pause_state
+                    .listen(
+                        mq.as_ref(),
+                        competition_name,
+                        &request_client,
+                        &paused_endpoint,
+                    )
+                    .await
             }
         });
+
+        let executor_limiter = Arc::new(Semaphore::new(self.executor_permits));
+        let running_games = RunningGames::new();
+
+        for execution_profile in self.execution_profiles.iter().cloned() {
+            let listener = MatchRequestListener::<C::GameClient, firecracker::FirecrackerBackend> {
+                competition_name,
+                execution_profile,
+                game_client: game_client.clone(),
+                competition_url: format!(
+                    "{}{}",
+                    self.settings.competitions_base_url, competition_name
+                ),
+                mq: self.settings.mq.clone(),
+                request_client: self.settings.request_client.clone(),
+                executor_settings: self.settings.executor_settings.clone(),
+                backend_settings: self.settings.firecracker_settings.clone(),
+                // The permits are shared by every profile
+                limiters: vec![executor_limiter.clone()],
+                max_running: self.executor_permits,
+                cancellation_registry: cancellation_registry.clone(),
+                pause_state: pause_state.clone(),
+                drain: self.settings.drain.clone(),
+                running_games: running_games.clone(),
+                // The server's executors don't send heartbeats so they rely on the broker to
+                // redeliver the games they were running if they crash
+                acknowledge_on_start: false,
+            };
+
+            tokio::spawn(listener.listen());
+        }
     }
 }
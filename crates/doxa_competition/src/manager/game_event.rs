@@ -1,21 +1,36 @@
 use std::sync::Arc;
 
+use derive_more::Display;
 use doxa_core::{
-    lapin::{
-        message::Delivery,
-        options::{BasicAckOptions, BasicNackOptions},
-    },
     tokio,
     tracing::{error, event, info, span, warn, Level},
     tracing_futures::Instrument,
 };
 
-use crate::Settings;
-use doxa_mq::model::GameEvent;
+use crate::{
+    error::{ContextError, ParseSystemMessageError},
+    Settings,
+};
+use doxa_db::diesel::Connection;
+use doxa_executor::event::{CancelledEvent, RetryEvent};
+use doxa_mq::{envelope::DecodeError, model::GameEvent, Delivery};
 
 use futures::StreamExt;
 
-use crate::client::{Competition, Context};
+use crate::client::{Competition, Context, GameClient};
+
+/// Why a game event could not be handled.
+#[derive(Display, Debug)]
+enum HandleGameEventError {
+    /// The event could not be stored, this may work if it is tried again.
+    #[display(fmt = "failed to store event: {}", _0)]
+    Store(ContextError),
+    /// The event was stored but it could not be applied.
+    /// Retrying will not help because events that are already stored are not applied again, so
+    /// these are acknowledged rather than dead lettered.
+    #[display(fmt = "failed to apply event: {}", _0)]
+    Apply(ContextError),
+}
 
 pub(super) struct GameEventManager<C: Competition> {
     settings: Arc<Settings>,
@@ -32,132 +47,223 @@ impl<C: Competition> GameEventManager<C> {
         }
     }
 
-    async fn handle_game_event(&self, delivery: Delivery) {
-        // Ack the second attempt because there's a good chance it might never work and there'll be
-        // a constant nack cycle
-        if delivery.redelivered {
-            info!("event is redelivery, postively acking regardless of outcome");
-            delivery
-                .ack(BasicAckOptions::default())
-                .await
-                .expect("Failed to acknowledge MQ");
-        }
-        let game_event: GameEvent<serde_json::Value> =
-            serde_json::from_slice(&delivery.data).expect("Improperly formatted message");
-        event!(Level::DEBUG, %game_event.game_id, %game_event.event_type, "received game event for agent");
+    async fn handle_delivery(&self, queue: &str, delivery: Delivery) {
+        let game_event = match Self::decode_game_event(&delivery.data) {
+            Ok(game_event) => game_event,
+            Err(error) => {
+                error!(%error, debug = ?error, "failed to decode game event");
+                self.dead_letter(queue, &delivery, error.to_string()).await;
+                return;
+            }
+        };
 
-        let res = tokio::task::spawn_blocking({
-            let game_event = game_event.clone();
-            let pool = self.settings.pg_pool.clone();
-            move || {
-                let db = pool.get().unwrap();
-                doxa_db::action::game::add_event(
-                    &db,
-                    &doxa_db::model::game::GameEvent {
-                        event_id: game_event.event_id as i32,
-                        game: game_event.game_id,
-                        event_timestamp: game_event.timestamp,
-                        event_type: game_event.event_type,
-                        payload: game_event.payload,
-                    },
-                )
+        match self.handle_game_event(game_event).await {
+            Ok(()) => {
+                if let Err(error) = delivery.ack().await {
+                    error!(%error, debug = ?error, "failed to acknowledge game event");
+                }
+            }
+            Err(error @ HandleGameEventError::Store(_)) if !delivery.redelivered => {
+                error!(%error, debug = ?error, "failed to handle game event, requeuing");
+                if let Err(error) = delivery.nack(true).await {
+                    error!(%error, debug = ?error, "failed to requeue game event");
+                }
+            }
+            Err(error @ HandleGameEventError::Apply(_)) => {
+                // A replayed event would be skipped as it is already stored, so dead lettering it
+                // wouldn't help
+                error!(%error, debug = ?error, "failed to handle game event");
+                if let Err(error) = delivery.ack().await {
+                    error!(%error, debug = ?error, "failed to acknowledge game event");
+                }
+            }
+            Err(error) => {
+                error!(%error, debug = ?error, "failed to handle game event");
+                self.dead_letter(queue, &delivery, error.to_string()).await;
             }
+        }
+    }
+
+    /// Decodes a game event, upgrading the payload of client events that were emitted with an older
+    /// version so that only the current version is stored.
+    fn decode_game_event(data: &[u8]) -> Result<GameEvent<serde_json::Value>, DecodeError> {
+        let (game_event, payload_version) = doxa_mq::envelope::decode_game_event(data)?;
+        let current_version = <C::GameClient as GameClient>::GAME_EVENT_VERSION;
+
+        if game_event.event_type.starts_with('_') || payload_version == current_version {
+            return Ok(game_event);
+        }
+
+        game_event.try_map_payload(|payload| {
+            let payload = doxa_mq::envelope::upgrade_game_event_payload(
+                payload,
+                payload_version,
+                current_version,
+                <C::GameClient as GameClient>::upgrade_game_event,
+            )?;
+
+            Ok(serde_json::to_value(payload)?)
         })
-        .await
-        .unwrap();
+    }
+
+    async fn handle_game_event(
+        &self,
+        game_event: GameEvent<serde_json::Value>,
+    ) -> Result<(), HandleGameEventError> {
+        event!(Level::DEBUG, %game_event.game_id, %game_event.event_type, "received game event for agent");
 
-        if let Err(error) = res {
-            if doxa_db::was_unique_key_violation(&error) {
+        let res = self
+            .context
+            .run_query({
+                let game_event = game_event.clone();
+                move |conn| {
+                    doxa_db::action::game::add_event(
+                        conn,
+                        &doxa_db::model::game::GameEvent {
+                            event_id: game_event.event_id as i32,
+                            game: game_event.game_id,
+                            event_timestamp: game_event.timestamp,
+                            event_type: game_event.event_type,
+                            payload: game_event.payload,
+                        },
+                    )
+                }
+            })
+            .await;
+
+        match res {
+            Ok(_) => self
+                .apply_game_event(game_event)
+                .await
+                .map_err(HandleGameEventError::Apply),
+            Err(ContextError::Diesel(error)) if doxa_db::was_unique_key_violation(&error) => {
                 warn!(?game_event, "already inserted game event into db, not inserting or notifying again as there was likely an error last time");
                 // TODO: decide whether to notify the event again.
-            } else {
-                error!(error=%error, debug=?error, ?game_event, "failed to insert event into db");
-                if !delivery.redelivered {
-                    delivery
-                        .nack(BasicNackOptions::default())
-                        .await
-                        .expect("Failed to acknowledge MQ");
-                }
-                return;
+                Ok(())
             }
-        } else {
-            let event_type = &game_event.event_type;
-
-            if event_type.starts_with('_') {
-                match event_type.as_str() {
-                    "_START" => {
-                        tokio::task::spawn_blocking({
-                            let started_at = game_event.timestamp;
-                            let game_id = game_event.game_id;
-                            let pool = self.settings.pg_pool.clone();
-                            move || {
-                                let db = pool.get().unwrap();
-                                doxa_db::action::game::set_game_start_time(&db, game_id, started_at)
-                            }
+            Err(error) => Err(HandleGameEventError::Store(error)),
+        }
+    }
+
+    /// Updates the game for system events or notifies the competition of other events.
+    async fn apply_game_event(
+        &self,
+        game_event: GameEvent<serde_json::Value>,
+    ) -> Result<(), ContextError> {
+        let event_type = &game_event.event_type;
+        let game_id = game_event.game_id;
+
+        if event_type.starts_with('_') {
+            match event_type.as_str() {
+                "_START" => {
+                    let started_at = game_event.timestamp;
+                    self.context
+                        .run_query(move |conn| {
+                            doxa_db::action::game::set_game_start_time(conn, game_id, started_at)
                         })
-                        .await
-                        .unwrap()
-                        .unwrap();
+                        .await?;
+                }
+
+                "_END" | "_CANCELLED" => {
+                    if event_type == "_CANCELLED" {
+                        let cancelled: CancelledEvent = serde_json::from_value(
+                            game_event.payload.clone(),
+                        )
+                        .map_err(|error| ParseSystemMessageError {
+                            event_type: event_type.clone(),
+                            game_id,
+                            error,
+                        })?;
+
+                        // The game is followed by `_RETRY` as it will run again
+                        if cancelled.requeued {
+                            return Ok(());
+                        }
                     }
 
-                    "_END" | "_CANCELLED" => {
-                        tokio::task::spawn_blocking({
-                            let complete_time = game_event.timestamp;
-                            let game_id = game_event.game_id;
-                            let pool = self.settings.pg_pool.clone();
-                            move || {
-                                let db = pool.get().unwrap();
+                    let complete_time = game_event.timestamp;
+                    self.context
+                        .run_query(move |conn| {
+                            conn.transaction(|| {
                                 doxa_db::action::game::set_game_complete_time(
-                                    &db,
+                                    conn,
                                     game_id,
                                     complete_time,
-                                )
-                            }
+                                )?;
+                                // The game won't be requeued so the match request isn't needed
+                                doxa_db::action::game::delete_match_request(conn, game_id)
+                            })
                         })
-                        .await
-                        .unwrap()
-                        .unwrap();
-                    }
-                    "_ERROR" => {}
+                        .await?;
+                }
+                "_RETRY" => {
+                    let retry: RetryEvent = serde_json::from_value(game_event.payload.clone())
+                        .map_err(|error| ParseSystemMessageError {
+                            event_type: event_type.clone(),
+                            game_id,
+                            error,
+                        })?;
 
-                    "_FORFEIT" => {}
-                    _ => {
-                        error!(%event_type, ?game_event, "unknown event type");
-                    }
+                    self.context
+                        .run_query(move |conn| {
+                            doxa_db::action::game::set_game_retries(
+                                conn,
+                                game_id,
+                                retry.attempt as i32,
+                            )
+                        })
+                        .await?;
+                }
+                "_ERROR" => {}
+
+                "_FORFEIT" => {}
+
+                "_LOGS" | "_TRANSCRIPT" => {}
+
+                // Checkpoints are only read back when a game is resumed
+                "_CHECKPOINT" | "_RESUME" => {}
+                _ => {
+                    error!(%event_type, ?game_event, "unknown event type");
                 }
-            } else {
-                let game_event = game_event
-                    .try_map_payload(serde_json::from_value)
-                    .expect("Improperly formatted client message");
-
-                if let Err(error) = self
-                    .competition
-                    .on_game_event(&self.context, game_event)
-                    .await
-                {
-                    event!(Level::ERROR, %error, debug = ?error, "on_game_event failed for agent");
+            }
+        } else {
+            let game_event = match game_event.try_map_payload(serde_json::from_value) {
+                Ok(game_event) => game_event,
+                Err(error) => {
+                    // This is a bug in the game client rather than a problem with the event
+                    event!(Level::ERROR, %error, debug = ?error, "improperly formatted client message");
+                    return Ok(());
                 }
+            };
+
+            if let Err(error) = self
+                .competition
+                .on_game_event(&self.context, game_event)
+                .await
+            {
+                event!(Level::ERROR, %error, debug = ?error, "on_game_event failed for agent");
             }
         }
 
-        delivery
-            .ack(BasicAckOptions::default())
-            .await
-            .expect("Failed to acknowledge MQ");
+        Ok(())
     }
 
-    pub async fn start(self) {
-        let connection = self
-            .settings
-            .mq_pool
-            .get()
-            .await
-            .expect("Failed to get MQ connection");
+    /// If the event can't be dead lettered it is put back on the queue so that it isn't lost.
+    async fn dead_letter(&self, queue: &str, delivery: &Delivery, reason: String) {
+        if let Err(error) =
+            doxa_mq::action::dead_letter(self.settings.mq.as_ref(), queue, delivery, reason).await
+        {
+            error!(%error, debug = ?error, "failed to dead letter game event");
+            if let Err(error) = delivery.nack(true).await {
+                error!(%error, debug = ?error, "failed to requeue game event");
+            }
+        }
+    }
 
+    pub async fn start(self) {
+        let queue = doxa_mq::action::game_event_queue_name(C::COMPETITION_NAME);
         let mut consumer =
-            doxa_mq::action::get_game_event_consumer(&connection, C::COMPETITION_NAME)
-                .await
-                .unwrap();
+            doxa_mq::action::get_game_event_consumer(self.settings.mq.clone(), C::COMPETITION_NAME);
         info!(
             competition = %C::COMPETITION_NAME,
             "started game event listener",
@@ -173,16 +279,15 @@ impl<C: Competition> GameEventManager<C> {
             // and if this event is not `last_handled_event + 1` then we delay handling it
             // otherwise we check to see if there are any events sequentially after this one and
             // handle each in turn (including the current)
-            while let Some(message) = consumer.next().await {
-                // It might be easier for error handling if this was moved into it's own async fn
-                let (_, delivery) = message.expect("Error getting message");
-
+            while let Some(delivery) = consumer.next().await {
                 let span = span!(
                     Level::DEBUG,
                     "handle game event",
                     competition = C::COMPETITION_NAME
                 );
-                self.handle_game_event(delivery).instrument(span).await;
+                self.handle_delivery(&queue, delivery)
+                    .instrument(span)
+                    .await;
             }
         };
 
@@ -0,0 +1,278 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use doxa_core::{
+    chrono::{self, DateTime, Utc},
+    tokio,
+    tracing::{debug, error, info, warn},
+};
+use doxa_db::model::execution_node::ExecutionNode;
+use doxa_executor::{context::run_of_event, event::StartEvent};
+
+use crate::{
+    client::{Competition, Context},
+    error::ContextError,
+    Settings,
+};
+
+/// Execution nodes that haven't sent a heartbeat for this long are considered lost, nodes send
+/// heartbeats much more often than this.
+pub(crate) const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// How often to look for games running on lost execution nodes
+const LOST_NODE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Periodically fails and requeues the games that were running on execution nodes which have
+/// stopped sending heartbeats or have restarted, as otherwise these games would never complete.
+///
+/// This is the only way games lost with an execution node are recovered, nodes acknowledge match
+/// requests as soon as their game starts so the broker doesn't also redeliver them (see
+/// [`doxa_executor::listener::MatchRequestListener::acknowledge_on_start`]). When several servers
+/// run this the lost run is taken over by one of them through [`Context::start_game_run`].
+pub(super) struct LostGameManager<C: Competition> {
+    settings: Arc<Settings>,
+    context: Arc<Context<C>>,
+}
+
+impl<C: Competition> LostGameManager<C> {
+    pub fn new(settings: Arc<Settings>, context: Arc<Context<C>>) -> Self {
+        LostGameManager { settings, context }
+    }
+
+    pub async fn start(self) {
+        let future = async move {
+            let mut interval = tokio::time::interval(LOST_NODE_CHECK_INTERVAL);
+            // The lost runs (the game and event ID of their `_START`) that this server has taken
+            // over along with the first event ID to fail them with, or `None` once they have been
+            // handled, the game event manager may not have processed their `_RETRY` events yet
+            let mut handled = HashMap::new();
+
+            loop {
+                interval.tick().await;
+
+                if let Err(error) = self.fail_lost_games(&mut handled).await {
+                    error!(%error, debug = ?error, competition = %C::COMPETITION_NAME, "failed to check for games on lost execution nodes")
+                }
+            }
+        };
+
+        tokio::spawn(future);
+    }
+
+    async fn fail_lost_games(
+        &self,
+        handled: &mut HashMap<(i32, i32), Option<u32>>,
+    ) -> Result<(), ContextError> {
+        let since = Utc::now()
+            - chrono::Duration::from_std(HEARTBEAT_TIMEOUT).expect("heartbeat timeout too large");
+        let nodes: HashMap<String, ExecutionNode> = self
+            .context
+            .run_query(doxa_db::action::execution_node::list_nodes)
+            .await?
+            .into_iter()
+            .map(|node| (node.name.clone(), node))
+            .collect();
+
+        let competition_id = self.context.competition_id();
+        let events = self
+            .context
+            .run_query(move |conn| {
+                doxa_db::action::game::get_running_games_events_by_event_types(
+                    conn,
+                    competition_id,
+                    vec!["_START".to_string(), "_RETRY".to_string()],
+                )
+            })
+            .await?;
+
+        // Only games where `_START` is the latest of these events are running somewhere
+        let mut latest_events = HashMap::new();
+        for event in events {
+            latest_events.insert(event.game, event);
+        }
+        let starts: Vec<_> = latest_events
+            .into_iter()
+            .map(|(_, event)| event)
+            .filter(|event| event.event_type == "_START")
+            .collect();
+
+        handled.retain(|(game_id, event_id), _| {
+            starts
+                .iter()
+                .any(|event| event.game == *game_id && event.event_id == *event_id)
+        });
+
+        for event in starts {
+            let key = (event.game, event.event_id);
+            if let Some(None) = handled.get(&key) {
+                continue;
+            }
+
+            let node = match serde_json::from_value::<StartEvent>(event.payload)
+                .ok()
+                .and_then(|start| start.node)
+                .and_then(|node| nodes.get(&node))
+            {
+                Some(node) => node,
+                // Executors that don't register aren't monitored
+                None => continue,
+            };
+
+            if !is_lost(node, event.game, event.event_timestamp, since) {
+                continue;
+            }
+
+            let game_id = event.game;
+            let next_event_id = match handled.get(&key) {
+                Some(next_event_id) => *next_event_id,
+                None => {
+                    // The lost node may still have events queued so the game is failed in a new
+                    // run, this also stops other servers from failing the same run
+                    let lost_run = run_of_event(event.event_id as u32);
+                    let next_event_id =
+                        self.context.start_game_run(game_id, Some(lost_run)).await?;
+                    if next_event_id.is_none() {
+                        debug!(%game_id, node = %node.name, competition = %C::COMPETITION_NAME, "game running on a lost execution node was already failed");
+                    }
+                    handled.insert(key, next_event_id);
+                    next_event_id
+                }
+            };
+            let next_event_id = match next_event_id {
+                Some(next_event_id) => next_event_id,
+                None => continue,
+            };
+
+            match self
+                .fail_lost_game(game_id, next_event_id, node.name.clone())
+                .await
+            {
+                Ok(requeued) => {
+                    if requeued {
+                        info!(%game_id, node = %node.name, competition = %C::COMPETITION_NAME, "failed game running on a lost execution node");
+                    }
+                    handled.insert(key, None);
+                }
+                Err(error) => {
+                    error!(%error, debug = ?error, %game_id, node = %node.name, competition = %C::COMPETITION_NAME, "failed to fail game running on a lost execution node")
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns false if the game or its match request wasn't stored so it can't be requeued.
+    /// `next_event_id` is the first event ID of the run taken over from the lost node.
+    async fn fail_lost_game(
+        &self,
+        game_id: i32,
+        next_event_id: u32,
+        node: String,
+    ) -> Result<bool, ContextError> {
+        let match_request = match self
+            .context
+            .run_query(move |conn| doxa_db::action::game::get_match_request(conn, game_id))
+            .await?
+        {
+            Some(match_request) => match_request,
+            None => {
+                warn!(%game_id, %node, competition = %C::COMPETITION_NAME, "can't requeue game running on a lost execution node as its match request wasn't stored");
+                return Ok(false);
+            }
+        };
+
+        let game = match self.context.get_game_by_id(game_id).await? {
+            Some(game) => game,
+            None => return Ok(false),
+        };
+        // The `_RETRY` event of the previous attempt was handled before the lost run's `_START`
+        let attempt = game.retries as u32;
+
+        doxa_executor::retry::fail_lost_game::<C::GameClient>(
+            &self.settings.executor_settings.retry_policy,
+            self.settings.mq.clone(),
+            C::COMPETITION_NAME,
+            &match_request.data,
+            match_request.queue,
+            attempt,
+            next_event_id,
+            node,
+        )
+        .await?;
+
+        Ok(true)
+    }
+}
+
+/// Whether the game that was started by the node at `started_at` has been lost, because the node
+/// stopped sending heartbeats (before `since`) or because it has restarted since.
+fn is_lost(
+    node: &ExecutionNode,
+    game_id: i32,
+    started_at: DateTime<Utc>,
+    since: DateTime<Utc>,
+) -> bool {
+    // A node that registers again has restarted so it isn't running any of the games it started
+    // before then, unless it has reported them since (in case the clocks differ)
+    let restarted = started_at < node.registered_at && !node.running_games.contains(&game_id);
+
+    node.last_heartbeat_at < since || restarted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(registered_at: DateTime<Utc>, last_heartbeat_at: DateTime<Utc>) -> ExecutionNode {
+        ExecutionNode {
+            name: "node".to_string(),
+            registered_at,
+            last_heartbeat_at,
+            capacity: 4,
+            available: 3,
+            running_games: vec![1],
+            competitions: serde_json::json!({}),
+            drain_timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn games_on_live_nodes_are_not_lost() {
+        let now = Utc::now();
+        let node = node(now - chrono::Duration::hours(1), now);
+
+        assert!(!is_lost(
+            &node,
+            1,
+            now - chrono::Duration::minutes(5),
+            now - chrono::Duration::seconds(90)
+        ));
+    }
+
+    #[test]
+    fn games_on_nodes_without_heartbeats_are_lost() {
+        let now = Utc::now();
+        let node = node(
+            now - chrono::Duration::hours(1),
+            now - chrono::Duration::minutes(5),
+        );
+
+        assert!(is_lost(
+            &node,
+            1,
+            now - chrono::Duration::minutes(10),
+            now - chrono::Duration::seconds(90)
+        ));
+    }
+
+    #[test]
+    fn games_started_before_a_restart_are_lost_unless_reported() {
+        let now = Utc::now();
+        let node = node(now - chrono::Duration::minutes(1), now);
+        let started_at = now - chrono::Duration::minutes(10);
+        let since = now - chrono::Duration::seconds(90);
+
+        assert!(!is_lost(&node, 1, started_at, since));
+        assert!(is_lost(&node, 2, started_at, since));
+    }
+}
@@ -0,0 +1,52 @@
+use std::{sync::Arc, time::Duration};
+
+use doxa_core::{
+    chrono::{self, Utc},
+    tokio,
+    tracing::{error, info},
+};
+
+use crate::client::{Competition, Context};
+
+/// How often expired transcripts are deleted
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically deletes transcripts that are older than [`Competition::TRANSCRIPT_RETENTION`].
+pub(super) struct TranscriptRetentionManager<C: Competition> {
+    context: Arc<Context<C>>,
+}
+
+impl<C: Competition> TranscriptRetentionManager<C> {
+    pub fn new(context: Arc<Context<C>>) -> Self {
+        TranscriptRetentionManager { context }
+    }
+
+    pub async fn start(self) {
+        let retention = chrono::Duration::from_std(C::TRANSCRIPT_RETENTION)
+            .expect("transcript retention was too large");
+
+        let future = async move {
+            let mut interval = tokio::time::interval(RETENTION_CHECK_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                match self
+                    .context
+                    .delete_transcripts_before(Utc::now() - retention)
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(deleted) => {
+                        info!(%deleted, competition = %C::COMPETITION_NAME, "deleted expired transcripts")
+                    }
+                    Err(error) => {
+                        error!(%error, debug = ?error, competition = %C::COMPETITION_NAME, "failed to delete expired transcripts")
+                    }
+                }
+            }
+        };
+
+        tokio::spawn(future);
+    }
+}
@@ -1,7 +1,11 @@
+pub(crate) mod admin;
 pub(crate) mod agent;
+pub(crate) mod enrollment;
 pub(crate) mod game;
 pub(crate) mod leaderboard;
 pub(crate) mod limits;
+pub(crate) mod node;
+pub(crate) mod team;
 pub(crate) mod upload;
 pub(crate) mod user;
 
@@ -0,0 +1,429 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time,
+};
+
+use doxa_auth::{
+    error::UserNotAdmin,
+    guard::AuthGuard,
+    role::{CompetitionPermission, CompetitionRoles},
+};
+use doxa_core::{
+    actix_web::{
+        http::header::{CacheControl, CacheDirective},
+        web,
+    },
+    chrono::{Duration, Utc},
+    error::{HttpResponse, RespondableErrorWrapper},
+    EndpointResult,
+};
+use doxa_db::{
+    diesel::{Connection, PgConnection},
+    model::audit::{actions, competition_target, InsertableAuditEntry},
+    DieselError, PgPool,
+};
+use doxa_executor::{
+    drain::{DEFAULT_DRAIN_TIMEOUT, MAX_DRAIN_TIMEOUT},
+    event::StartEvent,
+};
+use doxa_mq::{
+    model::{PauseEvent, Priority},
+    MQ,
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    client::{Competition, Context},
+    context::run_query_with_pool,
+    error::{CompetitionNotFound, ContextError, DrainTimeoutTooLong},
+    Settings,
+};
+
+use super::response::{
+    CompetitionQueuesResponse, DrainResponse, NodeGamesResponse, PausedResponse, QueueResponse,
+    QueuesResponse, ThroughputResponse,
+};
+
+/// The windows (in seconds) that throughput is reported over.
+const THROUGHPUT_WINDOWS_SECS: [i64; 3] = [5 * 60, 60 * 60, 24 * 60 * 60];
+
+/// The state shared by the `/admin/queues` routes, these cover every competition so they are
+/// registered once rather than under each competition's scope.
+pub(crate) struct QueueDashboard {
+    mq: MQ,
+    pg_pool: Arc<PgPool>,
+    /// The name, ID and declared execution profiles of every competition that was started
+    competitions: Vec<(String, i32, Vec<String>)>,
+}
+
+impl QueueDashboard {
+    pub(crate) fn new(
+        mq: MQ,
+        pg_pool: Arc<PgPool>,
+        competitions: Vec<(String, i32, Vec<String>)>,
+    ) -> Self {
+        QueueDashboard {
+            mq,
+            pg_pool,
+            competitions,
+        }
+    }
+
+    fn competition_id(&self, name: &str) -> Result<i32, CompetitionNotFound> {
+        self.competitions
+            .iter()
+            .find(|(competition, _, _)| competition == name)
+            .map(|(_, id, _)| *id)
+            .ok_or_else(|| CompetitionNotFound {
+                name: name.to_string(),
+            })
+    }
+
+    async fn run_query<
+        T: Send + 'static,
+        F: FnOnce(&PgConnection) -> Result<T, DieselError> + Send + 'static,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, ContextError> {
+        run_query_with_pool(&self.pg_pool, f).await
+    }
+
+    async fn competition_queues(
+        &self,
+        name: &str,
+        competition_id: i32,
+        execution_profiles: &[String],
+    ) -> Result<CompetitionQueuesResponse, ContextError> {
+        let mut queues = Vec::new();
+        for execution_profile in execution_profiles {
+            for priority in Priority::ALL {
+                let queue =
+                    doxa_mq::action::match_request_queue_name(name, execution_profile, priority);
+                let stats = self.mq.queue_stats(&queue).await?;
+                let dead_letters = self
+                    .mq
+                    .queue_stats(&doxa_mq::action::dead_letter_queue_name(&queue))
+                    .await?;
+
+                queues.push(QueueResponse {
+                    execution_profile: execution_profile.clone(),
+                    priority,
+                    queue,
+                    depth: stats.messages,
+                    consumers: stats.consumers,
+                    dead_letters: dead_letters.messages,
+                });
+            }
+        }
+
+        let paused = self
+            .run_query(move |conn| {
+                doxa_db::action::competition::get_competition_by_id(conn, competition_id)
+            })
+            .await?
+            .map(|competition| competition.paused)
+            .unwrap_or(false);
+
+        let now = Utc::now();
+
+        let oldest_queued_at = self
+            .run_query(move |conn| {
+                doxa_db::action::game::get_oldest_queued_at(conn, competition_id)
+            })
+            .await?;
+
+        let longest_window = Duration::seconds(*THROUGHPUT_WINDOWS_SECS.iter().max().unwrap());
+        let times = self
+            .run_query(move |conn| {
+                doxa_db::action::game::get_game_times_since(
+                    conn,
+                    competition_id,
+                    now - longest_window,
+                )
+            })
+            .await?;
+
+        let throughput = THROUGHPUT_WINDOWS_SECS
+            .iter()
+            .map(|&window_secs| {
+                let since = now - Duration::seconds(window_secs);
+
+                let waits: Vec<i64> = times
+                    .iter()
+                    .filter_map(|(queued_at, started_at, _)| match started_at {
+                        Some(started_at) if *started_at >= since => {
+                            Some((*started_at - *queued_at).num_seconds())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let completed = times
+                    .iter()
+                    .filter_map(|(_, _, completed_at)| *completed_at)
+                    .filter(|completed_at| *completed_at >= since)
+                    .count();
+
+                ThroughputResponse {
+                    window_secs,
+                    started: waits.len(),
+                    completed,
+                    mean_wait_secs: if waits.is_empty() {
+                        None
+                    } else {
+                        Some(waits.iter().sum::<i64>() as f64 / waits.len() as f64)
+                    },
+                }
+            })
+            .collect();
+
+        // A game that is waiting to be retried has started but isn't running anywhere, so only
+        // games where `_START` is the latest of these events are in flight
+        let events = self
+            .run_query(move |conn| {
+                doxa_db::action::game::get_running_games_events_by_event_types(
+                    conn,
+                    competition_id,
+                    vec!["_START".to_string(), "_RETRY".to_string()],
+                )
+            })
+            .await?;
+
+        let mut latest_events = HashMap::new();
+        for event in events {
+            latest_events.insert(event.game, event);
+        }
+
+        let mut in_flight: BTreeMap<Option<String>, Vec<i32>> = BTreeMap::new();
+        for (game_id, event) in latest_events {
+            if event.event_type != "_START" {
+                continue;
+            }
+
+            let node = serde_json::from_value::<StartEvent>(event.payload)
+                .ok()
+                .and_then(|start| start.node);
+            in_flight.entry(node).or_default().push(game_id);
+        }
+
+        Ok(CompetitionQueuesResponse {
+            competition: name.to_string(),
+            paused,
+            queues,
+            in_flight: in_flight
+                .into_iter()
+                .map(|(node, mut games)| {
+                    games.sort_unstable();
+                    NodeGamesResponse { node, games }
+                })
+                .collect(),
+            oldest_queued_at,
+            oldest_queued_age_secs: oldest_queued_at
+                .map(|queued_at| (now - queued_at).num_seconds()),
+            throughput,
+        })
+    }
+
+    /// Admins can pause every competition, other users need the
+    /// [`CompetitionPermission::Pause`] permission (i.e. be an organiser) in the competition.
+    async fn check_can_pause(
+        &self,
+        user: &AuthGuard<()>,
+        name: &str,
+    ) -> Result<(), RespondableErrorWrapper> {
+        if user.admin() {
+            return Ok(());
+        }
+
+        let user_id = user.id();
+        let competition = name.to_string();
+        let roles = self
+            .run_query(move |conn| CompetitionRoles::load(conn, user_id, false, &competition))
+            .await?;
+
+        roles.require(CompetitionPermission::Pause)?;
+
+        Ok(())
+    }
+
+    async fn set_paused(&self, name: &str, paused: bool, actor: Option<i32>) -> EndpointResult {
+        let competition_id = self.competition_id(name)?;
+
+        let audit_entry = InsertableAuditEntry::new(
+            actor,
+            if paused {
+                actions::PAUSE_COMPETITION
+            } else {
+                actions::RESUME_COMPETITION
+            },
+        )
+        .with_target(competition_target(name));
+
+        self.run_query(move |conn| {
+            conn.transaction(|| {
+                doxa_db::action::competition::set_competition_paused(conn, competition_id, paused)?;
+                doxa_db::action::audit::record(conn, &audit_entry)
+            })
+        })
+        .await?;
+
+        doxa_mq::action::emit_pause_event(self.mq.as_ref(), name, &PauseEvent { paused })
+            .await
+            .map_err(ContextError::from)?;
+
+        Ok(HttpResponse::Ok().json(PausedResponse { paused }))
+    }
+}
+
+/// Registers the `/admin/queues` routes, these are only available to admins except for pausing
+/// and resuming which competition organisers can also do.
+pub(crate) fn configure_queue_routes(service: &mut web::ServiceConfig) {
+    service.route("", web::get().to(queues));
+    service.route("/{competition}/pause", web::post().to(pause_competition));
+    service.route("/{competition}/resume", web::post().to(resume_competition));
+}
+
+/// The route for `/admin/queues`, this reports the state of the match request queues and the
+/// games table for every competition.
+async fn queues(user: AuthGuard<()>, dashboard: web::Data<QueueDashboard>) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    let mut competitions = Vec::with_capacity(dashboard.competitions.len());
+    for (name, competition_id, execution_profiles) in dashboard.competitions.iter() {
+        competitions.push(
+            dashboard
+                .competition_queues(name, *competition_id, execution_profiles)
+                .await?,
+        );
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(QueuesResponse { competitions }))
+}
+
+/// The route for `/admin/queues/{competition}/pause`.
+/// Executors finish the games they are already running but don't take any more match requests.
+async fn pause_competition(
+    path: web::Path<String>,
+    user: AuthGuard<()>,
+    dashboard: web::Data<QueueDashboard>,
+) -> EndpointResult {
+    let name = path.into_inner();
+    dashboard.check_can_pause(&user, &name).await?;
+
+    dashboard.set_paused(&name, true, user.id()).await
+}
+
+/// The route for `/admin/queues/{competition}/resume`.
+async fn resume_competition(
+    path: web::Path<String>,
+    user: AuthGuard<()>,
+    dashboard: web::Data<QueueDashboard>,
+) -> EndpointResult {
+    let name = path.into_inner();
+    dashboard.check_can_pause(&user, &name).await?;
+
+    dashboard.set_paused(&name, false, user.id()).await
+}
+
+#[derive(Deserialize)]
+pub struct DrainRequest {
+    /// How long running games are given to finish before they are cancelled and requeued, this
+    /// defaults to [`DEFAULT_DRAIN_TIMEOUT`] and can't be more than [`MAX_DRAIN_TIMEOUT`].
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+impl DrainRequest {
+    /// The body of drain requests is optional.
+    pub(crate) fn timeout(
+        request: Option<web::Json<DrainRequest>>,
+    ) -> Result<time::Duration, DrainTimeoutTooLong> {
+        let timeout = request
+            .and_then(|request| request.timeout_secs)
+            .map(time::Duration::from_secs)
+            .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+
+        if timeout > MAX_DRAIN_TIMEOUT {
+            return Err(DrainTimeoutTooLong {
+                max_secs: MAX_DRAIN_TIMEOUT.as_secs(),
+            });
+        }
+
+        Ok(timeout)
+    }
+}
+
+/// Registers the `/admin/drain` routes which drain the server's own execution managers, execution
+/// nodes are drained through `/admin/nodes/{name}/drain` instead.
+pub(crate) fn configure_drain_routes(service: &mut web::ServiceConfig) {
+    service.route("", web::get().to(drain_status));
+    service.route("", web::post().to(drain));
+}
+
+fn drain_response(settings: &Settings) -> DrainResponse {
+    DrainResponse {
+        draining: settings.drain.draining(),
+        running_games: settings.drain.running_games(),
+        deadline_secs: settings
+            .drain
+            .time_until_deadline()
+            .map(|deadline| deadline.as_secs()),
+    }
+}
+
+/// The route for `GET /admin/drain`.
+async fn drain_status(user: AuthGuard<()>, settings: web::Data<Settings>) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(drain_response(&settings)))
+}
+
+/// The route for `POST /admin/drain`.
+/// The server's executors stop taking match requests and the games that are still running once
+/// the timeout passes are cancelled and requeued. This can't be undone without restarting.
+async fn drain(
+    user: AuthGuard<()>,
+    settings: web::Data<Settings>,
+    db_pool: web::Data<PgPool>,
+    request: Option<web::Json<DrainRequest>>,
+) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    let timeout = DrainRequest::timeout(request)?;
+
+    doxa_auth::audit::record(
+        &db_pool,
+        InsertableAuditEntry::new(user.id(), actions::DRAIN_SERVER)
+            .with_metadata(json!({ "timeout_secs": timeout.as_secs() })),
+    )
+    .await?;
+
+    settings.drain.drain(timeout);
+
+    Ok(HttpResponse::Ok().json(drain_response(&settings)))
+}
+
+/// The default route for `_paused`, executors use this to find out whether the competition was
+/// paused before they subscribed to pause events.
+pub async fn competition_paused<C: Competition + ?Sized>(
+    context: web::Data<Context<C>>,
+) -> EndpointResult {
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(PausedResponse {
+            paused: context.is_paused().await?,
+        }))
+}
@@ -1,15 +1,66 @@
-use doxa_auth::guard::AuthGuard;
+use doxa_auth::{api_token::TokenScope, guard::AuthGuard};
 use doxa_core::{actix_web::web, error::HttpResponse, EndpointResult};
+use doxa_db::model::{
+    audit::{actions, agent_target, InsertableAuditEntry},
+    storage::AgentUpload,
+};
 use doxa_user::PublicBasicUserInfo;
 use serde_json::json;
 
 use crate::{
-    client::{Competition, Context},
-    error::{AgentAlreadyActive, AgentNotActive, AgentNotFound, TooManyActivations, UserNotOwner},
+    client::{Competition, CompetitionPermission, CompetitionRoleGuard, Context},
+    error::{AgentAlreadyActive, AgentNotActive, AgentNotFound, ContextError, UserNotOwner},
 };
 
 use super::limits::CompetitionLimits;
 
+/// Whether the actor owns the agent (either directly or through their team).
+pub(crate) async fn is_owner<C: Competition + ?Sized>(
+    context: &Context<C>,
+    actor: Option<i32>,
+    agent: &AgentUpload,
+) -> Result<bool, ContextError> {
+    match actor {
+        Some(actor) => context.is_agent_owner(actor, agent).await,
+        None => Ok(false),
+    }
+}
+
+/// Records changes made to other users' agents (e.g. by moderators) and activations that
+/// bypassed the activation limiter in the audit log.
+pub(crate) async fn audit_agent_action<C: Competition + ?Sized>(
+    context: &Context<C>,
+    actor: Option<i32>,
+    agent: &AgentUpload,
+    action: &str,
+    bypassed_limit: bool,
+) -> Result<(), ContextError> {
+    let metadata =
+        json!({ "competition": C::COMPETITION_NAME, "owner": agent.owner, "team": agent.team });
+
+    if !is_owner(context, actor, agent).await? {
+        context
+            .record_audit(
+                InsertableAuditEntry::new(actor, action)
+                    .with_target(agent_target(&agent.id))
+                    .with_metadata(metadata.clone()),
+            )
+            .await?;
+    }
+
+    if bypassed_limit {
+        context
+            .record_audit(
+                InsertableAuditEntry::new(actor, actions::ACTIVATE_BYPASSING_LIMIT)
+                    .with_target(agent_target(&agent.id))
+                    .with_metadata(metadata),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
 /// The default route for `_agent/{agent_id}/owner`.
 pub async fn agent_owner<C: Competition + ?Sized>(
     path: web::Path<String>,
@@ -98,7 +149,7 @@ pub async fn agent_score<C: Competition + ?Sized>(
 pub async fn reactivate_agent<C: Competition + ?Sized>(
     path: web::Path<String>,
     context: web::Data<Context<C>>,
-    user: AuthGuard,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
     limits: web::Data<CompetitionLimits>,
 ) -> EndpointResult {
     let agent_id = path.into_inner();
@@ -108,27 +159,39 @@ pub async fn reactivate_agent<C: Competition + ?Sized>(
         .await?
         .ok_or(AgentNotFound)?;
 
-    if !(Some(agent.owner) == user.id() || user.admin()) {
+    let actor = user.scoped_id(&TokenScope::Activate(C::COMPETITION_NAME.to_string()))?;
+    if !(is_owner(&context, actor, &agent).await?
+        || user.inner_ref().has(CompetitionPermission::ManageAgents))
+    {
         return Err(UserNotOwner.into());
     }
 
-    if !user.admin() {
+    let bypass_limits = user.inner_ref().has(CompetitionPermission::BypassLimits);
+    let mut response = HttpResponse::Ok();
+    if !bypass_limits {
         limits
-            .activations
-            .get_permit(format!("{}-{}", C::COMPETITION_NAME, agent.owner))
-            .await?
-            .map_err(TooManyActivations::from)?;
+            .take_activation(C::COMPETITION_NAME, &agent.agent_owner(), &mut response)
+            .await?;
     }
     context.activate_agent(agent_id).await?;
 
-    Ok(HttpResponse::Ok().json(json!({})))
+    audit_agent_action(
+        &context,
+        actor,
+        &agent,
+        actions::REACTIVATE_AGENT,
+        bypass_limits,
+    )
+    .await?;
+
+    Ok(response.json(json!({})))
 }
 
 /// The default route for `_agent/{agent_id}/activate`.
 pub async fn activate_agent<C: Competition + ?Sized>(
     path: web::Path<String>,
     context: web::Data<Context<C>>,
-    user: AuthGuard,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
     limits: web::Data<CompetitionLimits>,
 ) -> EndpointResult {
     let agent_id = path.into_inner();
@@ -138,7 +201,10 @@ pub async fn activate_agent<C: Competition + ?Sized>(
         .await?
         .ok_or(AgentNotFound)?;
 
-    if !(Some(agent.owner) == user.id() || user.admin()) {
+    let actor = user.scoped_id(&TokenScope::Activate(C::COMPETITION_NAME.to_string()))?;
+    if !(is_owner(&context, actor, &agent).await?
+        || user.inner_ref().has(CompetitionPermission::ManageAgents))
+    {
         return Err(UserNotOwner.into());
     }
 
@@ -146,24 +212,33 @@ pub async fn activate_agent<C: Competition + ?Sized>(
         return Err(AgentAlreadyActive.into());
     }
 
-    if !user.admin() {
+    let bypass_limits = user.inner_ref().has(CompetitionPermission::BypassLimits);
+    let mut response = HttpResponse::Ok();
+    if !bypass_limits {
         limits
-            .activations
-            .get_permit(format!("{}-{}", C::COMPETITION_NAME, agent.owner))
-            .await?
-            .map_err(TooManyActivations::from)?;
+            .take_activation(C::COMPETITION_NAME, &agent.agent_owner(), &mut response)
+            .await?;
     }
 
     context.activate_agent(agent_id).await?;
 
-    Ok(HttpResponse::Ok().json(json!({})))
+    audit_agent_action(
+        &context,
+        actor,
+        &agent,
+        actions::ACTIVATE_AGENT,
+        bypass_limits,
+    )
+    .await?;
+
+    Ok(response.json(json!({})))
 }
 
 /// The default route for `_agent/{agent_id}/deactivate`.
 pub async fn deactivate_agent<C: Competition + ?Sized>(
     path: web::Path<String>,
     context: web::Data<Context<C>>,
-    user: AuthGuard,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
 ) -> EndpointResult {
     let agent_id = path.into_inner();
 
@@ -172,7 +247,10 @@ pub async fn deactivate_agent<C: Competition + ?Sized>(
         .await?
         .ok_or(AgentNotFound)?;
 
-    if !(Some(agent.owner) == user.id() || user.admin()) {
+    let actor = user.scoped_id(&TokenScope::Activate(C::COMPETITION_NAME.to_string()))?;
+    if !(is_owner(&context, actor, &agent).await?
+        || user.inner_ref().has(CompetitionPermission::ManageAgents))
+    {
         return Err(UserNotOwner.into());
     }
 
@@ -182,5 +260,7 @@ pub async fn deactivate_agent<C: Competition + ?Sized>(
 
     context.deactivate_agent(agent_id).await?;
 
+    audit_agent_action(&context, actor, &agent, actions::DEACTIVATE_AGENT, false).await?;
+
     Ok(HttpResponse::Ok().json(json!({})))
 }
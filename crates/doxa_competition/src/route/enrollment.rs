@@ -0,0 +1,184 @@
+use doxa_auth::{
+    enrollment,
+    error::{CompetitionNotFound, InviteNotFound},
+    guard::AuthGuard,
+    role::CompetitionPermission,
+};
+use doxa_core::{actix_web::web, chrono::Utc, error::HttpResponse, EndpointResult};
+use doxa_db::{
+    action,
+    model::audit::{actions, competition_target, invite_target, InsertableAuditEntry},
+    PgPool,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::client::{Competition, CompetitionRoleGuard, Context};
+
+use super::response::{InviteResponse, InvitesResponse};
+
+#[derive(Deserialize)]
+pub struct EnrollRequest {
+    /// Not needed when the competition has open enrollment
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    pub max_uses: i32,
+    /// The invite never expires if this isn't set, otherwise it must be at most
+    /// [`doxa_auth::enrollment::MAX_INVITE_EXPIRY_DAYS`]
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+    /// e.g. `warwick.ac.uk`
+    #[serde(default)]
+    pub email_domain: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SetOpenEnrollmentRequest {
+    pub open_enrollment: bool,
+}
+
+/// The default route for `POST _enroll`, this enrolls the current user with an invite code or
+/// through open enrollment.
+pub async fn enroll<C: Competition + ?Sized>(
+    pool: web::Data<PgPool>,
+    body: web::Json<EnrollRequest>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let code = body.into_inner().code;
+
+    let conn = web::block(move || pool.get()).await??;
+    web::block(move || {
+        enrollment::self_enroll(&conn, user_id, C::COMPETITION_NAME, code.as_deref())
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(json!({ "competition": C::COMPETITION_NAME })))
+}
+
+/// The default route for `GET _enrollment`.
+pub async fn enrollment_status<C: Competition + ?Sized>(
+    context: web::Data<Context<C>>,
+) -> EndpointResult {
+    let competition_id = context.competition_id();
+    let competition = context
+        .run_query(move |conn| action::competition::get_competition_by_id(conn, competition_id))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "open_enrollment": competition.map(|c| c.open_enrollment).unwrap_or(false),
+    })))
+}
+
+/// The default route for `POST _enrollment`, this opens or closes enrollment.
+pub async fn set_open_enrollment<C: Competition + ?Sized>(
+    context: web::Data<Context<C>>,
+    body: web::Json<SetOpenEnrollmentRequest>,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
+) -> EndpointResult {
+    user.inner_ref()
+        .require(CompetitionPermission::ManageEnrollment)?;
+    let actor = Some(user.id_required()?);
+    let open_enrollment = body.open_enrollment;
+
+    let competition_id = context.competition_id();
+    context
+        .run_query(move |conn| {
+            action::competition::set_open_enrollment(conn, competition_id, open_enrollment)
+        })
+        .await?;
+
+    context
+        .record_audit(
+            InsertableAuditEntry::new(actor, actions::SET_OPEN_ENROLLMENT)
+                .with_target(competition_target(C::COMPETITION_NAME))
+                .with_metadata(json!({ "open_enrollment": open_enrollment })),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "open_enrollment": open_enrollment })))
+}
+
+/// The default route for `GET _invites`.
+pub async fn list_invites<C: Competition + ?Sized>(
+    context: web::Data<Context<C>>,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
+) -> EndpointResult {
+    user.inner_ref()
+        .require(CompetitionPermission::ManageEnrollment)?;
+
+    let competition_id = context.competition_id();
+    let now = Utc::now();
+    let invites = context
+        .run_query(move |conn| action::competition::list_competition_invites(conn, competition_id))
+        .await?
+        .into_iter()
+        .map(|invite| InviteResponse::new(invite, now))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(InvitesResponse { invites }))
+}
+
+/// The default route for `POST _invites`.
+pub async fn create_invite<C: Competition + ?Sized>(
+    pool: web::Data<PgPool>,
+    body: web::Json<CreateInviteRequest>,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
+) -> EndpointResult {
+    user.inner_ref()
+        .require(CompetitionPermission::ManageEnrollment)?;
+    let actor = Some(user.id_required()?);
+    let body = body.into_inner();
+
+    let conn = web::block(move || pool.get()).await??;
+    let invite = web::block(move || {
+        let competition = action::competition::get_competition_by_name(&conn, C::COMPETITION_NAME)?
+            .ok_or(CompetitionNotFound)?;
+
+        enrollment::create_invite(
+            &conn,
+            &competition,
+            actor,
+            body.max_uses,
+            body.expires_in_days,
+            body.email_domain,
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(InviteResponse::new(invite, Utc::now())))
+}
+
+/// The default route for `POST _invites/{invite_id}/revoke`.
+pub async fn revoke_invite<C: Competition + ?Sized>(
+    path: web::Path<i32>,
+    context: web::Data<Context<C>>,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
+) -> EndpointResult {
+    user.inner_ref()
+        .require(CompetitionPermission::ManageEnrollment)?;
+    let actor = Some(user.id_required()?);
+    let invite_id = path.into_inner();
+
+    let competition_id = context.competition_id();
+    context
+        .run_query(move |conn| {
+            action::competition::revoke_invite(conn, competition_id, invite_id, Utc::now())
+        })
+        .await?
+        .ok_or(InviteNotFound)?;
+
+    context
+        .record_audit(
+            InsertableAuditEntry::new(actor, actions::REVOKE_INVITE)
+                .with_target(invite_target(invite_id))
+                .with_metadata(json!({ "competition": C::COMPETITION_NAME })),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
@@ -1,30 +1,39 @@
-use doxa_auth::guard::AuthGuard;
+use doxa_auth::{error::UserNotAdmin, guard::AuthGuard};
 use doxa_core::{
     actix_web::{
-        http::header::{CacheControl, CacheDirective},
+        http::header::{CacheControl, CacheDirective, ContentType},
         web,
     },
-    error::HttpResponse,
+    error::{HttpResponse, RespondableErrorWrapper},
     EndpointResult,
 };
 
-use doxa_db::model::game::Game;
+use doxa_db::model::game::{Game, GameParticipantUser};
 use doxa_executor::{
     client::GameClient,
-    event::{ErrorEvent, ForfeitEvent, StartEvent},
+    context::run_of_event,
+    event::{
+        AgentLogsEvent, CancelledEvent, CheckpointEvent, ErrorEvent, ForfeitEvent, ResumeEvent,
+        RetryEvent, StartEvent, TranscriptEvent,
+    },
+    transcript::{join_chunks, Transcript},
 };
 use serde_json::json;
 
 use crate::{
-    client::{Competition, Context},
-    error::{GameNotFound, IncorrectEventFormatting, UnknownEventType},
+    client::{Competition, CompetitionPermission, CompetitionRoleGuard, Context},
+    error::{
+        AgentNotFound, ContextError, GameNotFound, IncorrectEventFormatting, InvalidTranscript,
+        TranscriptNotFound, UnknownEventType, UserNotOwner,
+    },
 };
 
 use serde::Deserialize;
 
 use super::response::{
-    CancelledResponse, GameEventResponse, GameEventsResponse, GameResponse, GameResultResponse,
-    PlayersResponse, PlayersResponsePlayer,
+    AgentLogsResponse, CancelledResponse, CheckpointResponse, CheckpointResponseCheckpoint,
+    GameEventResponse, GameEventsResponse, GameResponse, GameResultResponse, PlayersResponse,
+    PlayersResponsePlayer,
 };
 
 pub const ONE_DAY_SECONDS: u32 = 60 * 60 * 24;
@@ -54,6 +63,7 @@ pub async fn game<C: Competition + ?Sized>(
         started_at: game.started_at,
         completed_at: game.completed_at,
         outdated: game.outdated,
+        retries: game.retries,
     }))
 }
 
@@ -75,13 +85,64 @@ pub async fn game_cancelled<C: Competition + ?Sized>(
     }))
 }
 
+/// The default route for `POST _game/{game_id}/checkpoint`.
+/// Executors use this before running a game to find out where to start from (in case it was
+/// interrupted), each request starts a new run of the game (see [`Context::start_game_run`]).
+///
+/// The checkpoint contains the seed and state of the game and starting a run changes the game (a
+/// run that is still going would no longer be recovered if its node was lost) so this is only
+/// available to the system account and admins.
+pub async fn game_checkpoint<C: Competition + ?Sized>(
+    path: web::Path<i32>,
+    user: AuthGuard<()>,
+    context: web::Data<Context<C>>,
+) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+    let game_id = path.into_inner();
+
+    let next_event_id = context
+        .start_game_run(game_id, None)
+        .await?
+        .ok_or(GameNotFound { game_id })?;
+
+    let checkpoint = match context
+        .get_latest_event_by_type(game_id, "_CHECKPOINT".to_string())
+        .await?
+    {
+        Some(event) => {
+            let event_id = event.event_id;
+            let payload: CheckpointEvent =
+                serde_json::from_value(event.payload).map_err(|e| IncorrectEventFormatting {
+                    source: e,
+                    event_id: event_id as i32,
+                })?;
+
+            Some(CheckpointResponseCheckpoint {
+                event_id,
+                seed: payload.seed,
+                state: payload.state,
+            })
+        }
+        None => None,
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(CheckpointResponse {
+            next_event_id,
+            checkpoint,
+        }))
+}
+
 // TODO: get a single event endpoint
 // Also maybe extract the logic for mapping events into either a trait or at the very least a function
 
 /// The default route for `_game/{game_id}/events`.
 pub async fn game_events<C: Competition + ?Sized>(
     path: web::Path<i32>,
-    user: Option<AuthGuard<()>>,
+    user: Option<AuthGuard<CompetitionRoleGuard<C>>>,
     params: web::Query<GameEventsParams>,
     context: web::Data<Context<C>>,
 ) -> EndpointResult {
@@ -105,21 +166,28 @@ pub async fn game_events<C: Competition + ?Sized>(
         }
     };
 
-    let (is_admin, agent_id) = user
-            .map(|user| {
-                let agent_id = participants.iter().find(|p| Some(p.user) == user.id()).map(|p| {
-                    // The current user was a participant in the game, we are now finding their
-                    // agent ID in game which is equal to the position within the Vec.
-                    start_event.payload
-                        .agents
-                        .iter()
-                        .position(|agent| agent == &p.agent)
-                        .expect("agent was in the participant list but not in the list of agents in the start message")
-                });
+    let (view_private, agent_id) = match user {
+        Some(user) => {
+            let agent_id = match user.id() {
+                Some(user_id) => {
+                    owned_agent_index(
+                        &context,
+                        user_id,
+                        &participants,
+                        &start_event.payload.agents,
+                    )
+                    .await?
+                }
+                None => None,
+            };
 
-                (user.admin(), agent_id)
-            })
-            .unwrap_or((false, None));
+            (
+                user.inner_ref().has(CompetitionPermission::ViewPrivate),
+                agent_id,
+            )
+        }
+        None => (false, None),
+    };
 
     let events = if let Some(event_type) = params.event_type.clone() {
         context
@@ -141,7 +209,11 @@ pub async fn game_events<C: Competition + ?Sized>(
                     }
                 })?;
 
-                event.payload = json!({ "agents": payload.agents });
+                // Seeds are strings as they don't fit in a JavaScript number
+                event.payload = json!({
+                    "agents": payload.agents,
+                    "seed": payload.seed.map(|seed| seed.to_string()),
+                });
 
                 event
             }
@@ -152,9 +224,43 @@ pub async fn game_events<C: Competition + ?Sized>(
                 event
             }
             "_CANCELLED" => {
-                event.payload = serde_json::Value::Null;
+                let payload: CancelledEvent =
+                    serde_json::from_value(event.payload).map_err(|e| {
+                        IncorrectEventFormatting {
+                            source: e,
+                            event_id,
+                        }
+                    })?;
+
+                event.payload = json!({ "requeued": payload.requeued });
+                event
+            }
+            "_RETRY" => {
+                let payload: RetryEvent = serde_json::from_value(event.payload).map_err(|e| {
+                    IncorrectEventFormatting {
+                        source: e,
+                        event_id,
+                    }
+                })?;
+
+                event.payload = json!({ "attempt": payload.attempt });
+                event
+            }
+            "_RESUME" => {
+                let payload: ResumeEvent = serde_json::from_value(event.payload).map_err(|e| {
+                    IncorrectEventFormatting {
+                        source: e,
+                        event_id,
+                    }
+                })?;
+
+                event.payload = json!({ "checkpoint": payload.checkpoint_event_id });
                 event
             }
+            // Checkpoint state is internal to the game client
+            "_CHECKPOINT" => continue,
+            // Logs and transcripts are only available through their own routes
+            "_LOGS" | "_TRANSCRIPT" => continue,
             "_FORFEIT" => {
                 let payload: ForfeitEvent = serde_json::from_value(event.payload).map_err(|e| {
                     IncorrectEventFormatting {
@@ -163,8 +269,8 @@ pub async fn game_events<C: Competition + ?Sized>(
                     }
                 })?;
 
-                // Admins or the owner of the agent
-                if is_admin || agent_id == Some(payload.agent_id) {
+                // Users who can view private data or the owner of the agent
+                if view_private || agent_id == Some(payload.agent_id) {
                     event.payload = json!({ "agent": payload.agent_id, "stderr": payload.stderr, "reason": payload.error_message });
                 } else {
                     event.payload = json!({ "agent": payload.agent_id });
@@ -173,8 +279,8 @@ pub async fn game_events<C: Competition + ?Sized>(
                 event
             }
             "_ERROR" => {
-                // Admins get the full error
-                if is_admin {
+                // Users who can view private data get the full error
+                if view_private {
                     let payload: ErrorEvent =
                         serde_json::from_value(event.payload).map_err(|e| {
                             IncorrectEventFormatting {
@@ -197,7 +303,7 @@ pub async fn game_events<C: Competition + ?Sized>(
                             event_id,
                         }
                     })?;
-                if let Some(payload) = C::event_filter(payload, is_admin, agent_id) {
+                if let Some(payload) = C::event_filter(payload, view_private, agent_id) {
                     event.payload = payload;
                     event
                 } else {
@@ -227,6 +333,171 @@ pub async fn game_events<C: Competition + ?Sized>(
     }))
 }
 
+/// Checks that the user is allowed to view the private data (e.g. logs) of the agent at
+/// `agent_index` within a game, this is only the owner of the agent and users with the
+/// [`CompetitionPermission::ViewPrivate`] permission.
+async fn check_can_view_agent<C: Competition + ?Sized>(
+    context: &Context<C>,
+    user: &AuthGuard<CompetitionRoleGuard<C>>,
+    game_id: i32,
+    agent_index: usize,
+) -> Result<(), RespondableErrorWrapper> {
+    if context.get_game_by_id(game_id).await?.is_none() {
+        return Err(GameNotFound { game_id }.into());
+    }
+
+    let start_event = context
+        .get_start_event(game_id)
+        .await?
+        .ok_or(AgentNotFound)?;
+
+    let agent_id = start_event
+        .payload
+        .agents
+        .get(agent_index)
+        .ok_or(AgentNotFound)?
+        .clone();
+
+    let agent = context.get_agent(agent_id).await?.ok_or(AgentNotFound)?;
+
+    if user.inner_ref().has(CompetitionPermission::ViewPrivate) {
+        return Ok(());
+    }
+
+    match user.id() {
+        Some(user_id) if context.is_agent_owner(user_id, &agent).await? => Ok(()),
+        _ => Err(UserNotOwner.into()),
+    }
+}
+
+/// The agent ID within the game of the user's agent (or their team's, see
+/// [`Context::is_agent_owner`]) if they were a participant, which is its position in `agents`.
+async fn owned_agent_index<C: Competition + ?Sized>(
+    context: &Context<C>,
+    user_id: i32,
+    participants: &[GameParticipantUser],
+    agents: &[String],
+) -> Result<Option<usize>, ContextError> {
+    for participant in participants {
+        let agent = match context.get_agent(participant.agent.clone()).await? {
+            Some(agent) => agent,
+            None => continue,
+        };
+
+        if context.is_agent_owner(user_id, &agent).await? {
+            return Ok(Some(
+                agents
+                    .iter()
+                    .position(|agent| agent == &participant.agent)
+                    .expect("agent was in the participant list but not in the list of agents in the start message"),
+            ));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The default route for `_game/{game_id}/logs/{agent_index}`, which returns the logs of the latest
+/// run of the game.
+/// This is only viewable by the owner of the agent and users who can view private data.
+pub async fn game_agent_logs<C: Competition + ?Sized>(
+    path: web::Path<(i32, usize)>,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
+    context: web::Data<Context<C>>,
+) -> EndpointResult {
+    let (game_id, agent_index) = path.into_inner();
+
+    check_can_view_agent(&context, &user, game_id, agent_index).await?;
+
+    let mut logs = Vec::new();
+    for event in context
+        .get_game_events_by_event_type(game_id, "_LOGS".to_string())
+        .await?
+    {
+        let event_id = event.event_id;
+        let payload: AgentLogsEvent =
+            serde_json::from_value(event.payload).map_err(|e| IncorrectEventFormatting {
+                source: e,
+                event_id,
+            })?;
+
+        if payload.agent_id == agent_index {
+            logs.push((event_id, payload));
+        }
+    }
+
+    // Each run of the game (e.g. when it is retried) emits its own logs, only the logs of the
+    // latest run are returned. The events are ordered by event ID so the last one is the latest.
+    let latest_run = logs
+        .last()
+        .map(|(event_id, _)| run_of_event(*event_id as u32));
+
+    let mut stderr = String::new();
+    let mut truncated = false;
+    for (_, payload) in logs
+        .iter()
+        .filter(|(event_id, _)| Some(run_of_event(*event_id as u32)) == latest_run)
+    {
+        stderr.push_str(&payload.stderr);
+        truncated |= payload.truncated;
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(AgentLogsResponse { stderr, truncated }))
+}
+
+/// The default route for `_game/{game_id}/transcript/{agent_index}`.
+/// This is only viewable by the owner of the agent and users who can view private data.
+///
+/// The response is the JSON representation of [`doxa_executor::transcript::Transcript`].
+pub async fn game_agent_transcript<C: Competition + ?Sized>(
+    path: web::Path<(i32, usize)>,
+    user: AuthGuard<CompetitionRoleGuard<C>>,
+    context: web::Data<Context<C>>,
+) -> EndpointResult {
+    let (game_id, agent_index) = path.into_inner();
+
+    check_can_view_agent(&context, &user, game_id, agent_index).await?;
+
+    let mut chunks = Vec::new();
+    for event in context
+        .get_game_events_by_event_type(game_id, "_TRANSCRIPT".to_string())
+        .await?
+    {
+        let event_id = event.event_id;
+        let payload: TranscriptEvent =
+            serde_json::from_value(event.payload).map_err(|e| IncorrectEventFormatting {
+                source: e,
+                event_id,
+            })?;
+
+        if payload.agent_id == agent_index {
+            chunks.push((event_id, payload));
+        }
+    }
+
+    let compressed = join_chunks(
+        chunks
+            .iter()
+            .map(|(_, payload)| (payload.chunk, payload.chunks, payload.transcript.as_str())),
+    )
+    .ok_or(TranscriptNotFound)?;
+
+    let transcript = Transcript::decompress_json(&compressed).map_err(|e| InvalidTranscript {
+        source: e,
+        // The last part of the transcript
+        event_id: chunks
+            .last()
+            .map(|(event_id, _)| *event_id)
+            .unwrap_or_default(),
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(transcript))
+}
+
 /// The default route for `_game/{game_id}/players`.
 pub async fn game_players<C: Competition + ?Sized>(
     path: web::Path<i32>,
@@ -1,16 +1,19 @@
 use doxa_core::{actix_web::web, error::HttpResponse, EndpointResult};
-use doxa_db::model::{leaderboard::LeaderboardScore, storage::AgentUpload, user::User};
+use doxa_db::model::{leaderboard::LeaderboardScore, storage::AgentUpload, team::Team, user::User};
 use doxa_user::PublicBasicUserInfo;
 use serde_json::json;
 
 use crate::client::{Competition, Context};
 
-fn leaderboard_response(leaderboard: Vec<(User, LeaderboardScore, AgentUpload)>) -> HttpResponse {
+fn leaderboard_response(
+    leaderboard: Vec<(User, LeaderboardScore, AgentUpload, Option<Team>)>,
+) -> HttpResponse {
     let mut output = Vec::with_capacity(leaderboard.len());
 
-    for (user, entry, agent) in leaderboard {
+    for (user, entry, agent, team) in leaderboard {
+        let team = team.map(|team| json!({ "id": team.id, "name": team.name }));
         output
-            .push(json!({ "user": PublicBasicUserInfo::from(user), "agent": entry.agent, "score": entry.score, "activated_at": agent.activated_at, "uploaded_at": agent.uploaded_at }));
+            .push(json!({ "user": PublicBasicUserInfo::from(user), "team": team, "agent": entry.agent, "score": entry.score, "activated_at": agent.activated_at, "uploaded_at": agent.uploaded_at }));
     }
 
     HttpResponse::Ok().json(json!({ "leaderboard": output }))
@@ -1,6 +1,28 @@
+//! Uploads and activations share a rate limiter per competition.
+//! `GET /api/competition/{name}/_limits` shows the current user how many permits they have left in
+//! each of its buckets (`limit`, `remaining`, `window` and `reset` in seconds) without using one.
+//! Rate limited routes include the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`
+//! headers of the most restrictive bucket. The CLI checks the limits before uploading and warns
+//! when the next upload is the last one.
+
 use std::sync::Arc;
 
-use doxa_auth::limiter::{GenericLimiter, Limiter, LimiterConfig};
+use doxa_auth::{
+    api_token::TokenScope,
+    guard::AuthGuard,
+    limiter::{GenericLimiter, Limiter, LimiterConfig},
+};
+use doxa_core::{
+    actix_web::web,
+    error::{HttpResponse, HttpResponseBuilder, RespondableErrorWrapper},
+    EndpointResult,
+};
+use doxa_db::model::storage::AgentOwner;
+
+use crate::{
+    client::{Competition, Context},
+    error::TooManyActivations,
+};
 
 pub struct CompetitionLimits {
     pub activations: Limiter,
@@ -13,4 +35,43 @@ impl CompetitionLimits {
             activations: activations.build(&generic),
         }
     }
+
+    /// Charges an activation of one of the `owner`'s agents to the owner and adds the remaining
+    /// activations to the headers of the response.
+    pub async fn take_activation(
+        &self,
+        competition: &str,
+        owner: &AgentOwner,
+        response: &mut HttpResponseBuilder,
+    ) -> Result<(), RespondableErrorWrapper> {
+        let key = owner.limiter_key(competition);
+        self.activations
+            .get_permit(&key)
+            .await?
+            .map_err(TooManyActivations::from)?;
+        self.activations.peek(&key).await?.insert_headers(response);
+
+        Ok(())
+    }
+}
+
+/// The default route for `_limits`, this shows the current user how many uploads and activations
+/// they (or their team) have left (they share a limiter).
+pub async fn limit_status<C: Competition + ?Sized>(
+    user: AuthGuard<()>,
+    context: web::Data<Context<C>>,
+    limits: web::Data<CompetitionLimits>,
+) -> EndpointResult {
+    let user_id = user.scoped_id_required(&TokenScope::Read)?;
+    // Teams share their limits but their members are also limited individually when uploading
+    let owner = context.get_agent_owner_for_user(user_id).await?;
+    let status = limits
+        .activations
+        .peek_all(&owner.upload_limiter_keys(C::COMPETITION_NAME, user_id))
+        .await?;
+
+    let mut response = HttpResponse::Ok();
+    status.insert_headers(&mut response);
+
+    Ok(response.json(status))
 }
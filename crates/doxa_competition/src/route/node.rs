@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use doxa_auth::{error::UserNotAdmin, guard::AuthGuard};
+use doxa_core::{
+    actix_web::{
+        http::header::{CacheControl, CacheDirective},
+        web,
+    },
+    chrono::{self, DateTime, Utc},
+    error::HttpResponse,
+    EndpointResult,
+};
+use doxa_db::{
+    diesel::{Connection, PgConnection},
+    model::{
+        audit::{actions, node_target, InsertableAuditEntry},
+        execution_node::ExecutionNode,
+    },
+    DieselError, PgPool,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    context::run_query_with_pool,
+    error::{ContextError, NodeNotRegistered},
+    manager::node_monitor::HEARTBEAT_TIMEOUT,
+};
+
+use super::{
+    admin::DrainRequest,
+    response::{NodeResponse, NodesResponse},
+};
+
+/// The state shared by the `/admin/nodes` routes.
+pub(crate) struct NodeRegistry {
+    pg_pool: Arc<PgPool>,
+}
+
+impl NodeRegistry {
+    pub(crate) fn new(pg_pool: Arc<PgPool>) -> Self {
+        NodeRegistry { pg_pool }
+    }
+
+    async fn run_query<
+        T: Send + 'static,
+        F: FnOnce(&PgConnection) -> Result<T, DieselError> + Send + 'static,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, ContextError> {
+        run_query_with_pool(&self.pg_pool, f).await
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RegisterNodeRequest {
+    name: String,
+    capacity: u32,
+    available: u32,
+    /// The execution profiles the node runs for each competition along with how many games of
+    /// each it can run at once
+    competitions: BTreeMap<String, BTreeMap<String, u32>>,
+    /// Games that are still running from before the node registered, if it is registering again
+    #[serde(default)]
+    games: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct HeartbeatRequest {
+    capacity: u32,
+    available: u32,
+    /// The IDs of the games the node is currently running
+    games: Vec<i32>,
+}
+
+/// Registers the `/admin/nodes` routes, these are only available to admins (which includes the
+/// system account used by execution nodes).
+pub(crate) fn configure_node_routes(service: &mut web::ServiceConfig) {
+    service.route("", web::get().to(nodes));
+    service.route("/register", web::post().to(register_node));
+    service.route("/{name}/heartbeat", web::post().to(heartbeat));
+    service.route("/{name}/drain", web::post().to(drain_node));
+}
+
+fn node_response(node: ExecutionNode, now: DateTime<Utc>) -> NodeResponse {
+    let heartbeat_age = now - node.last_heartbeat_at;
+
+    NodeResponse {
+        alive: heartbeat_age
+            < chrono::Duration::from_std(HEARTBEAT_TIMEOUT).expect("heartbeat timeout too large"),
+        heartbeat_age_secs: heartbeat_age.num_seconds(),
+        name: node.name,
+        registered_at: node.registered_at,
+        last_heartbeat_at: node.last_heartbeat_at,
+        capacity: node.capacity,
+        available: node.available,
+        running_games: node.running_games,
+        competitions: node.competitions,
+        drain_timeout_secs: node.drain_timeout_secs,
+    }
+}
+
+/// The route for `/admin/nodes`, this lists every execution node that has registered along with
+/// what it was doing as of its last heartbeat.
+async fn nodes(user: AuthGuard<()>, registry: web::Data<NodeRegistry>) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    let now = Utc::now();
+    let nodes = registry
+        .run_query(doxa_db::action::execution_node::list_nodes)
+        .await?
+        .into_iter()
+        .map(|node| node_response(node, now))
+        .collect();
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(NodesResponse { nodes }))
+}
+
+/// The route for `/admin/nodes/register`, execution nodes call this before they start taking
+/// match requests. Games the node started before registering are treated as lost unless they are
+/// reported in the request.
+async fn register_node(
+    user: AuthGuard<()>,
+    registry: web::Data<NodeRegistry>,
+    request: web::Json<RegisterNodeRequest>,
+) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    let request = request.into_inner();
+    let now = Utc::now();
+    let node = ExecutionNode {
+        name: request.name,
+        registered_at: now,
+        last_heartbeat_at: now,
+        capacity: request.capacity as i32,
+        available: request.available as i32,
+        running_games: request.games,
+        competitions: serde_json::to_value(request.competitions).unwrap(),
+        drain_timeout_secs: None,
+    };
+
+    let node = registry
+        .run_query(move |conn| doxa_db::action::execution_node::register_node(conn, &node))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(node_response(node, now)))
+}
+
+/// The route for `/admin/nodes/{name}/heartbeat`, this responds with `NODE_NOT_REGISTERED` if the
+/// node needs to register (again).
+async fn heartbeat(
+    path: web::Path<String>,
+    user: AuthGuard<()>,
+    registry: web::Data<NodeRegistry>,
+    request: web::Json<HeartbeatRequest>,
+) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    let name = path.into_inner();
+    let request = request.into_inner();
+    let now = Utc::now();
+
+    let node = registry
+        .run_query({
+            let name = name.clone();
+            move |conn| {
+                doxa_db::action::execution_node::record_heartbeat(
+                    conn,
+                    &name,
+                    now,
+                    request.capacity as i32,
+                    request.available as i32,
+                    request.games,
+                )
+            }
+        })
+        .await?
+        .ok_or(NodeNotRegistered { name })?;
+
+    Ok(HttpResponse::Ok().json(node_response(node, now)))
+}
+
+/// The route for `/admin/nodes/{name}/drain`, the node stops taking match requests once it
+/// receives the request in response to its next heartbeat (see `/admin/drain`).
+async fn drain_node(
+    path: web::Path<String>,
+    user: AuthGuard<()>,
+    registry: web::Data<NodeRegistry>,
+    request: Option<web::Json<DrainRequest>>,
+) -> EndpointResult {
+    if !user.admin() {
+        return Err(UserNotAdmin.into());
+    }
+
+    let name = path.into_inner();
+    // This fits as the timeout is at most a day
+    let timeout_secs = DrainRequest::timeout(request)?.as_secs() as i32;
+
+    let audit_entry = InsertableAuditEntry::new(user.id(), actions::DRAIN_NODE)
+        .with_target(node_target(&name))
+        .with_metadata(json!({ "timeout_secs": timeout_secs }));
+
+    let node = registry
+        .run_query({
+            let name = name.clone();
+            move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let node = doxa_db::action::execution_node::request_node_drain(
+                        conn,
+                        &name,
+                        timeout_secs,
+                    )?;
+
+                    // Nothing was drained if the node isn't registered
+                    if node.is_some() {
+                        doxa_db::action::audit::record(conn, &audit_entry)?;
+                    }
+
+                    Ok(node)
+                })
+            }
+        })
+        .await?
+        .ok_or(NodeNotRegistered { name })?;
+
+    Ok(HttpResponse::Ok().json(node_response(node, Utc::now())))
+}
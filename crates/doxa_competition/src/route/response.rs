@@ -1,4 +1,6 @@
 use doxa_core::chrono::{DateTime, Utc};
+use doxa_db::model::competition::CompetitionInvite;
+use doxa_mq::model::Priority;
 use serde::Serialize;
 
 #[derive(Serialize, Debug)]
@@ -15,6 +17,13 @@ pub struct GameEventResponse {
     pub payload: serde_json::Value,
 }
 
+#[derive(Serialize, Debug)]
+pub struct AgentLogsResponse {
+    pub stderr: String,
+    /// Whether the logs were cut short because they exceeded the size limit
+    pub truncated: bool,
+}
+
 #[derive(Serialize, Debug)]
 pub struct PlayersResponse {
     pub players: Vec<PlayersResponsePlayer>,
@@ -39,6 +48,7 @@ pub struct GameResponse {
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub outdated: bool,
+    pub retries: i32,
 }
 
 #[derive(Serialize, Debug)]
@@ -61,3 +71,138 @@ pub struct UserScoreResponse {
 pub struct CancelledResponse {
     pub cancelled: bool,
 }
+
+#[derive(Serialize, Debug)]
+pub struct CheckpointResponse {
+    pub next_event_id: u32,
+    pub checkpoint: Option<CheckpointResponseCheckpoint>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckpointResponseCheckpoint {
+    pub event_id: u32,
+    #[serde(serialize_with = "doxa_executor::seed::serialize")]
+    pub seed: u64,
+    pub state: serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PausedResponse {
+    pub paused: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct QueuesResponse {
+    pub competitions: Vec<CompetitionQueuesResponse>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CompetitionQueuesResponse {
+    pub competition: String,
+    pub paused: bool,
+    pub queues: Vec<QueueResponse>,
+    /// Games that have started but not completed grouped by the executor running them
+    pub in_flight: Vec<NodeGamesResponse>,
+    /// When the game that has been waiting the longest to start was queued
+    pub oldest_queued_at: Option<DateTime<Utc>>,
+    pub oldest_queued_age_secs: Option<i64>,
+    pub throughput: Vec<ThroughputResponse>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct QueueResponse {
+    pub execution_profile: String,
+    pub priority: Priority,
+    pub queue: String,
+    /// Match requests that are waiting to be taken by an executor
+    pub depth: u64,
+    pub consumers: Option<u32>,
+    pub dead_letters: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NodeGamesResponse {
+    /// This is `None` for games started by executors that don't report their name
+    pub node: Option<String>,
+    pub games: Vec<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ThroughputResponse {
+    pub window_secs: i64,
+    pub started: usize,
+    pub completed: usize,
+    /// The mean time between `queued_at` and `started_at` for the games that started
+    pub mean_wait_secs: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NodesResponse {
+    pub nodes: Vec<NodeResponse>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NodeResponse {
+    pub name: String,
+    pub registered_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    pub heartbeat_age_secs: i64,
+    /// Whether the node has sent a heartbeat recently, the games of nodes that aren't alive are
+    /// failed and requeued
+    pub alive: bool,
+    /// The maximum number of games the node runs at once
+    pub capacity: i32,
+    /// How many more games the node could start as of the last heartbeat
+    pub available: i32,
+    pub running_games: Vec<i32>,
+    /// The execution profiles the node runs for each competition along with how many games of
+    /// each it can run at once
+    pub competitions: serde_json::Value,
+    /// Set once an admin has asked the node to drain, the node starts draining when it receives
+    /// this in response to a heartbeat
+    pub drain_timeout_secs: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DrainResponse {
+    pub draining: bool,
+    pub running_games: usize,
+    /// How long until the games that are still running are cancelled and requeued
+    pub deadline_secs: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InvitesResponse {
+    pub invites: Vec<InviteResponse>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InviteResponse {
+    pub id: i32,
+    pub code: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub email_domain: Option<String>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// Whether the invite can still be used
+    pub active: bool,
+}
+
+impl InviteResponse {
+    pub fn new(invite: CompetitionInvite, now: DateTime<Utc>) -> Self {
+        InviteResponse {
+            active: doxa_auth::enrollment::invite_is_active(&invite, now)
+                && invite.uses < invite.max_uses,
+            id: invite.id,
+            code: invite.code,
+            created_at: invite.created_at,
+            expires_at: invite.expires_at,
+            max_uses: invite.max_uses,
+            uses: invite.uses,
+            email_domain: invite.email_domain,
+            revoked_at: invite.revoked_at,
+        }
+    }
+}
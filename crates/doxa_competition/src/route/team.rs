@@ -0,0 +1,244 @@
+//! Teams of users enrolled in a competition, which can be formed once a maximum team size has been
+//! set with `doxa_adm competition set-max-team-size <competition> [size]` (leaving out the size
+//! stops new teams being created).
+//!
+//! A user can be in one team per competition. They create it with
+//! `POST /api/competition/{name}/_team` (`{"name": ...}`), invite others with
+//! `POST .../_team/invite` (`{"username": ...}`) and leave with `POST .../_team/leave`. Invites are
+//! listed with `GET .../_team/invites` and accepted or declined with
+//! `POST .../_team/invites/{team_id}/accept` and `.../decline`, `GET .../_team` shows the current
+//! team.
+//!
+//! Agents uploaded by a member belong to the team, so the team has one active agent and one
+//! leaderboard entry, any member can manage them and the `_user/{username}/...` routes and upload
+//! limits apply to the whole team (each member's uploads also count against their own limit). A
+//! user's own active agent is deactivated when they join a team, and the team's is deactivated when
+//! its last member leaves.
+
+use doxa_auth::guard::AuthGuard;
+use doxa_core::{actix_web::web, error::HttpResponse, EndpointResult};
+use doxa_db::{
+    action,
+    model::{
+        storage::{AgentOwner, AgentUpload},
+        team::Team,
+    },
+    PgPool,
+};
+use doxa_user::PublicBasicUserInfo;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    client::{Competition, Context},
+    error::{ContextError, TeamInviteNotFound},
+    team,
+};
+
+#[derive(Deserialize)]
+pub struct CreateTeamRequest {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct InviteToTeamRequest {
+    pub username: String,
+}
+
+fn team_json(team: &Team) -> serde_json::Value {
+    json!({ "id": team.id, "name": team.name })
+}
+
+/// The user's own agent is marked as inactive in the same transaction as joining the team, this
+/// finishes deactivating it (cancelling its games and removing it from the leaderboard).
+async fn finish_deactivating_own_agent<C: Competition + ?Sized>(
+    context: &Context<C>,
+    deactivated: Option<AgentUpload>,
+) -> Result<(), ContextError> {
+    if let Some(agent) = deactivated {
+        context.deactivate_agent(agent.id).await?;
+    }
+
+    Ok(())
+}
+
+/// The default route for `GET _team`, this shows the current user's team (if any) along with its
+/// members and pending invites.
+pub async fn user_team<C: Competition + ?Sized>(
+    context: web::Data<Context<C>>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let competition_id = context.competition_id();
+
+    let (competition, team) = context
+        .run_query(move |conn| {
+            let competition = action::competition::get_competition_by_id(conn, competition_id)?;
+            let team = match action::team::get_user_team(conn, user_id, competition_id)? {
+                Some(team) => Some((
+                    action::team::list_team_members(conn, team.id)?,
+                    action::team::list_team_invites(conn, team.id)?,
+                    team,
+                )),
+                None => None,
+            };
+
+            Ok((competition, team))
+        })
+        .await?;
+
+    let team = team.map(|(members, invites, team)| {
+        let members: Vec<_> = members
+            .into_iter()
+            .map(|(member, user)| {
+                json!({ "user": PublicBasicUserInfo::from(user), "joined_at": member.joined_at })
+            })
+            .collect();
+        let invites: Vec<_> = invites
+            .into_iter()
+            .map(|(invite, user)| {
+                json!({ "user": PublicBasicUserInfo::from(user), "invited_at": invite.created_at })
+            })
+            .collect();
+
+        json!({
+            "id": team.id,
+            "name": team.name,
+            "created_at": team.created_at,
+            "members": members,
+            "invites": invites,
+        })
+    });
+
+    Ok(HttpResponse::Ok().json(json!({
+        "team": team,
+        "max_team_size": competition.and_then(|c| c.max_team_size),
+    })))
+}
+
+/// The default route for `POST _team`, this creates a team with the current user as its first
+/// member.
+pub async fn create_team<C: Competition + ?Sized>(
+    pool: web::Data<PgPool>,
+    context: web::Data<Context<C>>,
+    body: web::Json<CreateTeamRequest>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let name = body.into_inner().name;
+
+    let conn = web::block(move || pool.get()).await??;
+    let (team, deactivated) =
+        web::block(move || team::create_team(&conn, user_id, C::COMPETITION_NAME, &name)).await??;
+
+    finish_deactivating_own_agent(&context, deactivated).await?;
+
+    Ok(HttpResponse::Ok().json(team_json(&team)))
+}
+
+/// The default route for `POST _team/invite`, this invites another user to the current user's
+/// team.
+pub async fn invite_to_team<C: Competition + ?Sized>(
+    pool: web::Data<PgPool>,
+    body: web::Json<InviteToTeamRequest>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let username = body.into_inner().username;
+
+    let conn = web::block(move || pool.get()).await??;
+    let (team, invite) =
+        web::block(move || team::invite_to_team(&conn, user_id, C::COMPETITION_NAME, &username))
+            .await??;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({ "team": team_json(&team), "invited_at": invite.created_at })))
+}
+
+/// The default route for `POST _team/leave`.
+///
+/// When the last member leaves the team's active agent is deactivated.
+pub async fn leave_team<C: Competition + ?Sized>(
+    pool: web::Data<PgPool>,
+    context: web::Data<Context<C>>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+
+    let conn = web::block(move || pool.get()).await??;
+    let (team, remaining) =
+        web::block(move || team::leave_team(&conn, user_id, C::COMPETITION_NAME)).await??;
+
+    if remaining == 0 {
+        let competition_id = context.competition_id();
+        let team_id = team.id;
+        if let Some(agent) = context
+            .run_query(move |conn| {
+                action::storage::get_active_agent(conn, AgentOwner::Team(team_id), competition_id)
+            })
+            .await?
+        {
+            context.deactivate_agent(agent.id).await?;
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// The default route for `GET _team/invites`, this lists the teams the current user has been
+/// invited to.
+pub async fn list_team_invites<C: Competition + ?Sized>(
+    context: web::Data<Context<C>>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let competition_id = context.competition_id();
+
+    let invites: Vec<_> = context
+        .run_query(move |conn| action::team::list_user_team_invites(conn, user_id, competition_id))
+        .await?
+        .into_iter()
+        .map(|(invite, team)| json!({ "team": team_json(&team), "invited_at": invite.created_at }))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({ "invites": invites })))
+}
+
+/// The default route for `POST _team/invites/{team_id}/accept`.
+///
+/// The user's own active agent is deactivated since their team's agent replaces it.
+pub async fn accept_invite<C: Competition + ?Sized>(
+    path: web::Path<i32>,
+    pool: web::Data<PgPool>,
+    context: web::Data<Context<C>>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let team_id = path.into_inner();
+
+    let conn = web::block(move || pool.get()).await??;
+    let (team, deactivated) =
+        web::block(move || team::accept_team_invite(&conn, user_id, C::COMPETITION_NAME, team_id))
+            .await??;
+
+    finish_deactivating_own_agent(&context, deactivated).await?;
+
+    Ok(HttpResponse::Ok().json(team_json(&team)))
+}
+
+/// The default route for `POST _team/invites/{team_id}/decline`.
+pub async fn decline_invite<C: Competition + ?Sized>(
+    path: web::Path<i32>,
+    context: web::Data<Context<C>>,
+    user: AuthGuard<()>,
+) -> EndpointResult {
+    let user_id = user.id_required()?;
+    let team_id = path.into_inner();
+
+    context
+        .run_query(move |conn| action::team::delete_team_invite(conn, team_id, user_id))
+        .await?
+        .ok_or(TeamInviteNotFound)?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
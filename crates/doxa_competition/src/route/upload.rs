@@ -1,30 +1,39 @@
 use doxa_auth::guard::AuthGuard;
 use doxa_core::{actix_web::web, EndpointResult};
 use doxa_db::PgPool;
-use doxa_mq::MQPool;
+use doxa_mq::QueueBackend;
 use doxa_storage::{LocalStorage, Multipart};
 
-use crate::client::Competition;
+use crate::client::{Competition, CompetitionPermission, CompetitionRoleGuard, GameClient};
 
 use super::limits::CompetitionLimits;
 
 /// The default route for `_upload`.
+/// Uploads are rejected if their `doxa.yaml` declares an execution profile that the game client
+/// doesn't.
 pub async fn upload<C: Competition + ?Sized>(
     pool: web::Data<PgPool>,
-    mq_pool: web::Data<MQPool>,
+    mq: web::Data<dyn QueueBackend>,
     storage: web::Data<LocalStorage>,
     payload: Multipart,
-    auth: AuthGuard<()>,
+    auth: AuthGuard<CompetitionRoleGuard<C>>,
     limits: web::Data<CompetitionLimits>,
 ) -> EndpointResult {
+    let bypass_limits = auth.inner_ref().has(CompetitionPermission::BypassLimits);
+
     doxa_storage::route::upload(
         pool,
-        mq_pool,
+        mq,
         storage,
         payload,
         C::COMPETITION_NAME.to_string(),
         auth,
         &limits.activations,
+        bypass_limits,
+        &<C::GameClient as GameClient>::execution_profiles()
+            .into_iter()
+            .map(|profile| profile.name)
+            .collect::<Vec<_>>(),
     )
     .await
 }
@@ -1,13 +1,15 @@
-use doxa_auth::{error::UserNotFound, guard::AuthGuard};
+use doxa_auth::{api_token::TokenScope, error::UserNotFound, guard::AuthGuard};
 use doxa_core::{actix_web::web, error::HttpResponse, EndpointResult};
+use doxa_db::model::audit::actions;
 use serde_json::json;
 
 use crate::{
-    client::{Competition, Context},
-    error::{NoActiveAgent, TooManyActivations, UserNotOwner},
+    client::{Competition, CompetitionPermission, CompetitionRoleGuard, Context},
+    error::{NoActiveAgent, UserNotOwner},
 };
 
 use super::{
+    agent::{audit_agent_action, is_owner},
     limits::CompetitionLimits,
     response::{ActiveAgentResponse, ActiveGamesResponse, GameResponse, UserScoreResponse},
 };
@@ -177,6 +179,7 @@ pub async fn user_active_games<C: Competition + ?Sized>(
             started_at: game.started_at,
             completed_at: game.completed_at,
             outdated: game.outdated,
+            retries: game.retries,
         })
         .collect::<Vec<_>>();
 
@@ -187,7 +190,7 @@ pub async fn user_active_games<C: Competition + ?Sized>(
 pub async fn reactivate_agent<C: Competition + ?Sized>(
     path: web::Path<String>,
     context: web::Data<Context<C>>,
-    user_auth: AuthGuard,
+    user_auth: AuthGuard<CompetitionRoleGuard<C>>,
     limits: web::Data<CompetitionLimits>,
 ) -> EndpointResult {
     let username = path.into_inner();
@@ -197,33 +200,49 @@ pub async fn reactivate_agent<C: Competition + ?Sized>(
         .await?
         .ok_or(UserNotFound)?;
 
-    if !(Some(user.id) == user_auth.id() || user_auth.admin()) {
-        return Err(UserNotOwner.into());
-    }
-
+    let actor = user_auth.scoped_id(&TokenScope::Activate(C::COMPETITION_NAME.to_string()))?;
     let agent = context
         .get_active_agent(user.id)
         .await?
         .ok_or(NoActiveAgent)?;
 
-    if !user_auth.admin() {
-        limits
-            .activations
-            .get_permit(format!("{}-{}", C::COMPETITION_NAME, agent.owner))
-            .await?
-            .map_err(TooManyActivations::from)?;
+    // Teammates share the active agent so they can also manage it
+    if !(is_owner(&context, actor, &agent).await?
+        || user_auth
+            .inner_ref()
+            .has(CompetitionPermission::ManageAgents))
+    {
+        return Err(UserNotOwner.into());
     }
 
-    context.activate_agent(agent.id).await?;
+    let bypass_limits = user_auth
+        .inner_ref()
+        .has(CompetitionPermission::BypassLimits);
+    let mut response = HttpResponse::Ok();
+    if !bypass_limits {
+        limits
+            .take_activation(C::COMPETITION_NAME, &agent.agent_owner(), &mut response)
+            .await?;
+    }
 
-    Ok(HttpResponse::Ok().json(json!({})))
+    context.activate_agent(agent.id.clone()).await?;
+    audit_agent_action(
+        &context,
+        actor,
+        &agent,
+        actions::REACTIVATE_AGENT,
+        bypass_limits,
+    )
+    .await?;
+
+    Ok(response.json(json!({})))
 }
 
 /// The default route for `_user/{username}/deactivate_active_agent`.
 pub async fn deactivate_agent<C: Competition + ?Sized>(
     path: web::Path<String>,
     context: web::Data<Context<C>>,
-    user_auth: AuthGuard,
+    user_auth: AuthGuard<CompetitionRoleGuard<C>>,
 ) -> EndpointResult {
     let username = path.into_inner();
 
@@ -232,16 +251,23 @@ pub async fn deactivate_agent<C: Competition + ?Sized>(
         .await?
         .ok_or(UserNotFound)?;
 
-    if !(Some(user.id) == user_auth.id() || user_auth.admin()) {
-        return Err(UserNotOwner.into());
-    }
-
+    let actor = user_auth.scoped_id(&TokenScope::Activate(C::COMPETITION_NAME.to_string()))?;
     let agent = context
         .get_active_agent(user.id)
         .await?
         .ok_or(NoActiveAgent)?;
 
-    context.deactivate_agent(agent.id).await?;
+    // Teammates share the active agent so they can also manage it
+    if !(is_owner(&context, actor, &agent).await?
+        || user_auth
+            .inner_ref()
+            .has(CompetitionPermission::ManageAgents))
+    {
+        return Err(UserNotOwner.into());
+    }
+
+    context.deactivate_agent(agent.id.clone()).await?;
+    audit_agent_action(&context, actor, &agent, actions::DEACTIVATE_AGENT, false).await?;
 
     Ok(HttpResponse::Ok().json(json!({})))
 }
@@ -2,14 +2,14 @@ use std::sync::Arc;
 
 use doxa_auth::limiter::GenericLimiter;
 use doxa_db::PgPool;
-use doxa_executor::client::firecracker::FirecrackerBackendSettings;
 pub use doxa_executor::HTTPClient;
-use doxa_mq::MQPool;
+use doxa_executor::{client::firecracker::FirecrackerBackendSettings, drain::DrainState};
+use doxa_mq::MQ;
 
 pub struct Settings {
     pub executor_settings: Arc<doxa_executor::Settings>,
     pub firecracker_settings: FirecrackerBackendSettings,
-    pub mq_pool: Arc<MQPool>,
+    pub mq: MQ,
     pub pg_pool: Arc<PgPool>,
     pub generic_limiter: Arc<GenericLimiter>,
     /// The base url to a competitions api such that appending `{competition_name}/_game/{game_id}/cancelled` yields the
@@ -17,4 +17,7 @@ pub struct Settings {
     pub competitions_base_url: String,
     /// A client for making HTTP requests
     pub request_client: HTTPClient,
+    /// Drains the server's execution managers, this can be started by an admin or when the server
+    /// is shutting down.
+    pub drain: DrainState,
 }
@@ -0,0 +1,256 @@
+//! Teams of users that share an agent in a competition.
+//!
+//! A user can be in at most one team per competition and the competition's `max_team_size` limits
+//! the number of members (teams can't be created while it is unset). Agents uploaded by a member
+//! are owned by the team, so the team has a single active agent and a single leaderboard entry.
+
+use doxa_auth::error::{CompetitionNotFound, UserNotEnrolled, UserNotFound};
+use doxa_core::chrono::Utc;
+use doxa_db::{
+    action,
+    diesel::{Connection, PgConnection},
+    model::{
+        audit::{actions, team_target, user_target, InsertableAuditEntry},
+        competition::Competition,
+        storage::{AgentOwner, AgentUpload},
+        team::{InsertableTeam, Team, TeamInvite, TeamMember},
+    },
+    DieselError,
+};
+use serde_json::json;
+
+use crate::error::{
+    AlreadyInTeam, CreateTeamError, InvalidTeamName, InviteToTeamError, JoinTeamError,
+    LeaveTeamError, NotInTeam, TeamFull, TeamInviteNotFound, TeamNameTaken, TeamsDisabled,
+    UserInOtherTeam,
+};
+
+const MAX_TEAM_NAME_LENGTH: usize = 32;
+
+fn is_enrolled(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: &Competition,
+) -> Result<bool, DieselError> {
+    Ok(action::competition::get_enrollment(conn, user_id, competition.name.clone())?.is_some())
+}
+
+/// Marks the user's own active agent as inactive when they join a team, agents that were active
+/// before would otherwise stay on the leaderboard with nobody able to manage them.
+/// The agent is returned so that the caller can finish deactivating it (e.g. cancelling its games)
+/// once the transaction has been committed.
+fn deactivate_own_agent(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: &Competition,
+) -> Result<Option<AgentUpload>, DieselError> {
+    action::storage::mark_active_agent_as_inactive(conn, competition.id, AgentOwner::User(user_id))
+}
+
+/// Creates a team with the user as its first member, any pending invites the user had to other
+/// teams in the competition are removed.
+/// This also returns the user's own agent if it was deactivated, see [`deactivate_own_agent`].
+pub fn create_team(
+    conn: &PgConnection,
+    user_id: i32,
+    competition_name: &str,
+    name: &str,
+) -> Result<(Team, Option<AgentUpload>), CreateTeamError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_TEAM_NAME_LENGTH {
+        return Err(InvalidTeamName.into());
+    }
+
+    conn.transaction(|| {
+        let now = Utc::now();
+        let competition = action::competition::get_competition_by_name(conn, competition_name)?
+            .ok_or(CompetitionNotFound)?;
+
+        if competition.max_team_size.is_none() {
+            return Err(TeamsDisabled.into());
+        }
+
+        if !is_enrolled(conn, user_id, &competition)? {
+            return Err(UserNotEnrolled.into());
+        }
+
+        if action::team::get_user_team(conn, user_id, competition.id)?.is_some() {
+            return Err(AlreadyInTeam.into());
+        }
+
+        if action::team::get_team_by_name(conn, competition.id, name)?.is_some() {
+            return Err(TeamNameTaken.into());
+        }
+
+        let team = action::team::create_team(
+            conn,
+            &InsertableTeam {
+                competition: competition.id,
+                name: name.to_string(),
+                created_by: user_id,
+                created_at: now,
+            },
+        )?;
+
+        action::team::add_team_member(
+            conn,
+            &TeamMember {
+                team: team.id,
+                user_id,
+                competition: competition.id,
+                joined_at: now,
+            },
+        )?
+        .ok_or(AlreadyInTeam)?;
+        action::team::delete_user_team_invites(conn, user_id, competition.id)?;
+        let deactivated = deactivate_own_agent(conn, user_id, &competition)?;
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(Some(user_id), actions::CREATE_TEAM)
+                .with_target(team_target(team.id))
+                .with_metadata(json!({ "competition": competition.name, "name": team.name })),
+        )?;
+
+        Ok((team, deactivated))
+    })
+}
+
+/// Invites the user with the username to the inviter's team.
+/// Inviting someone who has already been invited does nothing.
+pub fn invite_to_team(
+    conn: &PgConnection,
+    inviter_id: i32,
+    competition_name: &str,
+    username: &str,
+) -> Result<(Team, TeamInvite), InviteToTeamError> {
+    conn.transaction(|| {
+        let competition = action::competition::get_competition_by_name(conn, competition_name)?
+            .ok_or(CompetitionNotFound)?;
+        let max_team_size = competition.max_team_size.ok_or(TeamsDisabled)?;
+
+        let team =
+            action::team::get_user_team(conn, inviter_id, competition.id)?.ok_or(NotInTeam)?;
+        let invitee = action::user::get_user_by_username(conn, username)?.ok_or(UserNotFound)?;
+
+        if action::team::get_user_team(conn, invitee.id, competition.id)?.is_some() {
+            return Err(UserInOtherTeam.into());
+        }
+
+        if action::team::count_team_members(conn, team.id)? >= max_team_size as i64 {
+            return Err(TeamFull.into());
+        }
+
+        let invite = TeamInvite {
+            team: team.id,
+            user_id: invitee.id,
+            invited_by: inviter_id,
+            created_at: Utc::now(),
+        };
+
+        let invite = match action::team::create_team_invite(conn, &invite)? {
+            Some(invite) => invite,
+            // They were already invited
+            None => return Ok((team, invite)),
+        };
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(Some(inviter_id), actions::INVITE_TO_TEAM)
+                .with_target(team_target(team.id))
+                .with_metadata(json!({
+                    "competition": competition.name,
+                    "invitee": user_target(invitee.id),
+                })),
+        )?;
+
+        Ok((team, invite))
+    })
+}
+
+/// Adds the user to the team they were invited to, their other pending invites in the competition
+/// are removed.
+/// This also returns the user's own agent if it was deactivated, see [`deactivate_own_agent`].
+pub fn accept_team_invite(
+    conn: &PgConnection,
+    user_id: i32,
+    competition_name: &str,
+    team_id: i32,
+) -> Result<(Team, Option<AgentUpload>), JoinTeamError> {
+    conn.transaction(|| {
+        let competition = action::competition::get_competition_by_name(conn, competition_name)?
+            .ok_or(CompetitionNotFound)?;
+        let max_team_size = competition.max_team_size.ok_or(TeamsDisabled)?;
+
+        // Locked so that concurrent joins can't go over the max team size
+        let team = action::team::lock_team(conn, team_id)?
+            .filter(|team| team.competition == competition.id)
+            .ok_or(TeamInviteNotFound)?;
+
+        action::team::delete_team_invite(conn, team.id, user_id)?.ok_or(TeamInviteNotFound)?;
+
+        if !is_enrolled(conn, user_id, &competition)? {
+            return Err(UserNotEnrolled.into());
+        }
+
+        if action::team::count_team_members(conn, team.id)? >= max_team_size as i64 {
+            return Err(TeamFull.into());
+        }
+
+        action::team::add_team_member(
+            conn,
+            &TeamMember {
+                team: team.id,
+                user_id,
+                competition: competition.id,
+                joined_at: Utc::now(),
+            },
+        )?
+        .ok_or(AlreadyInTeam)?;
+        action::team::delete_user_team_invites(conn, user_id, competition.id)?;
+        let deactivated = deactivate_own_agent(conn, user_id, &competition)?;
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(Some(user_id), actions::JOIN_TEAM)
+                .with_target(team_target(team.id))
+                .with_metadata(json!({ "competition": competition.name })),
+        )?;
+
+        Ok((team, deactivated))
+    })
+}
+
+/// Removes the user from their team, returning the team and the number of members left.
+///
+/// Agents uploaded by the user stay with the team. Once the last member leaves the pending invites
+/// are removed so that nobody can join an empty team.
+pub fn leave_team(
+    conn: &PgConnection,
+    user_id: i32,
+    competition_name: &str,
+) -> Result<(Team, i64), LeaveTeamError> {
+    conn.transaction(|| {
+        let competition = action::competition::get_competition_by_name(conn, competition_name)?
+            .ok_or(CompetitionNotFound)?;
+        let team = action::team::get_user_team(conn, user_id, competition.id)?.ok_or(NotInTeam)?;
+        // Locked so that the last member leaving can't race with an invite being accepted
+        action::team::lock_team(conn, team.id)?;
+
+        action::team::remove_team_member(conn, team.id, user_id)?.ok_or(NotInTeam)?;
+
+        let remaining = action::team::count_team_members(conn, team.id)?;
+        if remaining == 0 {
+            action::team::delete_team_invites(conn, team.id)?;
+        }
+
+        action::audit::record(
+            conn,
+            &InsertableAuditEntry::new(Some(user_id), actions::LEAVE_TEAM)
+                .with_target(team_target(team.id))
+                .with_metadata(json!({ "competition": competition.name, "remaining": remaining })),
+        )?;
+
+        Ok((team, remaining))
+    })
+}
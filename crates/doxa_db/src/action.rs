@@ -1,5 +1,10 @@
+pub mod api_token;
+pub mod audit;
 pub mod competition;
+pub mod execution_node;
 pub mod game;
 pub mod leaderboard;
+pub mod local_account;
 pub mod storage;
+pub mod team;
 pub mod user;
@@ -0,0 +1,83 @@
+use crate::model::api_token::{ApiToken, InsertableApiToken};
+use crate::{schema as s, DieselError};
+use chrono::{DateTime, Duration, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+
+pub fn create_token(
+    conn: &PgConnection,
+    token: &InsertableApiToken,
+) -> Result<ApiToken, DieselError> {
+    diesel::insert_into(s::api_tokens::table)
+        .values(token)
+        .get_result(conn)
+}
+
+/// Gets the token with the hash if it has not been revoked or expired.
+/// This also records that the token was used, unless that was already recorded within
+/// `record_interval`.
+pub fn use_token(
+    conn: &PgConnection,
+    token_hash: &str,
+    now: DateTime<Utc>,
+    record_interval: Duration,
+) -> Result<Option<ApiToken>, DieselError> {
+    let token: Option<ApiToken> = s::api_tokens::table
+        .filter(s::api_tokens::columns::token_hash.eq(token_hash))
+        .filter(s::api_tokens::columns::revoked_at.is_null())
+        .filter(s::api_tokens::columns::expires_at.gt(now))
+        .first(conn)
+        .optional()?;
+
+    match token {
+        Some(token)
+            if token
+                .last_used_at
+                .map_or(true, |last_used_at| last_used_at + record_interval <= now) =>
+        {
+            diesel::update(s::api_tokens::table.find(token.id))
+                .set(s::api_tokens::columns::last_used_at.eq(now))
+                .get_result(conn)
+                .map(Some)
+        }
+        token => Ok(token),
+    }
+}
+
+/// Lists the user's tokens (including revoked and expired ones) newest first.
+pub fn list_tokens(conn: &PgConnection, owner: i32) -> Result<Vec<ApiToken>, DieselError> {
+    s::api_tokens::table
+        .filter(s::api_tokens::columns::owner.eq(owner))
+        .order_by(s::api_tokens::columns::created_at.desc())
+        .get_results(conn)
+}
+
+/// Revokes one of the user's tokens, this returns `None` if the user does not have a token with
+/// that ID that hasn't already been revoked.
+pub fn revoke_token(
+    conn: &PgConnection,
+    owner: i32,
+    id: i32,
+    now: DateTime<Utc>,
+) -> Result<Option<ApiToken>, DieselError> {
+    diesel::update(s::api_tokens::table)
+        .filter(s::api_tokens::columns::owner.eq(owner))
+        .filter(s::api_tokens::columns::id.eq(id))
+        .filter(s::api_tokens::columns::revoked_at.is_null())
+        .set(s::api_tokens::columns::revoked_at.eq(now))
+        .get_result(conn)
+        .optional()
+}
+
+/// Revokes all of the user's tokens that haven't already been revoked, returning how many were
+/// revoked.
+pub fn revoke_all_tokens(
+    conn: &PgConnection,
+    owner: i32,
+    now: DateTime<Utc>,
+) -> Result<usize, DieselError> {
+    diesel::update(s::api_tokens::table)
+        .filter(s::api_tokens::columns::owner.eq(owner))
+        .filter(s::api_tokens::columns::revoked_at.is_null())
+        .set(s::api_tokens::columns::revoked_at.eq(now))
+        .execute(conn)
+}
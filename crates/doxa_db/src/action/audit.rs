@@ -0,0 +1,46 @@
+use crate::model::audit::{AuditEntry, AuditLogFilter, InsertableAuditEntry};
+use crate::{schema as s, DieselError};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+
+/// Appends an entry to the audit log, entries are never updated or deleted.
+pub fn record(
+    conn: &PgConnection,
+    entry: &InsertableAuditEntry,
+) -> Result<AuditEntry, DieselError> {
+    diesel::insert_into(s::audit_log::table)
+        .values(entry)
+        .get_result(conn)
+}
+
+/// Lists the entries that match the filter newest first.
+pub fn list_entries(
+    conn: &PgConnection,
+    filter: &AuditLogFilter,
+    limit: i64,
+) -> Result<Vec<AuditEntry>, DieselError> {
+    let mut query = s::audit_log::table.into_boxed();
+
+    if let Some(actor) = filter.actor {
+        query = query.filter(s::audit_log::columns::actor.eq(actor));
+    }
+    if let Some(action) = &filter.action {
+        query = query.filter(s::audit_log::columns::action.eq(action));
+    }
+    if let Some(target) = &filter.target {
+        query = query.filter(s::audit_log::columns::target.eq(target));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(s::audit_log::columns::created_at.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(s::audit_log::columns::created_at.lt(until));
+    }
+    if let Some(before_id) = filter.before_id {
+        query = query.filter(s::audit_log::columns::id.lt(before_id));
+    }
+
+    query
+        .order_by(s::audit_log::columns::id.desc())
+        .limit(limit)
+        .get_results(conn)
+}
@@ -1,6 +1,10 @@
-use crate::model::competition::{Competition, Enrollment, InsertableCompetition};
+use crate::model::competition::{
+    Competition, CompetitionInvite, CompetitionRole, Enrollment, InsertableCompetition,
+    InsertableCompetitionInvite,
+};
 use crate::model::user::User;
 use crate::{schema as s, DieselError};
+use chrono::{DateTime, Utc};
 use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
 
 pub fn register_competition(
@@ -51,6 +55,62 @@ pub fn get_or_create_competition(
         .get_result(conn)
 }
 
+pub fn get_competition_by_id(
+    conn: &PgConnection,
+    id: i32,
+) -> Result<Option<Competition>, DieselError> {
+    s::competitions::table
+        .filter(s::competitions::columns::id.eq(id))
+        .first(conn)
+        .optional()
+}
+
+pub fn set_competition_paused(
+    conn: &PgConnection,
+    id: i32,
+    paused: bool,
+) -> Result<Competition, DieselError> {
+    diesel::update(s::competitions::table)
+        .filter(s::competitions::columns::id.eq(id))
+        .set(s::competitions::columns::paused.eq(paused))
+        .get_result(conn)
+}
+
+pub fn set_open_enrollment(
+    conn: &PgConnection,
+    id: i32,
+    open_enrollment: bool,
+) -> Result<Competition, DieselError> {
+    diesel::update(s::competitions::table)
+        .filter(s::competitions::columns::id.eq(id))
+        .set(s::competitions::columns::open_enrollment.eq(open_enrollment))
+        .get_result(conn)
+}
+
+/// `None` stops users from creating teams, existing teams are kept.
+pub fn set_max_team_size(
+    conn: &PgConnection,
+    id: i32,
+    max_team_size: Option<i32>,
+) -> Result<Competition, DieselError> {
+    diesel::update(s::competitions::table)
+        .filter(s::competitions::columns::id.eq(id))
+        .set(s::competitions::columns::max_team_size.eq(max_team_size))
+        .get_result(conn)
+}
+
+/// Enrolls the user, this returns `None` if they were already enrolled.
+pub fn enroll_user_if_not_enrolled(
+    conn: &PgConnection,
+    enrollment: &Enrollment,
+) -> Result<Option<Enrollment>, DieselError> {
+    diesel::insert_into(s::enrollment::table)
+        .values(enrollment)
+        .on_conflict_do_nothing()
+        .get_result(conn)
+        .optional()
+}
+
 pub fn get_enrollment(
     conn: &PgConnection,
     user_id: i32,
@@ -90,3 +150,122 @@ pub fn list_competition_enrollments(
         .select(s::users::all_columns)
         .get_results(conn)
 }
+
+/// Grants a role to a user, granting a role the user already has leaves the existing grant
+/// untouched. This returns `None` in that case.
+pub fn grant_competition_role(
+    conn: &PgConnection,
+    role: &CompetitionRole,
+) -> Result<Option<CompetitionRole>, DieselError> {
+    diesel::insert_into(s::competition_roles::table)
+        .values(role)
+        .on_conflict_do_nothing()
+        .get_result(conn)
+        .optional()
+}
+
+/// Revokes a role from a user, returning the removed grant if the user had the role.
+pub fn revoke_competition_role(
+    conn: &PgConnection,
+    user_id: i32,
+    competition_id: i32,
+    role: &str,
+) -> Result<Option<CompetitionRole>, DieselError> {
+    diesel::delete(s::competition_roles::table)
+        .filter(s::competition_roles::columns::user_id.eq(user_id))
+        .filter(s::competition_roles::columns::competition.eq(competition_id))
+        .filter(s::competition_roles::columns::role.eq(role))
+        .get_result(conn)
+        .optional()
+}
+
+/// Lists the names of the roles the user has in the competition.
+pub fn list_user_competition_roles(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: &str,
+) -> Result<Vec<String>, DieselError> {
+    s::competition_roles::table
+        .inner_join(s::competitions::table)
+        .filter(s::competitions::columns::name.eq(competition))
+        .filter(s::competition_roles::columns::user_id.eq(user_id))
+        .select(s::competition_roles::columns::role)
+        .get_results(conn)
+}
+
+pub fn list_competition_roles(
+    conn: &PgConnection,
+    competition_id: i32,
+) -> Result<Vec<(CompetitionRole, User)>, DieselError> {
+    s::competition_roles::table
+        .inner_join(s::users::table)
+        .filter(s::competition_roles::columns::competition.eq(competition_id))
+        .order_by((
+            s::competition_roles::columns::user_id,
+            s::competition_roles::columns::role,
+        ))
+        .get_results(conn)
+}
+
+pub fn create_invite(
+    conn: &PgConnection,
+    invite: &InsertableCompetitionInvite,
+) -> Result<CompetitionInvite, DieselError> {
+    diesel::insert_into(s::competition_invites::table)
+        .values(invite)
+        .get_result(conn)
+}
+
+pub fn get_invite_by_code(
+    conn: &PgConnection,
+    competition_id: i32,
+    code: &str,
+) -> Result<Option<CompetitionInvite>, DieselError> {
+    s::competition_invites::table
+        .filter(s::competition_invites::columns::competition.eq(competition_id))
+        .filter(s::competition_invites::columns::code.eq(code))
+        .first(conn)
+        .optional()
+}
+
+/// Lists the invites of a competition, newest first.
+pub fn list_competition_invites(
+    conn: &PgConnection,
+    competition_id: i32,
+) -> Result<Vec<CompetitionInvite>, DieselError> {
+    s::competition_invites::table
+        .filter(s::competition_invites::columns::competition.eq(competition_id))
+        .order_by(s::competition_invites::columns::id.desc())
+        .get_results(conn)
+}
+
+/// Counts a use of the invite if it still has uses left, this returns `None` if it doesn't.
+pub fn use_invite(
+    conn: &PgConnection,
+    invite_id: i32,
+) -> Result<Option<CompetitionInvite>, DieselError> {
+    use s::competition_invites::columns::{id, max_uses, uses};
+
+    diesel::update(s::competition_invites::table)
+        .filter(id.eq(invite_id))
+        .filter(uses.lt(max_uses))
+        .set(uses.eq(uses + 1))
+        .get_result(conn)
+        .optional()
+}
+
+/// Revokes the invite if it belongs to the competition and hasn't already been revoked.
+pub fn revoke_invite(
+    conn: &PgConnection,
+    competition_id: i32,
+    invite_id: i32,
+    now: DateTime<Utc>,
+) -> Result<Option<CompetitionInvite>, DieselError> {
+    diesel::update(s::competition_invites::table)
+        .filter(s::competition_invites::columns::id.eq(invite_id))
+        .filter(s::competition_invites::columns::competition.eq(competition_id))
+        .filter(s::competition_invites::columns::revoked_at.is_null())
+        .set(s::competition_invites::columns::revoked_at.eq(now))
+        .get_result(conn)
+        .optional()
+}
@@ -0,0 +1,66 @@
+use crate::model::execution_node::ExecutionNode;
+use crate::{schema as s, DieselError};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+
+/// Adds the node or replaces it if a node with the same name was already registered (e.g. because
+/// the node restarted), this clears any drain request.
+pub fn register_node(
+    conn: &PgConnection,
+    node: &ExecutionNode,
+) -> Result<ExecutionNode, DieselError> {
+    diesel::insert_into(s::execution_nodes::table)
+        .values(node)
+        .on_conflict(s::execution_nodes::name)
+        .do_update()
+        .set((
+            s::execution_nodes::registered_at.eq(node.registered_at),
+            s::execution_nodes::last_heartbeat_at.eq(node.last_heartbeat_at),
+            s::execution_nodes::capacity.eq(node.capacity),
+            s::execution_nodes::available.eq(node.available),
+            s::execution_nodes::running_games.eq(&node.running_games),
+            s::execution_nodes::competitions.eq(&node.competitions),
+            s::execution_nodes::drain_timeout_secs.eq(node.drain_timeout_secs),
+        ))
+        .get_result(conn)
+}
+
+/// Records a heartbeat from the node, this returns `None` if the node isn't registered.
+pub fn record_heartbeat(
+    conn: &PgConnection,
+    name: &str,
+    at: DateTime<Utc>,
+    capacity: i32,
+    available: i32,
+    running_games: Vec<i32>,
+) -> Result<Option<ExecutionNode>, DieselError> {
+    diesel::update(s::execution_nodes::table)
+        .filter(s::execution_nodes::columns::name.eq(name))
+        .set((
+            s::execution_nodes::last_heartbeat_at.eq(at),
+            s::execution_nodes::capacity.eq(capacity),
+            s::execution_nodes::available.eq(available),
+            s::execution_nodes::running_games.eq(running_games),
+        ))
+        .get_result(conn)
+        .optional()
+}
+
+pub fn list_nodes(conn: &PgConnection) -> Result<Vec<ExecutionNode>, DieselError> {
+    s::execution_nodes::table
+        .order_by(s::execution_nodes::columns::name.asc())
+        .get_results(conn)
+}
+
+/// Asks the node to drain, this returns `None` if the node isn't registered.
+pub fn request_node_drain(
+    conn: &PgConnection,
+    name: &str,
+    timeout_secs: i32,
+) -> Result<Option<ExecutionNode>, DieselError> {
+    diesel::update(s::execution_nodes::table)
+        .filter(s::execution_nodes::columns::name.eq(name))
+        .set(s::execution_nodes::drain_timeout_secs.eq(timeout_secs))
+        .get_result(conn)
+        .optional()
+}
@@ -1,9 +1,12 @@
+use crate::action::storage::filter_agent_owner;
 use crate::model::game as model;
+use crate::model::storage::AgentOwner;
 
 use crate::{schema as s, view, DieselError};
 use chrono::{DateTime, Utc};
 use diesel::{
-    dsl, ExpressionMethods, JoinOnDsl, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+    dsl, BoolExpressionMethods, ExpressionMethods, JoinOnDsl, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
 };
 
 pub fn create_game(
@@ -37,6 +40,38 @@ pub fn set_game_complete_time(
         .get_result(conn)
 }
 
+pub fn set_game_retries(
+    conn: &PgConnection,
+    game_id: i32,
+    retries: i32,
+) -> Result<model::Game, DieselError> {
+    diesel::update(s::games::table)
+        .filter(s::games::columns::id.eq(game_id))
+        .set(s::games::columns::retries.eq(retries))
+        .get_result(conn)
+}
+
+/// Gets the game and locks it until the end of the transaction so that concurrent runs can't be
+/// started with the same run number.
+pub fn lock_game(conn: &PgConnection, game_id: i32) -> Result<Option<model::Game>, DieselError> {
+    s::games::table
+        .filter(s::games::columns::id.eq(game_id))
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+pub fn set_game_runs(
+    conn: &PgConnection,
+    game_id: i32,
+    runs: i32,
+) -> Result<model::Game, DieselError> {
+    diesel::update(s::games::table)
+        .filter(s::games::columns::id.eq(game_id))
+        .set(s::games::columns::runs.eq(runs))
+        .get_result(conn)
+}
+
 pub fn add_participant(
     conn: &PgConnection,
     participant: &model::GameParticipant,
@@ -46,6 +81,33 @@ pub fn add_participant(
         .get_result(conn)
 }
 
+pub fn add_match_request(
+    conn: &PgConnection,
+    match_request: &model::GameMatchRequest,
+) -> Result<model::GameMatchRequest, DieselError> {
+    diesel::insert_into(s::game_match_requests::table)
+        .values(match_request)
+        .get_result(conn)
+}
+
+pub fn get_match_request(
+    conn: &PgConnection,
+    game_id: i32,
+) -> Result<Option<model::GameMatchRequest>, DieselError> {
+    s::game_match_requests::table
+        .filter(s::game_match_requests::columns::game.eq(game_id))
+        .first(conn)
+        .optional()
+}
+
+/// Called once the game has ended as it will no longer need to be requeued.
+pub fn delete_match_request(conn: &PgConnection, game_id: i32) -> Result<usize, DieselError> {
+    diesel::delete(
+        s::game_match_requests::table.filter(s::game_match_requests::columns::game.eq(game_id)),
+    )
+    .execute(conn)
+}
+
 pub fn add_event(
     conn: &PgConnection,
     event: &model::GameEvent,
@@ -115,6 +177,28 @@ pub fn get_single_game_event_by_event_type(
         .optional()
 }
 
+/// Returns the event of this type with the largest event ID.
+pub fn get_latest_game_event_by_event_type(
+    conn: &PgConnection,
+    id: i32,
+    event_type: String,
+) -> Result<Option<model::GameEvent>, DieselError> {
+    s::game_events::table
+        .filter(s::game_events::columns::game.eq(id))
+        .filter(s::game_events::columns::event_type.eq(event_type))
+        .order_by(s::game_events::columns::event_id.desc())
+        .first(conn)
+        .optional()
+}
+
+/// Returns the largest event ID of a game or `None` if it has no events.
+pub fn get_max_event_id(conn: &PgConnection, id: i32) -> Result<Option<i32>, DieselError> {
+    s::game_events::table
+        .filter(s::game_events::columns::game.eq(id))
+        .select(dsl::max(s::game_events::columns::event_id))
+        .first(conn)
+}
+
 pub fn get_game_participants_unordered(
     conn: &PgConnection,
     id: i32,
@@ -200,12 +284,12 @@ pub fn remove_game_result_by_participant(
 /// that they were queued_at (maybe change to only include started games and order by started_at)
 pub fn get_user_active_games(
     conn: &PgConnection,
-    user_id: i32,
+    owner: AgentOwner,
     competition_id: i32,
 ) -> Result<Vec<model::Game>, DieselError> {
-    view::active_agents::table
-        .filter(view::active_agents::competition.eq(competition_id))
-        .filter(view::active_agents::owner.eq(user_id))
+    use view::active_agents::columns as c;
+    let query = view::active_agents::table
+        .filter(c::competition.eq(competition_id))
         .inner_join(
             s::game_participants::table.on(s::game_participants::agent.eq(view::active_agents::id)),
         )
@@ -215,32 +299,129 @@ pub fn get_user_active_games(
         .inner_join(s::games::table.on(s::games::id.eq(s::game_participants::game)))
         .order_by(s::games::columns::queued_at.asc())
         .select(s::games::all_columns)
-        .get_results(conn)
+        .into_boxed();
+
+    filter_agent_owner!(query, c, owner).get_results(conn)
 }
 
-/// Finds all the games that involve a paticular user and mark them as inactive.
-/// This is done by player instead of agent to make it more resliant in the case of a crash when
+/// Finds all the games that involve a paticular owner (user or team) and mark them as inactive.
+/// This is done by owner instead of agent to make it more resliant in the case of a crash when
 /// activating / deactivating an agent
+///
+/// Returns the IDs of the newly outdated games that have not completed yet, these may still be
+/// running and should be cancelled.
 pub fn mark_games_with_player_as_outdated(
     conn: &PgConnection,
-    user: i32,
+    owner: AgentOwner,
     competition: i32,
-) -> Result<(), DieselError> {
+) -> Result<Vec<i32>, DieselError> {
+    use s::agents::columns as a_c;
     use s::game_participants::columns as p_c;
     use s::games::columns as g_c;
 
-    diesel::update(s::games::table)
+    let query = s::agents::table
+        .filter(a_c::competition.eq(competition))
+        .select(a_c::id)
+        .into_boxed();
+    let agents: Vec<String> = filter_agent_owner!(query, a_c, owner).load(conn)?;
+
+    let games: Vec<(i32, Option<DateTime<Utc>>)> = diesel::update(s::games::table)
         .filter(g_c::outdated.eq(false))
         .filter(g_c::competition.eq(competition))
         .filter(
             g_c::id.eq_any(
                 s::game_participants::table
-                    .inner_join(s::agents::table)
-                    .filter(s::agents::owner.eq(user))
+                    .filter(p_c::agent.eq_any(agents))
                     .select(p_c::game),
             ),
         )
         .set(g_c::outdated.eq(true))
-        .execute(conn)
-        .map(|_rows: usize| ())
+        .returning((g_c::id, g_c::completed_at))
+        .get_results(conn)?;
+
+    Ok(games
+        .into_iter()
+        .filter(|(_, completed_at)| completed_at.is_none())
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// Returns when the game that has been waiting the longest to start was queued, games that have
+/// been outdated are ignored as they may never run.
+pub fn get_oldest_queued_at(
+    conn: &PgConnection,
+    competition: i32,
+) -> Result<Option<DateTime<Utc>>, DieselError> {
+    use s::games::columns as g_c;
+
+    s::games::table
+        .filter(g_c::competition.eq(competition))
+        .filter(g_c::started_at.is_null())
+        .filter(g_c::completed_at.is_null())
+        .filter(g_c::outdated.eq(false))
+        .select(dsl::min(g_c::queued_at))
+        .first(conn)
+}
+
+/// Returns the `(queued_at, started_at, completed_at)` times of every game in a competition that
+/// started or completed after `since`.
+#[allow(clippy::type_complexity)]
+pub fn get_game_times_since(
+    conn: &PgConnection,
+    competition: i32,
+    since: DateTime<Utc>,
+) -> Result<Vec<(DateTime<Utc>, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>, DieselError> {
+    use s::games::columns as g_c;
+
+    s::games::table
+        .filter(g_c::competition.eq(competition))
+        .filter(g_c::started_at.ge(since).or(g_c::completed_at.ge(since)))
+        .select((g_c::queued_at, g_c::started_at, g_c::completed_at))
+        .get_results(conn)
+}
+
+/// Returns the events of the given types for every game in a competition that has started but
+/// not completed, ordered by game and then by event ID.
+pub fn get_running_games_events_by_event_types(
+    conn: &PgConnection,
+    competition: i32,
+    event_types: Vec<String>,
+) -> Result<Vec<model::GameEvent>, DieselError> {
+    use s::game_events::columns as e_c;
+    use s::games::columns as g_c;
+
+    s::game_events::table
+        .inner_join(s::games::table)
+        .filter(g_c::competition.eq(competition))
+        .filter(g_c::started_at.is_not_null())
+        .filter(g_c::completed_at.is_null())
+        .filter(e_c::event_type.eq_any(event_types))
+        .order_by((e_c::game.asc(), e_c::event_id.asc()))
+        .select(s::game_events::all_columns)
+        .get_results(conn)
+}
+
+/// Deletes every event of a particular type in a competition that occurred before `before`.
+/// This is used to enforce retention limits on large events such as transcripts.
+pub fn delete_competition_events_before(
+    conn: &PgConnection,
+    competition: i32,
+    event_type: String,
+    before: DateTime<Utc>,
+) -> Result<usize, DieselError> {
+    use s::game_events::columns as e_c;
+
+    diesel::delete(
+        s::game_events::table
+            .filter(e_c::event_type.eq(event_type))
+            .filter(e_c::event_timestamp.lt(before))
+            .filter(
+                e_c::game.eq_any(
+                    s::games::table
+                        .filter(s::games::competition.eq(competition))
+                        .select(s::games::id),
+                ),
+            ),
+    )
+    .execute(conn)
 }
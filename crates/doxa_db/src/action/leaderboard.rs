@@ -1,8 +1,12 @@
 use diesel::JoinOnDsl;
-use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use diesel::{
+    ExpressionMethods, NullableExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
+    RunQueryDsl,
+};
 
 use crate::model::leaderboard::{InsertableLeaderboardScore, LeaderboardScore};
 use crate::model::storage::AgentUpload;
+use crate::model::team::Team;
 use crate::model::user::User;
 use crate::schema as s;
 use crate::view;
@@ -103,23 +107,27 @@ pub fn get_user_rank(
 }
 
 /// Returns the list of agents in order of score (descending) for all active agents within a particular competition
+///
+/// The user is the one who uploaded the agent, the team is included for agents owned by a team.
 pub fn active_leaderboard(
     conn: &PgConnection,
     competition: i32,
     key: Option<String>,
-) -> Result<Vec<(User, LeaderboardScore, AgentUpload)>, DieselError> {
+) -> Result<Vec<(User, LeaderboardScore, AgentUpload, Option<Team>)>, DieselError> {
     let key = key.unwrap_or_else(|| DEFAULT_LEADERBOARD_KEY.to_string());
     view::active_agents::table
         .filter(view::active_agents::competition.eq(competition))
         .inner_join(s::leaderboard::table.on(s::leaderboard::agent.eq(view::active_agents::id)))
         .filter(s::leaderboard::columns::key.eq(key))
         .inner_join(s::users::table.on(s::users::id.eq(view::active_agents::owner)))
+        .left_join(s::teams::table.on(s::teams::id.nullable().eq(view::active_agents::team)))
         .order_by(s::leaderboard::score.desc())
         .then_order_by(view::active_agents::uploaded_at.asc())
         .select((
             s::users::all_columns,
             s::leaderboard::all_columns,
             view::active_agents::all_columns,
+            s::teams::all_columns.nullable(),
         ))
         .get_results(conn)
 }
@@ -0,0 +1,98 @@
+use crate::model::local_account::{LocalAccount, LocalAccountToken};
+use crate::model::user::{InsertableLocalUser, User};
+use crate::{schema as s, DieselError};
+use chrono::{DateTime, Utc};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+
+/// Creates the user and their account, `account.user_id` is replaced with the ID of the new user.
+pub fn create_account(
+    conn: &PgConnection,
+    user: &InsertableLocalUser,
+    mut account: LocalAccount,
+) -> Result<(User, LocalAccount), DieselError> {
+    conn.transaction(|| {
+        let user: User = diesel::insert_into(s::users::table)
+            .values(user)
+            .get_result(conn)?;
+
+        account.user_id = user.id;
+        let account = diesel::insert_into(s::local_accounts::table)
+            .values(&account)
+            .get_result(conn)?;
+
+        Ok((user, account))
+    })
+}
+
+pub fn get_account_by_username(
+    conn: &PgConnection,
+    username: &str,
+) -> Result<Option<(LocalAccount, User)>, DieselError> {
+    s::local_accounts::table
+        .inner_join(s::users::table)
+        .filter(s::users::columns::username.eq(username))
+        .first(conn)
+        .optional()
+}
+
+/// The email must already be normalised (trimmed and lowercased) as that is how it is stored.
+pub fn get_account_by_email(
+    conn: &PgConnection,
+    email: &str,
+) -> Result<Option<LocalAccount>, DieselError> {
+    s::local_accounts::table
+        .filter(s::local_accounts::columns::email.eq(email))
+        .first(conn)
+        .optional()
+}
+
+pub fn set_email_verified(
+    conn: &PgConnection,
+    user_id: i32,
+    now: DateTime<Utc>,
+) -> Result<LocalAccount, DieselError> {
+    diesel::update(s::local_accounts::table)
+        .filter(s::local_accounts::columns::user_id.eq(user_id))
+        .set(s::local_accounts::columns::email_verified_at.eq(now))
+        .get_result(conn)
+}
+
+pub fn set_password_hash(
+    conn: &PgConnection,
+    user_id: i32,
+    password_hash: String,
+) -> Result<LocalAccount, DieselError> {
+    diesel::update(s::local_accounts::table)
+        .filter(s::local_accounts::columns::user_id.eq(user_id))
+        .set(s::local_accounts::columns::password_hash.eq(password_hash))
+        .get_result(conn)
+}
+
+pub fn create_token(
+    conn: &PgConnection,
+    token: &LocalAccountToken,
+) -> Result<LocalAccountToken, DieselError> {
+    diesel::insert_into(s::local_account_tokens::table)
+        .values(token)
+        .get_result(conn)
+}
+
+/// Marks the token as used if it has the purpose and hasn't already been used or expired, tokens
+/// can only be used once.
+pub fn use_token(
+    conn: &PgConnection,
+    token_hash: &str,
+    purpose: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<LocalAccountToken>, DieselError> {
+    diesel::update(s::local_account_tokens::table)
+        .filter(s::local_account_tokens::columns::token_hash.eq(token_hash))
+        .filter(s::local_account_tokens::columns::purpose.eq(purpose))
+        .filter(s::local_account_tokens::columns::used_at.is_null())
+        .filter(s::local_account_tokens::columns::expires_at.gt(now))
+        .set(s::local_account_tokens::columns::used_at.eq(now))
+        .get_result(conn)
+        .optional()
+}
@@ -1,4 +1,4 @@
-use crate::model::storage::{ActivateChangeset, AgentUpload, InsertableAgentUpload};
+use crate::model::storage::{ActivateChangeset, AgentOwner, AgentUpload, InsertableAgentUpload};
 use crate::model::user::User;
 use crate::{schema as s, view, DieselError};
 use chrono::{DateTime, Utc};
@@ -41,15 +41,31 @@ pub fn mark_agent_as_deleted(conn: &PgConnection, id: String) -> Result<AgentUpl
         .get_result(conn)
 }
 
+/// Filters a boxed query of agents (or active agents) by who owns them.
+macro_rules! filter_agent_owner {
+    ($query:expr, $columns:ident, $owner:expr) => {
+        match $owner {
+            AgentOwner::User(user) => $query
+                .filter($columns::owner.eq(user))
+                .filter($columns::team.is_null()),
+            AgentOwner::Team(team) => $query.filter($columns::team.eq(team)),
+        }
+    };
+}
+
+pub(crate) use filter_agent_owner;
+
 pub fn list_agents(
     conn: &PgConnection,
-    user: i32,
+    owner: AgentOwner,
     competition: i32,
 ) -> Result<Vec<AgentUpload>, DieselError> {
-    s::agents::table
-        .filter(s::agents::columns::owner.eq(user))
-        .filter(s::agents::columns::competition.eq(competition))
-        .get_results(conn)
+    use s::agents::columns as c;
+    let query = s::agents::table
+        .filter(c::competition.eq(competition))
+        .into_boxed();
+
+    filter_agent_owner!(query, c, owner).get_results(conn)
 }
 
 pub fn get_agent(
@@ -82,33 +98,37 @@ pub fn get_agent_required(
 
 pub fn get_active_agent(
     conn: &PgConnection,
-    user: i32,
+    owner: AgentOwner,
     competition: i32,
 ) -> Result<Option<AgentUpload>, DieselError> {
     use view::active_agents::columns as c;
-    view::active_agents::table
+    let query = view::active_agents::table
         .filter(c::competition.eq(competition))
-        .filter(c::owner.eq(user))
-        .first(conn)
-        .optional()
+        .into_boxed();
+
+    filter_agent_owner!(query, c, owner).first(conn).optional()
 }
 
 /// Sets the active agent's active flag to false and the activated_at to NULL.
 ///
-/// If there was no active agent for that user in that competition at the time of
+/// If there was no active agent for that owner in that competition at the time of
 /// this query `Ok(None)` is returned.
 ///
 /// The return value is post update (i.e. active will always be false).
 pub fn mark_active_agent_as_inactive(
     conn: &PgConnection,
     competition: i32,
-    user: i32,
+    owner: AgentOwner,
 ) -> Result<Option<AgentUpload>, DieselError> {
+    let active_agent = match get_active_agent(conn, owner, competition)? {
+        Some(agent) => agent,
+        None => return Ok(None),
+    };
+
     use s::agents::columns as c;
     diesel::update(
         s::agents::table
-            .filter(c::owner.eq(user))
-            .filter(c::competition.eq(competition))
+            .filter(c::id.eq(active_agent.id))
             .filter(c::active.eq(true)),
     )
     .set(&ActivateChangeset {
@@ -184,15 +204,16 @@ pub fn get_active_agents_activated_before(
 pub fn get_deletable_agents_uploaded_before(
     conn: &PgConnection,
     competition: i32,
-    user: i32,
+    owner: AgentOwner,
     before: DateTime<Utc>,
 ) -> Result<Vec<AgentUpload>, DieselError> {
     use s::agents::columns as c;
-    s::agents::table
+    let query = s::agents::table
         .filter(c::competition.eq(competition))
-        .filter(c::owner.eq(user))
         .filter(c::uploaded.eq(true))
         .filter(c::deleted.eq(false))
         .filter(c::uploaded_at.lt(before))
-        .get_results(conn)
+        .into_boxed();
+
+    filter_agent_owner!(query, c, owner).get_results(conn)
 }
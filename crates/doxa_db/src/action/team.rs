@@ -0,0 +1,289 @@
+use crate::model::storage::AgentOwner;
+use crate::model::team::{InsertableTeam, Team, TeamInvite, TeamMember};
+use crate::model::user::User;
+use crate::{schema as s, DieselError};
+use diesel::{
+    ExpressionMethods, JoinOnDsl, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+
+pub fn create_team(conn: &PgConnection, team: &InsertableTeam) -> Result<Team, DieselError> {
+    diesel::insert_into(s::teams::table)
+        .values(team)
+        .get_result(conn)
+}
+
+pub fn get_team(conn: &PgConnection, team_id: i32) -> Result<Option<Team>, DieselError> {
+    s::teams::table
+        .filter(s::teams::columns::id.eq(team_id))
+        .first(conn)
+        .optional()
+}
+
+/// Gets the team and locks it until the end of the transaction so that members can't be added
+/// concurrently (e.g. to go over the competition's max team size).
+pub fn lock_team(conn: &PgConnection, team_id: i32) -> Result<Option<Team>, DieselError> {
+    s::teams::table
+        .filter(s::teams::columns::id.eq(team_id))
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+pub fn get_team_by_name(
+    conn: &PgConnection,
+    competition: i32,
+    name: &str,
+) -> Result<Option<Team>, DieselError> {
+    s::teams::table
+        .filter(s::teams::columns::competition.eq(competition))
+        .filter(s::teams::columns::name.eq(name))
+        .first(conn)
+        .optional()
+}
+
+/// The team the user is a member of in the competition (if any).
+pub fn get_user_team(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: i32,
+) -> Result<Option<Team>, DieselError> {
+    s::team_members::table
+        .filter(s::team_members::columns::user_id.eq(user_id))
+        .filter(s::team_members::columns::competition.eq(competition))
+        .inner_join(s::teams::table)
+        .select(s::teams::all_columns)
+        .first(conn)
+        .optional()
+}
+
+/// Who owns the agents that the user uploads to the competition, i.e. their team if they are in
+/// one.
+pub fn agent_owner(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: i32,
+) -> Result<AgentOwner, DieselError> {
+    Ok(match get_user_team(conn, user_id, competition)? {
+        Some(team) => AgentOwner::Team(team.id),
+        None => AgentOwner::User(user_id),
+    })
+}
+
+/// Lists the members of the team in the order they joined.
+pub fn list_team_members(
+    conn: &PgConnection,
+    team_id: i32,
+) -> Result<Vec<(TeamMember, User)>, DieselError> {
+    s::team_members::table
+        .filter(s::team_members::columns::team.eq(team_id))
+        .inner_join(s::users::table)
+        .order_by(s::team_members::columns::joined_at.asc())
+        .get_results(conn)
+}
+
+pub fn count_team_members(conn: &PgConnection, team_id: i32) -> Result<i64, DieselError> {
+    s::team_members::table
+        .filter(s::team_members::columns::team.eq(team_id))
+        .count()
+        .get_result(conn)
+}
+
+/// Adds the user to the team, this returns `None` if they are already in a team in the
+/// competition.
+pub fn add_team_member(
+    conn: &PgConnection,
+    member: &TeamMember,
+) -> Result<Option<TeamMember>, DieselError> {
+    diesel::insert_into(s::team_members::table)
+        .values(member)
+        .on_conflict_do_nothing()
+        .get_result(conn)
+        .optional()
+}
+
+/// Removes the user from the team, this returns `None` if they weren't a member.
+pub fn remove_team_member(
+    conn: &PgConnection,
+    team_id: i32,
+    user_id: i32,
+) -> Result<Option<TeamMember>, DieselError> {
+    diesel::delete(s::team_members::table)
+        .filter(s::team_members::columns::team.eq(team_id))
+        .filter(s::team_members::columns::user_id.eq(user_id))
+        .get_result(conn)
+        .optional()
+}
+
+/// Invites the user to the team, this returns `None` if they have already been invited.
+pub fn create_team_invite(
+    conn: &PgConnection,
+    invite: &TeamInvite,
+) -> Result<Option<TeamInvite>, DieselError> {
+    diesel::insert_into(s::team_invites::table)
+        .values(invite)
+        .on_conflict_do_nothing()
+        .get_result(conn)
+        .optional()
+}
+
+/// Removes the invite, this returns `None` if it didn't exist.
+pub fn delete_team_invite(
+    conn: &PgConnection,
+    team_id: i32,
+    user_id: i32,
+) -> Result<Option<TeamInvite>, DieselError> {
+    diesel::delete(s::team_invites::table)
+        .filter(s::team_invites::columns::team.eq(team_id))
+        .filter(s::team_invites::columns::user_id.eq(user_id))
+        .get_result(conn)
+        .optional()
+}
+
+/// Lists the teams the user has been invited to in the competition, oldest first.
+pub fn list_user_team_invites(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: i32,
+) -> Result<Vec<(TeamInvite, Team)>, DieselError> {
+    s::team_invites::table
+        .filter(s::team_invites::columns::user_id.eq(user_id))
+        .inner_join(s::teams::table)
+        .filter(s::teams::columns::competition.eq(competition))
+        .order_by(s::team_invites::columns::created_at.asc())
+        .get_results(conn)
+}
+
+/// Lists the users that have been invited to the team but haven't joined yet, oldest first.
+pub fn list_team_invites(
+    conn: &PgConnection,
+    team_id: i32,
+) -> Result<Vec<(TeamInvite, User)>, DieselError> {
+    s::team_invites::table
+        .filter(s::team_invites::columns::team.eq(team_id))
+        .inner_join(s::users::table.on(s::users::columns::id.eq(s::team_invites::columns::user_id)))
+        .order_by(s::team_invites::columns::created_at.asc())
+        .get_results(conn)
+}
+
+/// Removes all of the user's invites to teams in the competition, e.g. once they join a team.
+pub fn delete_user_team_invites(
+    conn: &PgConnection,
+    user_id: i32,
+    competition: i32,
+) -> Result<usize, DieselError> {
+    diesel::delete(s::team_invites::table)
+        .filter(s::team_invites::columns::user_id.eq(user_id))
+        .filter(
+            s::team_invites::columns::team.eq_any(
+                s::teams::table
+                    .filter(s::teams::columns::competition.eq(competition))
+                    .select(s::teams::columns::id),
+            ),
+        )
+        .execute(conn)
+}
+
+/// Removes every pending invite to the team.
+pub fn delete_team_invites(conn: &PgConnection, team_id: i32) -> Result<usize, DieselError> {
+    diesel::delete(s::team_invites::table)
+        .filter(s::team_invites::columns::team.eq(team_id))
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use diesel::Connection;
+
+    use super::*;
+    use crate::{
+        action::{competition::register_competition, storage, user::upsert_user},
+        model::{competition::InsertableCompetition, storage::InsertableAgentUpload, user},
+    };
+
+    fn insert_user(conn: &PgConnection, id: i32) -> User {
+        upsert_user(
+            conn,
+            &user::InsertableUser {
+                id,
+                username: format!("team-test-{}", id),
+                token_generation: String::new(),
+                extra: serde_json::json!({}),
+                admin: false,
+            },
+        )
+        .unwrap()
+    }
+
+    fn upload(conn: &PgConnection, id: &str, owner: i32, competition: i32, team: Option<i32>) {
+        storage::register_upload_start(
+            conn,
+            &InsertableAgentUpload {
+                id: id.to_string(),
+                owner,
+                competition,
+                extension: "tar.gz".to_string(),
+                team,
+            },
+        )
+        .unwrap();
+    }
+
+    /// Needs a Postgres database at `DATABASE_URL`, the changes are rolled back afterwards.
+    #[test]
+    #[ignore]
+    fn member_that_left_can_activate_their_own_agent() {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let conn = crate::establish_connection(&database_url);
+        crate::run_migrations(&conn);
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let uploader = insert_user(&conn, 1_000_001).id;
+            let other = insert_user(&conn, 1_000_002).id;
+            let competition = register_competition(
+                &conn,
+                &InsertableCompetition {
+                    name: "team-test".to_string(),
+                },
+            )?
+            .id;
+            let team = create_team(
+                &conn,
+                &InsertableTeam {
+                    competition,
+                    name: "team".to_string(),
+                    created_by: uploader,
+                    created_at: Utc::now(),
+                },
+            )?
+            .id;
+            for user_id in [uploader, other] {
+                add_team_member(
+                    &conn,
+                    &TeamMember {
+                        team,
+                        user_id,
+                        competition,
+                        joined_at: Utc::now(),
+                    },
+                )?;
+            }
+
+            // The uploader activates the team's agent then leaves, the agent stays active
+            upload(&conn, "team-agent", uploader, competition, Some(team));
+            storage::activate_agent(&conn, "team-agent".to_string(), Utc::now())?;
+            remove_team_member(&conn, team, uploader)?;
+
+            upload(&conn, "own-agent", uploader, competition, None);
+            let owner = agent_owner(&conn, uploader, competition)?;
+            assert_eq!(owner, AgentOwner::User(uploader));
+            assert!(storage::mark_active_agent_as_inactive(&conn, competition, owner)?.is_none());
+            storage::activate_agent(&conn, "own-agent".to_string(), Utc::now())?;
+
+            let team_agent = storage::get_active_agent(&conn, AgentOwner::Team(team), competition)?;
+            assert_eq!(team_agent.unwrap().id, "team-agent");
+
+            Ok(())
+        });
+    }
+}
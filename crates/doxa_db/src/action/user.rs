@@ -1,5 +1,6 @@
 use crate::model::user::{self as model};
 use crate::{schema as s, DieselError};
+use chrono::{DateTime, Utc};
 use diesel::pg::upsert::excluded;
 use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
 
@@ -61,6 +62,34 @@ pub fn set_admin_status(
         .get_result(conn)
 }
 
+/// Replaces the user's token generation and rejects all tokens issued before `now`.
+pub fn rotate_token_generation(
+    conn: &PgConnection,
+    user_id: i32,
+    token_generation: String,
+    now: DateTime<Utc>,
+) -> Result<model::User, DieselError> {
+    diesel::update(s::users::table)
+        .filter(s::users::columns::id.eq(user_id))
+        .set((
+            s::users::columns::token_generation.eq(token_generation),
+            s::users::columns::tokens_revoked_at.eq(now),
+        ))
+        .get_result(conn)
+}
+
+/// Records that the user's access tokens issued before `now` are no longer valid.
+pub fn revoke_access_tokens(
+    conn: &PgConnection,
+    user_id: i32,
+    now: DateTime<Utc>,
+) -> Result<model::User, DieselError> {
+    diesel::update(s::users::table)
+        .filter(s::users::columns::id.eq(user_id))
+        .set(s::users::columns::access_tokens_revoked_at.eq(now))
+        .get_result(conn)
+}
+
 pub fn list_users(conn: &PgConnection) -> Result<Vec<model::User>, DieselError> {
     s::users::table.get_results(conn)
 }
@@ -0,0 +1,29 @@
+use crate::schema::api_tokens;
+
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable};
+
+#[derive(Debug, Clone, Queryable)]
+pub struct ApiToken {
+    pub id: i32,
+    pub owner: i32,
+    pub name: String,
+    /// The SHA-256 hash of the token, the token itself is never stored
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "api_tokens"]
+pub struct InsertableApiToken {
+    pub owner: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
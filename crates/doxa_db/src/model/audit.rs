@@ -0,0 +1,141 @@
+use crate::schema::audit_log;
+
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable};
+use serde_json::Value;
+
+/// The names of the actions recorded in the audit log.
+pub mod actions {
+    /// A user logged out everywhere.
+    pub const REVOKE_SESSIONS: &str = "auth.revoke_sessions";
+    /// Every session and API token of a user was revoked (e.g. by an admin).
+    pub const FORCE_REVOKE_SESSIONS: &str = "auth.force_revoke_sessions";
+    /// A session created through delegated auth (e.g. the CLI) was revoked.
+    pub const REVOKE_DELEGATED_SESSION: &str = "auth.revoke_delegated_session";
+    pub const CREATE_API_TOKEN: &str = "auth.create_api_token";
+    pub const REVOKE_API_TOKEN: &str = "auth.revoke_api_token";
+
+    /// An agent was activated by someone other than its owner.
+    pub const ACTIVATE_AGENT: &str = "agent.activate";
+    /// An agent was reactivated by someone other than its owner.
+    pub const REACTIVATE_AGENT: &str = "agent.reactivate";
+    /// An agent was deactivated by someone other than its owner.
+    pub const DEACTIVATE_AGENT: &str = "agent.deactivate";
+    /// An agent was uploaded without going through the upload limiter.
+    pub const UPLOAD_BYPASSING_LIMIT: &str = "agent.upload_bypassing_limit";
+    /// An agent was (re)activated without going through the activation limiter.
+    pub const ACTIVATE_BYPASSING_LIMIT: &str = "agent.activate_bypassing_limit";
+
+    pub const CREATE_COMPETITION: &str = "competition.create";
+    pub const PAUSE_COMPETITION: &str = "competition.pause";
+    pub const RESUME_COMPETITION: &str = "competition.resume";
+    pub const ENROLL_USER: &str = "competition.enroll";
+    /// A user enrolled themselves with an invite code or because enrollment was open.
+    pub const SELF_ENROLL: &str = "competition.self_enroll";
+    pub const CREATE_INVITE: &str = "competition.create_invite";
+    pub const REVOKE_INVITE: &str = "competition.revoke_invite";
+    pub const SET_OPEN_ENROLLMENT: &str = "competition.set_open_enrollment";
+    pub const SET_MAX_TEAM_SIZE: &str = "competition.set_max_team_size";
+    pub const GRANT_ROLE: &str = "competition.grant_role";
+    pub const REVOKE_ROLE: &str = "competition.revoke_role";
+
+    pub const CREATE_TEAM: &str = "team.create";
+    pub const INVITE_TO_TEAM: &str = "team.invite";
+    pub const JOIN_TEAM: &str = "team.join";
+    pub const LEAVE_TEAM: &str = "team.leave";
+
+    pub const PROMOTE_ADMIN: &str = "user.promote_admin";
+    pub const DEMOTE_ADMIN: &str = "user.demote_admin";
+    /// A user was made admin with the `DOXA_BOOTSTRAP_ADMIN` environment variable.
+    pub const BOOTSTRAP_ADMIN: &str = "user.bootstrap_admin";
+
+    /// The server's own execution managers were drained through `/admin/drain`.
+    pub const DRAIN_SERVER: &str = "execution.drain_server";
+    pub const DRAIN_NODE: &str = "execution.drain_node";
+    /// Messages in a dead letter queue were published back to their queue.
+    pub const REPLAY_DEAD_LETTERS: &str = "queue.replay_dead_letters";
+}
+
+#[derive(Debug, Clone, Queryable)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    /// `None` if the action was performed by the system account or `doxa_adm`
+    pub actor: Option<i32>,
+    pub action: String,
+    /// What the action was performed on, e.g. `user:12` (see [`user_target`])
+    pub target: Option<String>,
+    pub metadata: Value,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "audit_log"]
+pub struct InsertableAuditEntry {
+    pub created_at: DateTime<Utc>,
+    pub actor: Option<i32>,
+    pub action: String,
+    pub target: Option<String>,
+    pub metadata: Value,
+}
+
+impl InsertableAuditEntry {
+    pub fn new(actor: Option<i32>, action: impl Into<String>) -> Self {
+        InsertableAuditEntry {
+            created_at: Utc::now(),
+            actor,
+            action: action.into(),
+            target: None,
+            metadata: Value::Object(Default::default()),
+        }
+    }
+
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+pub fn user_target(user_id: i32) -> String {
+    format!("user:{}", user_id)
+}
+
+pub fn agent_target(agent_id: &str) -> String {
+    format!("agent:{}", agent_id)
+}
+
+pub fn competition_target(competition: &str) -> String {
+    format!("competition:{}", competition)
+}
+
+pub fn invite_target(invite_id: i32) -> String {
+    format!("invite:{}", invite_id)
+}
+
+pub fn team_target(team_id: i32) -> String {
+    format!("team:{}", team_id)
+}
+
+pub fn node_target(name: &str) -> String {
+    format!("node:{}", name)
+}
+
+pub fn queue_target(queue: &str) -> String {
+    format!("queue:{}", queue)
+}
+
+/// Filters for listing the audit log, every filter that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLogFilter {
+    pub actor: Option<i32>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Only entries with an ID less than this, used for paging through the results
+    pub before_id: Option<i64>,
+}
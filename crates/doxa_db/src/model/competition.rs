@@ -1,4 +1,6 @@
-use crate::schema::{competitions, enrollment};
+use crate::schema::{competition_invites, competition_roles, competitions, enrollment};
+
+use chrono::{DateTime, Utc};
 
 use diesel::{AsChangeset, Insertable, Queryable};
 
@@ -6,6 +8,12 @@ use diesel::{AsChangeset, Insertable, Queryable};
 pub struct Competition {
     pub id: i32,
     pub name: String,
+    /// Whether executors should stop taking match requests for this competition
+    pub paused: bool,
+    /// Whether users can enroll themselves without an invite code
+    pub open_enrollment: bool,
+    /// The maximum number of members in a team, users can't create teams when this is `None`
+    pub max_team_size: Option<i32>,
 }
 
 #[derive(Debug, Clone, Insertable, AsChangeset)]
@@ -20,3 +28,41 @@ pub struct Enrollment {
     pub user_id: i32,
     pub competition: i32,
 }
+
+#[derive(Debug, Clone, Insertable, Queryable)]
+#[table_name = "competition_roles"]
+pub struct CompetitionRole {
+    pub user_id: i32,
+    pub competition: i32,
+    /// The name of the role, e.g. `organiser`
+    pub role: String,
+    pub granted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Queryable)]
+pub struct CompetitionInvite {
+    pub id: i32,
+    pub competition: i32,
+    pub code: String,
+    /// `None` when created through `doxa_adm`
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: i32,
+    pub uses: i32,
+    /// Only users with an email address at this domain (e.g. `warwick.ac.uk`) can use the invite
+    pub email_domain: Option<String>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "competition_invites"]
+pub struct InsertableCompetitionInvite {
+    pub competition: i32,
+    pub code: String,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: i32,
+    pub email_domain: Option<String>,
+}
@@ -0,0 +1,23 @@
+use crate::schema::execution_nodes;
+
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable};
+use serde_json::Value as JsonValue;
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "execution_nodes"]
+pub struct ExecutionNode {
+    pub name: String,
+    pub registered_at: DateTime<Utc>,
+    pub last_heartbeat_at: DateTime<Utc>,
+    /// The maximum number of games the node runs at once
+    pub capacity: i32,
+    /// How many more games the node could start as of the last heartbeat
+    pub available: i32,
+    /// The games the node was running as of the last heartbeat
+    pub running_games: Vec<i32>,
+    /// The execution profiles the node runs for each competition
+    pub competitions: JsonValue,
+    /// Set when an admin asks the node to drain, the node is told in its next heartbeat
+    pub drain_timeout_secs: Option<i32>,
+}
@@ -1,4 +1,4 @@
-use crate::schema::{game_events, game_participants, game_results, games};
+use crate::schema::{game_events, game_match_requests, game_participants, game_results, games};
 
 use chrono::{DateTime, Utc};
 use diesel::{Insertable, Queryable};
@@ -13,6 +13,11 @@ pub struct Game {
     pub completed_at: Option<DateTime<Utc>>,
     pub outdated: bool,
     pub competition: i32,
+    /// The number of times this game was requeued after an infrastructure failure
+    pub retries: i32,
+    /// The number of times the game has been started, a game is run again after being
+    /// interrupted and each run has its own range of event IDs
+    pub runs: i32,
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -55,3 +60,14 @@ pub struct GameResult {
     pub game: i32,
     pub result: i32,
 }
+
+/// The serialized match request of a game as it was first published, this is kept so that the
+/// game can be requeued if the execution node running it stops sending heartbeats.
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "game_match_requests"]
+pub struct GameMatchRequest {
+    pub game: i32,
+    /// The queue (priority lane) the match request was published to
+    pub queue: String,
+    pub data: Vec<u8>,
+}
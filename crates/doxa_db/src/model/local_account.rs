@@ -0,0 +1,31 @@
+use crate::schema::{local_account_tokens, local_accounts};
+
+use chrono::{DateTime, Utc};
+use diesel::{Insertable, Queryable};
+
+pub const VERIFY_EMAIL_PURPOSE: &str = "verify_email";
+pub const RESET_PASSWORD_PURPOSE: &str = "reset_password";
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "local_accounts"]
+pub struct LocalAccount {
+    pub user_id: i32,
+    pub email: String,
+    /// The PHC string of the argon2 hash of the password
+    pub password_hash: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "local_account_tokens"]
+pub struct LocalAccountToken {
+    /// The SHA-256 hash of the token, the token itself is only sent by email
+    pub token_hash: String,
+    pub user_id: i32,
+    /// Either [`VERIFY_EMAIL_PURPOSE`] or [`RESET_PASSWORD_PURPOSE`]
+    pub purpose: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::schema::agents;
 
 use chrono::{DateTime, Utc};
@@ -17,6 +19,62 @@ pub struct AgentUpload {
     pub active: bool,
     pub execution_environment: String,
     pub file_size: i32,
+    /// The team that owns this agent, `owner` is then just the member that uploaded it
+    pub team: Option<i32>,
+}
+
+impl AgentUpload {
+    pub fn agent_owner(&self) -> AgentOwner {
+        match self.team {
+            Some(team) => AgentOwner::Team(team),
+            None => AgentOwner::User(self.owner),
+        }
+    }
+}
+
+/// Who an agent belongs to, agents uploaded by a member of a team belong to the team.
+/// There can only be one active agent for each owner in a competition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentOwner {
+    User(i32),
+    Team(i32),
+}
+
+impl AgentOwner {
+    pub fn team(&self) -> Option<i32> {
+        match self {
+            AgentOwner::User(_) => None,
+            AgentOwner::Team(team) => Some(*team),
+        }
+    }
+
+    /// The limiter key for actions that are only charged to the owner, such as activating one of
+    /// their agents.
+    pub fn limiter_key(&self, competition: &str) -> String {
+        format!("{}-{}", competition, self)
+    }
+
+    /// The upload limiter keys that `user_id` uploading on behalf of this owner is charged to.
+    /// Members of a team are charged individually as well as sharing the team's limit, otherwise
+    /// they could start a new team whenever they run out.
+    pub fn upload_limiter_keys(&self, competition: &str, user_id: i32) -> Vec<String> {
+        let mut keys = vec![self.limiter_key(competition)];
+        if self.team().is_some() {
+            keys.push(AgentOwner::User(user_id).limiter_key(competition));
+        }
+
+        keys
+    }
+}
+
+/// This is used in rate limiter keys, users are just their ID so that their existing keys are kept.
+impl fmt::Display for AgentOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentOwner::User(user) => write!(f, "{}", user),
+            AgentOwner::Team(team) => write!(f, "team-{}", team),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -26,6 +84,7 @@ pub struct InsertableAgentUpload {
     pub owner: i32,
     pub competition: i32,
     pub extension: String,
+    pub team: Option<i32>,
 }
 
 #[derive(AsChangeset)]
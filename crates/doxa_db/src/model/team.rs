@@ -0,0 +1,41 @@
+use crate::schema::{team_invites, team_members, teams};
+
+use chrono::{DateTime, Utc};
+
+use diesel::{Insertable, Queryable};
+
+#[derive(Debug, Clone, Queryable)]
+pub struct Team {
+    pub id: i32,
+    pub competition: i32,
+    pub name: String,
+    pub created_by: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "teams"]
+pub struct InsertableTeam {
+    pub competition: i32,
+    pub name: String,
+    pub created_by: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable, Queryable)]
+#[table_name = "team_members"]
+pub struct TeamMember {
+    pub team: i32,
+    pub user_id: i32,
+    pub competition: i32,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable, Queryable)]
+#[table_name = "team_invites"]
+pub struct TeamInvite {
+    pub team: i32,
+    pub user_id: i32,
+    pub invited_by: i32,
+    pub created_at: DateTime<Utc>,
+}
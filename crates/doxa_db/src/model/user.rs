@@ -1,4 +1,5 @@
 use crate::schema::users;
+use chrono::{DateTime, Utc};
 use diesel::{Insertable, Queryable};
 use serde_json::Value;
 
@@ -12,6 +13,10 @@ pub struct User {
     pub username: String,
     pub token_generation: String,
     pub extra: Value,
+    /// Tokens from logging in that were issued at or before this time are no longer valid
+    pub tokens_revoked_at: Option<DateTime<Utc>>,
+    /// Access tokens that were issued at or before this time are no longer valid
+    pub access_tokens_revoked_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -23,3 +28,13 @@ pub struct InsertableUser {
     pub extra: Value,
     pub admin: bool,
 }
+
+/// A user created by the built-in auth provider, their ID is assigned by the database.
+#[derive(Debug, Clone, Insertable)]
+#[table_name = "users"]
+pub struct InsertableLocalUser {
+    pub username: String,
+    pub token_generation: String,
+    pub extra: Value,
+    pub admin: bool,
+}
@@ -12,6 +12,21 @@ table! {
         active -> Bool,
         execution_environment -> Text,
         file_size -> Int4,
+        team -> Nullable<Int4>,
+    }
+}
+
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        owner -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Array<Text>,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -19,6 +34,44 @@ table! {
     competitions (id) {
         id -> Int4,
         name -> Text,
+        paused -> Bool,
+        open_enrollment -> Bool,
+        max_team_size -> Nullable<Int4>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int8,
+        created_at -> Timestamptz,
+        actor -> Nullable<Int4>,
+        action -> Text,
+        target -> Nullable<Text>,
+        metadata -> Jsonb,
+    }
+}
+
+table! {
+    competition_invites (id) {
+        id -> Int4,
+        competition -> Int4,
+        code -> Text,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        max_uses -> Int4,
+        uses -> Int4,
+        email_domain -> Nullable<Text>,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    competition_roles (user_id, competition, role) {
+        user_id -> Int4,
+        competition -> Int4,
+        role -> Text,
+        granted_at -> Timestamptz,
     }
 }
 
@@ -29,6 +82,19 @@ table! {
     }
 }
 
+table! {
+    execution_nodes (name) {
+        name -> Text,
+        registered_at -> Timestamptz,
+        last_heartbeat_at -> Timestamptz,
+        capacity -> Int4,
+        available -> Int4,
+        running_games -> Array<Int4>,
+        competitions -> Jsonb,
+        drain_timeout_secs -> Nullable<Int4>,
+    }
+}
+
 table! {
     game_events (event_id, game) {
         game -> Int4,
@@ -39,6 +105,14 @@ table! {
     }
 }
 
+table! {
+    game_match_requests (game) {
+        game -> Int4,
+        queue -> Text,
+        data -> Bytea,
+    }
+}
+
 table! {
     game_participants (agent, game) {
         index -> Int4,
@@ -63,6 +137,8 @@ table! {
         completed_at -> Nullable<Timestamptz>,
         outdated -> Bool,
         competition -> Int4,
+        retries -> Int4,
+        runs -> Int4,
     }
 }
 
@@ -74,6 +150,55 @@ table! {
     }
 }
 
+table! {
+    local_account_tokens (token_hash) {
+        token_hash -> Text,
+        user_id -> Int4,
+        purpose -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    local_accounts (user_id) {
+        user_id -> Int4,
+        email -> Text,
+        password_hash -> Text,
+        email_verified_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    team_invites (team, user_id) {
+        team -> Int4,
+        user_id -> Int4,
+        invited_by -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    team_members (team, user_id) {
+        team -> Int4,
+        user_id -> Int4,
+        competition -> Int4,
+        joined_at -> Timestamptz,
+    }
+}
+
+table! {
+    teams (id) {
+        id -> Int4,
+        competition -> Int4,
+        name -> Text,
+        created_by -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
@@ -81,29 +206,58 @@ table! {
         username -> Text,
         token_generation -> Text,
         extra -> Jsonb,
+        tokens_revoked_at -> Nullable<Timestamptz>,
+        access_tokens_revoked_at -> Nullable<Timestamptz>,
     }
 }
 
 joinable!(agents -> competitions (competition));
+joinable!(agents -> teams (team));
 joinable!(agents -> users (owner));
+joinable!(api_tokens -> users (owner));
+joinable!(audit_log -> users (actor));
+joinable!(competition_invites -> competitions (competition));
+joinable!(competition_invites -> users (created_by));
+joinable!(competition_roles -> competitions (competition));
+joinable!(competition_roles -> users (user_id));
 joinable!(enrollment -> competitions (competition));
 joinable!(enrollment -> users (user_id));
 joinable!(game_events -> games (game));
+joinable!(game_match_requests -> games (game));
 joinable!(game_participants -> agents (agent));
 joinable!(game_participants -> games (game));
 joinable!(game_results -> agents (agent));
 joinable!(game_results -> games (game));
 joinable!(games -> competitions (competition));
 joinable!(leaderboard -> agents (agent));
+joinable!(local_account_tokens -> local_accounts (user_id));
+joinable!(local_accounts -> users (user_id));
+joinable!(team_invites -> teams (team));
+joinable!(team_members -> competitions (competition));
+joinable!(team_members -> teams (team));
+joinable!(team_members -> users (user_id));
+joinable!(teams -> competitions (competition));
+joinable!(teams -> users (created_by));
 
 allow_tables_to_appear_in_same_query!(
     agents,
+    api_tokens,
+    audit_log,
+    competition_invites,
+    competition_roles,
     competitions,
     enrollment,
+    execution_nodes,
     game_events,
+    game_match_requests,
     game_participants,
     game_results,
     games,
     leaderboard,
+    local_account_tokens,
+    local_accounts,
+    team_invites,
+    team_members,
+    teams,
     users,
 );
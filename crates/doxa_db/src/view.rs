@@ -1,4 +1,4 @@
-use crate::schema::{game_participants, games, leaderboard, users};
+use crate::schema::{game_participants, games, leaderboard, teams, users};
 
 table! {
     active_agents (id) {
@@ -14,6 +14,7 @@ table! {
         active -> Bool,
         execution_environment -> Text,
         file_size -> Int4,
+        team -> Nullable<Int4>,
     }
 }
 
@@ -27,6 +28,7 @@ table! {
 
 allow_tables_to_appear_in_same_query!(active_agents, leaderboard);
 allow_tables_to_appear_in_same_query!(active_agents, users);
+allow_tables_to_appear_in_same_query!(active_agents, teams);
 allow_tables_to_appear_in_same_query!(active_agents, games);
 allow_tables_to_appear_in_same_query!(active_agents, game_participants);
 allow_tables_to_appear_in_same_query!(active_agents, active_games);
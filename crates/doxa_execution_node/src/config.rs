@@ -1,15 +1,271 @@
-// use std::collections::HashMap;
-//
-// use serde::Deserialize;
-// use url::Url;
-//
-// #[derive(Deserialize)]
-// pub struct CompetitionNodeConfig {
-//     max_parallel_execution: u32,
-// }
-//
-// #[derive(Deserialize)]
-// pub struct NodeConfig {
-//     api_base_url: Url,
-//     competitions: HashMap<String, CompetitionNodeConfig>,
-// }
+//! The configuration file of an execution node, e.g.
+//!
+//! ```yaml
+//! api_base_url: https://doxa.example.com/api/
+//! max_parallel_executions: 4
+//! scratch_base_image: ./dev/vm/images/scratch.img
+//! backend:
+//!   type: docker
+//!   image: registry.example.com/doxa/evaluation_environment
+//!   runtime: nvidia
+//! competitions:
+//!   climatehack:
+//!     profiles:
+//!       gpu: 2
+//!   uttt:
+//!     profiles:
+//!       basic: 4
+//! ```
+//!
+//! Secrets (such as the system account secret and registry credentials) are not part of the
+//! config file, they are given to [`crate::ExecutionNode::new`] instead.
+
+use std::{collections::HashMap, io, path::Path, path::PathBuf};
+
+use derive_more::{Display, Error, From};
+use doxa_executor::{drain::MAX_DRAIN_TIMEOUT, profile::ExecutionProfile, settings::Mount};
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NodeConfig {
+    /// The URL of the DOXA API such that appending `competition/...` gives the competition routes
+    /// and `storage/download/...` gives the agent download route.
+    pub api_base_url: Url,
+    /// The maximum number of games that run at once across every competition and profile.
+    pub max_parallel_executions: usize,
+    /// How long running games are given to finish when the node receives SIGINT or SIGTERM before
+    /// they are cancelled and requeued, this defaults to
+    /// [`doxa_executor::drain::DEFAULT_DRAIN_TIMEOUT`] and can't be more than
+    /// [`doxa_executor::drain::MAX_DRAIN_TIMEOUT`].
+    #[serde(default)]
+    pub drain_timeout_secs: Option<u64>,
+    /// Identifies this node when registering and in the `_START` event of the games it runs, this
+    /// defaults to [`doxa_executor::settings::default_node_name`]. Names must be unique.
+    #[serde(default)]
+    pub node_name: Option<String>,
+    pub scratch_base_image: PathBuf,
+    /// File systems to mount for every competition.
+    #[serde(default)]
+    pub base_mounts: Vec<MountConfig>,
+    pub backend: BackendConfig,
+    /// The competitions to run keyed by name, every competition listed here must have been added
+    /// to the [`crate::ExecutionNode`].
+    pub competitions: HashMap<String, CompetitionNodeConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompetitionNodeConfig {
+    /// The execution profiles (e.g. `basic` or `gpu`) to take match requests for along with how
+    /// many games of that profile can run at once.
+    /// Each profile must be declared by the competition's game client.
+    pub profiles: HashMap<String, usize>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendConfig {
+    Docker {
+        image: String,
+        #[serde(default)]
+        runtime: Option<String>,
+        #[serde(default)]
+        memory_limit_bytes: Option<i64>,
+    },
+    Firecracker {
+        firecracker_path: PathBuf,
+        kernel_img: PathBuf,
+        kernel_boot_args: String,
+        original_rootfs: PathBuf,
+        vcpus: u64,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MountConfig {
+    pub path_on_host: PathBuf,
+    pub path_on_guest: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl From<MountConfig> for Mount {
+    fn from(mount: MountConfig) -> Self {
+        Mount {
+            path_on_host: mount.path_on_host,
+            path_on_guest: mount.path_on_guest,
+            read_only: mount.read_only,
+        }
+    }
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum ConfigError {
+    #[display(fmt = "failed to read config: {}", _0)]
+    Io(io::Error),
+    #[display(fmt = "invalid config: {}", _0)]
+    Yaml(serde_yaml::Error),
+    #[display(fmt = "invalid config: {}", _0)]
+    #[from(ignore)]
+    Invalid(#[error(not(source))] String),
+}
+
+impl NodeConfig {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let config: NodeConfig = serde_yaml::from_slice(&std::fs::read(path)?)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_parallel_executions == 0 {
+            return Err(ConfigError::Invalid(
+                "max_parallel_executions must be at least 1".to_string(),
+            ));
+        }
+
+        if self
+            .drain_timeout_secs
+            .map_or(false, |secs| secs > MAX_DRAIN_TIMEOUT.as_secs())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "drain_timeout_secs can't be more than {}",
+                MAX_DRAIN_TIMEOUT.as_secs()
+            )));
+        }
+
+        for (competition, config) in &self.competitions {
+            if config.profiles.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "competition `{}` has no execution profiles",
+                    competition
+                )));
+            }
+
+            if let Some((profile, _)) = config.profiles.iter().find(|(_, &parallel)| parallel == 0)
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "profile `{}` of competition `{}` must allow at least 1 game at once",
+                    profile, competition
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the config against the competitions that the node can run, `competitions` maps the
+    /// name of each of those competitions to the execution profiles it declares.
+    /// Every competition in the config must be one of them and each of its profiles must be
+    /// declared by the competition.
+    pub fn validate_competitions(
+        &self,
+        competitions: &HashMap<&str, Vec<ExecutionProfile>>,
+    ) -> Result<(), ConfigError> {
+        for (competition, config) in &self.competitions {
+            let declared = competitions.get(competition.as_str()).ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "competition `{}` was not added to the node",
+                    competition
+                ))
+            })?;
+
+            if let Some(profile) = config
+                .profiles
+                .keys()
+                .find(|profile| !declared.iter().any(|declared| &declared.name == *profile))
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "competition `{}` does not declare the execution profile `{}`",
+                    competition, profile
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(competitions: &str, max_parallel_executions: usize) -> NodeConfig {
+        serde_yaml::from_str(&format!(
+            r#"
+api_base_url: https://doxa.example.com/api/
+max_parallel_executions: {}
+scratch_base_image: ./scratch.img
+backend:
+  type: docker
+  image: doxa/evaluation_environment
+competitions:
+{}
+"#,
+            max_parallel_executions, competitions
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_config() {
+        let config = parse("  uttt:\n    profiles:\n      basic: 4\n      gpu: 1", 4);
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.competitions["uttt"].profiles["basic"], 4);
+    }
+
+    #[test]
+    fn max_parallel_executions_must_be_positive() {
+        let config = parse("  uttt:\n    profiles:\n      basic: 4", 0);
+
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn competitions_need_a_profile() {
+        let config = parse("  uttt:\n    profiles: {}", 4);
+
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn profiles_must_allow_a_game() {
+        let config = parse("  uttt:\n    profiles:\n      basic: 0", 4);
+
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn drain_timeout_is_limited() {
+        let mut config = parse("  uttt:\n    profiles:\n      basic: 4", 4);
+        config.drain_timeout_secs = Some(MAX_DRAIN_TIMEOUT.as_secs() + 1);
+
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn competitions_must_have_been_added() {
+        let config = parse("  uttt:\n    profiles:\n      basic: 4", 4);
+        let competitions = HashMap::from([("uttt", vec![ExecutionProfile::new("basic")])]);
+        assert!(config.validate_competitions(&competitions).is_ok());
+
+        let competitions = HashMap::from([("climatehack", vec![ExecutionProfile::new("basic")])]);
+        assert!(matches!(
+            config.validate_competitions(&competitions),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn profiles_must_be_declared() {
+        let config = parse("  uttt:\n    profiles:\n      gpu: 1", 4);
+        let competitions = HashMap::from([("uttt", vec![ExecutionProfile::new("basic")])]);
+
+        assert!(matches!(
+            config.validate_competitions(&competitions),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+//! Registering the node with the API and sending heartbeats, the API uses these to show what each
+//! node is doing and to requeue the games of nodes that are lost.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use doxa_competition::{
+    tokio::{self, sync::Semaphore},
+    tracing::{error, info, warn},
+};
+use doxa_executor::{drain::DrainState, listener::RunningGames};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+/// How often heartbeats are sent, this must be well within the API's heartbeat timeout (90
+/// seconds) otherwise the node's games are requeued.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Serialize)]
+struct RegisterRequest<'a> {
+    name: &'a str,
+    capacity: usize,
+    available: usize,
+    competitions: &'a BTreeMap<String, BTreeMap<String, usize>>,
+    games: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct HeartbeatRequest {
+    capacity: usize,
+    available: usize,
+    games: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+struct HeartbeatResponse {
+    /// Set once an admin has asked the node to drain
+    drain_timeout_secs: Option<u64>,
+}
+
+pub(crate) struct Heartbeat {
+    pub node_name: String,
+    pub api_base_url: Url,
+    pub system_account_secret: String,
+    pub request_client: reqwest::Client,
+    pub capacity: usize,
+    /// The execution profiles run for each competition along with how many games of each can run
+    /// at once
+    pub competitions: BTreeMap<String, BTreeMap<String, usize>>,
+    pub global_limiter: Arc<Semaphore>,
+    pub running_games: RunningGames,
+    pub drain: DrainState,
+}
+
+impl Heartbeat {
+    fn endpoint(&self, segments: &[&str]) -> Url {
+        let mut url = self.api_base_url.clone();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(["admin", "nodes"])
+            .extend(segments);
+        url
+    }
+
+    /// Registers the node, retrying until it succeeds.
+    ///
+    /// This must happen before the node starts any games, as the API treats games that were
+    /// started before the node registered as lost unless the node reports them.
+    pub async fn register(&self) {
+        loop {
+            match self.try_register().await {
+                Ok(()) => {
+                    info!(node = %self.node_name, "registered execution node");
+                    return;
+                }
+                Err(error) => {
+                    error!(%error, debug = ?error, "failed to register execution node");
+                    tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    async fn try_register(&self) -> Result<(), reqwest::Error> {
+        self.request_client
+            .post(self.endpoint(&["register"]))
+            .bearer_auth(&self.system_account_secret)
+            .json(&RegisterRequest {
+                name: &self.node_name,
+                capacity: self.capacity,
+                available: self.global_limiter.available_permits(),
+                competitions: &self.competitions,
+                games: self.running_games.games(),
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Sends heartbeats forever, registering again if the API no longer knows about the node.
+    /// The node starts draining if an admin asks it to in response to a heartbeat.
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let response = self
+                .request_client
+                .post(self.endpoint(&[&self.node_name, "heartbeat"]))
+                .bearer_auth(&self.system_account_secret)
+                .json(&HeartbeatRequest {
+                    capacity: self.capacity,
+                    available: self.global_limiter.available_permits(),
+                    games: self.running_games.games(),
+                })
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            let response = match response {
+                Ok(response) => response.json::<HeartbeatResponse>().await,
+                Err(error) => Err(error),
+            };
+
+            match response {
+                Ok(response) => {
+                    if let Some(timeout_secs) = response.drain_timeout_secs {
+                        self.drain.drain(Duration::from_secs(timeout_secs));
+                    }
+                }
+                Err(error) if error.status() == Some(StatusCode::NOT_FOUND) => {
+                    warn!(node = %self.node_name, "execution node is no longer registered");
+                    self.register().await;
+                }
+                Err(error) => {
+                    error!(%error, debug = ?error, "failed to send heartbeat");
+                }
+            }
+        }
+    }
+}
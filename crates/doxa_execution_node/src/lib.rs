@@ -1,7 +1,7 @@
 pub mod config;
+pub mod heartbeat;
 
 pub mod manager;
+pub mod node;
 
-// struct ExecutionNodeSettings {}
-//
-// pub struct ExecutionNode {}
+pub use node::{ExecutionNode, NodeSecrets};
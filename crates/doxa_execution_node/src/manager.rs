@@ -1,34 +1,52 @@
 use std::sync::Arc;
 
 use doxa_competition::{
-    client::{Competition, ForfeitError, GameClient},
+    client::Competition,
     tokio::{self, sync::Semaphore},
-    tracing::{event, info, span, Level},
+    tracing::info,
 };
-use doxa_core::{lapin::options::BasicAckOptions, tracing_futures::Instrument};
-use doxa_executor::{error::GameManagerError, game::GameManager};
-use doxa_mq::model::MatchRequest;
-use futures_util::{FutureExt, StreamExt};
+use doxa_executor::{
+    cancel::CancellationRegistry,
+    drain::DrainState,
+    listener::{MatchRequestListener, RunningGames},
+    pause::PauseState,
+    profile::ExecutionProfile,
+};
+use doxa_vm::backend::VMBackend;
 use reqwest::Url;
 
-pub use doxa_vm::backend::docker;
-
-pub struct CompetitionManagerSettings {
-    pub executor_permits: u32,
+pub use doxa_vm::backend::{docker, firecracker};
+
+pub struct CompetitionManagerSettings<B: VMBackend> {
+    /// The number of games of this competition and profile that can run at once
+    pub executor_permits: usize,
+    /// Shared by every manager on the node to limit the total number of games running at once
+    pub global_limiter: Arc<Semaphore>,
+    /// Shared by every manager on the node so that the running games are reported in heartbeats
+    pub running_games: RunningGames,
+    /// Shared by every manager on the node, once draining the manager stops taking match
+    /// requests and [`CompetitionNodeManager::start`] returns when the node's games have finished
+    pub drain: DrainState,
+    /// One of the profiles declared by the competition's game client
+    pub execution_profile: ExecutionProfile,
     pub api_base_url: Url,
     pub request_client: reqwest::Client,
-    pub mq_pool: doxa_mq::MQPool,
+    pub mq: doxa_mq::MQ,
     pub executor_settings: Arc<doxa_executor::Settings>,
-    pub docker_settings: docker::DockerBackendSettings,
+    pub backend_settings: B::BackendSettings,
 }
 
-pub struct CompetitionNodeManager<C: Competition> {
+/// Runs the match requests of a single competition and execution profile.
+pub struct CompetitionNodeManager<C: Competition, B: VMBackend> {
     competition: Arc<C>,
-    settings: CompetitionManagerSettings,
+    settings: CompetitionManagerSettings<B>,
 }
 
-impl<C: Competition> CompetitionNodeManager<C> {
-    pub fn new(competition: Arc<C>, settings: CompetitionManagerSettings) -> Self {
+impl<C: Competition, B: VMBackend + 'static> CompetitionNodeManager<C, B>
+where
+    B::BackendSettings: 'static,
+{
+    pub fn new(competition: Arc<C>, settings: CompetitionManagerSettings<B>) -> Self {
         CompetitionNodeManager {
             competition,
             settings,
@@ -37,132 +55,81 @@ impl<C: Competition> CompetitionNodeManager<C> {
 
     pub async fn start(self) {
         let competition_name = C::COMPETITION_NAME;
-        let connection = self
-            .settings
-            .mq_pool
-            .get()
-            .await
-            .expect("Failed to get MQ connection");
-
-        let mut consumer =
-            doxa_mq::action::get_match_request_consumer(&connection, competition_name, "gpu")
-                .await
-                .unwrap();
-
-        info!(
-            competition =%competition_name,
-            "execution event listener",
-        );
-
         let game_client = Arc::new(self.competition.build_game_client());
 
-        //let executor_settings = self.settings.executor_settings.clone();
-        let executor_limiter = Arc::new(Semaphore::new(self.settings.executor_permits as usize));
-
-        while let Some(message) = consumer.next().await {
-            let permit = executor_limiter.clone().acquire_owned().await.unwrap();
-            // TODO: remove expects and convert to error logging
-            let (_, delivery) = message.expect("Error connecting to MQ");
-            let match_request: MatchRequest<
-                <<C as Competition>::GameClient as GameClient>::MatchRequest,
-            > = doxa_mq::action::deserialize(&delivery.data).expect("Improperly formatted message");
-            let game_id = match_request.game_id;
-
-            let span = span!(
-                Level::INFO,
-                "handle match request",
-                game_id = %game_id,
-                agents = ?match_request.agents,
-                competition_name = %competition_name,
+        let cancellation_registry = CancellationRegistry::new();
+        tokio::spawn({
+            let cancellation_registry = cancellation_registry.clone();
+            let mq = self.settings.mq.clone();
+            async move {
+                cancellation_registry
+                    .listen(mq.as_ref(), competition_name)
+                    .await
+            }
+        });
+
+        let pause_state = PauseState::new();
+        tokio::spawn({
+            let pause_state = pause_state.clone();
+            let mq = self.settings.mq.clone();
+            let request_client = self.settings.request_client.clone();
+            let mut paused_endpoint = self.settings.api_base_url.clone();
+            paused_endpoint
+                .path_segments_mut()
+                .unwrap()
+                .pop_if_empty()
+                .extend(&["competition", C::COMPETITION_NAME, "_paused"]);
+            async move {
+                pause_state
+                    .listen(
+                        mq.as_ref(),
+                        competition_name,
+                        &request_client,
+                        paused_endpoint.as_str(),
+                    )
+                    .await
+            }
+        });
+
+        let mut competition_url = self.settings.api_base_url.clone();
+        competition_url
+            .path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(&["competition", C::COMPETITION_NAME]);
+
+        let listener = MatchRequestListener::<C::GameClient, B> {
+            competition_name,
+            execution_profile: self.settings.execution_profile,
+            game_client,
+            competition_url: competition_url.to_string(),
+            mq: self.settings.mq,
+            request_client: self.settings.request_client,
+            executor_settings: self.settings.executor_settings,
+            backend_settings: self.settings.backend_settings,
+            // The profile's own limit is taken first so that waiting for the node-wide limit
+            // only holds up this profile
+            limiters: vec![
+                Arc::new(Semaphore::new(self.settings.executor_permits)),
+                self.settings.global_limiter,
+            ],
+            max_running: self.settings.executor_permits,
+            cancellation_registry,
+            pause_state,
+            drain: self.settings.drain.clone(),
+            running_games: self.settings.running_games,
+            // Lost games are failed and requeued by the server once the node stops sending
+            // heartbeats
+            acknowledge_on_start: true,
+        };
+        listener.listen().await;
+
+        if self.settings.drain.draining() {
+            info!(
+                competition = %competition_name,
+                "waiting for running games as the node is draining",
             );
-
-            let event_channel = doxa_mq::action::game_event_channel(&connection, competition_name)
-                .await
-                .unwrap();
-            let event_channel_name = doxa_mq::action::game_event_queue_name(competition_name);
-
-            tokio::spawn({
-                let mut cancel_endpoint = self.settings.api_base_url.clone();
-                cancel_endpoint.path_segments_mut().unwrap().extend(&[
-                    "competition".to_string(),
-                    C::COMPETITION_NAME.to_string(),
-                    "_game".to_string(),
-                    format!("{}", game_id),
-                    "cancelled".to_string(),
-                ]);
-                // let cancel_endpoint = format!(
-                //     "{}{}/_game/{}/cancelled",
-                //     self.settings.api_base_url, competition_name, game_id
-                // );
-                let request_client = self.settings.request_client.clone();
-                let executor_settings = self.settings.executor_settings.clone();
-                let competition_name = competition_name;
-                let game_client = game_client.clone();
-                let docker_settings = self.settings.docker_settings.clone();
-
-                // let firecracker_settings = firecracker::FirecrackerBackendSettings {
-                //     kernel_img: executor_settings.kernel_img.clone(),
-                //     kernel_boot_args: executor_settings.kernel_boot_args.clone(),
-                //     firecracker_path: executor_settings.firecracker_path.clone(),
-                //     vcpus: 6,
-                // };
-                async move {
-                            // In future there can be some smarter code in the event that the
-                            // code below fails or the server unexpected shutsdown, we need to
-                            // consider that some game events may have been emitted and processed.
-                            // Perhaps it's more correct to only process game events at the end?
-                            delivery
-                                .ack(BasicAckOptions::default())
-                                .await
-                                .expect("Failed to acknowledge MQ");
-
-                            let game_manager = match GameManager::<C::GameClient, docker::DockerBackend>::new(
-                                executor_settings,
-                                docker_settings,
-                                event_channel,
-                                event_channel_name,
-                                competition_name,
-                                match_request,
-                                game_client
-                            )
-                            .await
-                            {
-                                Ok(game_manger) => game_manger,
-                                Err(error) => {
-                                    // Between now and when the agent was first queued it is no
-                                    // longer the correct active agent (e.g. because of error or
-                                    // because a new one was uploads or because it was deleted).
-                                    // This is not a problem, it's good that we don't run the game
-                                    // in the case.
-                                    if let GameManagerError::StartAgent(agent_error) = &error {
-                                        let agent_error = &agent_error.source;
-                                        if matches!(agent_error, &doxa_executor::error::AgentError::AgentGone) {
-                                            event!(Level::DEBUG, "not starting game because agent was gone");
-                                            return;
-                                        }
-                                    }
-                                    // Should probably emit an error game event
-                                    event!(Level::ERROR, %error, debug = ?error, "failed to start game manager");
-                                    return;
-                                }
-                            };
-
-                            info!("started game manager");
-
-                            match game_manager.run_with_cancel_check(cancel_endpoint.to_string(), request_client).await {
-                                Ok(()) => event!(Level::INFO, "game manager successfully completed"),
-                                Err(error) => {
-                                    if error.forfeit().is_some() {
-                                        event!(Level::INFO, forfeit=true, %error, debug = ?error, "error running game manager")
-                                    } else {
-                                        event!(Level::ERROR, forfeit=false, %error, debug = ?error, "error running game manager")
-                                    }
-                                }
-                            }
-                        }
-                        .then(|_| async move { drop(permit); })
-                        .instrument(span)
-            });
+            self.settings.drain.wait_until_drained().await;
         }
     }
 }
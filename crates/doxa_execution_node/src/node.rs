@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use doxa_competition::{
+    client::Competition,
+    tokio::{self, sync::Semaphore},
+    tracing::info,
+};
+use doxa_executor::{
+    client::GameClient,
+    drain::{DrainState, DEFAULT_DRAIN_TIMEOUT},
+    listener::RunningGames,
+    profile::ExecutionProfile,
+    settings::{default_node_name, AgentRetrieval},
+};
+use doxa_mq::MQ;
+use futures_util::future::{join_all, FutureExt, LocalBoxFuture};
+use reqwest::Url;
+
+use crate::{
+    config::{BackendConfig, ConfigError, NodeConfig},
+    heartbeat::Heartbeat,
+    manager::{
+        docker::{Docker, DockerBackend, DockerBackendSettings, DockerCredentials},
+        firecracker::{FirecrackerBackend, FirecrackerBackendSettings},
+        CompetitionManagerSettings, CompetitionNodeManager,
+    },
+};
+
+/// Values used by the node that shouldn't be part of the config file.
+pub struct NodeSecrets {
+    pub system_account_secret: String,
+    /// Used to pull the Docker backend's image
+    pub docker_credentials: Option<DockerCredentials>,
+}
+
+/// Runs the match requests of every competition and execution profile listed in a
+/// [`NodeConfig`], no more than [`NodeConfig::max_parallel_executions`] games run at once.
+pub struct ExecutionNode {
+    config: NodeConfig,
+    mq: MQ,
+    secrets: NodeSecrets,
+    competitions: HashMap<&'static str, Arc<dyn NodeCompetition>>,
+}
+
+impl ExecutionNode {
+    pub fn new(config: NodeConfig, mq: MQ, secrets: NodeSecrets) -> Self {
+        ExecutionNode {
+            config,
+            mq,
+            secrets,
+            competitions: HashMap::new(),
+        }
+    }
+
+    /// Makes a competition available to the node, it only runs games for the competition if it
+    /// is listed in the config.
+    ///
+    /// # Panics
+    /// If a competition with the same name has already been added.
+    pub fn add_competition<C: Competition>(&mut self, competition: C) {
+        if self
+            .competitions
+            .insert(C::COMPETITION_NAME, Arc::new(competition))
+            .is_some()
+        {
+            panic!(
+                "The name `{}` was already registered as a competition",
+                C::COMPETITION_NAME
+            );
+        }
+    }
+
+    /// Registers the node with the API and then runs every competition listed in the config,
+    /// sending heartbeats in the meantime.
+    ///
+    /// This returns once the node has been drained (because of SIGINT, SIGTERM or an admin
+    /// request) and its games have finished or been requeued.
+    /// It returns an error straight away if the config lists a competition that hasn't been added
+    /// with [`ExecutionNode::add_competition`] or a profile that the competition's game client
+    /// doesn't declare (see [`NodeConfig::validate_competitions`]).
+    ///
+    /// # Panics
+    /// If the Docker backend is used and Docker can't be reached.
+    pub async fn start(self) -> Result<(), ConfigError> {
+        // The config is checked before registering so that a config that doesn't match the
+        // competitions fails straight away
+        let declared_profiles: HashMap<_, _> = self
+            .competitions
+            .iter()
+            .map(|(&name, competition)| (name, competition.execution_profiles()))
+            .collect();
+        self.config.validate_competitions(&declared_profiles)?;
+
+        let mut profiles = Vec::new();
+        for (competition_name, competition_config) in &self.config.competitions {
+            let competition = &self.competitions[competition_name.as_str()];
+            let declared = &declared_profiles[competition_name.as_str()];
+
+            for (profile_name, &executor_permits) in &competition_config.profiles {
+                let execution_profile = declared
+                    .iter()
+                    .find(|profile| &profile.name == profile_name)
+                    .cloned()
+                    .expect("profiles are declared as the config was validated");
+
+                profiles.push((
+                    competition_name.clone(),
+                    competition.clone(),
+                    execution_profile,
+                    executor_permits,
+                ));
+            }
+        }
+
+        let node_name = self
+            .config
+            .node_name
+            .clone()
+            .unwrap_or_else(default_node_name);
+
+        let drain_timeout = self
+            .config
+            .drain_timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+
+        let executor_settings = Arc::new(doxa_executor::Settings {
+            agent_retrieval: AgentRetrieval::new(
+                format!(
+                    "{}/storage/download/",
+                    self.config.api_base_url.as_str().trim_end_matches('/')
+                ),
+                self.secrets.system_account_secret.clone(),
+            ),
+            scratch_base_image: self.config.scratch_base_image.clone(),
+            base_mounts: self
+                .config
+                .base_mounts
+                .iter()
+                .cloned()
+                .map(Into::into)
+                .collect(),
+            retry_policy: Default::default(),
+            drain_timeout,
+            // Match requests are acknowledged when their game starts so only the limits set by
+            // execution profiles apply
+            max_game_duration: None,
+            node_name: node_name.clone(),
+        });
+
+        let backend = match self.config.backend.clone() {
+            BackendConfig::Docker {
+                image,
+                runtime,
+                memory_limit_bytes,
+            } => NodeBackend::Docker(DockerBackendSettings {
+                image,
+                docker: Docker::connect_with_unix_defaults().expect("failed to connect to docker"),
+                credentials: self.secrets.docker_credentials.clone(),
+                runtime,
+                memory_limit_bytes,
+            }),
+            BackendConfig::Firecracker {
+                firecracker_path,
+                kernel_img,
+                kernel_boot_args,
+                original_rootfs,
+                vcpus,
+            } => NodeBackend::Firecracker(FirecrackerBackendSettings {
+                kernel_img,
+                kernel_boot_args,
+                firecracker_path,
+                vcpus,
+                original_rootfs,
+            }),
+        };
+
+        let global_limiter = Arc::new(Semaphore::new(self.config.max_parallel_executions));
+        let request_client = reqwest::Client::new();
+        let running_games = RunningGames::new();
+        let drain = DrainState::new();
+
+        let heartbeat = Heartbeat {
+            node_name: node_name.clone(),
+            api_base_url: self.config.api_base_url.clone(),
+            system_account_secret: self.secrets.system_account_secret.clone(),
+            request_client: request_client.clone(),
+            capacity: self.config.max_parallel_executions,
+            competitions: self
+                .config
+                .competitions
+                .iter()
+                .map(|(name, config)| {
+                    (
+                        name.clone(),
+                        config
+                            .profiles
+                            .clone()
+                            .into_iter()
+                            .collect::<BTreeMap<_, _>>(),
+                    )
+                })
+                .collect(),
+            global_limiter: global_limiter.clone(),
+            running_games: running_games.clone(),
+            drain: drain.clone(),
+        };
+        heartbeat.register().await;
+        tokio::spawn(heartbeat.run());
+
+        tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.drain_on_signal(drain_timeout).await }
+        });
+
+        let mut managers = Vec::new();
+        for (competition_name, competition, execution_profile, executor_permits) in profiles {
+            info!(
+                node = %node_name,
+                competition = %competition_name,
+                execution_profile = %execution_profile.name,
+                %executor_permits,
+                "starting competition manager"
+            );
+
+            managers.push(competition.start(ProfileSettings {
+                executor_permits,
+                global_limiter: global_limiter.clone(),
+                running_games: running_games.clone(),
+                drain: drain.clone(),
+                execution_profile,
+                api_base_url: self.config.api_base_url.clone(),
+                request_client: request_client.clone(),
+                mq: self.mq.clone(),
+                executor_settings: executor_settings.clone(),
+                backend: backend.clone(),
+            }));
+        }
+
+        join_all(managers).await;
+        info!(node = %node_name, "execution node drained");
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum NodeBackend {
+    Docker(DockerBackendSettings),
+    Firecracker(FirecrackerBackendSettings),
+}
+
+/// The same as [`CompetitionManagerSettings`] but the backend is chosen at runtime.
+struct ProfileSettings {
+    executor_permits: usize,
+    global_limiter: Arc<Semaphore>,
+    running_games: RunningGames,
+    drain: DrainState,
+    execution_profile: ExecutionProfile,
+    api_base_url: Url,
+    request_client: reqwest::Client,
+    mq: MQ,
+    executor_settings: Arc<doxa_executor::Settings>,
+    backend: NodeBackend,
+}
+
+/// Allows competitions of different types to be stored together.
+trait NodeCompetition {
+    fn execution_profiles(&self) -> Vec<ExecutionProfile>;
+
+    fn start(self: Arc<Self>, settings: ProfileSettings) -> LocalBoxFuture<'static, ()>;
+}
+
+impl<C: Competition> NodeCompetition for C {
+    fn execution_profiles(&self) -> Vec<ExecutionProfile> {
+        C::GameClient::execution_profiles()
+    }
+
+    fn start(self: Arc<Self>, settings: ProfileSettings) -> LocalBoxFuture<'static, ()> {
+        match settings.backend {
+            NodeBackend::Docker(backend_settings) => {
+                CompetitionNodeManager::<C, DockerBackend>::new(
+                    self,
+                    CompetitionManagerSettings {
+                        executor_permits: settings.executor_permits,
+                        global_limiter: settings.global_limiter,
+                        running_games: settings.running_games,
+                        drain: settings.drain,
+                        execution_profile: settings.execution_profile,
+                        api_base_url: settings.api_base_url,
+                        request_client: settings.request_client,
+                        mq: settings.mq,
+                        executor_settings: settings.executor_settings,
+                        backend_settings,
+                    },
+                )
+                .start()
+                .boxed_local()
+            }
+            NodeBackend::Firecracker(backend_settings) => {
+                CompetitionNodeManager::<C, FirecrackerBackend>::new(
+                    self,
+                    CompetitionManagerSettings {
+                        executor_permits: settings.executor_permits,
+                        global_limiter: settings.global_limiter,
+                        running_games: settings.running_games,
+                        drain: settings.drain,
+                        execution_profile: settings.execution_profile,
+                        api_base_url: settings.api_base_url,
+                        request_client: settings.request_client,
+                        mq: settings.mq,
+                        executor_settings: settings.executor_settings,
+                        backend_settings,
+                    },
+                )
+                .start()
+                .boxed_local()
+            }
+        }
+    }
+}
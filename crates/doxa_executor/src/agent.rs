@@ -18,10 +18,17 @@ use tokio::time::timeout;
 
 use crate::{
     error::{AgentError, AgentErrorLogContext, NextEventError, NextMessageError, Timeout},
+    transcript::{Transcript, TranscriptDirection, TranscriptRecorder},
     Settings,
 };
 
 pub const MAX_MSG_LEN: usize = 50_000;
+/// The maximum amount of stderr in bytes that is kept per agent per game, after this any further
+/// stderr is discarded.
+pub const MAX_AGENT_LOGS_LEN: usize = 1_000_000;
+/// Stderr is buffered until there is at least this many bytes before it is emitted as a `_LOGS`
+/// event.
+pub const AGENT_LOGS_CHUNK_LEN: usize = 50_000;
 
 pub struct VMAgent<B: VMBackend> {
     id: String,
@@ -29,6 +36,37 @@ pub struct VMAgent<B: VMBackend> {
     message_reader: MessageReader,
     /// Whether the process is running or not
     running: bool,
+    logs: AgentLogs,
+    transcript: Option<TranscriptRecorder>,
+}
+
+/// Stderr that has been streamed from the agent but not yet emitted.
+#[derive(Default)]
+struct AgentLogs {
+    pending: String,
+    /// The total length of stderr recorded so far including what has already been emitted
+    total_len: usize,
+    truncated: bool,
+    truncation_emitted: bool,
+}
+
+impl AgentLogs {
+    fn push_line(&mut self, line: &[u8]) {
+        if self.truncated {
+            return;
+        }
+
+        let line = String::from_utf8_lossy(line);
+
+        if self.total_len + line.len() + 1 > MAX_AGENT_LOGS_LEN {
+            self.truncated = true;
+            return;
+        }
+
+        self.pending.push_str(&line);
+        self.pending.push('\n');
+        self.total_len += line.len() + 1;
+    }
 }
 
 pub enum AgentEvent<'a> {
@@ -43,6 +81,8 @@ pub struct VMAgentSettings {
     pub swap_size_mb: u64,
     /// All mounts excluding the scratch and rootfs which are mounted automatically
     pub mounts: Vec<Mount>,
+    /// Whether to record a transcript of the agent's input and output
+    pub record_transcript: bool,
 }
 
 impl<B: VMBackend> VMAgent<B> {
@@ -97,6 +137,8 @@ impl<B: VMBackend> VMAgent<B> {
         //     mounts: vm_agent_settings.mounts,
         // };
 
+        let record_transcript = vm_agent_settings.record_transcript;
+
         let vm_settings = VMManagerSettings {
             swap_size_mib: vm_agent_settings.swap_size_mb,
             scratch_source_path: settings.scratch_base_image.clone(),
@@ -134,6 +176,8 @@ impl<B: VMBackend> VMAgent<B> {
             id: agent_id,
             message_reader: MessageReader::new(Vec::new(), MAX_MSG_LEN as MessageLen),
             running: false,
+            logs: AgentLogs::default(),
+            transcript: record_transcript.then(TranscriptRecorder::new),
         };
 
         Ok(agent)
@@ -145,6 +189,10 @@ impl<B: VMBackend> VMAgent<B> {
 
     /// See [`doxa_vm::Manager::send_agent_input`]
     pub async fn send_agent_input(&mut self, msg: &[u8]) -> Result<(), std::io::Error> {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.record(TranscriptDirection::Input, msg);
+        }
+
         self.vm_manager.send_agent_input(msg).await
     }
 
@@ -161,31 +209,38 @@ impl<B: VMBackend> VMAgent<B> {
     }
 
     /// Retrieves the next event sent by the VMExecutor.
+    /// Any stderr received while waiting is recorded in the agent's logs.
     /// This method is cancel safe.
     async fn next_event(&mut self) -> Result<AgentEvent<'_>, NextEventError> {
-        let msg = self
-            .message_reader
-            .read_full_message(self.vm_manager.stream_mut())
-            .await?;
-
-        let split_location = msg
-            .iter()
-            .position(|b| *b == b'_')
-            .ok_or(NextEventError::MissingSeparator)?;
-        let (prefix, msg) = msg.split_at(split_location);
-        // Exclude the _ character itself
-        let msg = &msg[1..];
-
-        match prefix {
-            b"OUTPUT" => {
-                // This is currently a line of output (without the newline)
-                return Ok(AgentEvent::Line(msg));
+        // The message can't be returned from inside the loop without upsetting the borrow
+        // checker, so once we have a message for the caller it is borrowed again afterwards.
+        loop {
+            let msg = self
+                .message_reader
+                .read_full_message(self.vm_manager.stream_mut())
+                .await?;
+
+            let (prefix, msg) = split_prefix(msg)?;
+
+            match prefix {
+                b"OUTPUT" | b"F" => break,
+                b"STDERR" => self.logs.push_line(msg),
+                _ => return Err(NextEventError::UnrecognisedPrefix),
             }
-            b"F" => {
-                self.running = false;
-                Ok(AgentEvent::Finished { stderr: msg })
+        }
+
+        let (prefix, msg) = split_prefix(self.message_reader.message())?;
+
+        if prefix == b"F" {
+            self.running = false;
+            Ok(AgentEvent::Finished { stderr: msg })
+        } else {
+            if let Some(transcript) = &mut self.transcript {
+                transcript.record(TranscriptDirection::Output, msg);
             }
-            _ => Err(NextEventError::UnrecognisedPrefix),
+
+            // This is currently a line of output (without the newline)
+            Ok(AgentEvent::Line(msg))
         }
     }
 
@@ -207,7 +262,46 @@ impl<B: VMBackend> VMAgent<B> {
         }
     }
 
+    /// Takes the stderr that has been buffered since the last call along with whether the logs
+    /// have been truncated.
+    /// Unless `force` is set this only returns logs once at least `AGENT_LOGS_CHUNK_LEN` bytes
+    /// have been buffered.
+    pub(crate) fn take_logs(&mut self, force: bool) -> Option<(String, bool)> {
+        let logs = &mut self.logs;
+        let report_truncation = logs.truncated && !logs.truncation_emitted;
+
+        let ready = logs.pending.len() >= AGENT_LOGS_CHUNK_LEN
+            || report_truncation
+            || (force && !logs.pending.is_empty());
+
+        if !ready {
+            return None;
+        }
+
+        logs.truncation_emitted = logs.truncated;
+
+        Some((std::mem::take(&mut logs.pending), logs.truncated))
+    }
+
+    /// Takes the transcript of the agent's input and output if one was being recorded.
+    pub(crate) fn take_transcript(&mut self) -> Option<Transcript> {
+        self.transcript.take().map(TranscriptRecorder::finish)
+    }
+
     pub async fn shutdown(self) -> Result<String, VMShutdownError> {
         self.vm_manager.shutdown().await
     }
 }
+
+/// Splits a message from the VMExecutor into its prefix and the rest of the message excluding the
+/// `_` separator.
+fn split_prefix(msg: &[u8]) -> Result<(&[u8], &[u8]), NextEventError> {
+    let split_location = msg
+        .iter()
+        .position(|b| *b == b'_')
+        .ok_or(NextEventError::MissingSeparator)?;
+    let (prefix, msg) = msg.split_at(split_location);
+
+    // Exclude the _ character itself
+    Ok((prefix, &msg[1..]))
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use doxa_core::{
+    tokio::{self, sync::Notify},
+    tracing::{debug, error, info},
+};
+use doxa_mq::{model::CancellationEvent, MQError, QueueBackend};
+use futures::StreamExt;
+
+/// How long to wait before subscribing again after losing the connection to the MQ.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Keeps track of the games running on this executor so that they can be cancelled as soon as a
+/// cancellation event is received.
+#[derive(Clone, Default)]
+pub struct CancellationRegistry {
+    games: Arc<Mutex<HashMap<i32, Arc<Notify>>>>,
+}
+
+impl CancellationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a game, the game stays registered until the returned handle is dropped.
+    pub fn register(&self, game_id: i32) -> CancellationHandle {
+        let notify = Arc::new(Notify::new());
+        self.games.lock().unwrap().insert(game_id, notify.clone());
+
+        CancellationHandle {
+            game_id,
+            notify,
+            registry: self.clone(),
+        }
+    }
+
+    /// Cancels the game if it is registered, returning whether it was.
+    pub fn cancel(&self, game_id: i32) -> bool {
+        match self.games.lock().unwrap().get(&game_id) {
+            Some(notify) => {
+                // `notify_one` stores a permit so the cancellation isn't lost if the game isn't
+                // currently waiting on it
+                notify.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Consumes the competition's cancellation events, cancelling any of the games that are
+    /// registered.
+    /// If the connection to the MQ is lost this subscribes again so it never returns, any
+    /// cancellations that are missed in the meantime are picked up by polling.
+    pub async fn listen(&self, mq: &dyn QueueBackend, competition_name: &str) {
+        loop {
+            if let Err(error) = self.listen_until_disconnected(mq, competition_name).await {
+                error!(%error, debug = ?error, "lost connection while listening for game cancellations");
+            }
+
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    async fn listen_until_disconnected(
+        &self,
+        mq: &dyn QueueBackend,
+        competition_name: &str,
+    ) -> Result<(), MQError> {
+        let mut consumer = doxa_mq::action::get_cancellation_consumer(mq, competition_name).await?;
+
+        info!(competition = %competition_name, "listening for game cancellations");
+
+        while let Some(message) = consumer.next().await {
+            let delivery = message?;
+            let event: CancellationEvent = match doxa_mq::action::decode(&delivery.data) {
+                Ok(event) => event,
+                Err(error) => {
+                    error!(%error, debug = ?error, "failed to decode cancellation event");
+                    continue;
+                }
+            };
+
+            for game_id in event.game_ids {
+                if self.cancel(game_id) {
+                    debug!(%game_id, "cancelling game");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct CancellationHandle {
+    game_id: i32,
+    notify: Arc<Notify>,
+    registry: CancellationRegistry,
+}
+
+impl CancellationHandle {
+    /// Resolves once the game has been cancelled.
+    pub async fn cancelled(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for CancellationHandle {
+    fn drop(&mut self) {
+        let mut games = self.registry.games.lock().unwrap();
+        // The game may have been registered again in the meantime (e.g. after a redelivery)
+        if matches!(games.get(&self.game_id), Some(notify) if Arc::ptr_eq(notify, &self.notify)) {
+            games.remove(&self.game_id);
+        }
+    }
+}
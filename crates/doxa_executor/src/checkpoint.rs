@@ -0,0 +1,56 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::GameContextError;
+
+/// The most recent state saved by [`crate::context::GameContext::checkpoint`] which is given to
+/// [`crate::client::GameClient::resume`] when an interrupted game is run again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    /// The ID of the `_CHECKPOINT` event, every event before this was emitted before the
+    /// checkpoint was saved.
+    pub event_id: u32,
+    /// The seed of the game that was interrupted, the resumed game uses the same seed.
+    #[serde(with = "crate::seed")]
+    pub seed: u64,
+    state: serde_json::Value,
+}
+
+impl Checkpoint {
+    /// Deserializes the state that was passed to [`crate::context::GameContext::checkpoint`].
+    pub fn state<T: DeserializeOwned>(&self) -> Result<T, GameContextError> {
+        serde_json::from_value(self.state.clone()).map_err(GameContextError::InvalidCheckpoint)
+    }
+}
+
+/// Where a run of a game should start from, if the game was interrupted (e.g. because the executor
+/// crashed, because it is being retried or because its executor was drained) this includes the
+/// latest checkpoint.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResumePoint {
+    /// The first event ID of the new run, this is greater than every event already stored for the
+    /// game and every event an earlier run could still have queued (see
+    /// [`crate::context::EVENT_IDS_PER_RUN`]).
+    pub next_event_id: u32,
+    pub checkpoint: Option<Checkpoint>,
+}
+
+/// Starts a run of a game and gets its resume point from the `_game/{game_id}/checkpoint`
+/// endpoint, which is only available to the system account (and admins) as it includes the seed
+/// and state of the game.
+///
+/// Game events are processed asynchronously so any checkpoint emitted just before the executor
+/// crashed may not yet be included.
+pub async fn fetch_resume_point(
+    client: &reqwest::Client,
+    system_account_secret: &str,
+    checkpoint_endpoint: &str,
+) -> Result<ResumePoint, reqwest::Error> {
+    client
+        .post(checkpoint_endpoint)
+        .bearer_auth(system_account_secret)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
@@ -6,7 +6,14 @@ pub use doxa_vm::mount::Mount;
 use serde::{de::DeserializeOwned, Serialize};
 
 pub use crate::error::ForfeitError;
-pub use crate::{context::GameContext, error::GameError};
+pub use crate::{
+    checkpoint::Checkpoint,
+    context::{GameContext, GameRng},
+    error::GameError,
+    profile::ExecutionProfile,
+};
+pub use doxa_mq::envelope::DecodeError;
+pub use rand;
 
 pub const DEFAULT_AGENT_RAM_MB: u64 = 512;
 pub const DEFAULT_AGENT_SCRATCH_MB: u64 = 256;
@@ -30,6 +37,17 @@ pub trait GameClient: Send + Sync + 'static {
     /// deserialization/storage simple.
     type GameEvent: Serialize + DeserializeOwned + Send + 'static;
 
+    /// The version of [`GameClient::MatchRequest`].
+    /// This must be incremented whenever `MatchRequest` changes in a way that means match requests
+    /// that are already queued can no longer be deserialized, those match requests are then
+    /// converted with [`GameClient::upgrade_match_request`].
+    const MATCH_REQUEST_VERSION: u32 = 0;
+
+    /// The version of [`GameClient::GameEvent`], see [`GameClient::MATCH_REQUEST_VERSION`].
+    /// Events with an older version are converted with [`GameClient::upgrade_game_event`] before
+    /// they are stored.
+    const GAME_EVENT_VERSION: u32 = 0;
+
     /// The amount of ram that an agent's VM is given measured in mega-bytes.
     /// This defaults to [`DEFAULT_AGENT_RAM_MB`].
     /// NOTE: this is the total amount of ram including that which is used by the guest OS not just
@@ -47,15 +65,78 @@ pub trait GameClient: Send + Sync + 'static {
     /// This will add to the total amount of memory that an agent will have, but typically swap will be slower as it is a file on disk.
     const AGENT_SWAP_MB: u64 = DEFAULT_AGENT_SCRATCH_MB;
 
+    /// The execution profiles that agents can declare with `execution_environment` in their
+    /// `doxa.yaml`, uploads that declare any other profile are rejected.
+    /// Agents that don't declare a profile use the first one.
+    ///
+    /// Each profile can override the agent resource constants above (e.g. a `high-memory`
+    /// profile).
+    /// This defaults to [`crate::profile::default_execution_profiles`] (`basic` then `gpu`).
+    fn execution_profiles() -> Vec<ExecutionProfile> {
+        crate::profile::default_execution_profiles()
+    }
+
+    /// Converts the payload of a match request that was queued with an older `version` of
+    /// [`GameClient::MatchRequest`].
+    /// The payload is serialized with bincode (see [`doxa_mq::action::deserialize`]).
+    ///
+    /// By default no version can be upgraded, so these match requests are dead lettered.
+    fn upgrade_match_request(
+        version: u32,
+        _payload: &[u8],
+    ) -> Result<Self::MatchRequest, DecodeError> {
+        Err(DecodeError::UnsupportedPayloadVersion {
+            found: version,
+            current: Self::MATCH_REQUEST_VERSION,
+        })
+    }
+
+    /// Converts the payload of a game event that was emitted with an older `version` of
+    /// [`GameClient::GameEvent`].
+    ///
+    /// By default no version can be upgraded, so these events are dead lettered.
+    fn upgrade_game_event(
+        version: u32,
+        _payload: serde_json::Value,
+    ) -> Result<Self::GameEvent, DecodeError> {
+        Err(DecodeError::UnsupportedPayloadVersion {
+            found: version,
+            current: Self::GAME_EVENT_VERSION,
+        })
+    }
+
     /// An optional list of additional mounts for the VM (defaults to empty vec)
     fn additional_mounts(&self, _match_request: &Self::MatchRequest) -> Vec<Mount> {
         vec![]
     }
 
+    /// Whether to record a transcript of every agent's input and output for this match (defaults
+    /// to false).
+    /// Transcripts are stored compressed alongside the game and can be viewed by the owner of the
+    /// agent and admins.
+    fn record_transcript(&self, _match_request: &Self::MatchRequest) -> bool {
+        false
+    }
+
     /// Runs the game until completion.
     async fn run<'a, B: VMBackend>(
         &self,
         match_request: Self::MatchRequest,
         context: &mut GameContext<'a, Self, B>,
     ) -> Result<(), GameError<Self::Error>>;
+
+    /// Continues a game that was interrupted (e.g. because the executor restarted) from the last
+    /// checkpoint saved with [`GameContext::checkpoint`].
+    /// The `context` uses the same seed as the interrupted game however the RNG starts again from
+    /// the beginning, so any RNG state that matters should be part of the checkpoint.
+    ///
+    /// By default this ignores the checkpoint and runs the game again from the start.
+    async fn resume<'a, B: VMBackend>(
+        &self,
+        match_request: Self::MatchRequest,
+        _checkpoint: Checkpoint,
+        context: &mut GameContext<'a, Self, B>,
+    ) -> Result<(), GameError<Self::Error>> {
+        self.run(match_request, context).await
+    }
 }
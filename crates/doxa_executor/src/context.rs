@@ -3,6 +3,8 @@ use std::{marker::PhantomData, path::PathBuf, time::Duration};
 use doxa_core::tokio;
 use doxa_vm::backend::VMBackend;
 use futures::TryFutureExt;
+use rand::SeedableRng;
+use serde::Serialize;
 use tokio::time::timeout;
 
 use crate::{
@@ -15,9 +17,14 @@ use crate::{
 mod game_event;
 
 pub(crate) use game_event::GameEventContext;
+pub use game_event::{run_of_event, EVENT_IDS_PER_RUN};
 
 pub const DEFAULT_MAX_MESSAGE_TIME: Duration = Duration::from_secs(120);
 
+/// The RNG provided to game clients, this is guaranteed to produce the same values for the same
+/// seed.
+pub type GameRng = rand_chacha::ChaCha8Rng;
+
 pub struct AsyncTempDir {
     tempdir: tempfile::TempDir,
 }
@@ -42,12 +49,15 @@ pub struct GameContext<'a, C: GameClient + ?Sized, B: VMBackend> {
     max_message_time: Duration,
     pub(crate) game_event_context: &'a mut GameEventContext<C>,
     work_dir: Option<AsyncTempDir>,
+    seed: u64,
+    rng: GameRng,
 }
 
 impl<'a, C: GameClient, B: VMBackend> GameContext<'a, C, B> {
     pub(crate) fn new(
         agents: &'a mut Vec<VMAgent<B>>,
         game_event_context: &'a mut GameEventContext<C>,
+        seed: u64,
     ) -> Self {
         GameContext {
             agents,
@@ -55,9 +65,30 @@ impl<'a, C: GameClient, B: VMBackend> GameContext<'a, C, B> {
             max_message_time: DEFAULT_MAX_MESSAGE_TIME,
             game_event_context,
             work_dir: None,
+            seed,
+            rng: GameRng::seed_from_u64(seed),
         }
     }
 
+    /// The seed of this game which is recorded in the `_START` event.
+    /// Replaying a game with the same seed (and agents) will give the same values from
+    /// [`GameContext::rng`].
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A random number generator seeded with [`GameContext::seed`].
+    /// All randomness in the game client should come from here so that games can be reproduced.
+    pub fn rng(&mut self) -> &mut GameRng {
+        &mut self.rng
+    }
+
+    /// An argument of the form `--seed={seed}` which can be passed to [`GameContext::reboot_agent`]
+    /// for agents that need to use the same seed as the game.
+    pub fn seed_arg(&self) -> String {
+        format!("--seed={}", self.seed)
+    }
+
     /// Gets a path to a temporary working directory that will be cleaned up at the end of
     /// execution.
     ///
@@ -89,6 +120,22 @@ impl<'a, C: GameClient, B: VMBackend> GameContext<'a, C, B> {
             .map_err(GameContextError::Emit)
     }
 
+    /// Saves the state of the game client so that, if the game is interrupted (e.g. because the
+    /// executor restarted), it is resumed from here with [`GameClient::resume`] rather than being
+    /// run again from the start.
+    ///
+    /// Events emitted before the checkpoint are kept so the state only needs to contain what is
+    /// required to carry on, such as the progress made so far.
+    /// Note: the state is only served to executors (the system account) and admins.
+    pub async fn checkpoint<S: Serialize>(&mut self, state: &S) -> Result<(), GameContextError> {
+        let state = serde_json::to_value(state).map_err(GameContextError::SerializeCheckpoint)?;
+
+        self.game_event_context
+            .emit_checkpoint_event(self.seed, state)
+            .await
+            .map_err(GameContextError::Emit)
+    }
+
     /// Forfeits an agent.
     ///
     /// The error message is viewable by the owner of the agent that forfeitted.
@@ -140,6 +187,8 @@ impl<'a, C: GameClient, B: VMBackend> GameContext<'a, C, B> {
     /// This will timeout if it does not receive a message within `max_message_time` which can be
     /// configured.
     pub async fn next_message(&mut self, agent_id: usize) -> Result<&[u8], GameContextError> {
+        self.flush_agent_logs(agent_id, false).await?;
+
         let max_message_time = self.max_message_time;
         let agent = self.agent_mut(agent_id)?;
 
@@ -158,6 +207,55 @@ impl<'a, C: GameClient, B: VMBackend> GameContext<'a, C, B> {
         Ok(msg)
     }
 
+    /// Emits any stderr that has been buffered for an agent as a `_LOGS` event.
+    /// Unless `force` is set, this will only emit once enough has been buffered.
+    pub(crate) async fn flush_agent_logs(
+        &mut self,
+        agent_id: usize,
+        force: bool,
+    ) -> Result<(), GameContextError> {
+        let agent = self.agent_mut(agent_id)?;
+
+        if let Some((stderr, truncated)) = agent.take_logs(force) {
+            self.game_event_context
+                .emit_logs_event(agent_id, stderr, truncated)
+                .await
+                .map_err(GameContextError::Emit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Emits all of the remaining buffered stderr for every agent.
+    pub(crate) async fn flush_all_agent_logs(&mut self) -> Result<(), GameContextError> {
+        for i in 0..self.agents() {
+            self.flush_agent_logs(i, true).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Emits the transcript of every agent that was recording one.
+    pub(crate) async fn emit_transcripts(&mut self) -> Result<(), GameContextError> {
+        for agent_id in 0..self.agents() {
+            let transcript = match self.agents[agent_id].take_transcript() {
+                Some(transcript) => transcript,
+                None => continue,
+            };
+
+            let transcript = transcript
+                .compress()
+                .map_err(GameContextError::CompressTranscript)?;
+
+            self.game_event_context
+                .emit_transcript_events(agent_id, &transcript)
+                .await
+                .map_err(GameContextError::Emit)?;
+        }
+
+        Ok(())
+    }
+
     /// Takes a file from a particular directory, assuming that it exists.
     /// This does not wait for the file to be created or done writing, it's important that you
     /// allow the indicate it has written **and flushed** the file.
@@ -254,3 +352,15 @@ impl<'a, C: GameClient, B: VMBackend> GameContext<'a, C, B> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runs_are_found_from_event_ids() {
+        assert_eq!(run_of_event(0), 0);
+        assert_eq!(run_of_event(12), 0);
+        assert_eq!(run_of_event(2 * EVENT_IDS_PER_RUN + 3), 2);
+    }
+}
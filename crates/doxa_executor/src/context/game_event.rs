@@ -1,34 +1,51 @@
 use std::marker::PhantomData;
 
-use doxa_core::{
-    chrono::Utc,
-    lapin::{self, Channel},
-};
-use doxa_mq::model::GameEvent;
+use doxa_core::chrono::Utc;
+use doxa_mq::{model::GameEvent, MQError, MQ};
 use serde::Serialize;
 
 use crate::{
     client::GameClient,
-    event::{CancelledEvent, ErrorEvent, StartEvent},
+    event::{
+        AgentLogsEvent, CancelledEvent, CheckpointEvent, ErrorEvent, ResumeEvent, RetryEvent,
+        StartEvent, TranscriptEvent,
+    },
+    transcript::split_chunks,
 };
 
-use std::error::Error;
+use std::{error::Error, time::Duration};
+
+/// Every time an interrupted game is run again it is given a new run number by the server and it
+/// starts its event IDs from at least `run * EVENT_IDS_PER_RUN`, so the events of an earlier run
+/// that are still queued can't collide with those of the new run.
+pub const EVENT_IDS_PER_RUN: u32 = 1_000_000;
+
+/// The run that emitted the event, as long as runs don't emit more than [`EVENT_IDS_PER_RUN`]
+/// events.
+pub fn run_of_event(event_id: u32) -> u32 {
+    event_id / EVENT_IDS_PER_RUN
+}
 
 pub(crate) struct GameEventContext<C: GameClient + ?Sized> {
     game_id: i32,
     event_id: u32,
-    event_queue_name: String,
-    event_channel: Channel,
+    competition_name: &'static str,
+    mq: MQ,
+    /// Whether this run has saved a checkpoint
+    checkpointed: bool,
     client: PhantomData<C>,
 }
 
 impl<'a, C: GameClient> GameEventContext<C> {
-    pub fn new(event_channel: Channel, event_queue_name: String, game_id: i32) -> Self {
+    /// `first_event_id` must be greater than any event emitted by an earlier run of the game (e.g.
+    /// the `next_event_id` of a [`crate::checkpoint::ResumePoint`]).
+    pub fn new(mq: MQ, competition_name: &'static str, game_id: i32, first_event_id: u32) -> Self {
         GameEventContext {
             game_id,
-            event_id: 0,
-            event_channel,
-            event_queue_name,
+            event_id: first_event_id,
+            competition_name,
+            mq,
+            checkpointed: false,
             client: PhantomData::default(),
         }
     }
@@ -36,26 +53,136 @@ impl<'a, C: GameClient> GameEventContext<C> {
     pub(crate) async fn emit_start_event(
         &mut self,
         agents: Vec<String>,
-    ) -> Result<(), lapin::Error> {
-        self.emit_event_raw(StartEvent { agents }, "_START".to_string())
-            .await
+        seed: u64,
+        node: String,
+    ) -> Result<(), MQError> {
+        self.emit_event_raw(
+            StartEvent {
+                agents,
+                seed: Some(seed),
+                node: Some(node),
+            },
+            "_START".to_string(),
+        )
+        .await
+    }
+
+    pub(crate) async fn emit_resume_event(
+        &mut self,
+        checkpoint_event_id: u32,
+    ) -> Result<(), MQError> {
+        self.emit_event_raw(
+            ResumeEvent {
+                checkpoint_event_id,
+            },
+            "_RESUME".to_string(),
+        )
+        .await
+    }
+
+    pub(crate) async fn emit_checkpoint_event(
+        &mut self,
+        seed: u64,
+        state: serde_json::Value,
+    ) -> Result<(), MQError> {
+        self.emit_event_raw(CheckpointEvent { seed, state }, "_CHECKPOINT".to_string())
+            .await?;
+        self.checkpointed = true;
+
+        Ok(())
+    }
+
+    /// Whether a checkpoint has been saved since this context was created, i.e. whether this run
+    /// of the game made progress that the next run can resume from.
+    pub(crate) fn checkpointed(&self) -> bool {
+        self.checkpointed
     }
 
-    pub(crate) async fn emit_cancelled_event(&mut self) -> Result<(), lapin::Error> {
-        self.emit_event_raw(CancelledEvent {}, "_CANCELLED".to_string())
+    pub(crate) async fn emit_cancelled_event(&mut self, requeued: bool) -> Result<(), MQError> {
+        self.emit_event_raw(CancelledEvent { requeued }, "_CANCELLED".to_string())
             .await
     }
-    pub(crate) async fn emit_end_event(&mut self) -> Result<(), lapin::Error> {
+
+    pub(crate) async fn emit_retry_event(
+        &mut self,
+        attempt: u32,
+        backoff: Duration,
+    ) -> Result<(), MQError> {
+        self.emit_event_raw(
+            RetryEvent {
+                attempt,
+                backoff_secs: backoff.as_secs(),
+            },
+            "_RETRY".to_string(),
+        )
+        .await
+    }
+
+    /// Emits `_RETRY` if the game will be retried otherwise `_END`.
+    pub(crate) async fn emit_final_event(
+        &mut self,
+        retry: Option<(u32, Duration)>,
+    ) -> Result<(), MQError> {
+        match retry {
+            Some((attempt, backoff)) => self.emit_retry_event(attempt, backoff).await,
+            None => self.emit_end_event().await,
+        }
+    }
+
+    pub(crate) async fn emit_end_event(&mut self) -> Result<(), MQError> {
         // TODO: end event data, e.g. total time spent, maybe whether it completed succesfully or
         // not
         self.emit_event_raw((), "_END".to_string()).await
     }
 
+    pub(crate) async fn emit_logs_event(
+        &mut self,
+        agent_id: usize,
+        stderr: String,
+        truncated: bool,
+    ) -> Result<(), MQError> {
+        self.emit_event_raw(
+            AgentLogsEvent {
+                agent_id,
+                stderr,
+                truncated,
+            },
+            "_LOGS".to_string(),
+        )
+        .await
+    }
+
+    /// Emits a compressed transcript as one or more `_TRANSCRIPT` events, see
+    /// [`crate::transcript::split_chunks`].
+    pub(crate) async fn emit_transcript_events(
+        &mut self,
+        agent_id: usize,
+        transcript: &str,
+    ) -> Result<(), MQError> {
+        let chunks = split_chunks(transcript);
+        let chunk_count = chunks.len() as u32;
+
+        for (chunk, data) in chunks.into_iter().enumerate() {
+            self.emit_event_raw(
+                TranscriptEvent {
+                    agent_id,
+                    transcript: data.to_string(),
+                    chunk: chunk as u32,
+                    chunks: chunk_count,
+                },
+                "_TRANSCRIPT".to_string(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn emit_error_event<E: Error>(
         &mut self,
         error: &E,
         vm_logs: Vec<Option<String>>,
-    ) -> Result<(), lapin::Error> {
+    ) -> Result<(), MQError> {
         self.emit_event_raw(
             ErrorEvent {
                 error: format!("{}", error),
@@ -71,7 +198,7 @@ impl<'a, C: GameClient> GameEventContext<C> {
         &mut self,
         payload: T,
         event_type: String,
-    ) -> Result<(), lapin::Error> {
+    ) -> Result<(), MQError> {
         let timestamp = Utc::now();
         let game_event = GameEvent {
             event_id: self.event_id,
@@ -82,12 +209,12 @@ impl<'a, C: GameClient> GameEventContext<C> {
         };
         self.event_id += 1;
 
-        doxa_mq::action::publish(
-            &self.event_channel,
-            &self.event_queue_name,
-            serde_json::to_vec(&game_event).unwrap(),
+        doxa_mq::action::emit_game_event(
+            self.mq.as_ref(),
+            self.competition_name,
+            &game_event,
+            C::GAME_EVENT_VERSION,
         )
         .await
-        .map(|_| ())
     }
 }
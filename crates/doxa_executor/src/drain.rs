@@ -0,0 +1,248 @@
+//! Draining the executors of a process before it shuts down so that games aren't killed part way
+//! through.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use doxa_core::{
+    tokio::{
+        self, signal,
+        sync::watch,
+        time::{self, Instant},
+    },
+    tracing::{error, info},
+};
+
+/// The default time that running games are given to finish once draining starts.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The longest time that running games can be given to finish once draining starts.
+pub const MAX_DRAIN_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Shared by the execution managers of a process. Once draining, managers stop taking match
+/// requests and the games that are still running when the deadline passes are cancelled and
+/// requeued (see [`crate::game::GameManager::run_with_cancel_check`]).
+#[derive(Clone)]
+pub struct DrainState {
+    deadline: Arc<watch::Sender<Option<Instant>>>,
+    deadline_receiver: watch::Receiver<Option<Instant>>,
+    running: Arc<Mutex<watch::Sender<usize>>>,
+    running_receiver: watch::Receiver<usize>,
+}
+
+impl DrainState {
+    pub fn new() -> Self {
+        let (deadline, deadline_receiver) = watch::channel(None);
+        let (running, running_receiver) = watch::channel(0);
+
+        DrainState {
+            deadline: Arc::new(deadline),
+            deadline_receiver,
+            running: Arc::new(Mutex::new(running)),
+            running_receiver,
+        }
+    }
+
+    /// Starts draining, games that are still running after `timeout` are cancelled.
+    /// If this is already draining the earlier deadline is kept.
+    /// A `timeout` too large to give a deadline is treated as [`MAX_DRAIN_TIMEOUT`].
+    pub fn drain(&self, timeout: Duration) {
+        let now = Instant::now();
+        let deadline = now
+            .checked_add(timeout)
+            .unwrap_or_else(|| now + MAX_DRAIN_TIMEOUT);
+
+        match *self.deadline_receiver.borrow() {
+            Some(current) if current <= deadline => return,
+            _ => {}
+        }
+
+        info!(?timeout, running_games = %self.running_games(), "draining executors");
+        // This can't fail as `self` holds a receiver
+        let _ = self.deadline.send(Some(deadline));
+    }
+
+    pub fn draining(&self) -> bool {
+        self.deadline_receiver.borrow().is_some()
+    }
+
+    /// How long until the games that are still running are cancelled, this is `None` if not
+    /// draining.
+    pub fn time_until_deadline(&self) -> Option<Duration> {
+        self.deadline_receiver
+            .borrow()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Resolves once draining has started.
+    pub async fn wait_until_draining(&self) {
+        let mut receiver = self.deadline_receiver.clone();
+
+        while receiver.borrow().is_none() {
+            if receiver.changed().await.is_err() {
+                // Nothing can start draining anymore
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Resolves once draining has started and the deadline has passed.
+    pub async fn deadline_passed(&self) {
+        let mut receiver = self.deadline_receiver.clone();
+
+        loop {
+            let deadline = *receiver.borrow();
+            let changed = match deadline {
+                Some(deadline) => tokio::select! {
+                    _ = time::sleep_until(deadline) => return,
+                    changed = receiver.changed() => changed,
+                },
+                None => receiver.changed().await,
+            };
+
+            if changed.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Starts draining with `timeout` once the process receives SIGINT or SIGTERM.
+    pub async fn drain_on_signal(&self, timeout: Duration) {
+        #[cfg(unix)]
+        let terminate = async {
+            match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+                Ok(mut terminate) => {
+                    terminate.recv().await;
+                }
+                Err(error) => {
+                    error!(%error, debug = ?error, "failed to listen for SIGTERM");
+                    futures::future::pending::<()>().await;
+                }
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = futures::future::pending::<()>();
+
+        tokio::select! {
+            _ = signal::ctrl_c() => {},
+            _ = terminate => {},
+        }
+
+        info!("received shutdown signal");
+        self.drain(timeout);
+    }
+
+    /// Counts a game as running until the returned guard is dropped.
+    pub fn start_game(&self) -> RunningGame {
+        self.update_running(|running| running + 1);
+
+        RunningGame {
+            state: self.clone(),
+        }
+    }
+
+    pub fn running_games(&self) -> usize {
+        *self.running_receiver.borrow()
+    }
+
+    fn update_running(&self, f: impl FnOnce(usize) -> usize) {
+        let sender = self.running.lock().unwrap();
+        let running = f(*self.running_receiver.borrow());
+        // This can't fail as `self` holds a receiver
+        let _ = sender.send(running);
+    }
+
+    /// Resolves once draining has started and every game has finished (or been cancelled).
+    pub async fn wait_until_drained(&self) {
+        self.wait_until_draining().await;
+
+        let mut receiver = self.running_receiver.clone();
+        while *receiver.borrow() > 0 {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for DrainState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct RunningGame {
+    state: DrainState,
+}
+
+impl Drop for RunningGame {
+    fn drop(&mut self) {
+        self.state.update_running(|running| running - 1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn the_earlier_deadline_is_kept() {
+        let drain = DrainState::new();
+        assert!(!drain.draining());
+        assert_eq!(drain.time_until_deadline(), None);
+
+        drain.drain(Duration::from_secs(60));
+        drain.drain(Duration::from_secs(600));
+        assert!(drain.draining());
+        assert!(drain.time_until_deadline().unwrap() <= Duration::from_secs(60));
+
+        drain.drain(Duration::from_secs(1));
+        assert!(drain.time_until_deadline().unwrap() <= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn huge_timeouts_dont_panic() {
+        let drain = DrainState::new();
+
+        drain.drain(Duration::MAX);
+        assert!(drain.draining());
+        assert!(drain.time_until_deadline().unwrap() <= MAX_DRAIN_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn drained_once_running_games_finish() {
+        let drain = DrainState::new();
+        let game = drain.start_game();
+        assert_eq!(drain.running_games(), 1);
+
+        drain.drain(Duration::from_secs(60));
+        assert!(
+            time::timeout(Duration::from_millis(20), drain.wait_until_drained())
+                .await
+                .is_err()
+        );
+
+        drop(game);
+        assert_eq!(drain.running_games(), 0);
+        time::timeout(Duration::from_secs(1), drain.wait_until_drained())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn deadline_passes_once_draining() {
+        let drain = DrainState::new();
+        assert!(
+            time::timeout(Duration::from_millis(20), drain.deadline_passed())
+                .await
+                .is_err()
+        );
+
+        drain.drain(Duration::ZERO);
+        time::timeout(Duration::from_secs(1), drain.deadline_passed())
+            .await
+            .unwrap();
+    }
+}
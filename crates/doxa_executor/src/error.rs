@@ -1,8 +1,7 @@
-use std::{convert::Infallible, io};
+use std::{convert::Infallible, io, time::Duration};
 
 use derive_more::{Display, Error, From};
-use doxa_core::lapin;
-use doxa_mq::action::BincodeError;
+use doxa_mq::{action::BincodeError, MQError};
 use doxa_storage::RetrievalError;
 use doxa_vm::{
     error::{
@@ -19,6 +18,43 @@ pub trait ForfeitError {
     fn forfeit_message(&self) -> Option<String>;
 }
 
+/// Who was responsible for a game failing, this decides whether the game is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// A problem with the system running the game (e.g. the VM failed to boot, the agent could
+    /// not be downloaded or an event could not be published). These games can be retried.
+    Infrastructure,
+    /// The agent misbehaved or is no longer available.
+    Agent,
+    /// The game client returned an error or used the context incorrectly.
+    GameClient,
+    /// The game was cancelled because its executor was drained (see [`crate::drain`]), these
+    /// games are always requeued straight away.
+    Drained,
+    /// The game ran for longer than its maximum duration after saving a checkpoint, these games
+    /// are requeued straight away (without using up a retry) so they resume from the checkpoint.
+    TimedOut,
+}
+
+impl FailureKind {
+    /// Whether the game is requeued straight away with the same attempt number.
+    pub fn keeps_attempt(self) -> bool {
+        matches!(self, FailureKind::Drained | FailureKind::TimedOut)
+    }
+}
+
+/// The execution node running a game stopped sending heartbeats (or restarted) so the game was
+/// failed by the server, see [`crate::retry::fail_lost_game`].
+/// This is always an infrastructure failure.
+#[derive(Error, Display, Debug)]
+#[display(
+    fmt = "the execution node `{}` running the game stopped sending heartbeats or restarted",
+    node
+)]
+pub struct NodeLost {
+    pub node: String,
+}
+
 #[derive(From, Error, Display, Debug)]
 pub struct Timeout {
     pub during: String,
@@ -51,6 +87,23 @@ pub enum AgentError {
     AgentGone,
 }
 
+impl AgentError {
+    pub fn failure_kind(&self) -> FailureKind {
+        match self {
+            AgentError::AgentNotFound | AgentError::AgentGone => FailureKind::Agent,
+            AgentError::IO(_)
+            | AgentError::Request(_)
+            | AgentError::BadStatusCode
+            | AgentError::SendAgentError(_)
+            | AgentError::VM(_)
+            | AgentError::CouldNotExtractFilename
+            | AgentError::CouldNotExtractFileSize
+            | AgentError::Timeout(_)
+            | AgentError::Socket(_) => FailureKind::Infrastructure,
+        }
+    }
+}
+
 #[derive(Error, Display, Debug)]
 #[display(fmt = "{}", source)]
 pub struct AgentErrorLogContext {
@@ -93,7 +146,7 @@ pub enum GameContextError {
     #[display(fmt = "failed to deserialize payload: {}", _0)]
     PayloadDeserialize(BincodeError),
     #[display(fmt = "failed to emit event: {}", _0)]
-    Emit(lapin::Error),
+    Emit(MQError),
     #[display(
         fmt = "ran out of time while waiting for next message from agent (assigned id={})",
         agent_id
@@ -124,12 +177,53 @@ pub enum GameContextError {
     TakeFile(TakeFileManagerError),
     #[display(fmt = "error creating/cleaning up the tempdir for the workspace")]
     WorkDir(TempDirError),
+    #[display(fmt = "failed to compress an agent's transcript")]
+    #[from(ignore)]
+    CompressTranscript(io::Error),
+    #[display(fmt = "failed to serialize checkpoint state: {}", _0)]
+    #[from(ignore)]
+    SerializeCheckpoint(serde_json::Error),
+    #[display(fmt = "failed to deserialize checkpoint state: {}", _0)]
+    #[from(ignore)]
+    InvalidCheckpoint(serde_json::Error),
 }
 
 impl GameContextError {
     pub fn is_message_receive_timeout(&self) -> bool {
         matches!(&self, GameContextError::TimeoutWaitingForMessage { .. })
     }
+
+    pub fn failure_kind(&self) -> FailureKind {
+        if self.forfeit().is_some() {
+            return FailureKind::Agent;
+        }
+
+        match &self {
+            GameContextError::NextEvent(_)
+            | GameContextError::SendInput(_)
+            | GameContextError::Emit(_)
+            | GameContextError::RebootError(_)
+            | GameContextError::TakeFile(_)
+            | GameContextError::WorkDir(_)
+            | GameContextError::CompressTranscript(_) => FailureKind::Infrastructure,
+            GameContextError::UnknownAgent { .. }
+            | GameContextError::PayloadDeserialize(_)
+            | GameContextError::IncorrectNumberAgents { .. }
+            | GameContextError::ZeroLengthEventType
+            | GameContextError::ReservedEventType
+            | GameContextError::SerializeCheckpoint(_)
+            | GameContextError::InvalidCheckpoint(_) => FailureKind::GameClient,
+            // These are forfeits which are handled above
+            GameContextError::TimeoutWaitingForMessage { .. }
+            | GameContextError::AgentTerminated(_) => FailureKind::Agent,
+        }
+    }
+}
+
+#[derive(Display, Error, From, Debug)]
+pub enum TranscriptDecodeError {
+    Base64(base64::DecodeError),
+    Decompress(io::Error),
 }
 
 #[derive(Display, Error, From, Debug)]
@@ -157,6 +251,9 @@ impl ForfeitError for GameContextError {
             GameContextError::RebootError(_) => None,
             GameContextError::TakeFile(_) => None,
             GameContextError::WorkDir(_) => None,
+            GameContextError::CompressTranscript(_) => None,
+            GameContextError::SerializeCheckpoint(_) => None,
+            GameContextError::InvalidCheckpoint(_) => None,
         }
     }
 
@@ -179,6 +276,9 @@ impl ForfeitError for GameContextError {
             GameContextError::RebootError(_) => None,
             GameContextError::TakeFile(_) => None,
             GameContextError::WorkDir(_) => None,
+            GameContextError::CompressTranscript(_) => None,
+            GameContextError::SerializeCheckpoint(_) => None,
+            GameContextError::InvalidCheckpoint(_) => None,
         }
     }
 }
@@ -188,6 +288,18 @@ pub enum GameError<E> {
     Context(GameContextError),
     #[from(ignore)]
     Client(E),
+    /// The executor was drained before the game finished so it was cancelled and requeued
+    #[display(fmt = "the executor was drained before the game finished")]
+    Drained,
+    /// The game ran for longer than its maximum duration (see
+    /// [`crate::profile::ExecutionProfile::max_game_duration`]) so it was cancelled and requeued.
+    /// `checkpointed` is whether the game saved a checkpoint before it was cancelled.
+    #[display(fmt = "the game ran for longer than {:?}", duration)]
+    #[from(ignore)]
+    TimedOut {
+        duration: Duration,
+        checkpointed: bool,
+    },
 }
 
 impl<E: ForfeitError> ForfeitError for GameError<E> {
@@ -195,6 +307,7 @@ impl<E: ForfeitError> ForfeitError for GameError<E> {
         match &self {
             GameError::Context(e) => e.forfeit(),
             GameError::Client(e) => e.forfeit(),
+            GameError::Drained | GameError::TimedOut { .. } => None,
         }
     }
 
@@ -202,6 +315,28 @@ impl<E: ForfeitError> ForfeitError for GameError<E> {
         match &self {
             GameError::Context(e) => e.forfeit_message(),
             GameError::Client(e) => e.forfeit_message(),
+            GameError::Drained | GameError::TimedOut { .. } => None,
+        }
+    }
+}
+
+impl<E: ForfeitError> GameError<E> {
+    pub fn failure_kind(&self) -> FailureKind {
+        match &self {
+            GameError::Context(e) => e.failure_kind(),
+            GameError::Client(e) if e.forfeit().is_some() => FailureKind::Agent,
+            GameError::Client(_) => FailureKind::GameClient,
+            GameError::Drained => FailureKind::Drained,
+            // The game made progress so it isn't counted as a failure, otherwise it is treated
+            // like an infrastructure failure so that it is dead lettered once it runs out of
+            // retries rather than requeued forever
+            GameError::TimedOut { checkpointed, .. } => {
+                if *checkpointed {
+                    FailureKind::TimedOut
+                } else {
+                    FailureKind::Infrastructure
+                }
+            }
         }
     }
 }
@@ -249,7 +384,17 @@ pub enum NextMessageError {
 #[derive(Display, Error, From, Debug)]
 pub enum GameManagerError<E> {
     StartAgent(AgentErrorLogContext),
-    EmitStartEvent(lapin::Error),
+    EmitStartEvent(MQError),
     #[from]
     Runtime(GameError<E>),
 }
+
+impl<E: ForfeitError> GameManagerError<E> {
+    pub fn failure_kind(&self) -> FailureKind {
+        match &self {
+            GameManagerError::StartAgent(e) => e.source.failure_kind(),
+            GameManagerError::EmitStartEvent(_) => FailureKind::Infrastructure,
+            GameManagerError::Runtime(e) => e.failure_kind(),
+        }
+    }
+}
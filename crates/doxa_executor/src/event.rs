@@ -3,10 +3,51 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize)]
 pub struct StartEvent {
     pub agents: Vec<String>,
+    /// The seed used for the game's RNG, this is `None` for games that were played before seeds
+    /// were recorded.
+    #[serde(default, with = "crate::seed::option")]
+    pub seed: Option<u64>,
+    /// The name of the executor running the game (see [`crate::Settings::node_name`]), this is
+    /// `None` for games that were played before it was recorded.
+    #[serde(default)]
+    pub node: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct CancelledEvent {}
+pub struct CancelledEvent {
+    /// Whether the game was cancelled because its executor was drained, these games are followed
+    /// by `_RETRY` rather than `_END` as they are requeued.
+    #[serde(default)]
+    pub requeued: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+/// The game failed because of an infrastructure error (or was cancelled because its executor was
+/// drained) and will be requeued, this is sent instead of `_END` after the `_ERROR` or
+/// `_CANCELLED` event.
+pub struct RetryEvent {
+    /// The attempt number of the retry (the first attempt is 0), drains don't count as attempts
+    pub attempt: u32,
+    /// How long until the match request will be requeued
+    pub backoff_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+/// State saved by the game client so that the game can be resumed from this point if it is
+/// interrupted.
+pub struct CheckpointEvent {
+    #[serde(with = "crate::seed")]
+    pub seed: u64,
+    pub state: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize)]
+/// The game was interrupted and has been resumed from an earlier checkpoint, this is sent after
+/// `_START`.
+pub struct ResumeEvent {
+    /// The event ID of the `_CHECKPOINT` event that the game was resumed from
+    pub checkpoint_event_id: u32,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct ErrorEvent {
@@ -29,3 +70,37 @@ pub struct ForfeitEvent {
     pub error_message: Option<String>,
     // TODO: maybe an enum of reasons?
 }
+
+#[derive(Serialize, Deserialize)]
+/// A chunk of an agent's stderr, an agent may have several of these events in a single game which
+/// should be concatenated in order.
+///
+/// These are only viewable by the owner of the agent and admins.
+pub struct AgentLogsEvent {
+    pub agent_id: usize,
+    pub stderr: String,
+    /// Whether some of the agent's stderr was discarded because it exceeded the size limit
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+/// The transcript of everything sent to and received from an agent, this is only emitted if the
+/// game client enabled transcripts for the match.
+///
+/// These are only viewable by the owner of the agent and admins.
+pub struct TranscriptEvent {
+    pub agent_id: usize,
+    /// Part of the compressed transcript, see [`crate::transcript::Transcript::compress`] and
+    /// [`crate::transcript::join_chunks`]
+    pub transcript: String,
+    /// The index of this part of the transcript
+    #[serde(default)]
+    pub chunk: u32,
+    /// The number of parts the transcript was split into
+    #[serde(default = "default_transcript_chunks")]
+    pub chunks: u32,
+}
+
+fn default_transcript_chunks() -> u32 {
+    1
+}
@@ -1,9 +1,9 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use doxa_core::tracing::info;
 use doxa_core::tracing::{debug, error};
-use doxa_core::{lapin::Channel, tracing::info};
-use doxa_mq::model::MatchRequest;
+use doxa_mq::{model::MatchRequest, MQ};
 use doxa_vm::backend::VMBackend;
 use futures::{
     future::{join_all, try_join_all},
@@ -13,38 +13,110 @@ use tokio::time::sleep;
 
 use crate::{
     agent::{VMAgent, VMAgentSettings},
+    cancel::CancellationHandle,
+    checkpoint::{Checkpoint, ResumePoint},
     client::{ForfeitError, GameClient, GameError},
     context::{GameContext, GameEventContext},
-    error::{AgentTerminated, GameContextError, GameManagerError},
+    drain::DrainState,
+    error::{AgentTerminated, FailureKind, GameContextError, GameManagerError},
+    profile::ExecutionProfile,
+    retry::RetryPolicy,
     Settings,
 };
 
 use doxa_core::tokio;
 
+/// How often the cancel endpoint is polled, this is only a fallback for when the cancellation
+/// event is missed.
+pub const CANCEL_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The default for [`Settings::max_game_duration`] on executors that only acknowledge match
+/// requests once their game has finished, this leaves some time before RabbitMQ's default
+/// `consumer_timeout` of 30 minutes for the game's final events to be emitted.
+pub const DEFAULT_MAX_GAME_DURATION: Duration = Duration::from_secs(25 * 60);
+
 pub struct GameManager<C: GameClient, B: VMBackend> {
     client: Arc<C>,
     agents: Vec<VMAgent<B>>,
     client_match_request: C::MatchRequest,
     game_event_context: GameEventContext<C>,
+    seed: u64,
+    attempt: u32,
+    retry_policy: RetryPolicy,
+    checkpoint: Option<Checkpoint>,
 }
 
 impl<C: GameClient, B: VMBackend> GameManager<C, B> {
+    /// `resume` is where this run of the game starts from, see
+    /// [`crate::checkpoint::fetch_resume_point`].
+    ///
+    /// `execution_profile` is the profile of the queue the match request was taken from, it
+    /// determines the resources given to each agent.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         settings: Arc<Settings>,
         backend_settings: B::BackendSettings,
-        event_channel: Channel,
-        event_queue_name: String,
+        mq: MQ,
         competition_name: &'static str,
+        execution_profile: &ExecutionProfile,
         match_request: MatchRequest<C::MatchRequest>,
         game_client: Arc<C>,
+        resume: ResumePoint,
     ) -> Result<Self, GameManagerError<C::Error>> {
-        let mut game_event_context =
-            GameEventContext::new(event_channel, event_queue_name, match_request.game_id);
+        let attempt = match_request.attempt;
+        let checkpoint = resume.checkpoint;
+        let mut game_event_context = GameEventContext::new(
+            mq,
+            competition_name,
+            match_request.game_id,
+            resume.next_event_id,
+        );
+
+        let seed = match &checkpoint {
+            Some(checkpoint) => checkpoint.seed,
+            None => match_request.seed.unwrap_or_else(rand::random),
+        };
+
+        let started = async {
+            game_event_context
+                .emit_start_event(
+                    match_request.agents.clone(),
+                    seed,
+                    settings.node_name.clone(),
+                )
+                .await?;
+
+            if let Some(checkpoint) = &checkpoint {
+                game_event_context
+                    .emit_resume_event(checkpoint.event_id)
+                    .await?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = started {
+            // The events are likely to fail as well but the game is requeued so the retry should
+            // be recorded if possible
+            if let Err(e) = game_event_context
+                .emit_error_event(&e, vec![None; match_request.agents.len()])
+                .await
+            {
+                error!(error=%e, debug=?e, "failed to emit error event after failing to emit the start event");
+            }
+
+            let retry = settings
+                .retry_policy
+                .retry_backoff(FailureKind::Infrastructure, attempt)
+                .map(|backoff| (attempt + 1, backoff));
+
+            if let Err(e) = game_event_context.emit_final_event(retry).await {
+                error!(error=%e, debug=?e, "failed to emit end/retry event");
+            }
 
-        game_event_context
-            .emit_start_event(match_request.agents.clone())
-            .await
-            .map_err(GameManagerError::EmitStartEvent)?;
+            return Err(GameManagerError::EmitStartEvent(e));
+        }
 
         let additional_mounts = game_client.additional_mounts(&match_request.payload);
 
@@ -52,10 +124,11 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
         mounts.extend(additional_mounts);
 
         let vm_agent_settings = VMAgentSettings {
-            agent_ram_mb: C::AGENT_RAM_MB,
-            scratch_size_mb: C::AGENT_SCRATCH_MB,
-            swap_size_mb: C::AGENT_SWAP_MB,
+            agent_ram_mb: execution_profile.agent_ram_mb::<C>(),
+            scratch_size_mb: execution_profile.agent_scratch_mb::<C>(),
+            swap_size_mb: execution_profile.agent_swap_mb::<C>(),
             mounts,
+            record_transcript: game_client.record_transcript(&match_request.payload),
         };
 
         let agents = match_request
@@ -95,8 +168,13 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
                     error!(error=%e, debug=?e, "failed to emit error event containing VM logs while processing a startup error");
                 }
 
-                if let Err(e) = game_event_context.emit_end_event().await {
-                    error!(error=%e, debug=?e, "failed to emit end event");
+                let retry = settings
+                    .retry_policy
+                    .retry_backoff(e.source.failure_kind(), attempt)
+                    .map(|backoff| (attempt + 1, backoff));
+
+                if let Err(e) = game_event_context.emit_final_event(retry).await {
+                    error!(error=%e, debug=?e, "failed to emit end/retry event");
                 }
 
                 return Err(GameManagerError::StartAgent(e));
@@ -108,19 +186,48 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
             client: game_client,
             game_event_context,
             client_match_request: match_request.payload,
+            seed,
+            attempt,
+            retry_policy: settings.retry_policy.clone(),
+            checkpoint,
         })
     }
 
     /// Runs the game to completion
+    #[allow(clippy::too_many_arguments)]
     async fn run(
         game_client: &C,
         mut agents: Vec<VMAgent<B>>,
         client_match_request: C::MatchRequest,
         game_event_context: &'_ mut GameEventContext<C>,
+        seed: u64,
+        attempt: u32,
+        retry_policy: &RetryPolicy,
+        checkpoint: Option<Checkpoint>,
     ) -> Result<(), GameError<C::Error>> {
-        let mut context = GameContext::new(&mut agents, game_event_context);
+        let mut context = GameContext::new(&mut agents, game_event_context, seed);
+
+        let res = match checkpoint {
+            Some(checkpoint) => {
+                info!(checkpoint_event_id=%checkpoint.event_id, "resuming game from checkpoint");
+                game_client
+                    .resume(client_match_request, checkpoint, &mut context)
+                    .await
+            }
+            None => game_client.run(client_match_request, &mut context).await,
+        };
 
-        let res = match game_client.run(client_match_request, &mut context).await {
+        // This happens before any forfeit so that the agent's logs are available by the time the
+        // forfeit is handled
+        if let Err(e) = context.flush_all_agent_logs().await {
+            error!(error=%e, debug=?e, "failed to emit remaining agent logs");
+        }
+
+        if let Err(e) = context.emit_transcripts().await {
+            error!(error=%e, debug=?e, "failed to emit agent transcripts");
+        }
+
+        let res = match res {
             Ok(()) => Ok(()),
             Err(mut error) => {
                 if let Some(agent_id) = error.forfeit() {
@@ -164,8 +271,14 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
             }
         };
 
-        if let Err(e) = context.game_event_context.emit_end_event().await {
-            error!(error=%e, debug=?e, "failed to emit end event");
+        let retry = res.as_ref().err().and_then(|error| {
+            retry_policy
+                .retry_backoff(error.failure_kind(), attempt)
+                .map(|backoff| (attempt + 1, backoff))
+        });
+
+        if let Err(e) = context.game_event_context.emit_final_event(retry).await {
+            error!(error=%e, debug=?e, "failed to emit end/retry event");
         }
 
         if let Err(e) = context.cleanup().await {
@@ -175,17 +288,27 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
         res
     }
 
-    /// Runs the game but polls regularily (every 30 seconds) to see if the game has been cancelled.
-    /// The `cancel_endpoint` is a string of a URL to poll that should output `{ "cancelled": bool
-    /// }`. If there is an error accessing the endpoint, it will be logged but otherwise it will
+    /// Runs the game until it completes or is cancelled.
+    /// Cancellations are normally pushed to the executor through the `cancellation` handle (see
+    /// [`crate::cancel::CancellationRegistry`]), but as a fallback the `cancel_endpoint` is also
+    /// polled (every [`CANCEL_POLL_INTERVAL`]) and should output `{ "cancelled": bool }`.
+    /// If there is an error accessing the endpoint, it will be logged but otherwise it will
     /// be treated as if it returned `{ "cancelled": false }` and the game (and polling) will
     /// continue.
+    ///
+    /// If the game is still running once the `drain` deadline passes it is cancelled and
+    /// [`GameError::Drained`] is returned, the caller should requeue the match request.
+    /// Games that run for longer than `max_game_duration` are cancelled in the same way and
+    /// [`GameError::TimedOut`] is returned so that they resume from their last checkpoint.
     pub async fn run_with_cancel_check(
         self,
         cancel_endpoint: String,
         client: reqwest::Client,
+        cancellation: CancellationHandle,
+        drain: DrainState,
+        max_game_duration: Option<Duration>,
     ) -> Result<(), GameError<C::Error>> {
-        let cancel_check = async move {
+        let cancel_poll = async move {
             #[derive(serde::Deserialize)]
             struct CancelResponse {
                 cancelled: bool,
@@ -197,7 +320,7 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
                 // Skip the first cancel check to see if the game was cancelled while it was in the
                 // queue.
                 if !first {
-                    sleep(Duration::from_secs(30)).await;
+                    sleep(CANCEL_POLL_INTERVAL).await;
                 }
                 first = false;
 
@@ -226,14 +349,36 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
             }
         };
 
+        let cancel_check = async move {
+            tokio::select! {
+                _ = cancel_poll => {},
+                _ = cancellation.cancelled() => {},
+            }
+        };
+
+        let timed_out = async move {
+            match max_game_duration {
+                Some(max_game_duration) => sleep(max_game_duration).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        let drain_retry = self
+            .retry_policy
+            .retry_backoff(FailureKind::Drained, self.attempt)
+            .map(|backoff| (self.attempt, backoff));
+
+        let attempt = self.attempt;
+        let retry_policy = self.retry_policy.clone();
+        let agent_count = self.agents.len();
         let mut game_event_context = self.game_event_context;
         tokio::select! {
-            res = Self::run(&self.client, self.agents, self.client_match_request, &mut game_event_context) => {
+            res = Self::run(&self.client, self.agents, self.client_match_request, &mut game_event_context, self.seed, self.attempt, &self.retry_policy, self.checkpoint) => {
                 res
             },
             _ = cancel_check => {
                 info!("game cancelled");
-                if let Err(e) = game_event_context.emit_cancelled_event().await {
+                if let Err(e) = game_event_context.emit_cancelled_event(false).await {
                     error!(error=%e, debug=?e, "failed to emit cancelled event");
                 }
 
@@ -243,7 +388,39 @@ impl<C: GameClient, B: VMBackend> GameManager<C, B> {
 
                 Ok(())
             }
+            _ = drain.deadline_passed() => {
+                info!("game cancelled as the executor was drained");
+                if let Err(e) = game_event_context.emit_cancelled_event(true).await {
+                    error!(error=%e, debug=?e, "failed to emit cancelled event");
+                }
+
+                if let Err(e) = game_event_context.emit_final_event(drain_retry).await {
+                    error!(error=%e, debug=?e, "failed to emit end/retry event");
+                }
 
+                Err(GameError::Drained)
+            }
+            _ = timed_out => {
+                let error = GameError::TimedOut {
+                    duration: max_game_duration.unwrap_or_default(),
+                    checkpointed: game_event_context.checkpointed(),
+                };
+                info!("game cancelled as it ran for too long");
+                if let Err(e) = game_event_context.emit_error_event(&error, vec![None; agent_count]).await {
+                    error!(error=%e, debug=?e, "failed to emit error event");
+                }
+
+                let kind = error.failure_kind();
+                let next_attempt = if kind.keeps_attempt() { attempt } else { attempt + 1 };
+                let retry = retry_policy
+                    .retry_backoff(kind, attempt)
+                    .map(|backoff| (next_attempt, backoff));
+                if let Err(e) = game_event_context.emit_final_event(retry).await {
+                    error!(error=%e, debug=?e, "failed to emit end/retry event");
+                }
+
+                Err(error)
+            }
         }
     }
 }
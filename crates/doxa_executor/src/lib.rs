@@ -1,10 +1,19 @@
 pub mod agent;
+pub mod cancel;
+pub mod checkpoint;
 pub mod client;
 pub mod context;
+pub mod drain;
 pub mod error;
 pub mod event;
 pub mod game;
+pub mod listener;
+pub mod pause;
+pub mod profile;
+pub mod retry;
+pub mod seed;
 pub mod settings;
+pub mod transcript;
 
 pub use settings::Settings;
 
@@ -0,0 +1,363 @@
+//! Taking match requests from the queues of an execution profile and running their games, this is
+//! shared by the execution managers of the server and by execution nodes.
+
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+};
+
+use doxa_core::{
+    tokio::{
+        self,
+        sync::{OwnedSemaphorePermit, Semaphore},
+    },
+    tracing::{event, info, span, Level},
+    tracing_futures::Instrument,
+};
+use doxa_mq::{model::MatchRequest, Delivery, MQ};
+use doxa_vm::backend::VMBackend;
+use futures::{FutureExt, StreamExt};
+
+use crate::{
+    cancel::CancellationRegistry,
+    checkpoint::fetch_resume_point,
+    client::{ForfeitError, GameClient},
+    drain::DrainState,
+    error::{AgentError, FailureKind, GameManagerError},
+    game::GameManager,
+    pause::PauseState,
+    profile::ExecutionProfile,
+    retry::requeue_or_dead_letter,
+    Settings,
+};
+
+/// Keeps track of the games that are running so that they can be reported (e.g. in the heartbeats
+/// of an execution node).
+#[derive(Clone, Default)]
+pub struct RunningGames {
+    games: Arc<Mutex<HashSet<i32>>>,
+}
+
+impl RunningGames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a game, the game is removed once the returned guard is dropped.
+    pub fn insert(&self, game_id: i32) -> RunningGame {
+        self.games.lock().unwrap().insert(game_id);
+
+        RunningGame {
+            game_id,
+            games: self.clone(),
+        }
+    }
+
+    pub fn games(&self) -> Vec<i32> {
+        let mut games: Vec<_> = self.games.lock().unwrap().iter().copied().collect();
+        games.sort_unstable();
+        games
+    }
+}
+
+pub struct RunningGame {
+    game_id: i32,
+    games: RunningGames,
+}
+
+impl Drop for RunningGame {
+    fn drop(&mut self) {
+        self.games.games.lock().unwrap().remove(&self.game_id);
+    }
+}
+
+/// Runs the match requests of a single competition and execution profile.
+pub struct MatchRequestListener<C: GameClient, B: VMBackend> {
+    pub competition_name: &'static str,
+    /// One of the profiles declared by the game client
+    pub execution_profile: ExecutionProfile,
+    pub game_client: Arc<C>,
+    /// The URL of the competition's routes without a trailing slash, e.g.
+    /// `https://doxa.example.com/api/competition/uttt`.
+    pub competition_url: String,
+    pub mq: MQ,
+    pub request_client: reqwest::Client,
+    pub executor_settings: Arc<Settings>,
+    pub backend_settings: B::BackendSettings,
+    /// A game only starts once it has a permit from each of these, which are taken in order.
+    pub limiters: Vec<Arc<Semaphore>>,
+    /// The most games this listener can run at once, no more match requests than this are taken
+    /// from each lane before they have finished.
+    pub max_running: usize,
+    pub cancellation_registry: CancellationRegistry,
+    pub pause_state: PauseState,
+    /// Once draining the listener stops taking match requests and [`MatchRequestListener::listen`]
+    /// returns
+    pub drain: DrainState,
+    pub running_games: RunningGames,
+    /// Acknowledge match requests once their game is about to start rather than once it has
+    /// finished. Execution nodes do this as the server fails and requeues the games of nodes that
+    /// stop sending heartbeats, so the broker mustn't also redeliver them.
+    pub acknowledge_on_start: bool,
+}
+
+/// Takes a permit from each limiter in order.
+async fn acquire_permits(limiters: &[Arc<Semaphore>]) -> Vec<OwnedSemaphorePermit> {
+    let mut permits = Vec::with_capacity(limiters.len());
+    for limiter in limiters {
+        permits.push(limiter.clone().acquire_owned().await.unwrap());
+    }
+
+    permits
+}
+
+impl<C: GameClient, B: VMBackend + 'static> MatchRequestListener<C, B>
+where
+    B::BackendSettings: 'static,
+{
+    fn game_endpoint(&self, game_id: i32, endpoint: &str) -> String {
+        format!("{}/_game/{}/{}", self.competition_url, game_id, endpoint)
+    }
+
+    /// Takes match requests and spawns their games until the listener is drained, this doesn't
+    /// wait for the games that are still running.
+    pub async fn listen(self) {
+        let competition_name = self.competition_name;
+        let mut consumer = doxa_mq::action::get_match_request_consumer(
+            self.mq.clone(),
+            competition_name,
+            &self.execution_profile.name,
+            u16::try_from(self.max_running).unwrap_or(u16::MAX),
+        );
+
+        info!(
+            competition =%competition_name,
+            execution_profile = %self.execution_profile.name,
+            "execution event listener",
+        );
+
+        loop {
+            let next = async {
+                self.pause_state.wait_until_resumed().await;
+                consumer.next().await
+            };
+            let (priority, delivery) = tokio::select! {
+                biased;
+                _ = self.drain.wait_until_draining() => break,
+                next = next => match next {
+                    Some(next) => next,
+                    None => break,
+                },
+            };
+            // The competition may have been paused while waiting for the match request, in which
+            // case it is put back for when the competition is resumed
+            if self.pause_state.paused() {
+                if let Err(error) = delivery.nack(true).await {
+                    event!(Level::ERROR, %error, debug = ?error, "failed to requeue match request while paused");
+                }
+                continue;
+            }
+            // Limiters can be shared with other listeners so permits are only taken once there is
+            // a match request to run, otherwise a listener without match requests would hold on
+            // to a permit that the others could use
+            let permits = tokio::select! {
+                biased;
+                _ = self.drain.wait_until_draining() => {
+                    if let Err(error) = delivery.nack(true).await {
+                        event!(Level::ERROR, %error, debug = ?error, "failed to requeue match request while draining");
+                    }
+                    break;
+                }
+                permits = acquire_permits(&self.limiters) => permits,
+            };
+
+            self.spawn_game(priority, delivery, permits).await;
+        }
+
+        if self.drain.draining() {
+            info!(
+                competition = %competition_name,
+                execution_profile = %self.execution_profile.name,
+                "stopped taking match requests as the executor is draining",
+            );
+        }
+    }
+
+    async fn spawn_game(
+        &self,
+        priority: doxa_mq::model::Priority,
+        delivery: Delivery,
+        permits: Vec<OwnedSemaphorePermit>,
+    ) {
+        let competition_name = self.competition_name;
+        let queue = doxa_mq::action::match_request_queue_name(
+            competition_name,
+            &self.execution_profile.name,
+            priority,
+        );
+        let match_request: MatchRequest<C::MatchRequest> =
+            match doxa_mq::envelope::decode_match_request(
+                &delivery.data,
+                C::MATCH_REQUEST_VERSION,
+                C::upgrade_match_request,
+            ) {
+                Ok(match_request) => match_request,
+                Err(error) => {
+                    event!(Level::ERROR, %error, debug = ?error, "failed to decode match request");
+                    if let Err(error) = doxa_mq::action::dead_letter(
+                        self.mq.as_ref(),
+                        &queue,
+                        &delivery,
+                        error.to_string(),
+                    )
+                    .await
+                    {
+                        event!(Level::ERROR, %error, debug = ?error, "failed to dead letter match request");
+                        // Put it back rather than leaving it unacknowledged until the channel
+                        // closes
+                        if let Err(error) = delivery.nack(true).await {
+                            event!(Level::ERROR, %error, debug = ?error, "failed to requeue match request");
+                        }
+                    }
+                    return;
+                }
+            };
+        if self.acknowledge_on_start {
+            if let Err(error) = delivery.ack().await {
+                // The broker will deliver the match request again
+                event!(Level::ERROR, %error, debug = ?error, "failed to acknowledge match request");
+                return;
+            }
+        }
+        let acknowledge_on_start = self.acknowledge_on_start;
+        let game_id = match_request.game_id;
+        let attempt = match_request.attempt;
+
+        let span = span!(
+            Level::INFO,
+            "handle match request",
+            game_id = %game_id,
+            priority = ?priority,
+            agents = ?match_request.agents,
+            execution_profile = %self.execution_profile.name,
+            competition_name = %competition_name,
+        );
+
+        let cancel_endpoint = self.game_endpoint(game_id, "cancelled");
+        let checkpoint_endpoint = self.game_endpoint(game_id, "checkpoint");
+        let cancellation = self.cancellation_registry.register(game_id);
+        let running_game = self.running_games.insert(game_id);
+        let drain = self.drain.clone();
+        let drain_guard = drain.start_game();
+        let request_client = self.request_client.clone();
+        let executor_settings = self.executor_settings.clone();
+        let retry_policy = executor_settings.retry_policy.clone();
+        let mq = self.mq.clone();
+        let game_client = self.game_client.clone();
+        let backend_settings = self.backend_settings.clone();
+        let execution_profile = self.execution_profile.clone();
+        let max_game_duration = execution_profile
+            .max_game_duration()
+            .or(if acknowledge_on_start {
+                None
+            } else {
+                executor_settings.max_game_duration
+            });
+
+        tokio::spawn(
+            async move {
+                // Unless `acknowledge_on_start` is set the delivery is only acknowledged once the
+                // game has finished (or been requeued) so that if the executor crashes the match
+                // request is redelivered and the game resumes from its last checkpoint.
+                // In that case games are limited to `max_game_duration` which must be shorter than
+                // RabbitMQ's `consumer_timeout`, otherwise the broker closes the channel.
+                let data = delivery.data.clone();
+                async {
+                    // Every run starts from the server's resume point, the game may already have
+                    // been (partially) run if the match request was redelivered, retried or
+                    // requeued after a drain
+                    let resume = match fetch_resume_point(
+                        &request_client,
+                        executor_settings.agent_retrieval.system_account_secret(),
+                        &checkpoint_endpoint,
+                    )
+                    .await
+                    {
+                        Ok(resume) => resume,
+                        Err(error) => {
+                            // Without knowing which events were already emitted the game can't be
+                            // run safely so try again later
+                            event!(Level::ERROR, %error, debug = ?error, "failed to fetch resume point");
+                            requeue_or_dead_letter(&retry_policy, mq, data, queue.clone(), FailureKind::Infrastructure, attempt, error.to_string()).await;
+                            return;
+                        }
+                    };
+
+                    let game_manager = match GameManager::<C, B>::new(
+                        executor_settings,
+                        backend_settings,
+                        mq.clone(),
+                        competition_name,
+                        &execution_profile,
+                        match_request,
+                        game_client,
+                        resume,
+                    )
+                    .await
+                    {
+                        Ok(game_manger) => game_manger,
+                        Err(error) => {
+                            // Between now and when the agent was first queued it is no
+                            // longer the correct active agent (e.g. because of error or
+                            // because a new one was uploads or because it was deleted).
+                            // This is not a problem, it's good that we don't run the game
+                            // in the case.
+                            if let GameManagerError::StartAgent(agent_error) = &error {
+                                if matches!(&agent_error.source, AgentError::AgentGone) {
+                                    event!(Level::DEBUG, "not starting game because agent was gone");
+                                    return;
+                                }
+                            }
+                            event!(Level::ERROR, %error, debug = ?error, "failed to start game manager");
+
+                            requeue_or_dead_letter(&retry_policy, mq, data, queue.clone(), error.failure_kind(), attempt, error.to_string()).await;
+                            return;
+                        }
+                    };
+
+                    info!("started game manager");
+
+                    match game_manager
+                        .run_with_cancel_check(cancel_endpoint, request_client, cancellation, drain, max_game_duration)
+                        .await
+                    {
+                        Ok(()) => event!(Level::INFO, "game manager successfully completed"),
+                        Err(error) => {
+                            if error.forfeit().is_some() {
+                                event!(Level::INFO, forfeit=true, %error, debug = ?error, "error running game manager")
+                            } else {
+                                event!(Level::ERROR, forfeit=false, %error, debug = ?error, "error running game manager")
+                            }
+
+                            requeue_or_dead_letter(&retry_policy, mq, data, queue.clone(), error.failure_kind(), attempt, error.to_string()).await;
+                        }
+                    }
+                }
+                .await;
+
+                if !acknowledge_on_start {
+                    if let Err(error) = delivery.ack().await {
+                        event!(Level::ERROR, %error, debug = ?error, "failed to acknowledge match request");
+                    }
+                }
+            }
+            .then(|_| async move {
+                drop(permits);
+                drop(running_game);
+                drop(drain_guard);
+            })
+            .instrument(span),
+        );
+    }
+}
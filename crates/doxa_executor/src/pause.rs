@@ -0,0 +1,141 @@
+use std::{sync::Arc, time::Duration};
+
+use doxa_core::{
+    tokio::{self, sync::watch},
+    tracing::{error, info},
+};
+use doxa_mq::{model::PauseEvent, MQError, QueueBackend};
+use futures::StreamExt;
+use serde::Deserialize;
+
+/// How long to wait before subscribing again after losing the connection to the MQ.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct PausedResponse {
+    paused: bool,
+}
+
+/// Whether an admin has paused the competition, executors should not take any more match
+/// requests while it is paused.
+///
+/// This starts off unpaused until the current state has been fetched.
+#[derive(Clone)]
+pub struct PauseState {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl PauseState {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+
+        PauseState {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        if self.paused() != paused {
+            info!(%paused, "competition pause state changed");
+        }
+
+        // This can't fail as `self` holds a receiver
+        let _ = self.sender.send(paused);
+    }
+
+    /// Resolves immediately if the competition isn't paused otherwise once it is resumed.
+    pub async fn wait_until_resumed(&self) {
+        let mut receiver = self.receiver.clone();
+
+        loop {
+            let paused = *receiver.borrow();
+            if !paused || receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Consumes the competition's pause events, after subscribing the current state is fetched
+    /// from `paused_endpoint` (the `_paused` route of the competition).
+    /// If the connection to the MQ is lost this subscribes again so it never returns.
+    pub async fn listen(
+        &self,
+        mq: &dyn QueueBackend,
+        competition_name: &str,
+        client: &reqwest::Client,
+        paused_endpoint: &str,
+    ) {
+        loop {
+            if let Err(error) = self
+                .listen_until_disconnected(mq, competition_name, client, paused_endpoint)
+                .await
+            {
+                error!(%error, debug = ?error, "lost connection while listening for pause events");
+            }
+
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    async fn listen_until_disconnected(
+        &self,
+        mq: &dyn QueueBackend,
+        competition_name: &str,
+        client: &reqwest::Client,
+        paused_endpoint: &str,
+    ) -> Result<(), MQError> {
+        let mut consumer = doxa_mq::action::get_pause_consumer(mq, competition_name).await?;
+
+        info!(competition = %competition_name, "listening for pause events");
+
+        // Anything that changed before subscribing would otherwise be missed
+        match fetch_paused(client, paused_endpoint).await {
+            Ok(paused) => self.set_paused(paused),
+            Err(error) => {
+                error!(%error, debug = ?error, "failed to fetch whether the competition is paused")
+            }
+        }
+
+        while let Some(message) = consumer.next().await {
+            let delivery = message?;
+            let event: PauseEvent = match doxa_mq::action::decode(&delivery.data) {
+                Ok(event) => event,
+                Err(error) => {
+                    error!(%error, debug = ?error, "failed to decode pause event");
+                    continue;
+                }
+            };
+
+            self.set_paused(event.paused);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PauseState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn fetch_paused(
+    client: &reqwest::Client,
+    paused_endpoint: &str,
+) -> Result<bool, reqwest::Error> {
+    let response: PausedResponse = client
+        .get(paused_endpoint)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(response.paused)
+}
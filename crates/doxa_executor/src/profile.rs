@@ -0,0 +1,103 @@
+//! Execution profiles describe the kind of machine an agent needs, agents choose one with the
+//! `execution_environment` key of their `doxa.yaml` and their match requests are queued for that
+//! profile.
+//! Each competition declares the profiles it supports with [`GameClient::execution_profiles`],
+//! managers and execution nodes then choose which of those profiles they take match requests for.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::client::GameClient;
+
+/// The profile used by agents that don't declare an `execution_environment` (when the competition
+/// declares it).
+pub const BASIC_EXECUTION_PROFILE: &str = "basic";
+
+/// The profile for agents that require a GPU, these match requests are typically run by execution
+/// nodes rather than the server.
+pub const GPU_EXECUTION_PROFILE: &str = "gpu";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExecutionProfile {
+    /// The name used in `doxa.yaml` and in the name of the match request queues, e.g. `basic`.
+    pub name: String,
+    /// Overrides [`GameClient::AGENT_RAM_MB`] for games run with this profile.
+    pub agent_ram_mb: Option<u64>,
+    /// Overrides [`GameClient::AGENT_SCRATCH_MB`] for games run with this profile.
+    pub agent_scratch_mb: Option<u64>,
+    /// Overrides [`GameClient::AGENT_SWAP_MB`] for games run with this profile.
+    pub agent_swap_mb: Option<u64>,
+    /// Games run with this profile are cancelled and requeued once they have run for this many
+    /// seconds so that they resume from their last checkpoint, see
+    /// [`ExecutionProfile::max_game_duration`].
+    pub max_game_duration_secs: Option<u64>,
+}
+
+impl ExecutionProfile {
+    /// A profile that uses the resources given by the constants of the [`GameClient`].
+    pub fn new(name: impl Into<String>) -> Self {
+        ExecutionProfile {
+            name: name.into(),
+            agent_ram_mb: None,
+            agent_scratch_mb: None,
+            agent_swap_mb: None,
+            max_game_duration_secs: None,
+        }
+    }
+
+    pub fn with_agent_ram_mb(mut self, agent_ram_mb: u64) -> Self {
+        self.agent_ram_mb = Some(agent_ram_mb);
+        self
+    }
+
+    pub fn with_agent_scratch_mb(mut self, agent_scratch_mb: u64) -> Self {
+        self.agent_scratch_mb = Some(agent_scratch_mb);
+        self
+    }
+
+    pub fn with_agent_swap_mb(mut self, agent_swap_mb: u64) -> Self {
+        self.agent_swap_mb = Some(agent_swap_mb);
+        self
+    }
+
+    pub fn with_max_game_duration(mut self, max_game_duration: Duration) -> Self {
+        self.max_game_duration_secs = Some(max_game_duration.as_secs());
+        self
+    }
+
+    pub fn agent_ram_mb<C: GameClient>(&self) -> u64 {
+        self.agent_ram_mb.unwrap_or(C::AGENT_RAM_MB)
+    }
+
+    pub fn agent_scratch_mb<C: GameClient>(&self) -> u64 {
+        self.agent_scratch_mb.unwrap_or(C::AGENT_SCRATCH_MB)
+    }
+
+    pub fn agent_swap_mb<C: GameClient>(&self) -> u64 {
+        self.agent_swap_mb.unwrap_or(C::AGENT_SWAP_MB)
+    }
+
+    /// How long games of this profile may run before they are cancelled and requeued.
+    /// By default there is no limit unless the executor only acknowledges match requests once
+    /// their game has finished, then [`crate::Settings::max_game_duration`] is used instead.
+    pub fn max_game_duration(&self) -> Option<Duration> {
+        self.max_game_duration_secs.map(Duration::from_secs)
+    }
+}
+
+/// The profiles a competition supports when it doesn't declare its own, `basic` and `gpu` both
+/// using the resources given by the constants of the [`GameClient`].
+pub fn default_execution_profiles() -> Vec<ExecutionProfile> {
+    vec![
+        ExecutionProfile::new(BASIC_EXECUTION_PROFILE),
+        ExecutionProfile::new(GPU_EXECUTION_PROFILE),
+    ]
+}
+
+/// Finds the profile with the given name out of those declared by the game client.
+pub fn find_execution_profile<C: GameClient>(name: &str) -> Option<ExecutionProfile> {
+    C::execution_profiles()
+        .into_iter()
+        .find(|profile| profile.name == name)
+}
@@ -0,0 +1,276 @@
+//! Retrying games that failed because of infrastructure errors.
+
+use std::time::Duration;
+
+use doxa_core::tracing::{error, info};
+use doxa_mq::{MQError, MQ};
+
+use crate::{
+    client::GameClient,
+    context::GameEventContext,
+    error::{FailureKind, NodeLost},
+};
+
+/// Controls how games that fail because of an infrastructure error (see
+/// [`crate::error::FailureKind`]) are requeued.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// The maximum number of times a game will be retried, 0 disables retries.
+    pub max_retries: u32,
+    /// The delay before the first retry, this doubles for each subsequent retry.
+    pub base_backoff: Duration,
+    /// The upper bound on the delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(60 * 10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// If the game should be retried after failing on `attempt` (starting from 0) this returns
+    /// how long to wait before requeuing it.
+    /// Games cancelled by draining or that timed out after saving a checkpoint are always requeued
+    /// without waiting.
+    pub fn retry_backoff(&self, kind: FailureKind, attempt: u32) -> Option<Duration> {
+        if kind.keeps_attempt() {
+            return Some(Duration::ZERO);
+        }
+
+        if kind != FailureKind::Infrastructure || attempt >= self.max_retries {
+            return None;
+        }
+
+        let backoff = self
+            .base_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_backoff);
+
+        Some(backoff.min(self.max_backoff))
+    }
+}
+
+/// Handles a match request from `queue` whose game failed on `attempt` because of `error`.
+///
+/// If the policy allows another attempt it is requeued (with the same attempt number if it was
+/// drained or timed out after saving a checkpoint). With a backoff the broker holds on to it until the backoff has passed (see
+/// [`doxa_mq::action::requeue_match_request`]) so the delivery can be acknowledged once this
+/// returns without the retry being lost.
+/// Otherwise, if the failure was caused by the infrastructure, it is moved to the dead letter queue
+/// so that it can be replayed once the problem has been fixed.
+pub async fn requeue_or_dead_letter(
+    policy: &RetryPolicy,
+    mq: MQ,
+    data: Vec<u8>,
+    queue: String,
+    kind: FailureKind,
+    attempt: u32,
+    error: String,
+) {
+    if let Some(backoff) = policy.retry_backoff(kind, attempt) {
+        // Drained games and those that timed out after saving a checkpoint didn't fail so they
+        // don't use up a retry
+        let next_attempt = if kind.keeps_attempt() {
+            attempt
+        } else {
+            attempt + 1
+        };
+
+        info!(?backoff, %next_attempt, "requeuing game");
+        if let Err(error) = doxa_mq::action::requeue_match_request(
+            mq.as_ref(),
+            &data,
+            &queue,
+            next_attempt,
+            backoff,
+        )
+        .await
+        {
+            error!(%error, debug = ?error, "failed to requeue match request");
+        }
+    } else if kind == FailureKind::Infrastructure {
+        let reason = format!(
+            "failed after {} attempts because of an infrastructure error: {}",
+            attempt + 1,
+            error
+        );
+
+        if let Err(error) =
+            doxa_mq::action::emit_dead_letter(mq.as_ref(), &queue, data, reason).await
+        {
+            error!(%error, debug = ?error, "failed to dead letter match request");
+        }
+    }
+}
+
+/// Fails a game that was lost because the execution node running it stopped sending heartbeats or
+/// restarted, the node can't do this itself so it is done by the server instead.
+///
+/// As with any other infrastructure failure this emits `_ERROR` followed by `_RETRY` or `_END`
+/// and then requeues or dead letters the match request.
+/// `data` is the serialized match request, it is sent with its attempt number set to `attempt`
+/// (the attempt that was lost). `next_event_id` must be greater than every event the lost run
+/// could have emitted, i.e. the start of a new run's range (see
+/// [`crate::context::EVENT_IDS_PER_RUN`]).
+#[allow(clippy::too_many_arguments)]
+pub async fn fail_lost_game<C: GameClient>(
+    policy: &RetryPolicy,
+    mq: MQ,
+    competition_name: &'static str,
+    data: &[u8],
+    queue: String,
+    attempt: u32,
+    next_event_id: u32,
+    node: String,
+) -> Result<(), MQError> {
+    let (mut match_request, payload_version) = doxa_mq::envelope::decode_raw_match_request(data)?;
+    match_request.attempt = attempt;
+
+    let mut game_event_context = GameEventContext::<C>::new(
+        mq.clone(),
+        competition_name,
+        match_request.game_id,
+        next_event_id,
+    );
+
+    let error = NodeLost { node };
+    game_event_context
+        .emit_error_event(&error, vec![None; match_request.agents.len()])
+        .await?;
+
+    let retry = policy
+        .retry_backoff(FailureKind::Infrastructure, attempt)
+        .map(|backoff| (attempt + 1, backoff));
+    game_event_context.emit_final_event(retry).await?;
+
+    requeue_or_dead_letter(
+        policy,
+        mq,
+        doxa_mq::envelope::encode_raw_match_request(&match_request, payload_version),
+        queue,
+        FailureKind::Infrastructure,
+        attempt,
+        error.to_string(),
+    )
+    .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use doxa_mq::{model::MatchRequest, InMemoryBackend};
+
+    use super::*;
+
+    fn match_request(attempt: u32) -> Vec<u8> {
+        doxa_mq::envelope::encode_raw_match_request(
+            &MatchRequest {
+                agents: vec!["agent".to_string()],
+                payload: vec![1, 2, 3],
+                game_id: 1,
+                seed: None,
+                attempt,
+            },
+            0,
+        )
+    }
+
+    async fn requeued_attempt(mq: &MQ) -> Option<u32> {
+        let delivery = mq.get("queue").await.unwrap()?;
+        delivery.ack().await.unwrap();
+
+        let (match_request, _) =
+            doxa_mq::envelope::decode_raw_match_request(&delivery.data).unwrap();
+        Some(match_request.attempt)
+    }
+
+    #[tokio::test]
+    async fn drained_games_keep_their_attempt() {
+        let mq: MQ = Arc::new(InMemoryBackend::new());
+        let policy = RetryPolicy::default();
+
+        // Even once every retry has been used
+        let attempt = policy.max_retries;
+        requeue_or_dead_letter(
+            &policy,
+            mq.clone(),
+            match_request(attempt),
+            "queue".to_string(),
+            FailureKind::Drained,
+            attempt,
+            "drained".to_string(),
+        )
+        .await;
+
+        assert_eq!(requeued_attempt(&mq).await, Some(attempt));
+    }
+
+    #[tokio::test]
+    async fn checkpointed_timeouts_keep_their_attempt() {
+        let mq: MQ = Arc::new(InMemoryBackend::new());
+        let policy = RetryPolicy::default();
+
+        let attempt = policy.max_retries;
+        requeue_or_dead_letter(
+            &policy,
+            mq.clone(),
+            match_request(attempt),
+            "queue".to_string(),
+            FailureKind::TimedOut,
+            attempt,
+            "timed out".to_string(),
+        )
+        .await;
+
+        assert_eq!(requeued_attempt(&mq).await, Some(attempt));
+    }
+
+    #[tokio::test]
+    async fn infrastructure_failures_use_up_a_retry() {
+        let mq: MQ = Arc::new(InMemoryBackend::new());
+        let policy = RetryPolicy {
+            base_backoff: Duration::ZERO,
+            ..Default::default()
+        };
+
+        requeue_or_dead_letter(
+            &policy,
+            mq.clone(),
+            match_request(1),
+            "queue".to_string(),
+            FailureKind::Infrastructure,
+            1,
+            "failed".to_string(),
+        )
+        .await;
+
+        assert_eq!(requeued_attempt(&mq).await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn agent_failures_are_not_requeued() {
+        let mq: MQ = Arc::new(InMemoryBackend::new());
+
+        requeue_or_dead_letter(
+            &RetryPolicy::default(),
+            mq.clone(),
+            match_request(0),
+            "queue".to_string(),
+            FailureKind::Agent,
+            0,
+            "failed".to_string(),
+        )
+        .await;
+
+        assert_eq!(requeued_attempt(&mq).await, None);
+    }
+}
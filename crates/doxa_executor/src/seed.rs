@@ -0,0 +1,93 @@
+//! Seeds are serialized as strings in human readable formats such as JSON because JavaScript
+//! numbers can't represent every `u64`. Numbers are still accepted so that events that were stored
+//! before this can be read. Use with `#[serde(with = "doxa_executor::seed")]`.
+
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(u64),
+}
+
+pub fn serialize<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.collect_str(seed)
+    } else {
+        serializer.serialize_u64(*seed)
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    if !deserializer.is_human_readable() {
+        return u64::deserialize(deserializer);
+    }
+
+    match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(seed) => seed.parse().map_err(D::Error::custom),
+        StringOrNumber::Number(seed) => Ok(seed),
+    }
+}
+
+/// The same for optional seeds, use with `#[serde(default, with = "doxa_executor::seed::option")]`.
+pub mod option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match seed {
+            Some(seed) => super::serialize(seed, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        #[derive(Deserialize)]
+        struct Seed(#[serde(with = "super")] u64);
+
+        Ok(Option::<Seed>::deserialize(deserializer)?.map(|Seed(seed)| seed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Seeded {
+        #[serde(with = "super")]
+        seed: u64,
+        #[serde(default, with = "super::option")]
+        optional: Option<u64>,
+    }
+
+    #[test]
+    fn seeds_are_strings_in_json() {
+        let seeded = Seeded {
+            seed: u64::MAX,
+            optional: Some(1),
+        };
+
+        let json = serde_json::to_value(&seeded).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "seed": "18446744073709551615", "optional": "1" })
+        );
+        assert_eq!(serde_json::from_value::<Seeded>(json).unwrap(), seeded);
+    }
+
+    #[test]
+    fn numeric_seeds_are_accepted() {
+        let seeded: Seeded = serde_json::from_str(r#"{ "seed": 5 }"#).unwrap();
+
+        assert_eq!(
+            seeded,
+            Seeded {
+                seed: 5,
+                optional: None
+            }
+        );
+    }
+}
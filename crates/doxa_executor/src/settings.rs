@@ -1,5 +1,6 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
+pub use crate::retry::RetryPolicy;
 pub use doxa_storage::AgentRetrieval;
 pub use doxa_vm::mount::Mount;
 
@@ -14,4 +15,27 @@ pub struct Settings {
     /// automatically and do not need to be listed here).
     /// Competitions can also define additional mounts on top of these.
     pub base_mounts: Vec<Mount>,
+    pub retry_policy: RetryPolicy,
+    /// How long running games are given to finish when the executor is drained before they are
+    /// cancelled and requeued, see [`crate::drain`].
+    pub drain_timeout: Duration,
+    /// Games that are still running after this long are cancelled and requeued to resume from
+    /// their last checkpoint. This only applies when match requests are acknowledged once their
+    /// game has finished (i.e. unless
+    /// [`crate::listener::MatchRequestListener::acknowledge_on_start`] is set) and the game's
+    /// execution profile doesn't set its own limit (see
+    /// [`crate::profile::ExecutionProfile::max_game_duration`]), it must then be shorter than the
+    /// broker's consumer timeout (`consumer_timeout` is 30 minutes by default in RabbitMQ), see
+    /// [`crate::game::DEFAULT_MAX_GAME_DURATION`].
+    pub max_game_duration: Option<Duration>,
+    /// Identifies this executor in the `_START` event of the games it runs, see
+    /// [`default_node_name`].
+    pub node_name: String,
+}
+
+/// Uses `DOXA_NODE_NAME` if it is set otherwise the host name.
+pub fn default_node_name() -> String {
+    std::env::var("DOXA_NODE_NAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
 }
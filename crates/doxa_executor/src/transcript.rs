@@ -0,0 +1,226 @@
+//! An optional record of everything that was sent to and received from an agent during a game.
+//! This is enabled per match request by [`crate::client::GameClient::record_transcript`].
+
+use std::{
+    io::{self, Read, Write},
+    time::Instant,
+};
+
+use doxa_core::chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::error::TranscriptDecodeError;
+
+/// The maximum amount of agent input and output (in bytes) that is recorded in a single
+/// transcript. Anything after this is not recorded and the transcript is marked as truncated.
+pub const MAX_TRANSCRIPT_LEN: usize = 5_000_000;
+
+/// Compressed transcripts are split across `_TRANSCRIPT` events of at most this many bytes so that
+/// no single game event (or row of the events table) is too large.
+pub const TRANSCRIPT_CHUNK_LEN: usize = 256 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptDirection {
+    /// Data that was sent to the agent's stdin
+    Input,
+    /// A line of the agent's stdout (without the newline)
+    Output,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TranscriptEntry {
+    /// The number of milliseconds since `Transcript::started_at`
+    pub time_ms: u64,
+    pub direction: TranscriptDirection,
+    /// The data that was sent or received, any invalid UTF-8 is replaced.
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Transcript {
+    pub started_at: DateTime<Utc>,
+    pub entries: Vec<TranscriptEntry>,
+    pub truncated: bool,
+}
+
+impl Transcript {
+    /// Serializes the transcript as JSON, compresses it with gzip and then base64 encodes it so
+    /// that it can be stored as part of an event.
+    pub fn compress(&self) -> io::Result<String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        serde_json::to_writer(&mut encoder, self)?;
+        encoder.flush()?;
+
+        Ok(base64::encode(encoder.finish()?))
+    }
+
+    /// Reverses [`Transcript::compress`] returning the JSON representation of the transcript.
+    pub fn decompress_json(data: &str) -> Result<Vec<u8>, TranscriptDecodeError> {
+        let compressed = base64::decode(data)?;
+
+        let mut json = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut json)?;
+
+        Ok(json)
+    }
+}
+
+/// Splits a compressed transcript (see [`Transcript::compress`]) into chunks of at most
+/// [`TRANSCRIPT_CHUNK_LEN`] bytes.
+pub fn split_chunks(compressed: &str) -> Vec<&str> {
+    if compressed.is_empty() {
+        return vec![compressed];
+    }
+
+    compressed
+        .as_bytes()
+        .chunks(TRANSCRIPT_CHUNK_LEN)
+        // Base64 is ASCII so this never splits a character
+        .map(|chunk| std::str::from_utf8(chunk).unwrap())
+        .collect()
+}
+
+/// Joins the chunks of a transcript that were produced by [`split_chunks`], given in the order
+/// they were emitted as `(chunk, chunks, data)`.
+///
+/// A game that was run more than once can have several transcripts for an agent, in which case
+/// the latest complete one is returned.
+pub fn join_chunks<'a>(chunks: impl IntoIterator<Item = (u32, u32, &'a str)>) -> Option<String> {
+    let mut current = String::new();
+    let mut next_chunk = 0;
+    let mut complete = None;
+
+    for (chunk, chunks, data) in chunks {
+        if chunk == 0 {
+            current.clear();
+            next_chunk = 0;
+        } else if chunk != next_chunk {
+            // Part of the transcript is missing
+            current.clear();
+            next_chunk = 0;
+            continue;
+        }
+
+        current.push_str(data);
+        next_chunk += 1;
+
+        if next_chunk == chunks {
+            complete = Some(std::mem::take(&mut current));
+            next_chunk = 0;
+        }
+    }
+
+    complete
+}
+
+pub(crate) struct TranscriptRecorder {
+    start: Instant,
+    len: usize,
+    transcript: Transcript,
+}
+
+impl TranscriptRecorder {
+    pub fn new() -> Self {
+        TranscriptRecorder {
+            start: Instant::now(),
+            len: 0,
+            transcript: Transcript {
+                started_at: Utc::now(),
+                entries: Vec::new(),
+                truncated: false,
+            },
+        }
+    }
+
+    pub fn record(&mut self, direction: TranscriptDirection, data: &[u8]) {
+        if self.transcript.truncated {
+            return;
+        }
+
+        if self.len + data.len() > MAX_TRANSCRIPT_LEN {
+            self.transcript.truncated = true;
+            return;
+        }
+
+        self.len += data.len();
+        self.transcript.entries.push(TranscriptEntry {
+            time_ms: self.start.elapsed().as_millis() as u64,
+            direction,
+            data: String::from_utf8_lossy(data).to_string(),
+        });
+    }
+
+    pub fn finish(self) -> Transcript {
+        self.transcript
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transcript() -> Transcript {
+        let mut recorder = TranscriptRecorder::new();
+        recorder.record(TranscriptDirection::Input, b"move 1 2\n");
+        recorder.record(TranscriptDirection::Output, b"ok");
+
+        recorder.finish()
+    }
+
+    #[test]
+    fn compressed_transcripts_round_trip() {
+        let compressed = transcript().compress().unwrap();
+        let json = Transcript::decompress_json(&compressed).unwrap();
+        let decoded: Transcript = serde_json::from_slice(&json).unwrap();
+
+        assert_eq!(decoded.entries.len(), 2);
+        assert_eq!(decoded.entries[0].data, "move 1 2\n");
+        assert!(matches!(
+            decoded.entries[1].direction,
+            TranscriptDirection::Output
+        ));
+        assert!(!decoded.truncated);
+    }
+
+    #[test]
+    fn long_transcripts_are_truncated() {
+        let mut recorder = TranscriptRecorder::new();
+        recorder.record(TranscriptDirection::Output, &vec![b'a'; MAX_TRANSCRIPT_LEN]);
+        recorder.record(TranscriptDirection::Output, b"b");
+
+        let transcript = recorder.finish();
+        assert_eq!(transcript.entries.len(), 1);
+        assert!(transcript.truncated);
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let compressed = "a".repeat(TRANSCRIPT_CHUNK_LEN * 2 + 10);
+        let chunks = split_chunks(&compressed);
+        assert_eq!(chunks.len(), 3);
+
+        let joined = join_chunks(
+            chunks
+                .iter()
+                .enumerate()
+                .map(|(i, chunk)| (i as u32, chunks.len() as u32, *chunk)),
+        );
+        assert_eq!(joined.as_deref(), Some(compressed.as_str()));
+    }
+
+    #[test]
+    fn the_latest_complete_transcript_is_joined() {
+        let chunks = vec![
+            (0, 1, "first"),
+            (0, 2, "second"),
+            (1, 2, "run"),
+            // An interrupted run
+            (0, 2, "third"),
+        ];
+
+        assert_eq!(join_chunks(chunks).as_deref(), Some("secondrun"));
+        assert_eq!(join_chunks(vec![(1, 2, "missing")]), None);
+    }
+}
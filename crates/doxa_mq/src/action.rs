@@ -1,19 +1,28 @@
-use doxa_core::lapin::options::BasicConsumeOptions;
-use doxa_core::lapin::{self, Connection, Consumer};
-
-use lapin::{
-    options::{BasicPublishOptions, QueueDeclareOptions},
-    publisher_confirm::PublisherConfirm,
-    types::FieldTable,
-    BasicProperties, Channel,
+use std::time::Duration;
+
+use doxa_core::{
+    chrono::Utc,
+    tokio,
+    tracing::{error, warn},
 };
+use futures::{stream::BoxStream, StreamExt};
 use serde::Serialize;
 
-use crate::model::{ActivationEvent, MatchRequest};
+use crate::{
+    envelope::{encode_game_event, encode_match_request},
+    lanes::WeightedLanes,
+    model::{
+        ActivationEvent, CancellationEvent, DeadLetter, GameEvent, MatchRequest, PauseEvent,
+        Priority,
+    },
+    Delivery, MQError, QueueBackend, QueueConsumer, MQ,
+};
 
 pub use bincode::Error as BincodeError;
 pub use bincode::{deserialize, serialize};
 
+pub use crate::envelope::{decode, encode};
+
 pub fn activation_queue_name(competition_name: &str) -> String {
     format!("activationevent.{}", competition_name)
 }
@@ -22,176 +31,372 @@ pub fn game_event_queue_name(competition_name: &str) -> String {
     format!("gameevent.{}", competition_name)
 }
 
-pub fn match_request_queue_name(competition_name: &str, execution_profile: &str) -> String {
-    format!("matchrequest.{}.{}", competition_name, execution_profile)
+/// Cancellations are broadcast so that every executor receives them.
+pub fn cancellation_exchange_name(competition_name: &str) -> String {
+    format!("cancellation.{}", competition_name)
 }
 
-pub async fn declare_activation_queue(
-    channel: &Channel,
-    competition_name: &str,
-) -> Result<lapin::Queue, lapin::Error> {
-    declare(channel, &activation_queue_name(competition_name), true).await
+/// Pausing and resuming is broadcast so that every executor receives it.
+pub fn pause_exchange_name(competition_name: &str) -> String {
+    format!("pause.{}", competition_name)
 }
 
-pub async fn declare_game_event_queue(
-    channel: &Channel,
-    competition_name: &str,
-) -> Result<lapin::Queue, lapin::Error> {
-    declare(channel, &game_event_queue_name(competition_name), true).await
+/// Messages that could not be processed are moved here, see [`dead_letter`].
+pub fn dead_letter_queue_name(queue: &str) -> String {
+    format!("deadletter.{}", queue)
 }
 
-pub async fn declare_match_request_queue(
-    channel: &Channel,
+/// Each priority has its own lane, regular match requests use the name without a suffix.
+pub fn match_request_queue_name(
     competition_name: &str,
     execution_profile: &str,
-) -> Result<lapin::Queue, lapin::Error> {
-    declare(
-        channel,
-        &match_request_queue_name(competition_name, execution_profile),
-        true,
-    )
-    .await
-}
-
-async fn declare(
-    channel: &Channel,
-    queue_name: &str,
-    durable: bool,
-) -> Result<lapin::Queue, lapin::Error> {
-    channel
-        .queue_declare(
-            queue_name,
-            QueueDeclareOptions {
-                // It may not need to be durable, as part of the startup proceedure the system
-                // could go through agents that have no queued games or there could be a field on
-                // agent which determines if it's been processed or not (this probably isn't a good
-                // idea as then what's the point of rabbitmq)
-                durable,
-                ..Default::default()
-            },
-            FieldTable::default(),
-        )
-        .await
+    priority: Priority,
+) -> String {
+    match priority {
+        Priority::Placement => format!(
+            "matchrequest.{}.{}.placement",
+            competition_name, execution_profile
+        ),
+        Priority::Regular => format!("matchrequest.{}.{}", competition_name, execution_profile),
+        Priority::Background => format!(
+            "matchrequest.{}.{}.background",
+            competition_name, execution_profile
+        ),
+    }
 }
 
-pub async fn publish(
-    channel: &Channel,
-    queue_name: &str,
-    payload: Vec<u8>,
-) -> Result<PublisherConfirm, lapin::Error> {
-    channel
-        .basic_publish(
-            "",
-            queue_name,
-            BasicPublishOptions::default(),
-            payload,
-            BasicProperties::default(),
-        )
-        .await
-}
-
-async fn consume(channel: &Channel, queue_name: &str) -> Result<Consumer, lapin::Error> {
-    channel
-        .basic_consume(
-            queue_name,
-            "",
-            BasicConsumeOptions::default(),
-            FieldTable::default(),
-        )
-        .await
-}
-
-/// This declares the correct queue then submits the event.
 pub async fn emit_activation_event(
-    conn: &Connection,
+    mq: &dyn QueueBackend,
     upload_event: &ActivationEvent,
-) -> Result<PublisherConfirm, lapin::Error> {
-    let channel = conn.create_channel().await?;
-    declare_activation_queue(&channel, &upload_event.competition).await?;
-
-    publish(
-        &channel,
+) -> Result<(), MQError> {
+    mq.publish(
         &activation_queue_name(&upload_event.competition),
-        serialize(upload_event).unwrap(),
+        encode(upload_event),
     )
     .await
 }
 
-/// This declares the correct queue then returns the consumer which can yield messages.
-pub async fn get_activation_event_consumer(
-    conn: &Connection,
+pub fn get_activation_event_consumer(
+    mq: MQ,
     competition_name: &str,
-) -> Result<Consumer, lapin::Error> {
-    let channel = conn.create_channel().await?;
-    declare_activation_queue(&channel, competition_name).await?;
-
-    consume(&channel, &activation_queue_name(competition_name)).await
+) -> BoxStream<'static, Delivery> {
+    consume_forever(mq, activation_queue_name(competition_name), EVENT_PREFETCH)
 }
 
+/// `payload_version` is the competition's version of `T`, see [`crate::envelope`].
 pub async fn emit_match_request<T: Serialize>(
-    conn: &Connection,
-    match_request: &MatchRequest<T>,
+    mq: &dyn QueueBackend,
+    match_request: MatchRequest<T>,
+    payload_version: u32,
     competition: &str,
     execution_profile: &str,
-) -> Result<PublisherConfirm, lapin::Error> {
-    let channel = conn.create_channel().await?;
-    declare_match_request_queue(&channel, competition, execution_profile).await?;
-
-    publish(
-        &channel,
-        &match_request_queue_name(competition, execution_profile),
-        serialize(match_request).unwrap(),
+    priority: Priority,
+) -> Result<(), MQError> {
+    mq.publish(
+        &match_request_queue_name(competition, execution_profile, priority),
+        encode_match_request(match_request, payload_version),
     )
     .await
 }
 
-pub async fn get_match_request_consumer(
-    conn: &Connection,
+/// Publishes a match request again to `queue` (the lane it was received from) with its attempt
+/// number set to `attempt` so that it can be retried. It is only delivered once `backoff` has
+/// passed (see [`QueueBackend::publish_delayed`]).
+/// `data` is the serialized match request as it was received from the queue, the payload is kept
+/// as is (along with its version).
+pub async fn requeue_match_request(
+    mq: &dyn QueueBackend,
+    data: &[u8],
+    queue: &str,
+    attempt: u32,
+    backoff: Duration,
+) -> Result<(), MQError> {
+    let (mut match_request, payload_version) = crate::envelope::decode_raw_match_request(data)?;
+    match_request.attempt = attempt;
+    let data = crate::envelope::encode_raw_match_request(&match_request, payload_version);
+
+    if backoff.is_zero() {
+        mq.publish(queue, data).await
+    } else {
+        mq.publish_delayed(queue, data, backoff).await
+    }
+}
+
+/// Consumes the match requests of every priority, taking from each lane in proportion to its
+/// [`Priority::weight`] whenever more than one lane has match requests waiting.
+///
+/// When several consumers share capacity, it should only be taken once a match request has been
+/// received so that a consumer without match requests doesn't hold on to capacity the others could
+/// use.
+/// `prefetch` should be the number of games the consumer can run at once, otherwise it holds on to
+/// match requests that other executors could be running while it waits for capacity.
+pub fn get_match_request_consumer(
+    mq: MQ,
     competition_name: &str,
     execution_profile: &str,
-) -> Result<Consumer, lapin::Error> {
-    let channel = conn.create_channel().await?;
-    declare_match_request_queue(&channel, competition_name, execution_profile).await?;
+    prefetch: u16,
+) -> BoxStream<'static, (Priority, Delivery)> {
+    let lanes = Priority::ALL
+        .iter()
+        .map(|&priority| {
+            let queue = match_request_queue_name(competition_name, execution_profile, priority);
+            (priority, consume_forever(mq.clone(), queue, prefetch))
+        })
+        .collect();
+
+    WeightedLanes::new(lanes).boxed()
+}
+
+/// `payload_version` is the competition's version of its game events, system events (which begin
+/// with an underscore) are covered by [`crate::envelope::MESSAGE_VERSION`] instead.
+pub async fn emit_game_event<T: Serialize>(
+    mq: &dyn QueueBackend,
+    competition_name: &str,
+    game_event: &GameEvent<T>,
+    payload_version: u32,
+) -> Result<(), MQError> {
+    mq.publish(
+        &game_event_queue_name(competition_name),
+        encode_game_event(game_event, payload_version),
+    )
+    .await
+}
 
-    consume(
-        &channel,
-        &match_request_queue_name(competition_name, execution_profile),
+pub fn get_game_event_consumer(mq: MQ, competition_name: &str) -> BoxStream<'static, Delivery> {
+    consume_forever(mq, game_event_queue_name(competition_name), EVENT_PREFETCH)
+}
+
+/// Tells every executor to stop the listed games if they are running them.
+pub async fn emit_cancellation_event(
+    mq: &dyn QueueBackend,
+    competition: &str,
+    cancellation_event: &CancellationEvent,
+) -> Result<(), MQError> {
+    mq.broadcast(
+        &cancellation_exchange_name(competition),
+        encode(cancellation_event),
     )
     .await
 }
 
-/// This declares the correct queue then returns the consumer which can yield messages.
-pub async fn get_game_event_consumer(
-    conn: &Connection,
+/// Every consumer receives every cancellation event that is emitted while it is subscribed.
+/// Messages do not need to be acknowledged.
+pub async fn get_cancellation_consumer(
+    mq: &dyn QueueBackend,
     competition_name: &str,
-) -> Result<Consumer, lapin::Error> {
-    let channel = conn.create_channel().await?;
-    declare_game_event_queue(&channel, competition_name).await?;
-
-    consume(&channel, &game_event_queue_name(competition_name)).await
-}
-// pub async fn emit_game_event<T: Serialize>(
-//     conn: &Connection,
-//     game_event: &GameEvent<T>,
-//     competition: &str,
-// ) -> Result<PublisherConfirm, lapin::Error> {
-//     let channel = conn.create_channel().await?;
-//     declare_game_event_queue(&channel, competition).await?;
-//
-//     publish(
-//         &channel,
-//         &game_event_queue_name(competition),
-//         serialize(game_event).unwrap(),
-//     )
-//     .await
-// }
-
-/// Declares the queue then returns the channel where it is safe to use publish later
-pub async fn game_event_channel(
-    conn: &Connection,
+) -> Result<QueueConsumer, MQError> {
+    mq.subscribe(&cancellation_exchange_name(competition_name))
+        .await
+}
+
+/// Tells every executor to stop (or start again) taking match requests for the competition.
+pub async fn emit_pause_event(
+    mq: &dyn QueueBackend,
     competition: &str,
-) -> Result<Channel, lapin::Error> {
-    let channel = conn.create_channel().await?;
-    declare_game_event_queue(&channel, competition).await?;
-    Ok(channel)
+    pause_event: &PauseEvent,
+) -> Result<(), MQError> {
+    mq.broadcast(&pause_exchange_name(competition), encode(pause_event))
+        .await
+}
+
+/// Every consumer receives every pause event that is emitted while it is subscribed, so the
+/// current state should be fetched after subscribing.
+/// Messages do not need to be acknowledged.
+pub async fn get_pause_consumer(
+    mq: &dyn QueueBackend,
+    competition_name: &str,
+) -> Result<QueueConsumer, MQError> {
+    mq.subscribe(&pause_exchange_name(competition_name)).await
+}
+
+/// How long to wait before reconnecting after a consumer fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The number of activation and game events that are delivered ahead of being handled, these are
+/// handled one at a time and acknowledged straight away.
+const EVENT_PREFETCH: u16 = 32;
+
+/// Consumes `queue` until the process exits.
+/// If consuming fails (e.g. the connection to the broker is lost) the error is logged and a new
+/// consumer is created after a delay, so unlike [`QueueBackend::consume`] the stream never ends.
+///
+/// Any message that was unacknowledged when the connection was lost will be redelivered.
+pub fn consume_forever(mq: MQ, queue: String, prefetch: u16) -> BoxStream<'static, Delivery> {
+    futures::stream::unfold(
+        (mq, queue, None::<QueueConsumer>),
+        move |(mq, queue, mut consumer)| async move {
+            loop {
+                let current = match consumer.as_mut() {
+                    Some(current) => current,
+                    None => match mq.consume(&queue, prefetch).await {
+                        Ok(new) => consumer.insert(new),
+                        Err(error) => {
+                            error!(%queue, %error, debug = ?error, "failed to start consuming queue, retrying");
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                            continue;
+                        }
+                    },
+                };
+
+                let next = current.next().await;
+                match next {
+                    Some(Ok(delivery)) => return Some((delivery, (mq, queue, consumer))),
+                    Some(Err(error)) => {
+                        error!(%queue, %error, debug = ?error, "lost connection while consuming queue, reconnecting");
+                    }
+                    None => {
+                        warn!(%queue, "consumer was closed, reconnecting");
+                    }
+                }
+
+                consumer = None;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        },
+    )
+    .boxed()
+}
+
+/// Moves a message from `queue` that could not be processed (e.g. because it could not be
+/// decoded or because it keeps failing) to the queue's dead letter queue.
+/// The delivery is acknowledged so that it is not delivered again.
+///
+/// Dead letters can be inspected with [`peek_dead_letters`] and sent back to the original queue
+/// with [`replay_dead_letters`].
+pub async fn dead_letter(
+    mq: &dyn QueueBackend,
+    queue: &str,
+    delivery: &Delivery,
+    reason: String,
+) -> Result<(), MQError> {
+    emit_dead_letter(mq, queue, delivery.data.clone(), reason).await?;
+
+    delivery.ack().await
+}
+
+/// The same as [`dead_letter`] for a message that has already been acknowledged.
+pub async fn emit_dead_letter(
+    mq: &dyn QueueBackend,
+    queue: &str,
+    data: Vec<u8>,
+    reason: String,
+) -> Result<(), MQError> {
+    let dead_letter = DeadLetter {
+        queue: queue.to_string(),
+        reason,
+        failed_at: Utc::now(),
+        data,
+    };
+
+    mq.publish(&dead_letter_queue_name(queue), encode(&dead_letter))
+        .await
+}
+
+/// Returns up to `limit` of the oldest dead letters from `queue` without removing them.
+pub async fn peek_dead_letters(
+    mq: &dyn QueueBackend,
+    queue: &str,
+    limit: usize,
+) -> Result<Vec<DeadLetter>, MQError> {
+    let dead_letter_queue = dead_letter_queue_name(queue);
+
+    // Every message taken must be rejected afterwards (even on error) so that it goes back on the
+    // queue
+    let mut deliveries = Vec::new();
+    let mut result = Ok(());
+    while deliveries.len() < limit {
+        match mq.get(&dead_letter_queue).await {
+            Ok(Some(delivery)) => deliveries.push(delivery),
+            Ok(None) => break,
+            Err(error) => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+
+    let dead_letters = deliveries
+        .iter()
+        .map(|delivery| decode(&delivery.data))
+        .collect::<Result<Vec<DeadLetter>, _>>();
+
+    for delivery in deliveries {
+        delivery.nack(true).await?;
+    }
+
+    result?;
+    Ok(dead_letters?)
+}
+
+/// Publishes up to `limit` of the oldest dead letters from `queue` back to `queue` so that they
+/// are processed again, returning how many were replayed.
+pub async fn replay_dead_letters(
+    mq: &dyn QueueBackend,
+    queue: &str,
+    limit: usize,
+) -> Result<usize, MQError> {
+    let dead_letter_queue = dead_letter_queue_name(queue);
+
+    let mut replayed = 0;
+    while replayed < limit {
+        let delivery = match mq.get(&dead_letter_queue).await? {
+            Some(delivery) => delivery,
+            None => break,
+        };
+
+        let dead_letter: DeadLetter = match decode(&delivery.data) {
+            Ok(dead_letter) => dead_letter,
+            Err(error) => {
+                delivery.nack(true).await?;
+                return Err(error.into());
+            }
+        };
+
+        if let Err(error) = mq.publish(&dead_letter.queue, dead_letter.data).await {
+            delivery.nack(true).await?;
+            return Err(error);
+        }
+
+        delivery.ack().await?;
+        replayed += 1;
+    }
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::InMemoryBackend;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn dead_letters_can_be_peeked_and_replayed() {
+        let backend = InMemoryBackend::new();
+        backend.publish("test", vec![1]).await.unwrap();
+
+        let delivery = backend.get("test").await.unwrap().unwrap();
+        dead_letter(&backend, "test", &delivery, "bad message".to_string())
+            .await
+            .unwrap();
+        assert!(backend.get("test").await.unwrap().is_none());
+
+        // Peeking must not remove the dead letters
+        for _ in 0..2 {
+            let dead_letters = peek_dead_letters(&backend, "test", 10).await.unwrap();
+            assert_eq!(dead_letters.len(), 1);
+            assert_eq!(dead_letters[0].queue, "test");
+            assert_eq!(dead_letters[0].reason, "bad message");
+            assert_eq!(dead_letters[0].data, vec![1]);
+        }
+
+        assert_eq!(replay_dead_letters(&backend, "test", 10).await.unwrap(), 1);
+        assert!(peek_dead_letters(&backend, "test", 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let replayed = backend.get("test").await.unwrap().unwrap();
+        assert_eq!(replayed.data, vec![1]);
+    }
 }
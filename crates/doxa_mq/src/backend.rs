@@ -0,0 +1,141 @@
+use std::{fmt, io, time::Duration};
+
+use async_trait::async_trait;
+use derive_more::{Display, Error, From};
+use doxa_core::{actix_web, deadpool_lapin::PoolError, impl_respondable_error, lapin};
+use futures::stream::BoxStream;
+
+use crate::envelope::DecodeError;
+
+pub mod amqp;
+pub mod memory;
+
+/// A stream of messages from a queue.
+pub type QueueConsumer = BoxStream<'static, Result<Delivery, MQError>>;
+
+/// The message queue that is used to communicate between the different parts of the system.
+///
+/// There are two kinds of destinations:
+/// - queues (see [`QueueBackend::publish`] and [`QueueBackend::consume`]) where each message is
+///   delivered to a single consumer and must be acknowledged.
+/// - exchanges (see [`QueueBackend::broadcast`] and [`QueueBackend::subscribe`]) where every
+///   subscriber receives a copy of each message, these do not need to be acknowledged.
+///
+/// Queues and exchanges are created on first use.
+#[async_trait]
+pub trait QueueBackend: Send + Sync + 'static {
+    async fn publish(&self, queue: &str, payload: Vec<u8>) -> Result<(), MQError>;
+
+    /// Publishes a message that is only delivered from `queue` once `delay` has passed.
+    /// The message is stored straight away so that it isn't lost if the publisher exits while it
+    /// is waiting.
+    async fn publish_delayed(
+        &self,
+        queue: &str,
+        payload: Vec<u8>,
+        delay: Duration,
+    ) -> Result<(), MQError>;
+
+    /// At most `prefetch` messages are delivered to the consumer before they are acknowledged,
+    /// so that a consumer doesn't take more messages than it can process while other consumers
+    /// are idle.
+    async fn consume(&self, queue: &str, prefetch: u16) -> Result<QueueConsumer, MQError>;
+
+    /// Takes the next message from the queue without waiting, returning `None` if it is empty.
+    /// This is intended for inspecting queues, long running consumers should use
+    /// [`QueueBackend::consume`].
+    async fn get(&self, queue: &str) -> Result<Option<Delivery>, MQError>;
+
+    /// Returns the number of messages waiting in the queue (not including messages that have been
+    /// delivered but not yet acknowledged) and how many consumers it has.
+    async fn queue_stats(&self, queue: &str) -> Result<QueueStats, MQError>;
+
+    /// Publishes a message to every subscriber of the exchange.
+    /// Messages are discarded if there are no subscribers.
+    async fn broadcast(&self, exchange: &str, payload: Vec<u8>) -> Result<(), MQError>;
+
+    async fn subscribe(&self, exchange: &str) -> Result<QueueConsumer, MQError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub messages: u64,
+    /// This is `None` if the backend doesn't keep track of consumers
+    pub consumers: Option<u32>,
+}
+
+#[async_trait]
+pub(crate) trait Acker: Send + Sync {
+    async fn ack(&self) -> Result<(), MQError>;
+
+    async fn nack(&self, requeue: bool) -> Result<(), MQError>;
+}
+
+/// A message received from a queue.
+/// If a delivery is dropped without being acknowledged it will be delivered again later (for
+/// AMQP this happens once the channel closes).
+pub struct Delivery {
+    pub data: Vec<u8>,
+    /// Whether this message may have been delivered before (e.g. the previous consumer crashed
+    /// before acknowledging it).
+    pub redelivered: bool,
+    acker: Box<dyn Acker>,
+}
+
+impl Delivery {
+    pub(crate) fn new(data: Vec<u8>, redelivered: bool, acker: Box<dyn Acker>) -> Self {
+        Delivery {
+            data,
+            redelivered,
+            acker,
+        }
+    }
+
+    /// Marks the message as processed so that it is removed from the queue.
+    pub async fn ack(&self) -> Result<(), MQError> {
+        self.acker.ack().await
+    }
+
+    /// Rejects the message, if `requeue` is set it will be delivered again otherwise it is
+    /// discarded.
+    pub async fn nack(&self, requeue: bool) -> Result<(), MQError> {
+        self.acker.nack(requeue).await
+    }
+}
+
+impl fmt::Debug for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Delivery")
+            .field("data_len", &self.data.len())
+            .field("redelivered", &self.redelivered)
+            .finish()
+    }
+}
+
+/// Acker for deliveries that do not need to be acknowledged.
+pub(crate) struct NoAck;
+
+#[async_trait]
+impl Acker for NoAck {
+    async fn ack(&self) -> Result<(), MQError> {
+        Ok(())
+    }
+
+    async fn nack(&self, _requeue: bool) -> Result<(), MQError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum MQError {
+    Amqp(lapin::Error),
+    Pool(PoolError),
+    #[display(fmt = "failed to persist message queue: {}", _0)]
+    Persistence(io::Error),
+    Decode(DecodeError),
+    #[display(fmt = "unsupported message queue url `{}`", _0)]
+    #[from(ignore)]
+    UnsupportedUrl(#[error(not(source))] String),
+}
+
+impl_respondable_error!(MQError, INTERNAL_SERVER_ERROR, "INTERNAL_SERVER_ERROR");
@@ -0,0 +1,384 @@
+use std::{collections::HashSet, time::Duration};
+
+use async_trait::async_trait;
+use deadpool::managed::Timeouts;
+use doxa_core::{
+    deadpool_lapin::{Manager, Pool},
+    lapin::{
+        self,
+        message::Delivery as LapinDelivery,
+        options::{
+            BasicAckOptions, BasicConsumeOptions, BasicGetOptions, BasicNackOptions,
+            BasicPublishOptions, BasicQosOptions, ExchangeDeclareOptions, QueueBindOptions,
+            QueueDeclareOptions,
+        },
+        protocol::{AMQPErrorKind, AMQPSoftError},
+        types::{AMQPValue, FieldTable},
+        BasicProperties, Channel, ConnectionProperties, ExchangeKind,
+    },
+    tokio::{self, sync::Mutex},
+    tracing::{info, warn},
+};
+use futures::StreamExt;
+use tokio_amqp::LapinTokioExt;
+
+use super::{Acker, Delivery, MQError, NoAck, QueueBackend, QueueConsumer, QueueStats};
+
+/// A [`QueueBackend`] using an AMQP broker such as RabbitMQ.
+pub struct AmqpBackend {
+    pool: Pool,
+    /// Publishing happens often (e.g. for every game event) so a single channel is reused
+    publish_channel: Mutex<Option<Channel>>,
+    declared: Mutex<HashSet<String>>,
+}
+
+impl AmqpBackend {
+    pub fn new(addr: String, max_connections: usize) -> Self {
+        let manager = Manager::new(addr, ConnectionProperties::default().with_tokio());
+
+        AmqpBackend {
+            pool: Pool::new(manager, max_connections),
+            publish_channel: Mutex::new(None),
+            declared: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// TODO: this is more of stopgap because the competition system can't handle MQ not being on.
+    /// The better solution (compared to this) is to make the competition system auto reboot/retry on
+    /// connection issues.
+    ///
+    /// This methods tries to get a MQ connection with a timeout of 2 seconds
+    pub async fn wait_until_ready(&self) {
+        let mut i = 0;
+
+        loop {
+            match self
+                .pool
+                .timeout_get(&Timeouts {
+                    wait: Some(Duration::from_secs(2)),
+                    create: Some(Duration::from_secs(2)),
+                    recycle: Some(Duration::from_secs(2)),
+                })
+                .await
+            {
+                Ok(_) => return,
+                Err(e) => {
+                    i += 1;
+                    // Reached max attempts
+                    if i == 10 {
+                        panic!("failed to connect to rabbit mq: {}", e);
+                    }
+
+                    info!(attempt=%i, reason=%e, "failed to connect to rabbit mq, trying again");
+
+                    tokio::time::sleep(Duration::from_millis(750)).await;
+                }
+            }
+        }
+    }
+
+    async fn create_channel(&self) -> Result<Channel, MQError> {
+        let connection = self.pool.get().await?;
+        Ok(connection.create_channel().await?)
+    }
+
+    /// Returns the shared publish channel, replacing it if it has been closed
+    async fn publish_channel(&self) -> Result<Channel, MQError> {
+        let mut publish_channel = self.publish_channel.lock().await;
+
+        match &*publish_channel {
+            Some(channel) if channel.status().connected() => Ok(channel.clone()),
+            _ => {
+                let channel = self.create_channel().await?;
+                *publish_channel = Some(channel.clone());
+                Ok(channel)
+            }
+        }
+    }
+
+    async fn declare_queue(&self, channel: &Channel, queue: &str) -> Result<(), MQError> {
+        self.declare_queue_with_arguments(channel, queue, FieldTable::default())
+            .await
+    }
+
+    async fn declare_queue_with_arguments(
+        &self,
+        channel: &Channel,
+        queue: &str,
+        arguments: FieldTable,
+    ) -> Result<(), MQError> {
+        if self.declared.lock().await.contains(queue) {
+            return Ok(());
+        }
+
+        channel
+            .queue_declare(
+                queue,
+                QueueDeclareOptions {
+                    // It may not need to be durable, as part of the startup proceedure the system
+                    // could go through agents that have no queued games or there could be a field on
+                    // agent which determines if it's been processed or not (this probably isn't a good
+                    // idea as then what's the point of rabbitmq)
+                    durable: true,
+                    ..Default::default()
+                },
+                arguments,
+            )
+            .await?;
+
+        self.declared.lock().await.insert(queue.to_string());
+
+        Ok(())
+    }
+
+    async fn declare_exchange(&self, channel: &Channel, exchange: &str) -> Result<(), MQError> {
+        channel
+            .exchange_declare(
+                exchange,
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueueBackend for AmqpBackend {
+    async fn publish(&self, queue: &str, payload: Vec<u8>) -> Result<(), MQError> {
+        let channel = self.publish_channel().await?;
+        self.declare_queue(&channel, queue).await?;
+
+        channel
+            .basic_publish(
+                "",
+                queue,
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Messages are published to a queue named `delay.{delay_ms}.{queue}` without any consumers,
+    /// once they have been in it for the delay the broker moves them to `queue`. Each delay has
+    /// its own queue as messages only expire from the front of a queue.
+    async fn publish_delayed(
+        &self,
+        queue: &str,
+        payload: Vec<u8>,
+        delay: Duration,
+    ) -> Result<(), MQError> {
+        let delay_ms = delay.as_millis() as i64;
+        let delay_queue = format!("delay.{}.{}", delay_ms, queue);
+
+        let channel = self.publish_channel().await?;
+        // The messages are dropped if the queue they expire to doesn't exist
+        self.declare_queue(&channel, queue).await?;
+
+        let mut arguments = FieldTable::default();
+        arguments.insert("x-message-ttl".into(), AMQPValue::LongLongInt(delay_ms));
+        arguments.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString("".into()),
+        );
+        arguments.insert(
+            "x-dead-letter-routing-key".into(),
+            AMQPValue::LongString(queue.into()),
+        );
+        self.declare_queue_with_arguments(&channel, &delay_queue, arguments)
+            .await?;
+
+        channel
+            .basic_publish(
+                "",
+                &delay_queue,
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn consume(&self, queue: &str, prefetch: u16) -> Result<QueueConsumer, MQError> {
+        let channel = self.create_channel().await?;
+        self.declare_queue(&channel, queue).await?;
+        // Each consumer has its own channel so this only limits this consumer
+        channel
+            .basic_qos(prefetch, BasicQosOptions::default())
+            .await?;
+
+        let consumer = channel
+            .basic_consume(
+                queue,
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(consumer
+            .map(|message| {
+                let (_, mut delivery) = message?;
+                let data = std::mem::take(&mut delivery.data);
+                let redelivered = delivery.redelivered;
+
+                Ok(Delivery::new(
+                    data,
+                    redelivered,
+                    Box::new(AmqpAcker { delivery }),
+                ))
+            })
+            .boxed())
+    }
+
+    async fn get(&self, queue: &str) -> Result<Option<Delivery>, MQError> {
+        let channel = self.publish_channel().await?;
+        self.declare_queue(&channel, queue).await?;
+
+        let message = channel.basic_get(queue, BasicGetOptions::default()).await?;
+
+        Ok(message.map(|message| {
+            let mut delivery = message.delivery;
+            let data = std::mem::take(&mut delivery.data);
+            let redelivered = delivery.redelivered;
+
+            Delivery::new(data, redelivered, Box::new(AmqpAcker { delivery }))
+        }))
+    }
+
+    async fn queue_stats(&self, queue: &str) -> Result<QueueStats, MQError> {
+        // The broker closes the channel if a passive declare fails so this can't use the shared
+        // publish channel
+        let channel = self.create_channel().await?;
+
+        // A passive declare returns the current message and consumer counts without creating the
+        // queue or checking that its options match
+        let stats = match channel
+            .queue_declare(
+                queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+        {
+            Ok(queue) => QueueStats {
+                messages: queue.message_count() as u64,
+                consumers: Some(queue.consumer_count()),
+            },
+            // Nothing has been published to or consumed from the queue yet
+            Err(lapin::Error::ProtocolError(error))
+                if matches!(error.kind(), AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND)) =>
+            {
+                return Ok(QueueStats {
+                    messages: 0,
+                    consumers: Some(0),
+                });
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        if let Err(error) = channel.close(200, "OK").await {
+            warn!(%error, "failed to close queue stats channel");
+        }
+
+        Ok(stats)
+    }
+
+    async fn broadcast(&self, exchange: &str, payload: Vec<u8>) -> Result<(), MQError> {
+        let channel = self.publish_channel().await?;
+        self.declare_exchange(&channel, exchange).await?;
+
+        channel
+            .basic_publish(
+                exchange,
+                "",
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, exchange: &str) -> Result<QueueConsumer, MQError> {
+        let channel = self.create_channel().await?;
+        self.declare_exchange(&channel, exchange).await?;
+
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                exchange,
+                "",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        let consumer = channel
+            .basic_consume(
+                queue.name().as_str(),
+                "",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(consumer
+            .map(|message| {
+                let (_, delivery) = message?;
+                Ok(Delivery::new(delivery.data, false, Box::new(NoAck)))
+            })
+            .boxed())
+    }
+}
+
+struct AmqpAcker {
+    delivery: LapinDelivery,
+}
+
+#[async_trait]
+impl Acker for AmqpAcker {
+    async fn ack(&self) -> Result<(), MQError> {
+        self.delivery.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
+
+    async fn nack(&self, requeue: bool) -> Result<(), MQError> {
+        self.delivery
+            .nack(BasicNackOptions {
+                requeue,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use doxa_core::tokio::{
+    self,
+    sync::{broadcast, Notify},
+};
+use futures::StreamExt;
+
+use super::{Acker, Delivery, MQError, NoAck, QueueBackend, QueueConsumer, QueueStats};
+
+/// The number of broadcast messages that are buffered for each subscriber, if a subscriber falls
+/// further behind than this the oldest messages are skipped.
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// A [`QueueBackend`] that runs inside the process, so every producer and consumer must be part
+/// of the same process (e.g. a single `doxa_server` that also runs the games).
+///
+/// By default messages are lost when the process exits, see [`InMemoryBackend::persistent`].
+#[derive(Default)]
+pub struct InMemoryBackend {
+    queues: Mutex<HashMap<String, Arc<MemoryQueue>>>,
+    exchanges: Mutex<HashMap<String, broadcast::Sender<Vec<u8>>>>,
+    persistence_dir: Option<PathBuf>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a backend that stores each unacknowledged message as a file in `dir` so that it
+    /// survives restarts.
+    /// Any messages left in `dir` by a previous run are loaded and will be delivered again.
+    ///
+    /// Broadcast messages are never persisted.
+    pub async fn persistent(dir: PathBuf) -> io::Result<Self> {
+        let mut queues = HashMap::new();
+
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut queue_dirs = tokio::fs::read_dir(&dir).await?;
+        while let Some(queue_dir) = queue_dirs.next_entry().await? {
+            let queue_name = queue_dir.file_name().to_string_lossy().into_owned();
+            let queue = MemoryQueue::load(queue_dir.path()).await?;
+
+            queues.insert(queue_name, Arc::new(queue));
+        }
+
+        Ok(InMemoryBackend {
+            queues: Mutex::new(queues),
+            exchanges: Default::default(),
+            persistence_dir: Some(dir),
+        })
+    }
+
+    fn queue(&self, name: &str) -> Arc<MemoryQueue> {
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Arc::new(MemoryQueue::new(
+                    self.persistence_dir.as_ref().map(|dir| dir.join(name)),
+                ))
+            })
+            .clone()
+    }
+
+    fn exchange(&self, name: &str) -> broadcast::Sender<Vec<u8>> {
+        self.exchanges
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| broadcast::channel(BROADCAST_CAPACITY).0)
+            .clone()
+    }
+}
+
+#[async_trait]
+impl QueueBackend for InMemoryBackend {
+    async fn publish(&self, queue: &str, payload: Vec<u8>) -> Result<(), MQError> {
+        self.queue(queue).push(payload).await?;
+
+        Ok(())
+    }
+
+    /// The message is persisted straight away, if the process restarts before the delay has
+    /// passed it is delivered early.
+    async fn publish_delayed(
+        &self,
+        queue: &str,
+        payload: Vec<u8>,
+        delay: Duration,
+    ) -> Result<(), MQError> {
+        let queue = self.queue(queue);
+        let message = queue.store(payload).await?;
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            queue.requeue(message);
+        });
+
+        Ok(())
+    }
+
+    /// Messages are only taken from the queue once the consumer is polled, so there is no need
+    /// to limit the number that are prefetched.
+    async fn consume(&self, queue: &str, _prefetch: u16) -> Result<QueueConsumer, MQError> {
+        let queue = self.queue(queue);
+
+        Ok(futures::stream::unfold(queue, |queue| async move {
+            loop {
+                if let Some(delivery) = queue.pop_delivery() {
+                    return Some((Ok(delivery), queue));
+                }
+
+                queue.notify.notified().await;
+            }
+        })
+        .boxed())
+    }
+
+    async fn get(&self, queue: &str) -> Result<Option<Delivery>, MQError> {
+        Ok(self.queue(queue).pop_delivery())
+    }
+
+    async fn queue_stats(&self, queue: &str) -> Result<QueueStats, MQError> {
+        Ok(QueueStats {
+            messages: self.queue(queue).len() as u64,
+            consumers: None,
+        })
+    }
+
+    async fn broadcast(&self, exchange: &str, payload: Vec<u8>) -> Result<(), MQError> {
+        // An error only means there are no subscribers
+        let _ = self.exchange(exchange).send(payload);
+
+        Ok(())
+    }
+
+    async fn subscribe(&self, exchange: &str) -> Result<QueueConsumer, MQError> {
+        let receiver = self.exchange(exchange).subscribe();
+
+        Ok(
+            futures::stream::unfold(receiver, |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(data) => {
+                            return Some((
+                                Ok(Delivery::new(data, false, Box::new(NoAck))),
+                                receiver,
+                            ))
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            })
+            .boxed(),
+        )
+    }
+}
+
+struct Message {
+    id: u64,
+    data: Vec<u8>,
+    redelivered: bool,
+}
+
+struct MemoryQueue {
+    messages: Mutex<VecDeque<Message>>,
+    notify: Notify,
+    next_id: AtomicU64,
+    dir: Option<PathBuf>,
+}
+
+impl MemoryQueue {
+    fn new(dir: Option<PathBuf>) -> Self {
+        MemoryQueue {
+            messages: Default::default(),
+            notify: Notify::new(),
+            next_id: AtomicU64::new(0),
+            dir,
+        }
+    }
+
+    /// Loads the messages persisted in `dir` in the order they were published.
+    async fn load(dir: PathBuf) -> io::Result<Self> {
+        let mut messages = Vec::new();
+
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let id = match entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+            {
+                Some(id) => id,
+                None => continue,
+            };
+
+            messages.push(Message {
+                id,
+                data: tokio::fs::read(entry.path()).await?,
+                // It's unknown whether these were being processed when the process exited
+                redelivered: true,
+            });
+        }
+
+        messages.sort_by_key(|message| message.id);
+        let next_id = messages.last().map(|message| message.id + 1).unwrap_or(0);
+
+        Ok(MemoryQueue {
+            messages: Mutex::new(messages.into()),
+            notify: Notify::new(),
+            next_id: AtomicU64::new(next_id),
+            dir: Some(dir),
+        })
+    }
+
+    fn message_path(&self, id: u64) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(id.to_string()))
+    }
+
+    async fn push(&self, data: Vec<u8>) -> io::Result<()> {
+        let message = self.store(data).await?;
+        self.requeue(message);
+
+        Ok(())
+    }
+
+    /// Persists a new message without adding it to the queue.
+    async fn store(&self, data: Vec<u8>) -> io::Result<Message> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        if let (Some(dir), Some(path)) = (&self.dir, self.message_path(id)) {
+            tokio::fs::create_dir_all(dir).await?;
+            tokio::fs::write(path, &data).await?;
+        }
+
+        Ok(Message {
+            id,
+            data,
+            redelivered: false,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+
+    fn pop(&self) -> Option<Message> {
+        self.messages.lock().unwrap().pop_front()
+    }
+
+    /// Pops the next message, it is requeued unless it is acknowledged.
+    fn pop_delivery(self: &Arc<Self>) -> Option<Delivery> {
+        let message = self.pop()?;
+
+        Some(Delivery::new(
+            message.data.clone(),
+            message.redelivered,
+            Box::new(MemoryAcker {
+                queue: self.clone(),
+                message: Mutex::new(Some(message)),
+            }),
+        ))
+    }
+
+    fn requeue(&self, message: Message) {
+        {
+            let mut messages = self.messages.lock().unwrap();
+            // Keep the queue in publish order so redelivered messages go first
+            let index = messages.partition_point(|queued| queued.id < message.id);
+            messages.insert(index, message);
+        }
+
+        // This stores a permit if no consumer is waiting so the message isn't missed
+        self.notify.notify_one();
+    }
+
+    async fn remove(&self, message: Message) -> io::Result<()> {
+        if let Some(path) = self.message_path(message.id) {
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct MemoryAcker {
+    queue: Arc<MemoryQueue>,
+    /// This is taken once the message is acknowledged or rejected
+    message: Mutex<Option<Message>>,
+}
+
+#[async_trait]
+impl Acker for MemoryAcker {
+    async fn ack(&self) -> Result<(), MQError> {
+        let message = self.message.lock().unwrap().take();
+        if let Some(message) = message {
+            self.queue.remove(message).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn nack(&self, requeue: bool) -> Result<(), MQError> {
+        let message = self.message.lock().unwrap().take();
+        if let Some(mut message) = message {
+            if requeue {
+                message.redelivered = true;
+                self.queue.requeue(message);
+            } else {
+                self.queue.remove(message).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MemoryAcker {
+    fn drop(&mut self) {
+        // The same as an AMQP channel closing with unacknowledged messages
+        if let Some(mut message) = self.message.get_mut().unwrap().take() {
+            message.redelivered = true;
+            self.queue.requeue(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn unacknowledged_messages_are_redelivered() {
+        let backend = InMemoryBackend::new();
+        let mut consumer = backend.consume("test", 1).await.unwrap();
+
+        backend.publish("test", vec![1]).await.unwrap();
+        backend.publish("test", vec![2]).await.unwrap();
+
+        let first = consumer.next().await.unwrap().unwrap();
+        assert_eq!(first.data, vec![1]);
+        assert!(!first.redelivered);
+        drop(first);
+
+        let first = consumer.next().await.unwrap().unwrap();
+        assert_eq!(first.data, vec![1]);
+        assert!(first.redelivered);
+        first.ack().await.unwrap();
+
+        let second = consumer.next().await.unwrap().unwrap();
+        assert_eq!(second.data, vec![2]);
+        second.ack().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn persisted_messages_are_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let backend = InMemoryBackend::persistent(dir.path().to_owned())
+                .await
+                .unwrap();
+            backend.publish("test", vec![1]).await.unwrap();
+            backend.publish("test", vec![2]).await.unwrap();
+
+            let mut consumer = backend.consume("test", 1).await.unwrap();
+            consumer.next().await.unwrap().unwrap().ack().await.unwrap();
+        }
+
+        let backend = InMemoryBackend::persistent(dir.path().to_owned())
+            .await
+            .unwrap();
+        let mut consumer = backend.consume("test", 1).await.unwrap();
+
+        let message = consumer.next().await.unwrap().unwrap();
+        assert_eq!(message.data, vec![2]);
+        assert!(message.redelivered);
+    }
+
+    #[tokio::test]
+    async fn queue_stats_exclude_unacknowledged_messages() {
+        let backend = InMemoryBackend::new();
+        backend.publish("test", vec![1]).await.unwrap();
+        backend.publish("test", vec![2]).await.unwrap();
+
+        let delivery = backend.get("test").await.unwrap().unwrap();
+        assert_eq!(backend.queue_stats("test").await.unwrap().messages, 1);
+
+        drop(delivery);
+        assert_eq!(backend.queue_stats("test").await.unwrap().messages, 2);
+    }
+
+    #[tokio::test]
+    async fn delayed_messages_are_delivered_after_the_delay() {
+        let backend = InMemoryBackend::new();
+        backend
+            .publish_delayed("test", vec![1], Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        assert!(backend.get("test").await.unwrap().is_none());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let delivery = backend.get("test").await.unwrap().unwrap();
+        assert_eq!(delivery.data, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn delayed_messages_are_persisted_straight_away() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let backend = InMemoryBackend::persistent(dir.path().to_owned())
+                .await
+                .unwrap();
+            backend
+                .publish_delayed("test", vec![1], Duration::from_secs(60))
+                .await
+                .unwrap();
+        }
+
+        let backend = InMemoryBackend::persistent(dir.path().to_owned())
+            .await
+            .unwrap();
+        let delivery = backend.get("test").await.unwrap().unwrap();
+        assert_eq!(delivery.data, vec![1]);
+    }
+}
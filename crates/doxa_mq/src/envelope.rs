@@ -0,0 +1,373 @@
+//! Every message is wrapped in an [`Envelope`] that records the versions it was serialized with, so
+//! that consumers can detect messages they don't understand instead of misinterpreting them.
+//!
+//! Competition specific payloads (the payload of a [`MatchRequest`] and of client
+//! [`GameEvent`]s) are versioned separately by the competition which can provide functions to
+//! upgrade payloads with an older version, e.g. match requests that were queued before a
+//! deployment.
+//!
+//! Messages that were queued before envelopes were introduced are decoded as version 0, their
+//! payloads also have version 0.
+
+use derive_more::{Display, Error, From};
+use doxa_db::serde_json;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::model::{GameEvent, MatchRequest};
+
+/// The version of the messages in [`crate::model`].
+/// This must be incremented whenever one of them changes in a way that means messages serialized
+/// with the previous version can no longer be deserialized.
+pub const MESSAGE_VERSION: u32 = 1;
+
+/// Precedes every envelope so that they can be told apart from messages without one.
+/// Messages without an envelope begin with either the length of a string or vec as a little endian
+/// `u64` (bincode) or `{` (JSON) so they never start with this.
+const ENVELOPE_MAGIC: &[u8] = b"DOXA";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    /// The [`MESSAGE_VERSION`] of the producer
+    pub version: u32,
+    /// The version of the competition specific payload, this is 0 for messages without one
+    pub payload_version: u32,
+    /// The serialized message
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum DecodeError {
+    #[display(
+        fmt = "message has version {} but this consumer only supports version {}",
+        found,
+        supported
+    )]
+    #[from(ignore)]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[display(
+        fmt = "payload has version {} which can't be upgraded to the current version {}",
+        found,
+        current
+    )]
+    #[from(ignore)]
+    UnsupportedPayloadVersion { found: u32, current: u32 },
+    #[display(fmt = "improperly formatted message: {}", _0)]
+    Bincode(bincode::Error),
+    #[display(fmt = "improperly formatted message: {}", _0)]
+    Json(serde_json::Error),
+}
+
+fn seal(payload_version: u32, data: Vec<u8>) -> Vec<u8> {
+    let mut sealed = ENVELOPE_MAGIC.to_vec();
+    bincode::serialize_into(
+        &mut sealed,
+        &Envelope {
+            version: MESSAGE_VERSION,
+            payload_version,
+            data,
+        },
+    )
+    .unwrap();
+
+    sealed
+}
+
+/// Returns `None` for messages without an envelope (version 0).
+fn open(data: &[u8]) -> Result<Option<Envelope>, DecodeError> {
+    let data = match data.strip_prefix(ENVELOPE_MAGIC) {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let envelope: Envelope = bincode::deserialize(data)?;
+
+    if envelope.version != MESSAGE_VERSION {
+        return Err(DecodeError::UnsupportedVersion {
+            found: envelope.version,
+            supported: MESSAGE_VERSION,
+        });
+    }
+
+    Ok(Some(envelope))
+}
+
+/// Version 0 match requests had no seed or attempt and their payload was serialized in place, this
+/// splits out the payload using the fact that the game ID is always the last 4 bytes.
+fn decode_legacy_match_request(data: &[u8]) -> Result<MatchRequest<Vec<u8>>, DecodeError> {
+    let mut rest = data;
+    let agents: Vec<String> = bincode::deserialize_from(&mut rest)?;
+
+    if rest.len() < 4 {
+        return Err(DecodeError::Bincode(Box::new(bincode::ErrorKind::Custom(
+            "match request is missing its game id".to_string(),
+        ))));
+    }
+    let (payload, game_id) = rest.split_at(rest.len() - 4);
+
+    Ok(MatchRequest {
+        agents,
+        payload: payload.to_vec(),
+        game_id: bincode::deserialize(game_id)?,
+        seed: None,
+        attempt: 0,
+    })
+}
+
+/// Deserializes a payload with version `found`, using `upgrade` if it is older than `current`.
+fn decode_payload<T, P>(
+    payload: P,
+    found: u32,
+    current: u32,
+    deserialize: impl FnOnce(P) -> Result<T, DecodeError>,
+    upgrade: impl FnOnce(u32, P) -> Result<T, DecodeError>,
+) -> Result<T, DecodeError> {
+    if found == current {
+        deserialize(payload)
+    } else if found < current {
+        upgrade(found, payload)
+    } else {
+        // This consumer is older than the producer, it should be replayed once it is updated
+        Err(DecodeError::UnsupportedPayloadVersion { found, current })
+    }
+}
+
+/// Serializes a message that doesn't have a competition specific payload.
+pub fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    seal(0, bincode::serialize(message).unwrap())
+}
+
+/// Deserializes a message that was serialized with [`encode`].
+/// Messages without an envelope were serialized the same way.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, DecodeError> {
+    match open(data)? {
+        Some(envelope) => Ok(bincode::deserialize(&envelope.data)?),
+        None => Ok(bincode::deserialize(data)?),
+    }
+}
+
+/// Serializes a match request, `payload_version` is the competition's version of `T`.
+/// The payload is serialized separately so that it can be upgraded on its own.
+pub fn encode_match_request<T: Serialize>(
+    match_request: MatchRequest<T>,
+    payload_version: u32,
+) -> Vec<u8> {
+    let match_request = match_request
+        .try_map_payload(|payload| bincode::serialize(&payload))
+        .unwrap();
+
+    encode_raw_match_request(&match_request, payload_version)
+}
+
+/// Serializes a match request with a payload that has already been serialized, see
+/// [`decode_raw_match_request`].
+pub fn encode_raw_match_request(
+    match_request: &MatchRequest<Vec<u8>>,
+    payload_version: u32,
+) -> Vec<u8> {
+    seal(payload_version, bincode::serialize(match_request).unwrap())
+}
+
+/// Deserializes a match request along with the version of its payload without deserializing the
+/// payload, e.g. so that it can be requeued by something that doesn't know the payload type.
+pub fn decode_raw_match_request(data: &[u8]) -> Result<(MatchRequest<Vec<u8>>, u32), DecodeError> {
+    match open(data)? {
+        Some(envelope) => Ok((
+            bincode::deserialize(&envelope.data)?,
+            envelope.payload_version,
+        )),
+        None => Ok((decode_legacy_match_request(data)?, 0)),
+    }
+}
+
+/// Deserializes a match request where `payload_version` is the current version of `T`.
+/// Payloads with an older version are converted with `upgrade` which is given the version and
+/// the payload serialized with bincode.
+pub fn decode_match_request<T: DeserializeOwned>(
+    data: &[u8],
+    payload_version: u32,
+    upgrade: impl FnOnce(u32, &[u8]) -> Result<T, DecodeError>,
+) -> Result<MatchRequest<T>, DecodeError> {
+    let (match_request, found) = decode_raw_match_request(data)?;
+
+    match_request.try_map_payload(|payload| {
+        decode_payload(
+            payload.as_slice(),
+            found,
+            payload_version,
+            |payload| Ok(bincode::deserialize(payload)?),
+            upgrade,
+        )
+    })
+}
+
+/// Serializes a game event, `payload_version` is the competition's version of its game events.
+/// Game events are serialized with JSON (unlike the other messages) as their payloads are stored
+/// in the database as JSON.
+pub fn encode_game_event<T: Serialize>(game_event: &GameEvent<T>, payload_version: u32) -> Vec<u8> {
+    seal(payload_version, serde_json::to_vec(game_event).unwrap())
+}
+
+/// Deserializes a game event along with the version of its payload.
+/// The payload is left as JSON so that system events can be handled without knowing the type of
+/// the competition's events, see [`upgrade_game_event_payload`].
+pub fn decode_game_event(data: &[u8]) -> Result<(GameEvent<serde_json::Value>, u32), DecodeError> {
+    match open(data)? {
+        Some(envelope) => Ok((
+            serde_json::from_slice(&envelope.data)?,
+            envelope.payload_version,
+        )),
+        None => Ok((serde_json::from_slice(data)?, 0)),
+    }
+}
+
+/// Deserializes the payload of a game event with version `found` where `current` is the current
+/// version of `T`, converting older payloads with `upgrade`.
+pub fn upgrade_game_event_payload<T: DeserializeOwned>(
+    payload: serde_json::Value,
+    found: u32,
+    current: u32,
+    upgrade: impl FnOnce(u32, serde_json::Value) -> Result<T, DecodeError>,
+) -> Result<T, DecodeError> {
+    decode_payload(
+        payload,
+        found,
+        current,
+        |payload| Ok(serde_json::from_value(payload)?),
+        upgrade,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct PayloadV0 {
+        size: u8,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct PayloadV1 {
+        width: u8,
+        height: u8,
+    }
+
+    fn match_request<T>(payload: T) -> MatchRequest<T> {
+        MatchRequest {
+            agents: vec!["a".to_string(), "b".to_string()],
+            payload,
+            game_id: 1,
+            seed: None,
+            attempt: 0,
+        }
+    }
+
+    fn upgrade(version: u32, payload: &[u8]) -> Result<PayloadV1, DecodeError> {
+        match version {
+            0 => {
+                let old: PayloadV0 = bincode::deserialize(payload)?;
+                Ok(PayloadV1 {
+                    width: old.size,
+                    height: old.size,
+                })
+            }
+            _ => Err(DecodeError::UnsupportedPayloadVersion {
+                found: version,
+                current: 1,
+            }),
+        }
+    }
+
+    #[test]
+    fn older_match_request_payloads_are_upgraded() {
+        let data = encode_match_request(match_request(PayloadV0 { size: 3 }), 0);
+
+        let match_request = decode_match_request(&data, 1, upgrade).unwrap();
+        assert_eq!(
+            match_request.payload,
+            PayloadV1 {
+                width: 3,
+                height: 3
+            }
+        );
+    }
+
+    #[test]
+    fn newer_match_request_payloads_are_rejected() {
+        let data = encode_match_request(
+            match_request(PayloadV1 {
+                width: 3,
+                height: 3,
+            }),
+            1,
+        );
+
+        let error = decode_match_request::<PayloadV0>(&data, 0, |_, _| unreachable!()).unwrap_err();
+        assert!(matches!(
+            error,
+            DecodeError::UnsupportedPayloadVersion {
+                found: 1,
+                current: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn messages_without_an_envelope_are_upgraded() {
+        #[derive(Serialize)]
+        struct LegacyMatchRequest {
+            agents: Vec<String>,
+            payload: PayloadV0,
+            game_id: i32,
+        }
+
+        let data = bincode::serialize(&LegacyMatchRequest {
+            agents: vec!["a".to_string(), "b".to_string()],
+            payload: PayloadV0 { size: 3 },
+            game_id: 7,
+        })
+        .unwrap();
+
+        let match_request = decode_match_request(&data, 1, upgrade).unwrap();
+        assert_eq!(match_request.agents, vec!["a", "b"]);
+        assert_eq!(match_request.game_id, 7);
+        assert_eq!(match_request.attempt, 0);
+        assert_eq!(
+            match_request.payload,
+            PayloadV1 {
+                width: 3,
+                height: 3
+            }
+        );
+
+        let data = serde_json::to_vec(&GameEvent {
+            timestamp: doxa_core::chrono::Utc::now(),
+            event_type: "_START".to_string(),
+            event_id: 0,
+            game_id: 7,
+            payload: serde_json::Value::Object(Default::default()),
+        })
+        .unwrap();
+        let (game_event, payload_version) = decode_game_event(&data).unwrap();
+        assert_eq!(game_event.game_id, 7);
+        assert_eq!(payload_version, 0);
+    }
+
+    #[test]
+    fn unknown_message_versions_are_rejected() {
+        let mut data = ENVELOPE_MAGIC.to_vec();
+        bincode::serialize_into(
+            &mut data,
+            &Envelope {
+                version: MESSAGE_VERSION + 1,
+                payload_version: 0,
+                data: vec![],
+            },
+        )
+        .unwrap();
+
+        assert!(matches!(
+            decode::<()>(&data),
+            Err(DecodeError::UnsupportedVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{stream::BoxStream, Stream, StreamExt};
+
+use crate::{model::Priority, Delivery};
+
+/// Merges the streams of several lanes using weighted round robin.
+///
+/// Lanes are visited in a fixed schedule where each lane appears [`Priority::weight`] times (spread
+/// out as evenly as possible). Lanes without a message ready are skipped, so no capacity is wasted
+/// waiting on an empty lane.
+pub(crate) struct WeightedLanes {
+    lanes: Vec<(Priority, BoxStream<'static, Delivery>)>,
+    /// Indexes into `lanes`
+    schedule: Vec<usize>,
+    /// The position in `schedule` to start from on the next poll
+    cursor: usize,
+}
+
+impl WeightedLanes {
+    pub(crate) fn new(lanes: Vec<(Priority, BoxStream<'static, Delivery>)>) -> Self {
+        let weights: Vec<u32> = lanes
+            .iter()
+            .map(|(priority, _)| priority.weight())
+            .collect();
+
+        WeightedLanes {
+            schedule: smooth_schedule(&weights),
+            lanes,
+            cursor: 0,
+        }
+    }
+}
+
+/// Creates a schedule where each index appears `weights[index]` times, interleaved as evenly as
+/// possible (the same as nginx's smooth weighted round robin).
+fn smooth_schedule(weights: &[u32]) -> Vec<usize> {
+    let total: i64 = weights.iter().map(|&weight| weight as i64).sum();
+    let mut current = vec![0i64; weights.len()];
+
+    (0..total)
+        .map(|_| {
+            for (current, &weight) in current.iter_mut().zip(weights) {
+                *current += weight as i64;
+            }
+
+            let (best, _) = current
+                .iter()
+                .enumerate()
+                .max_by_key(|&(index, &current)| (current, std::cmp::Reverse(index)))
+                .unwrap();
+            current[best] -= total;
+
+            best
+        })
+        .collect()
+}
+
+impl Stream for WeightedLanes {
+    type Item = (Priority, Delivery);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        for offset in 0..this.schedule.len() {
+            let position = (this.cursor + offset) % this.schedule.len();
+            let (priority, lane) = &mut this.lanes[this.schedule[position]];
+
+            // Lanes never end as they reconnect on failure
+            if let Poll::Ready(Some(delivery)) = lane.poll_next_unpin(cx) {
+                this.cursor = position + 1;
+                return Poll::Ready(Some((*priority, delivery)));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{InMemoryBackend, QueueBackend};
+
+    use super::*;
+
+    #[test]
+    fn schedule_is_interleaved() {
+        assert_eq!(smooth_schedule(&[2, 1]), vec![0, 1, 0]);
+        assert_eq!(smooth_schedule(&[6, 3, 1]).len(), 10);
+    }
+
+    #[tokio::test]
+    async fn lanes_are_consumed_by_weight() {
+        let backend = InMemoryBackend::new();
+        let mut lanes = Vec::new();
+        for priority in Priority::ALL {
+            let name = format!("{:?}", priority);
+            for _ in 0..20 {
+                backend.publish(&name, vec![]).await.unwrap();
+            }
+
+            let consumer = backend
+                .consume(&name, 1)
+                .await
+                .unwrap()
+                .map(|delivery| delivery.unwrap())
+                .boxed();
+            lanes.push((priority, consumer));
+        }
+
+        let mut consumer = WeightedLanes::new(lanes);
+        let mut taken = Vec::new();
+        for _ in 0..10 {
+            let (priority, delivery) = consumer.next().await.unwrap();
+            delivery.ack().await.unwrap();
+            taken.push(priority);
+        }
+
+        for priority in Priority::ALL {
+            let count = taken.iter().filter(|&&taken| taken == priority).count();
+            assert_eq!(count, priority.weight() as usize);
+        }
+    }
+}
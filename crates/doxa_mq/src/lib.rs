@@ -1,59 +1,44 @@
-use std::time::Duration;
+use std::{path::PathBuf, sync::Arc};
 
-use deadpool::managed::Timeouts;
 pub use doxa_core::lapin;
 pub use tokio_amqp;
 
-pub use lapin::Connection;
-
-use doxa_core::{deadpool_lapin::Manager, tokio, tracing::info};
-use lapin::ConnectionProperties;
-use tokio_amqp::LapinTokioExt;
-
-pub use doxa_core::deadpool_lapin::Pool as MQPool;
+pub use backend::{
+    amqp::AmqpBackend, memory::InMemoryBackend, Delivery, MQError, QueueBackend, QueueConsumer,
+    QueueStats,
+};
 
 pub mod action;
+pub mod backend;
+pub mod envelope;
+mod lanes;
 pub mod model;
 
-/// Get a dedicated connection to the message queue
-pub async fn establish_mq_connection(addr: &str) -> Result<Connection, lapin::Error> {
-    Connection::connect(addr, ConnectionProperties::default().with_tokio()).await
-}
-
-pub async fn establish_pool(addr: String, max_connections: usize) -> MQPool {
-    let manager = Manager::new(addr, ConnectionProperties::default().with_tokio());
-    MQPool::new(manager, max_connections)
-}
+/// A shared handle to the message queue backend.
+pub type MQ = Arc<dyn QueueBackend>;
 
-/// TODO: this is more of stopgap because the competition system can't handle MQ not being on.
-/// The better solution (compared to this) is to make the competition system auto reboot/retry on
-/// connection issues.
+/// Connects to the message queue at `url`.
 ///
-/// This methods tries to get a MQ connection with a timeout of 2 seconds
-pub async fn wait_for_mq(pool: &MQPool) {
-    let mut i = 0;
-
-    loop {
-        match pool
-            .timeout_get(&Timeouts {
-                wait: Some(Duration::from_secs(2)),
-                create: Some(Duration::from_secs(2)),
-                recycle: Some(Duration::from_secs(2)),
-            })
-            .await
-        {
-            Ok(_) => return,
-            Err(e) => {
-                i += 1;
-                // Reached max attempts
-                if i == 10 {
-                    panic!("failed to connect to rabbit mq: {}", e);
-                }
-
-                info!(attempt=%i, reason=%e, "failed to connect to rabbit mq, trying again");
-
-                tokio::time::sleep(Duration::from_millis(750)).await;
-            }
+/// - `amqp://...` (or `amqps://...`) uses an AMQP broker such as RabbitMQ, this waits until the
+///   broker is reachable.
+/// - `memory://` uses an [`InMemoryBackend`] so no broker is required, but every part of the
+///   system must run in this process.
+/// - `memory:///some/dir` is the same as `memory://` but messages are persisted in `/some/dir`.
+pub async fn establish_mq(url: &str, max_connections: usize) -> Result<MQ, MQError> {
+    if url.starts_with("amqp://") || url.starts_with("amqps://") {
+        let backend = AmqpBackend::new(url.to_string(), max_connections);
+        backend.wait_until_ready().await;
+
+        Ok(Arc::new(backend))
+    } else if let Some(dir) = url.strip_prefix("memory://") {
+        if dir.is_empty() {
+            Ok(Arc::new(InMemoryBackend::new()))
+        } else {
+            Ok(Arc::new(
+                InMemoryBackend::persistent(PathBuf::from(dir)).await?,
+            ))
         }
+    } else {
+        Err(MQError::UnsupportedUrl(url.to_string()))
     }
 }
@@ -11,6 +11,59 @@ pub struct ActivationEvent {
     pub competition: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CancellationEvent {
+    /// The ids of the games that should be stopped if they are currently running
+    pub game_ids: Vec<i32>,
+}
+
+/// Sent whenever an admin pauses or resumes a competition.
+/// Executors don't take any more match requests for a paused competition, but games that are
+/// already running are unaffected.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PauseEvent {
+    pub paused: bool,
+}
+
+/// A message that could not be processed, see [`crate::action::dead_letter`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetter {
+    /// The queue the message was originally published to
+    pub queue: String,
+    /// Why the message could not be processed
+    pub reason: String,
+    /// When the message was dead lettered
+    pub failed_at: DateTime<Utc>,
+    /// The original message
+    pub data: Vec<u8>,
+}
+
+/// Match requests of each priority are queued separately and executors take from each lane in
+/// proportion to its [`Priority::weight`], so that lower priority work is never starved but can't
+/// hold up higher priority work either.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Games that are needed to place a newly activated agent
+    Placement,
+    Regular,
+    /// Work that isn't urgent such as re-evaluating existing agents
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Placement, Priority::Regular, Priority::Background];
+
+    /// The relative share of games that are taken from this lane when every lane has games
+    /// queued.
+    pub fn weight(self) -> u32 {
+        match self {
+            Priority::Placement => 6,
+            Priority::Regular => 3,
+            Priority::Background => 1,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MatchRequest<T> {
     /// The ids of the agents that are participating
@@ -19,6 +72,26 @@ pub struct MatchRequest<T> {
     pub payload: T,
     /// The id of the game
     pub game_id: i32,
+    /// The seed for the game's RNG, if this is `None` then the executor will generate one.
+    pub seed: Option<u64>,
+    /// The number of times this game has previously failed due to an infrastructure error and
+    /// been requeued (0 for the first attempt).
+    pub attempt: u32,
+}
+
+impl<T> MatchRequest<T> {
+    pub fn try_map_payload<F: FnOnce(T) -> Result<New, Error>, New, Error>(
+        self,
+        f: F,
+    ) -> Result<MatchRequest<New>, Error> {
+        Ok(MatchRequest {
+            agents: self.agents,
+            payload: f(self.payload)?,
+            game_id: self.game_id,
+            seed: self.seed,
+            attempt: self.attempt,
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -1,11 +1,23 @@
 //! This crate enables conveniently setting up a new deployment of DOXA with specified
 //! competitions.
 
-use std::{env, path::PathBuf, sync::Arc};
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
 
-use doxa_auth::{limiter::GenericLimiter, AuthaClient};
+use doxa_auth::{
+    limiter::{GenericLimiter, LimiterConfig, TokenBucket, ONE_HOUR},
+    local::LocalAuth,
+    mailer::{FileMailer, LogMailer, Mailer},
+    provider::AuthProvider,
+    rate_limit::{RateLimit, RateLimitKey, RouteGroup},
+    AuthaClient,
+};
 use doxa_core::actix_web::{web, App, HttpServer};
-use doxa_executor::{client::firecracker::FirecrackerBackendSettings, settings::Mount};
+use doxa_executor::{
+    client::firecracker::FirecrackerBackendSettings,
+    drain::{DrainState, DEFAULT_DRAIN_TIMEOUT},
+    game::DEFAULT_MAX_GAME_DURATION,
+    settings::Mount,
+};
 use doxa_storage::AgentRetrieval;
 use tracing::{info, warn};
 use tracing_actix_web::TracingLogger;
@@ -40,46 +52,61 @@ pub async fn setup_server_from_env(
 
     let doxa_storage_path = env::var("DOXA_STORAGE").unwrap_or_else(|_| "dev/doxa_storage".into());
 
-    let redis_pool = doxa_core::redis::establish_pool(redis_url, 500).await;
+    let auth_backends = doxa_auth::backend::establish_backends(&redis_url, 500).await;
 
-    let generic_limiter = Arc::new(GenericLimiter::new(redis_pool.clone()));
+    let generic_limiter = Arc::new(GenericLimiter::new(auth_backends.limiter.clone()));
 
-    let autha_base_url = env::var("AUTHA_BASE_URL").unwrap();
-    let autha_shared_secret = env::var("AUTHA_SHARED_SECRET").unwrap();
     let delegated_auth_redirect = env::var("DOXA_DELEGATED_AUTH_URL").unwrap();
     let system_account_secret = env::var("DOXA_SYSTEM_ACCOUNT_SECRET").unwrap();
+    let allow_registration = env::var("DOXA_ALLOW_REGISTRATION")
+        .map(|allow| allow == "true")
+        .unwrap_or(false);
 
-    let autha_client = Arc::new(
-        AuthaClient::new(
-            autha_base_url
-                .parse()
-                .expect("AUTHA_BASE_URL was not parsable base url"),
-            autha_shared_secret,
-        )
-        .await
-        .expect("failed to startup autha client"),
-    );
+    let auth_provider = match env::var("DOXA_AUTH_PROVIDER").as_deref() {
+        Ok("local") => AuthProvider::Local(Arc::new(local_auth_from_env(&generic_limiter))),
+        Ok("autha") | Err(_) => AuthProvider::Autha(Arc::new(autha_client_from_env().await)),
+        Ok(provider) => panic!("unknown DOXA_AUTH_PROVIDER `{}`", provider),
+    };
 
     if let Ok(username) = env::var("DOXA_BOOTSTRAP_ADMIN") {
         warn!(username=%username, "bootstrapping a user to become admin (unset this enironment variable after the user is an admin)");
-        autha_client
-            .make_admin_by_username(username.clone())
-            .await
-            .expect("failed to send request to autha when making user admin")
-            .expect("failed to make user admin");
+        match &auth_provider {
+            AuthProvider::Autha(autha_client) => {
+                autha_client
+                    .make_admin_by_username(username.clone())
+                    .await
+                    .expect("failed to send request to autha when making user admin")
+                    .expect("failed to make user admin");
+            }
+            AuthProvider::Local(_) => {
+                let conn = doxa_db::establish_connection(&database_url);
+                doxa_db::run_migrations(&conn);
+                doxa_db::action::user::set_admin_status(&conn, username.clone(), true)
+                    .expect("failed to make user admin, they must register first");
+            }
+        }
         info!(username=%username, "user is now an admin");
+
+        record_bootstrap_admin(&database_url, &username);
     }
 
+    let trust_proxy_headers = env::var("DOXA_TRUST_PROXY_HEADERS")
+        .map(|trust| trust == "true")
+        .unwrap_or(false);
+
     let auth_settings = doxa_auth::Settings {
-        allow_registration: false,
-        autha_client,
-        redis_db: redis_pool.clone(),
+        allow_registration,
+        auth_provider,
+        delegated_auth_backend: auth_backends.delegated_auth.clone(),
         delegated_auth_url: delegated_auth_redirect
             .parse()
             .expect("The delegated auth URL is not valid"),
         system_account_secret,
+        trust_proxy_headers,
     };
 
+    let rate_limit = default_rate_limit(&auth_settings, &generic_limiter, trust_proxy_headers);
+
     let storage_settings = doxa_storage::Settings {
         root: PathBuf::from(doxa_storage_path),
         generic_limiter: generic_limiter.clone(),
@@ -96,6 +123,10 @@ pub async fn setup_server_from_env(
             path_on_guest: "/python_env".to_string(),
             read_only: true,
         }],
+        retry_policy: Default::default(),
+        drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        max_game_duration: Some(DEFAULT_MAX_GAME_DURATION),
+        node_name: doxa_executor::settings::default_node_name(),
     };
 
     setup_server(
@@ -104,13 +135,123 @@ pub async fn setup_server_from_env(
         auth_settings,
         storage_settings,
         executor_settings,
+        rate_limit,
         competition_system,
     )
     .await
 }
 
+/// Rate limits for the API routes that are expensive to serve or that are open to brute forcing.
+/// The auth flows are limited per IP address as they're used before logging in, everything else
+/// is limited per user and admins are exempt.
+pub fn default_rate_limit(
+    auth_settings: &doxa_auth::Settings,
+    generic_limiter: &Arc<GenericLimiter>,
+    trust_proxy_headers: bool,
+) -> RateLimit {
+    let one_minute = Duration::from_secs(60);
+
+    let mut game_events = LimiterConfig::new("API_GAME_EVENTS".to_string());
+    game_events
+        .add_limit(TokenBucket::new(one_minute, 60))
+        .add_limit(TokenBucket::new(ONE_HOUR, 1000));
+
+    let mut leaderboards = LimiterConfig::new("API_LEADERBOARDS".to_string());
+    leaderboards.add_limit(TokenBucket::new(one_minute, 60));
+
+    let mut auth = LimiterConfig::new("API_AUTH".to_string());
+    auth.add_limit(TokenBucket::new(one_minute, 20))
+        .add_limit(TokenBucket::new(ONE_HOUR, 200));
+
+    RateLimit::new(
+        auth_settings,
+        generic_limiter,
+        trust_proxy_headers,
+        vec![
+            RouteGroup::new(game_events)
+                .route("/api/competition/*/_game/*/events")
+                .route("/api/competition/*/_game/*/transcript/*"),
+            RouteGroup::new(leaderboards).route("/api/competition/*/_leaderboard/**"),
+            RouteGroup::new(auth)
+                .key(RateLimitKey::Ip)
+                .route("/api/auth/start_delegated")
+                .route("/api/auth/check_delegated")
+                .route("/api/auth/provider_flow")
+                .route("/api/auth/verify_email")
+                .route("/api/auth/authorize"),
+        ],
+    )
+}
+
+async fn autha_client_from_env() -> AuthaClient {
+    let autha_base_url = env::var("AUTHA_BASE_URL").unwrap();
+    let autha_shared_secret = env::var("AUTHA_SHARED_SECRET").unwrap();
+
+    AuthaClient::new(
+        autha_base_url
+            .parse()
+            .expect("AUTHA_BASE_URL was not parsable base url"),
+        autha_shared_secret,
+    )
+    .await
+    .expect("failed to startup autha client")
+}
+
+/// The built-in provider sends its emails to files in `DOXA_MAIL_DIR` or to the log if that isn't
+/// set.
+fn local_auth_from_env(generic_limiter: &Arc<GenericLimiter>) -> LocalAuth {
+    let jwt_secret = env::var("DOXA_JWT_SECRET").expect("DOXA_JWT_SECRET must be set");
+    let verify_email_url = env::var("DOXA_VERIFY_EMAIL_URL")
+        .expect("DOXA_VERIFY_EMAIL_URL must be set")
+        .parse()
+        .expect("The verify email URL is not valid");
+    let reset_password_url = env::var("DOXA_RESET_PASSWORD_URL")
+        .expect("DOXA_RESET_PASSWORD_URL must be set")
+        .parse()
+        .expect("The reset password URL is not valid");
+
+    let mailer: Arc<dyn Mailer> = match env::var("DOXA_MAIL_DIR") {
+        Ok(dir) => {
+            Arc::new(FileMailer::new(PathBuf::from(dir)).expect("failed to create DOXA_MAIL_DIR"))
+        }
+        Err(_) => Arc::new(LogMailer),
+    };
+
+    LocalAuth::new(
+        jwt_secret.into_bytes(),
+        mailer,
+        verify_email_url,
+        reset_password_url,
+        generic_limiter,
+    )
+}
+
+/// Records that `DOXA_BOOTSTRAP_ADMIN` was used in the audit log, the user is only known by their
+/// username if they haven't logged in yet.
+fn record_bootstrap_admin(database_url: &str, username: &str) {
+    use doxa_db::model::audit::{actions, user_target, InsertableAuditEntry};
+
+    let conn = doxa_db::establish_connection(database_url);
+    // The audit log may not exist yet on a fresh database
+    doxa_db::run_migrations(&conn);
+
+    let mut entry = InsertableAuditEntry::new(None, actions::BOOTSTRAP_ADMIN)
+        .with_metadata(doxa_db::serde_json::json!({ "username": username }));
+    match doxa_db::action::user::get_user_by_username(&conn, username) {
+        Ok(Some(user)) => entry = entry.with_target(user_target(user.id)),
+        Ok(None) => {}
+        Err(e) => warn!(error=%e, "failed to find the bootstrapped admin user"),
+    }
+
+    if let Err(e) = doxa_db::action::audit::record(&conn, &entry) {
+        warn!(error=%e, "failed to record the bootstrapped admin in the audit log");
+    }
+}
+
 /// Sets up server based on the given settings for each system.
 /// This will start the server and run until exit.
+/// Once the HTTP server stops (e.g. after SIGTERM) the execution managers are drained, so this
+/// only returns once their games have finished or been requeued.
 ///
 /// This will also automatically initialize telemetry and run database migrations.
 pub async fn setup_server(
@@ -119,14 +260,18 @@ pub async fn setup_server(
     auth_settings: doxa_auth::Settings,
     storage_settings: doxa_storage::Settings,
     executor_settings: doxa_executor::Settings,
+    rate_limit: RateLimit,
     competition_system: CompetitionSystem,
 ) -> std::io::Result<()> {
     doxa_db::run_migrations(&doxa_db::establish_connection(database_url));
 
     let db_pool = web::Data::new(doxa_db::establish_pool(database_url));
-    let mq_pool = web::Data::new(doxa_mq::establish_pool(mq_url.to_string(), 25).await);
+    let mq = doxa_mq::establish_mq(mq_url, 25)
+        .await
+        .expect("failed to set up the message queue");
 
-    doxa_mq::wait_for_mq(&mq_pool).await;
+    let drain = DrainState::new();
+    let drain_timeout = executor_settings.drain_timeout;
 
     let competition_settings = doxa_competition::Settings {
         firecracker_settings: FirecrackerBackendSettings {
@@ -137,11 +282,12 @@ pub async fn setup_server(
             vcpus: 6,
         },
         executor_settings: Arc::new(executor_settings),
-        mq_pool: Arc::clone(&mq_pool),
+        mq: mq.clone(),
         pg_pool: Arc::clone(&db_pool),
         generic_limiter: storage_settings.generic_limiter.clone(),
         request_client: doxa_competition::settings::HTTPClient::new(),
         competitions_base_url: "http://localhost:3001/api/competition/".to_string(),
+        drain: drain.clone(),
     };
 
     let configure_competition_routes = competition_system
@@ -155,7 +301,7 @@ pub async fn setup_server(
 
         App::new()
             .app_data(db_pool.clone())
-            .app_data(mq_pool.clone())
+            .app_data(web::Data::from(mq.clone()))
             .service(
                 api_scope.service(
                     // The configure happens before the scope is applied so the scope could be set to anything
@@ -168,9 +314,16 @@ pub async fn setup_server(
                         .configure(configure_competition_routes.clone()),
                 ),
             )
+            .wrap(rate_limit.clone())
             .wrap(TracingLogger::default())
     })
     .bind(("0.0.0.0", 3001))?
     .run()
-    .await
+    .await?;
+
+    drain.drain(drain_timeout);
+    drain.wait_until_drained().await;
+    info!("execution managers drained");
+
+    Ok(())
 }
@@ -8,16 +8,21 @@ use doxa_core::{
 };
 use doxa_db::{
     action,
-    model::storage::{AgentUpload, InsertableAgentUpload},
+    model::storage::{AgentOwner, AgentUpload, InsertableAgentUpload},
     DieselError, PgPool,
 };
 
-use crate::{error::DeleteOldAgentsError, storage::LocalStorage};
+use crate::{
+    error::{DeleteOldAgentsError, UnknownExecutionProfile},
+    storage::LocalStorage,
+};
 
+/// `team` is the uploader's team (if any), which then owns the agent.
 pub fn register_upload_start(
     conn: &PgConnection,
     id: String,
     user_id: i32,
+    team: Option<i32>,
     competition: i32,
     extension: String,
 ) -> Result<AgentUpload, DieselError> {
@@ -28,6 +33,7 @@ pub fn register_upload_start(
             owner: user_id,
             competition,
             extension,
+            team,
         },
     )
 }
@@ -56,14 +62,14 @@ pub async fn delete_old_uploads(
     pool: web::Data<PgPool>,
     competition_name: &str,
     competition: i32,
-    user: i32,
+    owner: AgentOwner,
     before: DateTime<Utc>,
 ) -> Result<(), DeleteOldAgentsError> {
     let agents = web::block({
         let pool = pool.clone();
         let conn = web::block(move || pool.get()).await??;
         move || {
-            action::storage::get_deletable_agents_uploaded_before(&conn, competition, user, before)
+            action::storage::get_deletable_agents_uploaded_before(&conn, competition, owner, before)
         }
     })
     .await??;
@@ -98,34 +104,47 @@ pub async fn delete_old_uploads(
     Ok(())
 }
 
+/// Finds the execution profile the agent declared with `execution_environment` in its
+/// `doxa.yaml`.
+/// `execution_profiles` are the profiles declared by the competition, agents that don't declare a
+/// profile use the first of these.
 pub async fn get_execution_environment(
     storage: &LocalStorage,
     competition: &str,
     agent_id: &str,
-) -> String {
+    execution_profiles: &[String],
+) -> Result<String, UnknownExecutionProfile> {
+    let default = execution_profiles
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "basic".to_string());
+
     let yaml = match storage.read_doxa_yaml(competition, agent_id).await {
         Ok(yaml) => yaml,
         Err(e) => {
             warn!(error=%e, debug=?e, "failed to read doxa yaml");
-            return "basic".to_string();
+            return Ok(default);
         }
     };
 
     if let serde_yaml::Value::Mapping(map) = yaml {
         if let Some(environment) = map.get(&"execution_environment".into()) {
             if let serde_yaml::Value::String(environment) = environment {
-                if environment == "gpu" {
-                    return "gpu".to_string();
-                } else if environment == "basic" {
-                    warn!(execution_environment=?environment, "execution_environment wasn't basic or gpu");
+                if execution_profiles.contains(environment) {
+                    return Ok(environment.clone());
                 }
+
+                return Err(UnknownExecutionProfile {
+                    execution_environment: environment.clone(),
+                    execution_profiles: execution_profiles.to_vec(),
+                });
             } else {
                 warn!(execution_environment=?environment, "execution_environment wasn't a string");
             }
         }
     }
 
-    "basic".to_string()
+    Ok(default)
 }
 
 // pub struct DeleteStatistics {
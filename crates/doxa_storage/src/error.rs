@@ -101,6 +101,23 @@ impl_respondable_error!(
     "The provided file extension is not supported"
 );
 
+#[derive(Debug, Display, Error)]
+#[display(
+    fmt = "Unknown execution environment (execution_environment = `{}`, supported = {:?})",
+    execution_environment,
+    execution_profiles
+)]
+pub struct UnknownExecutionProfile {
+    pub execution_environment: String,
+    pub execution_profiles: Vec<String>,
+}
+impl_respondable_error!(
+    UnknownExecutionProfile,
+    BAD_REQUEST,
+    "UNKNOWN_EXECUTION_ENVIRONMENT",
+    "The execution_environment in doxa.yaml is not supported by this competition"
+);
+
 #[derive(Debug, Display, Error)]
 pub struct FileMissing;
 
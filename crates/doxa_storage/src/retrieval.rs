@@ -24,6 +24,12 @@ impl AgentRetrieval {
         }
     }
 
+    /// The secret used to authenticate as the system account, this is also needed for the other
+    /// requests executors make to the server.
+    pub fn system_account_secret(&self) -> &str {
+        &self.system_account_secret
+    }
+
     pub async fn download_agent(
         &self,
         agent_id: &str,
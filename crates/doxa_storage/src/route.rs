@@ -4,16 +4,25 @@ use crate::route::request::DownloadParams;
 use actix_files::NamedFile;
 use actix_multipart::{Field, Multipart};
 use actix_web::{web, HttpRequest, HttpResponse};
+use doxa_auth::api_token::TokenScope;
 use doxa_auth::error::UserNotAdmin;
 use doxa_auth::limiter::Limiter;
-use doxa_auth::{error::CompetitionNotFound, guard::AuthGuard};
+use doxa_auth::{
+    error::CompetitionNotFound,
+    guard::{AuthGuard, AuthGuardInner},
+};
 // use doxa_core::chrono::{DateTime, Utc};
 use doxa_core::tokio::fs::File;
 use doxa_core::tokio::io::AsyncWriteExt;
 use doxa_core::tracing::error;
 use doxa_core::EndpointResult;
-use doxa_db::PgPool;
-use doxa_mq::MQPool;
+use doxa_db::{
+    action,
+    model::audit::{actions, agent_target, InsertableAuditEntry},
+    serde_json::json,
+    PgPool,
+};
+use doxa_mq::QueueBackend;
 use futures::{StreamExt, TryStreamExt};
 
 mod request;
@@ -118,16 +127,19 @@ async fn process_field_upload(
     Ok(())
 }
 
-pub async fn upload(
+/// Uploads an agent for the user, `bypass_limits` skips the upload rate limit (e.g. for admins).
+pub async fn upload<T: AuthGuardInner>(
     pool: web::Data<PgPool>,
-    mq_pool: web::Data<MQPool>,
+    mq: web::Data<dyn QueueBackend>,
     storage: web::Data<LocalStorage>,
     mut payload: Multipart,
     competition: String,
-    auth: AuthGuard<()>,
+    auth: AuthGuard<T>,
     limiter: &Limiter,
+    bypass_limits: bool,
+    execution_profiles: &[String],
 ) -> EndpointResult {
-    let user_id = auth.id_required()?;
+    let user_id = auth.scoped_id_required(&TokenScope::Upload(competition.clone()))?;
 
     // Check if the user is enrolled
     let enrollment = web::block({
@@ -140,15 +152,27 @@ pub async fn upload(
 
     let competition_id = enrollment.competition;
 
-    if !auth.admin() {
+    // Members of a team upload on behalf of the team, so they also share its limits
+    let owner = web::block({
+        let pool = pool.clone();
+        let conn = web::block(move || pool.get()).await??;
+        move || action::team::agent_owner(&conn, user_id, competition_id)
+    })
+    .await??;
+
+    let mut builder = HttpResponse::Ok();
+    if !bypass_limits {
         // if Utc::now() > DateTime::parse_from_rfc2822("Thu, 17 Mar 2022 00:05:00 GMT").unwrap() {
         //     return Err(SubmissionsClosed.into());
         // }
 
+        let keys = owner.upload_limiter_keys(&competition, user_id);
         limiter
-            .get_permit(format!("{}-{}", competition, user_id))
+            .get_permits(&keys)
             .await?
             .map_err(TooManyUploadAttempts::from)?;
+
+        limiter.peek_all(&keys).await?.insert_headers(&mut builder);
     }
 
     let field = payload
@@ -184,6 +208,7 @@ pub async fn upload(
                 &conn,
                 id,
                 user_id,
+                owner.team(),
                 competition_id,
                 extension.to_string(),
             )
@@ -214,8 +239,31 @@ pub async fn upload(
         }
     }
 
-    let execution_environment =
-        crate::controller::get_execution_environment(&storage, &competition, &id).await;
+    let execution_environment = match crate::controller::get_execution_environment(
+        &storage,
+        &competition,
+        &id,
+        execution_profiles,
+    )
+    .await
+    {
+        Ok(execution_environment) => execution_environment,
+        Err(e) => {
+            web::block({
+                let pool = pool.clone();
+                let id = id.clone();
+                let conn = web::block(move || pool.get()).await??;
+                move || crate::controller::mark_upload_as_failed(&conn, id)
+            })
+            .await??;
+
+            if let Err(delete_error) = storage.delete_file(&competition, &id).await {
+                error!(upload_error=%e, %delete_error, "error when deleting upload file with an unknown execution environment");
+            }
+
+            return Err(e.into());
+        }
+    };
 
     // We want file size in kb
     let file_size_kb = (storage
@@ -239,9 +287,8 @@ pub async fn upload(
     })
     .await??;
 
-    let mq_conn = mq_pool.get().await?;
     doxa_mq::action::emit_activation_event(
-        &mq_conn,
+        mq.as_ref(),
         &doxa_mq::model::ActivationEvent {
             competition: competition.clone(),
             agent: id.clone(),
@@ -250,12 +297,22 @@ pub async fn upload(
     )
     .await?;
 
+    if bypass_limits {
+        doxa_auth::audit::record(
+            &pool,
+            InsertableAuditEntry::new(Some(user_id), actions::UPLOAD_BYPASSING_LIMIT)
+                .with_target(agent_target(&id))
+                .with_metadata(json!({ "competition": competition })),
+        )
+        .await?;
+    }
+
     if let Err(e) = crate::controller::delete_old_uploads(
         storage,
         pool,
         &competition,
         enrollment.competition,
-        user_id,
+        owner,
         uploaded_agent.uploaded_at,
     )
     .await
@@ -263,5 +320,5 @@ pub async fn upload(
         error!(error=%e, "failed to delete old uploads");
     }
 
-    Ok(HttpResponse::Ok().json(response::Upload { id, competition }))
+    Ok(builder.json(response::Upload { id, competition }))
 }
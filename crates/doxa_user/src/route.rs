@@ -1,4 +1,4 @@
-use doxa_auth::{error::UserNotFound, guard::AuthGuard};
+use doxa_auth::{api_token::TokenScope, error::UserNotFound, guard::AuthGuard};
 use doxa_core::{actix_web::web, error::HttpResponse, EndpointResult};
 use doxa_db::PgPool;
 
@@ -46,7 +46,8 @@ async fn current_user_info(db_pool: web::Data<PgPool>, user: AuthGuard<()>) -> E
     })
     .await??;
 
-    let user_id = user.id_required()?;
+    // This only reads the user's info so any API token can use it
+    let user_id = user.scoped_id_required(&TokenScope::Read)?;
     let user = web::block(move || doxa_db::action::user::get_user_by_id(&conn, user_id)).await??;
 
     let conn = web::block(move || db_pool.get()).await??;
@@ -27,7 +27,7 @@ use crate::{
     ExecutionConfig,
 };
 
-use self::agent::RunningAgent;
+use self::agent::{AgentOutput, RunningAgent};
 
 mod agent;
 pub mod spawn;
@@ -42,6 +42,8 @@ pub const MAX_AGENT_SIZE: usize = 8_000_000_000;
 pub const MAX_MSG_LEN: usize = 50_000_000;
 pub const MAX_FILE_NAME_LEN: usize = 300;
 pub const STDERR_LEN: usize = 100_000;
+/// Lines of stderr longer than this are truncated as they are read, before being sent to the host
+pub const MAX_STDERR_LINE_LEN: usize = 10_000;
 
 /// This is the server that runs inside of the VM.
 pub struct VMExecutor<S: AsyncWrite + AsyncRead + Unpin + Send + 'static> {
@@ -88,11 +90,12 @@ impl<S: AsyncWrite + AsyncRead + Unpin + Send + 'static> VMExecutor<S> {
             // Change next_full_message to return a struct that impl's future and is cancellable
             loop {
                 tokio::select! {
-                    Some(result) = OptionFuture::from(executor.agent.as_mut().map(|agent| agent.next_line())) => {
+                    Some(result) = OptionFuture::from(executor.agent.as_mut().map(|agent| agent.next_output())) => {
                         match result.unwrap() {
-                            Some(line) => executor.handle_output_line(line).await.unwrap(),
+                            AgentOutput::Stdout(line) => executor.handle_output_line(line).await.unwrap(),
+                            AgentOutput::Stderr(line) => executor.handle_stderr_line(line).await.unwrap(),
                             // Agent proecss finished
-                            None => executor.handle_agent_terminated().await.unwrap(),
+                            AgentOutput::Exited => executor.handle_agent_terminated().await.unwrap(),
                         }
                     }
                     message = message_reader.read_full_message(&mut executor.stream) => {
@@ -107,21 +110,16 @@ impl<S: AsyncWrite + AsyncRead + Unpin + Send + 'static> VMExecutor<S> {
 
     async fn handle_agent_terminated(&mut self) -> io::Result<()> {
         println!("Agent terminated");
-        let mut err_output = String::new();
-        if let Some(mut agent) = self.agent.take() {
-            // NOTE: currently STDERR get's stored in memory until the agent exits.
-            agent
-                .child_process
-                .stderr
-                .take()
-                .unwrap()
-                .take(STDERR_LEN as u64)
-                .read_to_string(&mut err_output)
-                .await?;
-        }
+        // The full stderr has already been streamed to the host, this just includes the start of
+        // it for convenience.
+        let agent = self.agent.take();
+        let err_output = agent
+            .as_ref()
+            .map(|agent| agent.stderr_head())
+            .unwrap_or_default();
 
         self.stream
-            .send_prefixed_full_message(b"F_", err_output.as_bytes())
+            .send_prefixed_full_message(b"F_", err_output)
             .await?;
 
         Ok(())
@@ -134,6 +132,13 @@ impl<S: AsyncWrite + AsyncRead + Unpin + Send + 'static> VMExecutor<S> {
             .await
     }
 
+    async fn handle_stderr_line(&mut self, line: Vec<u8>) -> io::Result<()> {
+        // The line has already been truncated to `MAX_STDERR_LINE_LEN` as it was read
+        self.stream
+            .send_prefixed_full_message(b"STDERR_", &line)
+            .await
+    }
+
     async fn handle_message(&mut self, msg: &[u8]) -> Result<(), HandleMessageError> {
         let split_location = msg
             .iter()
@@ -1,17 +1,35 @@
 use std::ffi::OsStr;
 use std::path::Path;
 
-use tokio::io::{AsyncBufReadExt, BufReader, Lines};
-use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, Lines};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout};
 
 use crate::executor::spawn::Python;
 use crate::{error::ExecutionSpawnError, executor::spawn, ExecutionConfig};
 
+use super::{MAX_STDERR_LINE_LEN, STDERR_LEN};
+
 /// Represents a running agent
 pub struct RunningAgent {
     pub child_process: Child,
     pub stdin: ChildStdin,
     stdout_lines: Lines<BufReader<ChildStdout>>,
+    stdout_closed: bool,
+    stderr_lines: BoundedLines<ChildStderr>,
+    stderr_closed: bool,
+    /// The first `STDERR_LEN` bytes of stderr, this is sent to the host when the agent exits.
+    stderr_head: Vec<u8>,
+    /// Set once a line didn't fit in `stderr_head`, later lines are left out so it has no gaps.
+    stderr_head_full: bool,
+}
+
+pub enum AgentOutput {
+    Stdout(String),
+    /// A line of stderr (without the newline) truncated to `MAX_STDERR_LINE_LEN` bytes, this is not
+    /// guaranteed to be valid UTF-8.
+    Stderr(Vec<u8>),
+    /// Both stdout and stderr have been closed which means the agent has exited.
+    Exited,
 }
 
 impl RunningAgent {
@@ -31,16 +49,145 @@ impl RunningAgent {
         }?;
 
         let stdout = child_process.stdout.take().unwrap();
+        let stderr = child_process.stderr.take().unwrap();
         let stdin = child_process.stdin.take().unwrap();
 
         Ok(RunningAgent {
             child_process,
             stdin,
             stdout_lines: BufReader::new(stdout).lines(),
+            stdout_closed: false,
+            stderr_lines: BoundedLines::new(stderr, MAX_STDERR_LINE_LEN),
+            stderr_closed: false,
+            stderr_head: Vec::new(),
+            stderr_head_full: false,
         })
     }
 
-    pub async fn next_line(&mut self) -> std::io::Result<Option<String>> {
-        self.stdout_lines.next_line().await
+    /// Waits for the next line on either stdout or stderr.
+    /// This method is cancel safe.
+    pub async fn next_output(&mut self) -> std::io::Result<AgentOutput> {
+        loop {
+            tokio::select! {
+                line = self.stdout_lines.next_line(), if !self.stdout_closed => {
+                    match line? {
+                        Some(line) => return Ok(AgentOutput::Stdout(line)),
+                        None => self.stdout_closed = true,
+                    }
+                }
+                line = self.stderr_lines.next_line(), if !self.stderr_closed => {
+                    match line? {
+                        Some(line) => {
+                            if !self.stderr_head_full
+                                && self.stderr_head.len() + line.len() < STDERR_LEN
+                            {
+                                self.stderr_head.extend_from_slice(&line);
+                                self.stderr_head.push(b'\n');
+                            } else {
+                                self.stderr_head_full = true;
+                            }
+
+                            return Ok(AgentOutput::Stderr(line));
+                        }
+                        None => self.stderr_closed = true,
+                    }
+                }
+                else => return Ok(AgentOutput::Exited),
+            }
+        }
+    }
+
+    /// The start of the agent's stderr, this is capped at `STDERR_LEN` bytes.
+    pub fn stderr_head(&self) -> &[u8] {
+        &self.stderr_head
+    }
+}
+
+/// Splits a reader into lines like [`AsyncBufReadExt::split`] but only keeps the first `max_len`
+/// bytes of each line, the rest is dropped as it is read so that a line without a newline can't
+/// use up the VM's memory.
+struct BoundedLines<R> {
+    reader: BufReader<R>,
+    max_len: usize,
+    /// The start of the line that is being read, this is kept here so that `next_line` is cancel
+    /// safe
+    line: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> BoundedLines<R> {
+    fn new(reader: R, max_len: usize) -> Self {
+        BoundedLines {
+            reader: BufReader::new(reader),
+            max_len,
+            line: Vec::new(),
+        }
+    }
+
+    /// The next line without the newline, or `None` once the reader has been closed.
+    /// This method is cancel safe.
+    async fn next_line(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            let buf = self.reader.fill_buf().await?;
+            if buf.is_empty() {
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+
+                return Ok(Some(std::mem::take(&mut self.line)));
+            }
+
+            let newline = buf.iter().position(|b| *b == b'\n');
+            let segment = &buf[..newline.unwrap_or(buf.len())];
+            let space = self.max_len.saturating_sub(self.line.len());
+            self.line
+                .extend_from_slice(&segment[..segment.len().min(space)]);
+
+            let read = segment.len() + newline.map_or(0, |_| 1);
+            self.reader.consume(read);
+
+            if newline.is_some() {
+                return Ok(Some(std::mem::take(&mut self.line)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn lines(input: &[u8], max_len: usize) -> Vec<Vec<u8>> {
+        let mut reader = BoundedLines::new(input, max_len);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    #[tokio::test]
+    async fn splits_lines() {
+        assert_eq!(
+            lines(b"first\n\nthird\nlast", 100).await,
+            vec![
+                b"first".to_vec(),
+                b"".to_vec(),
+                b"third".to_vec(),
+                b"last".to_vec()
+            ]
+        );
+        assert!(lines(b"", 100).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_truncated() {
+        let mut input = vec![b'a'; 100_000];
+        input.extend_from_slice(b"\nnext\n");
+
+        assert_eq!(
+            lines(&input, 10).await,
+            vec![b"aaaaaaaaaa".to_vec(), b"next".to_vec()]
+        );
     }
 }
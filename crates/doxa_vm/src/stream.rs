@@ -488,6 +488,12 @@ impl MessageReader {
         }
     }
 
+    /// The message that was most recently read by `read_full_message`.
+    /// If the last read did not complete this will only contain part of the message.
+    pub fn message(&self) -> &[u8] {
+        &self.msg_buf
+    }
+
     pub fn take_buf(self) -> Vec<u8> {
         self.msg_buf
     }
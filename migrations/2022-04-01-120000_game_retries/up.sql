@@ -0,0 +1,2 @@
+ALTER TABLE games
+ADD COLUMN retries INT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE competitions
+DROP COLUMN paused;
@@ -0,0 +1,2 @@
+ALTER TABLE competitions
+ADD COLUMN paused BOOLEAN NOT NULL DEFAULT FALSE;
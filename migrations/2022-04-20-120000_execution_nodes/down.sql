@@ -0,0 +1,2 @@
+DROP TABLE game_match_requests;
+DROP TABLE execution_nodes;
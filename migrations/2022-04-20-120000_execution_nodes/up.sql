@@ -0,0 +1,21 @@
+CREATE TABLE execution_nodes(
+    name TEXT PRIMARY KEY,
+    registered_at timestamptz NOT NULL,
+    last_heartbeat_at timestamptz NOT NULL,
+    -- The maximum number of games the node runs at once
+    capacity INT NOT NULL,
+    -- How many more games the node could start as of the last heartbeat
+    available INT NOT NULL,
+    running_games INT[] NOT NULL DEFAULT '{}',
+    -- The execution profiles the node runs for each competition
+    competitions JSONB DEFAULT '{}'::jsonb NOT NULL
+);
+
+-- Kept so that games can be requeued if the node running them is lost, the row is deleted once the
+-- game has ended
+CREATE TABLE game_match_requests(
+    game INT references games(id) ON DELETE CASCADE PRIMARY KEY,
+    -- The queue (priority lane) the match request was published to
+    queue TEXT NOT NULL,
+    data BYTEA NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE execution_nodes
+DROP COLUMN drain_timeout_secs;
@@ -0,0 +1,2 @@
+ALTER TABLE execution_nodes
+ADD COLUMN drain_timeout_secs INT;
@@ -0,0 +1,14 @@
+-- Personal access tokens that users create for non-interactive use such as uploading from CI
+CREATE TABLE api_tokens(
+    id SERIAL PRIMARY KEY,
+    owner INT references users(id) NOT NULL,
+    name TEXT NOT NULL,
+    -- The SHA-256 hash of the token, the token itself is only shown once when it is created
+    token_hash TEXT NOT NULL UNIQUE,
+    -- e.g. `read`, `upload:uttt` or `activate:*`
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
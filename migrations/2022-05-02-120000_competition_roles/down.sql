@@ -0,0 +1 @@
+DROP TABLE competition_roles;
@@ -0,0 +1,9 @@
+-- Roles give users extra permissions within a single competition without making them a global admin
+CREATE TABLE competition_roles(
+    user_id INT references users(id) NOT NULL,
+    competition INT references competitions(id) NOT NULL,
+    -- `organiser`, `moderator` or `viewer`
+    role TEXT NOT NULL,
+    granted_at timestamptz NOT NULL,
+    PRIMARY KEY(user_id, competition, role)
+);
@@ -0,0 +1,6 @@
+-- Tokens from logging in that were issued before this time are rejected, this is set
+-- whenever the token generation is rotated
+ALTER TABLE users ADD COLUMN tokens_revoked_at timestamptz;
+-- Access tokens (but not refresh tokens) issued before this time are rejected, this is set
+-- when a single delegated session is revoked as access tokens can't be traced to their session
+ALTER TABLE users ADD COLUMN access_tokens_revoked_at timestamptz;
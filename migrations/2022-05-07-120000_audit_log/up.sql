@@ -0,0 +1,26 @@
+-- Rows are only ever inserted, the triggers below reject updating or deleting them
+CREATE TABLE audit_log(
+    id BIGSERIAL PRIMARY KEY,
+    created_at timestamptz NOT NULL,
+    -- NULL when the action was performed by the system account or `doxa_adm`
+    actor INT references users(id),
+    action TEXT NOT NULL,
+    -- e.g. `user:12` or `agent:abc`
+    target TEXT,
+    metadata JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_log_created_at ON audit_log(created_at);
+CREATE INDEX audit_log_actor ON audit_log(actor);
+CREATE INDEX audit_log_target ON audit_log(target);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'the audit log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE PROCEDURE audit_log_append_only();
+CREATE TRIGGER audit_log_no_truncate BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE PROCEDURE audit_log_append_only();
@@ -0,0 +1,2 @@
+DROP TABLE local_account_tokens;
+DROP TABLE local_accounts;
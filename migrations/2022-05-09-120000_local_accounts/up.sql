@@ -0,0 +1,21 @@
+-- Credentials for the built-in auth provider, only used when the server isn't using autha
+CREATE TABLE local_accounts(
+    user_id INT PRIMARY KEY references users(id),
+    -- Trimmed and lowercased
+    email TEXT NOT NULL UNIQUE,
+    -- PHC string of the argon2 hash
+    password_hash TEXT NOT NULL,
+    email_verified_at timestamptz,
+    created_at timestamptz NOT NULL
+);
+
+-- Single use tokens sent by email for verifying the email address or resetting the password
+CREATE TABLE local_account_tokens(
+    token_hash TEXT PRIMARY KEY,
+    user_id INT references local_accounts(user_id) NOT NULL,
+    -- `verify_email` or `reset_password`
+    purpose TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz
+);
@@ -0,0 +1,2 @@
+DROP TABLE competition_invites;
+ALTER TABLE competitions DROP COLUMN open_enrollment;
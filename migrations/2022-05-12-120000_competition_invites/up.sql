@@ -0,0 +1,24 @@
+-- Whether any user can enroll themselves in the competition without an invite code
+ALTER TABLE competitions ADD COLUMN open_enrollment BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE competition_invites(
+    id SERIAL PRIMARY KEY,
+    competition INT references competitions(id) NOT NULL,
+    code TEXT NOT NULL UNIQUE,
+    -- NULL when created through doxa_adm
+    created_by INT references users(id),
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz,
+    max_uses INT NOT NULL,
+    uses INT NOT NULL DEFAULT 0,
+    -- Only users with an email address at this domain can use the invite
+    email_domain TEXT,
+    revoked_at timestamptz
+);
+
+CREATE INDEX competition_invites_competition_idx ON competition_invites(competition);
+
+-- Uploads now require an enrollment, so enroll everyone that already uploaded an agent without one
+INSERT INTO enrollment(user_id, competition)
+SELECT DISTINCT owner, competition FROM agents
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,18 @@
+DROP VIEW active_agents;
+
+DROP INDEX agents_active_unique;
+CREATE UNIQUE INDEX agents_active_unique ON agents (owner, competition)
+    WHERE active;
+
+DROP INDEX agents_one_active_per_team;
+ALTER TABLE agents DROP COLUMN team;
+
+CREATE VIEW active_agents AS
+SELECT *
+FROM agents
+WHERE active = true;
+
+DROP TABLE team_invites;
+DROP TABLE team_members;
+DROP TABLE teams;
+ALTER TABLE competitions DROP COLUMN max_team_size;
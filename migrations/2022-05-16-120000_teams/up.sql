@@ -0,0 +1,49 @@
+-- The maximum number of members in a team, users can't create teams when this is NULL
+ALTER TABLE competitions ADD COLUMN max_team_size INT;
+
+CREATE TABLE teams(
+    id SERIAL PRIMARY KEY,
+    competition INT references competitions(id) NOT NULL,
+    name TEXT NOT NULL,
+    created_by INT references users(id) NOT NULL,
+    created_at timestamptz NOT NULL,
+    UNIQUE (competition, name)
+);
+
+CREATE TABLE team_members(
+    team INT references teams(id) NOT NULL,
+    user_id INT references users(id) NOT NULL,
+    -- Copied from the team so that users can only be in one team per competition
+    competition INT references competitions(id) NOT NULL,
+    joined_at timestamptz NOT NULL,
+    PRIMARY KEY (team, user_id),
+    UNIQUE (competition, user_id)
+);
+
+CREATE TABLE team_invites(
+    team INT references teams(id) NOT NULL,
+    user_id INT references users(id) NOT NULL,
+    invited_by INT references users(id) NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (team, user_id)
+);
+
+CREATE INDEX team_invites_user_id_idx ON team_invites(user_id);
+
+-- Agents uploaded by a member of a team belong to the team rather than the uploader
+ALTER TABLE agents ADD COLUMN team INT references teams(id);
+
+CREATE UNIQUE INDEX agents_one_active_per_team ON agents(team) WHERE active = true AND team IS NOT NULL;
+
+-- A team's active agent keeps the member that uploaded it as the owner even after they leave, so
+-- only personal agents are limited to one active agent per owner
+DROP INDEX agents_active_unique;
+CREATE UNIQUE INDEX agents_active_unique ON agents (owner, competition)
+    WHERE active AND team IS NULL;
+
+DROP VIEW active_agents;
+
+CREATE VIEW active_agents AS
+SELECT *
+FROM agents
+WHERE active = true;
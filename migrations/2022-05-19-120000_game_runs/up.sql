@@ -0,0 +1,2 @@
+ALTER TABLE games
+ADD COLUMN runs INT NOT NULL DEFAULT 0;